@@ -24,8 +24,9 @@ use crate::view::sketch::Sketch;
 use crate::input::{DeviceEvent, PowerSource, ButtonCode, ButtonStatus, VAL_RELEASE, VAL_PRESS};
 use crate::input::{raw_events, device_events, usb_events, display_rotate_event, button_scheme_event};
 use crate::gesture::{GestureEvent, gesture_events};
-use crate::helpers::{load_json, save_json, load_toml, save_toml};
-use crate::metadata::{Metadata, METADATA_FILENAME, auto_import};
+use crate::helpers::{load_json, load_toml, save_toml};
+use crate::metadata::{Metadata, METADATA_FILENAME, auto_import, load_metadata, save_metadata};
+use crate::watcher::watch_library;
 use crate::settings::{ButtonScheme, Settings, SETTINGS_PATH, RotationLock};
 use crate::frontlight::{Frontlight, StandardFrontlight, NaturalFrontlight, PremixedFrontlight};
 use crate::lightsensor::{LightSensor, KoboLightSensor};
@@ -156,7 +157,8 @@ struct HistoryItem {
     monochrome: bool,
 }
 
-fn build_context(fb: Box<dyn Framebuffer>) -> Result<Context, Error> {
+// Also returns a message for the user about the loading of the metadata.
+fn build_context(fb: Box<dyn Framebuffer>) -> Result<(Context, Option<String>), Error> {
     let rtc = Rtc::new("/dev/rtc0")
                   .map_err(|e| eprintln!("Can't open RTC device: {}.", e))
                   .ok();
@@ -175,12 +177,17 @@ fn build_context(fb: Box<dyn Framebuffer>) -> Result<Context, Error> {
     let mut settings = settings.unwrap_or_default();
 
     let path = settings.library_path.join(METADATA_FILENAME);
-    let mut metadata = load_json::<Metadata, _>(path)
-                                 .map_err(|e| eprintln!("Can't load metadata: {}", e))
-                                 .or_else(|_| auto_import(&settings.library_path,
-                                                          &Vec::new(),
-                                                          &settings.import))
-                                 .unwrap_or_default();
+    let notice;
+    let mut metadata = match load_metadata(&path) {
+        Ok((metadata, restoration)) => {
+            notice = restoration.map(|r| r.report());
+            metadata
+        },
+        Err(e) => {
+            notice = e.report();
+            auto_import(&settings.library_path, &Vec::new(), &settings.import).unwrap_or_default()
+        },
+    };
 
     if initial_run && metadata.is_empty() && settings.library_path != PathBuf::from(INTERNAL_CARD_ROOT) {
         settings.library_path = PathBuf::from(INTERNAL_CARD_ROOT);
@@ -214,8 +221,8 @@ fn build_context(fb: Box<dyn Framebuffer>) -> Result<Context, Error> {
                                         .context("Can't create premixed frontlight.")?) as Box<dyn Frontlight>,
     };
 
-    Ok(Context::new(fb, rtc, settings, metadata, PathBuf::from(METADATA_FILENAME),
-                    fonts, battery, frontlight, lightsensor))
+    Ok((Context::new(fb, rtc, settings, metadata, PathBuf::from(METADATA_FILENAME),
+                     fonts, battery, frontlight, lightsensor), notice))
 }
 
 fn schedule_task(id: TaskId, event: Event, delay: Duration, hub: &Sender<Event>, tasks: &mut Vec<Task>) {
@@ -301,7 +308,7 @@ pub fn run() -> Result<(), Error> {
         fb.set_rotation(startup_rotation).ok();
     }
 
-    let mut context = build_context(Box::new(fb)).context("Can't build context.")?;
+    let (mut context, notice) = build_context(Box::new(fb)).context("Can't build context.")?;
 
     context.load_dictionaries();
     context.load_keyboard_layouts();
@@ -354,6 +361,29 @@ pub fn run() -> Result<(), Error> {
         });
     }
 
+    // Tells the library watcher that the library might be back.
+    let mut watcher_restart = None;
+
+    if context.settings.import.watch_trigger {
+        let tx7 = tx.clone();
+        let (restart_tx, restart_rx) = mpsc::channel();
+        let library_path = context.settings.library_path.clone();
+        let import_settings = context.settings.import.clone();
+        thread::spawn(move || {
+            loop {
+                if let Err(e) = watch_library(&library_path, import_settings.clone(), |change| {
+                    tx7.send(Event::LibraryChanged(change)).ok();
+                }) {
+                    eprintln!("Can't watch the library: {}", e);
+                }
+                if restart_rx.recv().is_err() {
+                    break;
+                }
+            }
+        });
+        watcher_restart = Some(restart_tx);
+    }
+
     if context.settings.wifi {
         Command::new("scripts/wifi-enable.sh").status().ok();
     } else {
@@ -373,6 +403,10 @@ pub fn run() -> Result<(), Error> {
     let mut history: Vec<HistoryItem> = Vec::new();
     let mut view: Box<dyn View> = Box::new(Home::new(context.fb.rect(), &tx, &mut context)?);
 
+    if let Some(msg) = notice {
+        tx.send(Event::Notify(msg)).ok();
+    }
+
     let mut updating = FnvHashMap::default();
 
     println!("{} is running on a Kobo {}.", APP_NAME,
@@ -546,12 +580,22 @@ pub fn run() -> Result<(), Error> {
                             if Path::new("/mnt/onboard/.kobo/KoboRoot.tgz").exists() {
                                 tx.send(Event::Select(EntryId::Reboot)).ok();
                             }
+                            // The metadata might have been edited by hand.
                             let path = context.settings.library_path.join(&context.filename);
-                            let metadata = load_json::<Metadata, _>(path)
-                                                     .map_err(|e| eprintln!("Can't load metadata: {}", e))
-                                                     .unwrap_or_default();
-                            if !metadata.is_empty() {
-                                context.metadata = metadata;
+                            match load_metadata(&path) {
+                                Ok((metadata, restoration)) => {
+                                    if let Some(restoration) = restoration {
+                                        tx.send(Event::Notify(restoration.report())).ok();
+                                    }
+                                    if !metadata.is_empty() {
+                                        context.metadata = metadata;
+                                    }
+                                },
+                                Err(e) => {
+                                    if let Some(msg) = e.report() {
+                                        tx.send(Event::Notify(msg)).ok();
+                                    }
+                                },
                             }
                             if context.settings.import.unshare_trigger {
                                 let metadata = auto_import(&context.settings.library_path,
@@ -559,6 +603,9 @@ pub fn run() -> Result<(), Error> {
                                                            &context.settings.import);
                                 context.metadata.append(&mut metadata.unwrap_or_default());
                             }
+                            if let Some(restart) = watcher_restart.as_ref() {
+                                restart.send(()).ok();
+                            }
                             view.handle_event(&Event::Reseed, &tx, &mut bus, &mut context);
                         } else {
                             context.plugged = false;
@@ -583,6 +630,10 @@ pub fn run() -> Result<(), Error> {
                             continue;
                         }
 
+                        if view.rotation_locked() {
+                            continue;
+                        }
+
                         if let Some(rotation_lock) = context.settings.rotation_lock {
                             let orientation = CURRENT_DEVICE.orientation(n);
                             if rotation_lock == RotationLock::Current ||
@@ -628,7 +679,7 @@ pub fn run() -> Result<(), Error> {
                 let path = Path::new(SETTINGS_PATH);
                 save_toml(&context.settings, path).map_err(|e| eprintln!("Can't save settings: {}", e)).ok();
                 let path = context.settings.library_path.join(&context.filename);
-                save_json(&context.metadata, path).map_err(|e| eprintln!("Can't save metadata: {}", e)).ok();
+                save_metadata(&context.metadata, &path).map_err(|e| eprintln!("Can't save metadata: {}", e)).ok();
                 if context.settings.frontlight {
                     context.settings.frontlight_levels = context.frontlight.levels();
                     context.frontlight.set_intensity(0.0);
@@ -702,7 +753,7 @@ pub fn run() -> Result<(), Error> {
                 let path = Path::new(SETTINGS_PATH);
                 save_toml(&context.settings, path).map_err(|e| eprintln!("Can't save settings: {}", e)).ok();
                 let path = context.settings.library_path.join(&context.filename);
-                save_json(&context.metadata, path).map_err(|e| eprintln!("Can't save metadata: {}", e)).ok();
+                save_metadata(&context.metadata, &path).map_err(|e| eprintln!("Can't save metadata: {}", e)).ok();
                 if context.settings.frontlight {
                     context.settings.frontlight_levels = context.frontlight.levels();
                     context.frontlight.set_intensity(0.0);
@@ -802,7 +853,10 @@ pub fn run() -> Result<(), Error> {
             Event::Open(info) => {
                 let rotation = context.display.rotation;
                 if let Some(n) = info.reader.as_ref().and_then(|r| r.rotation) {
-                    if CURRENT_DEVICE.orientation(n) != CURRENT_DEVICE.orientation(rotation) {
+                    // A locked rotation is applied as is, the others only restore the orientation.
+                    let locked = info.reader.as_ref().and_then(|r| r.locked_rotation()).is_some();
+                    if (locked && n != rotation) ||
+                       CURRENT_DEVICE.orientation(n) != CURRENT_DEVICE.orientation(rotation) {
                         updating.retain(|tok, _| context.fb.wait(*tok).is_err());
                         if let Ok(dims) = context.fb.set_rotation(n) {
                             raw_sender.send(display_rotate_event(n)).ok();
@@ -986,7 +1040,8 @@ pub fn run() -> Result<(), Error> {
                                               msg, &tx, &mut context);
                 view.children_mut().push(Box::new(notif) as Box<dyn View>);
             },
-            Event::AddDocument(..) | Event::RemoveDocument(..) => {
+            Event::LibraryChanged(..) if context.shared => (),
+            Event::AddDocument(..) | Event::RemoveDocument(..) | Event::LibraryChanged(..) => {
                 if view.is::<Home>() {
                     view.handle_event(&evt, &tx, &mut bus, &mut context);
                 } else {
@@ -1047,7 +1102,7 @@ pub fn run() -> Result<(), Error> {
     }
 
     let path = context.settings.library_path.join(&context.filename);
-    save_json(&context.metadata, path).context("Can't save metadata.")?;
+    save_metadata(&context.metadata, &path).context("Can't save metadata.")?;
 
     let path = Path::new(SETTINGS_PATH);
     save_toml(&context.settings, path).context("Can't save settings.")?;
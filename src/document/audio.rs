@@ -0,0 +1,539 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::fs::File;
+use std::path::Path;
+use byteorder::{BigEndian, LittleEndian, ByteOrder};
+use failure::{Error, format_err};
+use crate::metadata::{Metadata, normalize_year};
+
+pub const AUDIOBOOK_KINDS: [&str; 3] = ["m4b", "mp3", "opus"];
+
+// The number of bytes read at the beginning of the file.
+const HEAD_SIZE: u64 = 4096;
+// The number of bytes, at the end of an Ogg file, searched for the last page.
+const OGG_TAIL_SIZE: u64 = 65_536;
+const OGG_PAGE_HEADER_SIZE: usize = 27;
+const OPUS_SAMPLE_RATE: u64 = 48_000;
+// The atoms and packets bigger than that aren't loaded.
+const MAX_CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+
+// In kbit/s, for MPEG-1 and MPEG-2 Layer III.
+const MPEG1_BITRATES: [u64; 15] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+const MPEG2_BITRATES: [u64; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+// The metadata found in the tags of an MP3, M4B or Opus file.
+#[derive(Debug, Clone, Default)]
+pub struct AudioMetadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub narrator: Option<String>,
+    pub date: Option<String>,
+    // In seconds.
+    pub duration: Option<u64>,
+}
+
+impl AudioMetadata {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<AudioMetadata, Error> {
+        let mut file = File::open(path)?;
+        let file_size = file.metadata()?.len();
+        let mut head = Vec::new();
+        (&mut file).take(HEAD_SIZE).read_to_end(&mut head)?;
+        file.seek(SeekFrom::Start(0))?;
+
+        let mut metadata = AudioMetadata::default();
+
+        if head.starts_with(b"ID3") || is_mpeg_frame(&head) {
+            metadata.read_mp3(&mut file, file_size)?;
+        } else if head.get(4..8) == Some(&b"ftyp"[..]) {
+            metadata.read_mp4(&mut file, file_size)?;
+        } else if head.starts_with(b"OggS") {
+            metadata.read_opus(&mut file, file_size)?;
+        } else {
+            return Err(format_err!("unknown audio format"));
+        }
+
+        Ok(metadata)
+    }
+
+    // The tags are named after the Vorbis comments. For audiobooks, the album is the book,
+    // whereas the title might only be a chapter's. The narrator is often stored as the composer.
+    fn set_tag(&mut self, name: &str, value: String) {
+        let value = value.trim().to_string();
+        if value.is_empty() {
+            return;
+        }
+        match name {
+            "album" => self.title = Some(value),
+            "title" => { self.title.get_or_insert(value); },
+            "artist" => self.author = Some(value),
+            "albumartist" => { self.author.get_or_insert(value); },
+            "narrator" => self.narrator = Some(value),
+            "composer" => { self.narrator.get_or_insert(value); },
+            "date" => self.date = Some(value),
+            _ => (),
+        }
+    }
+
+    fn read_mp3(&mut self, file: &mut File, file_size: u64) -> Result<(), Error> {
+        let mut header = [0u8; 10];
+        file.read_exact(&mut header)?;
+        let mut audio_start = 0;
+
+        if header.starts_with(b"ID3") {
+            let major = header[3];
+            let flags = header[5];
+            let tag_size = syncsafe(&header[6..10]) as u64;
+            audio_start = 10 + tag_size;
+            if tag_size > MAX_CHUNK_SIZE {
+                return Err(format_err!("oversized ID3 tag"));
+            }
+            let mut tag = Vec::with_capacity(tag_size as usize);
+            (&mut *file).take(tag_size).read_to_end(&mut tag)?;
+            // ID3v2.2 has shorter frame headers, and is rarely found nowadays.
+            if major == 3 || major == 4 {
+                self.read_id3_frames(&tag, major, flags);
+            }
+        }
+
+        if self.duration.is_none() {
+            // Assumes a constant bit rate.
+            let mut buf = Vec::new();
+            file.seek(SeekFrom::Start(audio_start))?;
+            (&mut *file).take(HEAD_SIZE).read_to_end(&mut buf)?;
+            self.duration = (0..buf.len()).find(|&i| is_mpeg_frame(&buf[i..]))
+                                          .and_then(|i| mpeg_bitrate(&buf[i..]))
+                                          .map(|bitrate| file_size.saturating_sub(audio_start) * 8 / (bitrate * 1000));
+        }
+
+        Ok(())
+    }
+
+    fn read_id3_frames(&mut self, tag: &[u8], major: u8, flags: u8) {
+        let mut offset = 0;
+
+        if flags & 0x40 != 0 && tag.len() >= 4 {
+            // The size of the extended header includes itself in ID3v2.4.
+            offset = if major == 4 {
+                syncsafe(&tag[..4]) as usize
+            } else {
+                (BigEndian::read_u32(&tag[..4]) as usize).saturating_add(4)
+            };
+        }
+
+        while offset.checked_add(10).map_or(false, |end| end <= tag.len()) {
+            let id = &tag[offset..offset+4];
+            // The padding.
+            if id[0] == 0 {
+                break;
+            }
+            let size = if major == 4 {
+                syncsafe(&tag[offset+4..offset+8])
+            } else {
+                BigEndian::read_u32(&tag[offset+4..offset+8])
+            } as usize;
+            offset += 10;
+            if size == 0 || size > tag.len() - offset {
+                break;
+            }
+            let data = &tag[offset..offset+size];
+            offset = match offset.checked_add(size) {
+                Some(offset) => offset,
+                None => break,
+            };
+
+            let name = match id {
+                b"TALB" => "album",
+                b"TIT2" => "title",
+                b"TPE1" => "artist",
+                b"TPE2" => "albumartist",
+                b"TCOM" => "composer",
+                b"TYER" | b"TDRC" => "date",
+                b"TLEN" => {
+                    self.duration = decode_id3_text(data).parse::<u64>().ok().map(|ms| ms / 1000);
+                    continue;
+                },
+                b"TXXX" => {
+                    let (description, value) = split_id3_text(data);
+                    if description.eq_ignore_ascii_case("narrator") {
+                        self.set_tag("narrator", value);
+                    }
+                    continue;
+                },
+                _ => continue,
+            };
+            self.set_tag(name, decode_id3_text(data));
+        }
+    }
+
+    fn read_mp4(&mut self, file: &mut File, file_size: u64) -> Result<(), Error> {
+        let mut offset: u64 = 0;
+
+        while offset.checked_add(8).map_or(false, |end| end <= file_size) {
+            let mut header = [0u8; 16];
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut header[..8])?;
+            let mut header_size = 8;
+            let size = match BigEndian::read_u32(&header[..4]) as u64 {
+                0 => file_size - offset,
+                1 => {
+                    file.read_exact(&mut header[8..])?;
+                    header_size = 16;
+                    BigEndian::read_u64(&header[8..])
+                },
+                size => size,
+            };
+            if size < header_size {
+                return Err(format_err!("invalid atom size"));
+            }
+
+            if &header[4..8] == b"moov" {
+                if size > MAX_CHUNK_SIZE {
+                    return Err(format_err!("oversized movie atom"));
+                }
+                let mut moov = Vec::with_capacity(size as usize);
+                (&mut *file).take(size - header_size).read_to_end(&mut moov)?;
+                self.read_moov(&moov);
+                return Ok(());
+            }
+
+            offset = offset.checked_add(size)
+                           .ok_or_else(|| format_err!("invalid atom size"))?;
+        }
+
+        Err(format_err!("missing movie atom"))
+    }
+
+    fn read_moov(&mut self, moov: &[u8]) {
+        for (kind, data) in atoms(moov) {
+            match kind {
+                b"mvhd" if !data.is_empty() => {
+                    let (timescale, duration) = if data[0] == 1 && data.len() >= 32 {
+                        (BigEndian::read_u32(&data[20..24]) as u64, BigEndian::read_u64(&data[24..32]))
+                    } else if data.len() >= 20 {
+                        (BigEndian::read_u32(&data[12..16]) as u64, BigEndian::read_u32(&data[16..20]) as u64)
+                    } else {
+                        continue;
+                    };
+                    if timescale > 0 {
+                        self.duration = Some(duration / timescale);
+                    }
+                },
+                b"udta" => {
+                    for (kind, data) in atoms(data) {
+                        if kind != b"meta" || data.len() < 8 {
+                            continue;
+                        }
+                        // The version and flags are missing from QuickTime's meta atoms.
+                        let children = if &data[4..8] == b"hdlr" { data } else { &data[4..] };
+                        for (kind, data) in atoms(children) {
+                            if kind == b"ilst" {
+                                self.read_ilst(data);
+                            }
+                        }
+                    }
+                },
+                _ => (),
+            }
+        }
+    }
+
+    fn read_ilst(&mut self, ilst: &[u8]) {
+        for (kind, item) in atoms(ilst) {
+            let mut name = match kind {
+                b"\xA9alb" => "album",
+                b"\xA9nam" => "title",
+                b"\xA9ART" => "artist",
+                b"aART" => "albumartist",
+                b"\xA9nrt" => "narrator",
+                b"\xA9wrt" => "composer",
+                b"\xA9day" => "date",
+                b"----" => "",
+                _ => continue,
+            };
+            let mut value = None;
+            for (kind, data) in atoms(item) {
+                match kind {
+                    // The name of a free-form item.
+                    b"name" if data.len() >= 4 => {
+                        if String::from_utf8_lossy(&data[4..]).eq_ignore_ascii_case("narrator") {
+                            name = "narrator";
+                        }
+                    },
+                    // The type and the locale precede the value.
+                    b"data" if data.len() >= 8 => {
+                        value = Some(String::from_utf8_lossy(&data[8..]).into_owned());
+                    },
+                    _ => (),
+                }
+            }
+            if let Some(value) = value {
+                self.set_tag(name, value);
+            }
+        }
+    }
+
+    fn read_opus(&mut self, file: &mut File, file_size: u64) -> Result<(), Error> {
+        let mut packets: Vec<Vec<u8>> = vec![Vec::new()];
+
+        // The identification and comment headers are the first two packets.
+        while packets.len() <= 2 {
+            let mut header = [0u8; OGG_PAGE_HEADER_SIZE];
+            file.read_exact(&mut header)?;
+            if &header[..4] != b"OggS" {
+                return Err(format_err!("invalid Ogg page"));
+            }
+            let mut segments = vec![0u8; header[26] as usize];
+            file.read_exact(&mut segments)?;
+            for len in segments {
+                let packet = packets.last_mut().unwrap();
+                let start = packet.len();
+                if start as u64 + len as u64 > MAX_CHUNK_SIZE {
+                    return Err(format_err!("oversized Ogg packet"));
+                }
+                packet.resize(start + len as usize, 0);
+                file.read_exact(&mut packet[start..])?;
+                // A packet ends with the first segment shorter than 255 bytes.
+                if len < 255 {
+                    packets.push(Vec::new());
+                }
+            }
+        }
+
+        let head = &packets[0];
+        if !head.starts_with(b"OpusHead") || head.len() < 12 {
+            return Err(format_err!("missing Opus header"));
+        }
+        let pre_skip = LittleEndian::read_u16(&head[10..12]) as u64;
+
+        let tags = &packets[1];
+        if !tags.starts_with(b"OpusTags") {
+            return Err(format_err!("missing Opus tags"));
+        }
+        for comment in vorbis_comments(&tags[8..]) {
+            if let Some(index) = comment.find('=') {
+                let name = comment[..index].to_lowercase();
+                self.set_tag(&name, comment[index+1..].to_string());
+            }
+        }
+
+        // The granule position of the last page is the number of samples.
+        let tail_start = file_size.saturating_sub(OGG_TAIL_SIZE);
+        let mut tail = Vec::new();
+        file.seek(SeekFrom::Start(tail_start))?;
+        file.read_to_end(&mut tail)?;
+        self.duration = tail.windows(4).rposition(|w| w == b"OggS")
+                            .filter(|&i| i + 14 <= tail.len())
+                            .map(|i| LittleEndian::read_u64(&tail[i+6..i+14]))
+                            .filter(|&granule| granule != u64::MAX)
+                            .map(|granule| granule.saturating_sub(pre_skip) / OPUS_SAMPLE_RATE);
+
+        Ok(())
+    }
+}
+
+fn syncsafe(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |n, &b| (n << 7) | (b & 0x7F) as u32)
+}
+
+fn is_mpeg_frame(bytes: &[u8]) -> bool {
+    bytes.len() >= 4 && bytes[0] == 0xFF && bytes[1] & 0xE0 == 0xE0
+}
+
+// The bit rate, in kbit/s, of a Layer III frame.
+fn mpeg_bitrate(frame: &[u8]) -> Option<u64> {
+    let version = (frame[1] >> 3) & 0x03;
+    let layer = (frame[1] >> 1) & 0x03;
+    let index = (frame[2] >> 4) as usize;
+    if layer != 1 || version == 1 || index == 0 || index >= MPEG1_BITRATES.len() {
+        return None;
+    }
+    Some(if version == 3 { MPEG1_BITRATES[index] } else { MPEG2_BITRATES[index] })
+}
+
+fn decode_id3_text(data: &[u8]) -> String {
+    match data.split_first() {
+        Some((&encoding, text)) => decode_id3_string(encoding, text),
+        None => String::new(),
+    }
+}
+
+// Splits the text of a user defined frame into its description and its value.
+fn split_id3_text(data: &[u8]) -> (String, String) {
+    let (encoding, text) = match data.split_first() {
+        Some((&encoding, text)) => (encoding, text),
+        None => return (String::new(), String::new()),
+    };
+    let index = if encoding == 1 || encoding == 2 {
+        (0..text.len() / 2).map(|i| 2 * i).find(|&i| text[i] == 0 && text[i+1] == 0)
+                           .map(|i| (i, i + 2))
+    } else {
+        text.iter().position(|&b| b == 0).map(|i| (i, i + 1))
+    };
+    match index {
+        Some((end, start)) => (decode_id3_string(encoding, &text[..end]),
+                               decode_id3_string(encoding, &text[start..])),
+        None => (decode_id3_string(encoding, text), String::new()),
+    }
+}
+
+// The values of multi-valued frames are separated by null characters.
+fn decode_id3_string(encoding: u8, bytes: &[u8]) -> String {
+    let text = match encoding {
+        1 | 2 => {
+            let mut units = bytes.chunks_exact(2).map(|c| [c[0], c[1]]).collect::<Vec<[u8; 2]>>();
+            let mut big_endian = encoding == 2;
+            match units.first() {
+                Some([0xFF, 0xFE]) => { units.remove(0); big_endian = false; },
+                Some([0xFE, 0xFF]) => { units.remove(0); big_endian = true; },
+                _ => (),
+            }
+            let units = units.iter().map(|u| if big_endian {
+                BigEndian::read_u16(u)
+            } else {
+                LittleEndian::read_u16(u)
+            }).collect::<Vec<u16>>();
+            String::from_utf16_lossy(&units)
+        },
+        3 => String::from_utf8_lossy(bytes).into_owned(),
+        _ => bytes.iter().map(|&b| b as char).collect(),
+    };
+    text.trim_end_matches('\0').split('\0')
+        .map(str::trim).filter(|s| !s.is_empty())
+        .collect::<Vec<&str>>().join(", ")
+}
+
+// The children of an MP4 atom.
+fn atoms(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut children = Vec::new();
+    let mut offset = 0;
+    while offset + 8 <= data.len() {
+        let size = BigEndian::read_u32(&data[offset..offset+4]) as usize;
+        if size < 8 || size > data.len() - offset {
+            break;
+        }
+        children.push((&data[offset+4..offset+8], &data[offset+8..offset+size]));
+        offset = match offset.checked_add(size) {
+            Some(offset) => offset,
+            None => break,
+        };
+    }
+    children
+}
+
+// The comments that follow the vendor string.
+fn vorbis_comments(data: &[u8]) -> Vec<String> {
+    let mut comments = Vec::new();
+    let read_len = |offset: usize| data.get(offset..offset.checked_add(4)?).map(|b| LittleEndian::read_u32(b) as usize);
+    let mut offset = match read_len(0) {
+        Some(len) => len.saturating_add(4),
+        None => return comments,
+    };
+    let count = match read_len(offset) {
+        Some(count) => count,
+        None => return comments,
+    };
+    offset += 4;
+    for _ in 0..count {
+        let len = match read_len(offset) {
+            Some(len) if len <= data.len() - offset - 4 => len,
+            _ => break,
+        };
+        comments.push(String::from_utf8_lossy(&data[offset+4..offset+4+len]).into_owned());
+        offset += 4 + len;
+    }
+    comments
+}
+
+// The duration of an audiobook, in seconds, stands for its number of pages.
+pub fn extract_metadata_from_audio(dir: &Path, metadata: &mut Metadata) {
+    for info in metadata {
+        if !info.title.is_empty() || !AUDIOBOOK_KINDS.contains(&info.file.kind.as_str()) {
+            continue;
+        }
+
+        let path = dir.join(&info.file.path);
+
+        match AudioMetadata::new(&path) {
+            Ok(md) => {
+                info.title = md.title.unwrap_or_default();
+                info.author = md.author.unwrap_or_default();
+                info.narrator = md.narrator.unwrap_or_default();
+                info.year = md.date.map(|date| normalize_year(&date)).unwrap_or_default();
+                if let Some(duration) = md.duration.filter(|&d| d > 0) {
+                    info.pages = Some(duration as usize);
+                }
+                println!("{}", info.label());
+            },
+            Err(e) => eprintln!("{}: {}.", info.file.path.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use crate::helpers::TempDir;
+    use super::*;
+    use std::path::PathBuf;
+    use regex::Regex;
+    use crate::metadata::{Info, FileInfo};
+    use crate::document::file_kind;
+
+    #[test]
+    fn id3_texts() {
+        assert_eq!(decode_id3_text(b"\x00Caf\xE9"), "Caf\u{e9}");
+        assert_eq!(decode_id3_text(b"\x01\xFF\xFEN\x00o\x00\x00\x00"), "No");
+        assert_eq!(decode_id3_text(b"\x02\x00N\x00o"), "No");
+        assert_eq!(decode_id3_text(b"\x03Jane\x00John\x00"), "Jane, John");
+        assert_eq!(split_id3_text(b"\x03NARRATOR\x00Jane Doe"), ("NARRATOR".to_string(), "Jane Doe".to_string()));
+        assert_eq!(split_id3_text(b"\x01\xFF\xFEN\x00\x00\x00\xFF\xFEJ\x00"), ("N".to_string(), "J".to_string()));
+        assert_eq!(syncsafe(&[0x00, 0x00, 0x02, 0x01]), 257);
+        assert_eq!(mpeg_bitrate(&[0xFF, 0xFB, 0x90, 0x00]), Some(128));
+    }
+
+    #[test]
+    fn oversized_atoms() {
+        let dir = TempDir::new("audio-atoms");
+        let path = dir.join("book.m4b");
+        let mut buf = Vec::new();
+        buf.extend_from_slice(b"\x00\x00\x00\x10ftypM4B \x00\x00\x00\x00");
+        // A 64-bit size that wraps around the file offset.
+        buf.extend_from_slice(b"\x00\x00\x00\x01free");
+        buf.extend_from_slice(&(u64::MAX - 8).to_be_bytes());
+        fs::write(&path, &buf).unwrap();
+        assert!(AudioMetadata::new(&path).is_err());
+
+        let mut ilst = Vec::new();
+        ilst.extend_from_slice(b"\x00\x00\x00\x08free\xFF\xFF\xFF\xFFfree");
+        assert_eq!(atoms(&ilst).len(), 1);
+        assert!(vorbis_comments(b"\xFF\xFF\xFF\xFF").is_empty());
+        assert!(crate::document::open(&path).is_none());
+    }
+
+    #[test]
+    fn audio_metadata() {
+        let audio_info = |name: &str| Info {
+            file: FileInfo { path: PathBuf::from(name), kind: file_kind(name).unwrap(), .. Default::default() },
+            .. Default::default()
+        };
+        let mut md = vec![audio_info("flatland.mp3"), audio_info("walden.m4b"), audio_info("time-machine.opus")];
+        extract_metadata_from_audio(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/audio")), &mut md);
+
+        assert_eq!(md[0].title, "Flatland");
+        assert_eq!(md[0].author, "Edwin A. Abbott");
+        assert_eq!(md[0].narrator, "Ruth Golding");
+        assert_eq!(md[0].year, "1884");
+        assert_eq!(md[0].pages, Some(3723));
+        assert_eq!(md[1].title, "Walden");
+        assert_eq!(md[1].author, "Henry David Thoreau");
+        assert_eq!(md[1].narrator, "Gordon Mackenzie");
+        assert_eq!(md[1].year, "1854");
+        assert_eq!(md[1].pages, Some(5400));
+        assert_eq!(md[2].title, "The Time Machine");
+        assert_eq!(md[2].author, "H. G. Wells");
+        assert_eq!(md[2].narrator, "Derek Jacobi");
+        assert_eq!(md[2].pages, Some(600));
+
+        assert!(md[2].is_match(&Regex::new("Jacobi").ok()));
+        assert!(!md[1].is_match(&Regex::new("Jacobi").ok()));
+    }
+}
@@ -0,0 +1,191 @@
+use std::io::Read;
+use std::fs::File;
+use std::path::Path;
+use zip::ZipArchive;
+use regex::Regex;
+use lazy_static::lazy_static;
+use failure::Error;
+use crate::helpers::decode_entities;
+use crate::metadata::{Info, Metadata, normalize_year, normalize_language};
+use super::html::dom::Node;
+use super::html::xml::XmlParser;
+
+lazy_static! {
+    // Matches file stems such as *Series v01 012*.
+    static ref COMIC_FILENAME: Regex = Regex::new(r"^(?P<series>.+?)(?:\s+v(?:ol\.?\s*)?(?P<volume>\d+))?(?:\s+#?(?P<number>\d+))?$").unwrap();
+
+    static ref COMIC_FILENAME_TAGS: Regex = Regex::new(r"\s*(\([^)]*\)|\[[^\]]*\])").unwrap();
+}
+
+const COMIC_INFO_NAME: &str = "ComicInfo.xml";
+const IMAGE_KINDS: [&str; 8] = ["jpg", "jpeg", "png", "gif", "bmp", "webp", "tif", "tiff"];
+
+pub struct CbzArchive {
+    archive: ZipArchive<File>,
+}
+
+impl CbzArchive {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<CbzArchive, Error> {
+        let file = File::open(path)?;
+        let archive = ZipArchive::new(file)?;
+        Ok(CbzArchive { archive })
+    }
+
+    fn names(&mut self) -> Vec<String> {
+        (0..self.archive.len()).filter_map(|i| self.archive.by_index(i).ok()
+                                                   .map(|f| f.name().to_string()))
+                               .collect()
+    }
+
+    // The number of images, which is also the number of pages of the comic.
+    pub fn images_count(&mut self) -> usize {
+        self.names().iter().filter(|name| {
+            !name.ends_with('/') &&
+            name.rsplit('.').next().map_or(false, |ext| {
+                IMAGE_KINDS.iter().any(|kind| ext.eq_ignore_ascii_case(kind))
+            })
+        }).count()
+    }
+
+    // The root of the *ComicInfo.xml* file, looked up case-insensitively.
+    pub fn comic_info(&mut self) -> Option<Node> {
+        let name = self.names().into_iter().find(|name| {
+            name.rsplit('/').next().map_or(false, |n| n.eq_ignore_ascii_case(COMIC_INFO_NAME))
+        })?;
+        let mut file = self.archive.by_name(&name).ok()?;
+        let mut text = String::new();
+        file.read_to_string(&mut text).ok()?;
+        Some(XmlParser::new(&text).parse())
+    }
+}
+
+pub fn comic_info_value(node: &Node, name: &str) -> Option<String> {
+    node.find(name)
+        .and_then(|child| child.children().and_then(|c| c.get(0)))
+        .and_then(|child| child.text().map(|s| decode_entities(s).trim().to_string()))
+        .filter(|s| !s.is_empty())
+}
+
+pub fn extract_metadata_from_cbz(dir: &Path, metadata: &mut Metadata) {
+    for info in metadata {
+        if !info.title.is_empty() || info.file.kind != "cbz" {
+            continue;
+        }
+
+        let path = dir.join(&info.file.path);
+
+        let mut archive = match CbzArchive::new(&path) {
+            Ok(archive) => archive,
+            Err(e) => {
+                eprintln!("{}: {}.", info.file.path.display(), e);
+                continue;
+            },
+        };
+
+        let images_count = archive.images_count();
+        if images_count > 0 {
+            info.pages = Some(images_count);
+        }
+
+        match archive.comic_info() {
+            Some(node) => {
+                let value = |name| comic_info_value(&node, name);
+                if let Some(series) = value("Series") {
+                    info.series = series;
+                }
+                if let Some(title) = value("Title").or_else(|| value("Series")) {
+                    info.title = title;
+                }
+                if let Some(author) = value("Writer") {
+                    info.author = author;
+                }
+                if let Some(volume) = value("Volume") {
+                    info.volume = volume;
+                }
+                if let Some(number) = value("Number") {
+                    info.number = number;
+                }
+                if let Some(year) = value("Year") {
+                    info.year = normalize_year(&year);
+                }
+                if let Some(publisher) = value("Publisher") {
+                    info.publisher = publisher;
+                }
+                if let Some(language) = value("LanguageISO") {
+                    info.language = normalize_language(&language);
+                }
+                if let Some(summary) = value("Summary") {
+                    info.description = summary;
+                }
+                println!("{}", info.label());
+            },
+            None => parse_comic_filename(info),
+        }
+    }
+}
+
+fn parse_comic_filename(info: &mut Info) {
+    let stem = info.file_stem().replace('_', " ");
+    let stem = COMIC_FILENAME_TAGS.replace_all(&stem, "");
+    if let Some(caps) = COMIC_FILENAME.captures(stem.trim()) {
+        if caps.name("volume").is_none() && caps.name("number").is_none() {
+            return;
+        }
+        let series = caps["series"].trim_end_matches(|c: char| c.is_whitespace() || c == '-').to_string();
+        if let Some(volume) = caps.name("volume") {
+            info.volume = trim_leading_zeros(volume.as_str()).to_string();
+        }
+        if let Some(number) = caps.name("number") {
+            info.number = trim_leading_zeros(number.as_str()).to_string();
+        }
+        info.title = series.clone();
+        info.series = series;
+        println!("{}", info.label());
+    }
+}
+
+fn trim_leading_zeros(number: &str) -> &str {
+    let trimmed = number.trim_start_matches('0');
+    if trimmed.is_empty() {
+        "0"
+    } else {
+        trimmed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::metadata::{FileInfo, SortMethod, sort};
+
+    #[test]
+    fn cbz_metadata() {
+        let cbz_info = |name: &str| Info {
+            file: FileInfo { path: PathBuf::from(name), kind: "cbz".to_string(), .. Default::default() },
+            .. Default::default()
+        };
+        let mut md = vec![cbz_info("comic-info.cbz"),
+                          cbz_info("Usagi Yojimbo v02 012 (1988) (Digital).cbz")];
+        extract_metadata_from_cbz(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/cbz")), &mut md);
+
+        assert_eq!(md[0].title, "Chapter One");
+        assert_eq!(md[0].series, "Saga");
+        assert_eq!(md[0].number, "1");
+        assert_eq!(md[0].volume, "2012");
+        assert_eq!(md[0].author, "Brian K. Vaughan");
+        assert_eq!(md[0].year, "2012");
+        assert_eq!(md[0].publisher, "Image");
+        assert_eq!(md[0].description, "Two lovers & their newborn daughter.");
+        assert_eq!(md[0].pages, Some(3));
+
+        assert_eq!(md[1].title, "Usagi Yojimbo");
+        assert_eq!(md[1].series, "Usagi Yojimbo");
+        assert_eq!(md[1].volume, "2");
+        assert_eq!(md[1].number, "12");
+        assert_eq!(md[1].pages, Some(2));
+
+        sort(&mut md, SortMethod::Pages, false);
+        assert_eq!(md[0].pages_count(), Some(2));
+    }
+}
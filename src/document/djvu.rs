@@ -5,9 +5,10 @@ use std::rc::Rc;
 use std::path::Path;
 use std::ffi::{CStr, CString};
 use std::os::unix::ffi::OsStrExt;
-use super::{Document, Location, TextLocation, BoundedText, TocEntry};
+use super::{Document, Location, TextLocation, BoundedText, TocEntry, simplify_toc};
 use super::{chapter, chapter_relative};
-use crate::metadata::TextAlign;
+use crate::metadata::{Metadata, TextAlign, MAX_TOC_ENTRIES, normalize_year, parse_filename};
+use crate::settings::ImportSettings;
 use crate::framebuffer::Pixmap;
 use crate::geom::{Rectangle, CycleDir};
 
@@ -214,6 +215,7 @@ impl Document for DjvuDocument {
                 let key = miniexp_symbol(key.as_ptr());
                 let val = ddjvu_anno_get_metadata(exp, key);
                 if val.is_null() {
+                    ddjvu_miniexp_release(self.doc, exp);
                     None
                 } else {
                     ddjvu_miniexp_release(self.doc, exp);
@@ -252,6 +254,10 @@ impl Document for DjvuDocument {
 }
 
 impl DjvuDocument {
+    pub fn year(&self) -> Option<String> {
+        self.metadata("year")
+    }
+
     pub fn page(&self, index: usize) -> Option<DjvuPage> {
         unsafe {
             let page = ddjvu_page_create_by_pageno(self.doc, index as libc::c_int);
@@ -440,3 +446,72 @@ impl Drop for DjvuContext {
         unsafe { ddjvu_context_release(self.0); }
     }
 }
+
+pub fn extract_metadata_from_djvu(dir: &Path, metadata: &mut Metadata, settings: &ImportSettings) {
+    let opener = match DjvuOpener::new() {
+        Some(opener) => opener,
+        None => {
+            eprintln!("Can't create DjVu opener.");
+            return;
+        },
+    };
+
+    for info in metadata {
+        if !info.title.is_empty() || info.file.kind != "djvu" {
+            continue;
+        }
+
+        let path = dir.join(&info.file.path);
+
+        let mut doc = match opener.open(&path) {
+            Some(doc) => doc,
+            None => {
+                eprintln!("{}: can't open document.", info.file.path.display());
+                continue;
+            },
+        };
+
+        if info.toc.is_none() {
+            let pages_count = doc.pages_count();
+            info.toc = doc.toc().map(|toc| simplify_toc(&toc, pages_count, MAX_TOC_ENTRIES));
+        }
+
+        match doc.title().filter(|t| !t.trim().is_empty()) {
+            Some(title) => {
+                info.title = title.trim().to_string();
+                if let Some(author) = doc.author().filter(|a| !a.trim().is_empty()) {
+                    info.author = author.trim().to_string();
+                }
+                if let Some(year) = doc.year() {
+                    info.year = normalize_year(&year);
+                }
+                println!("{}", info.label());
+            },
+            None => parse_filename(info, settings.title_case),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::metadata::{Info, FileInfo};
+
+    #[test]
+    fn djvu_metadata() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/djvu");
+        let mut md = vec![Info {
+            file: FileInfo {
+                path: PathBuf::from("metadata.djvu"),
+                kind: "djvu".to_string(),
+                .. Default::default()
+            },
+            .. Default::default()
+        }];
+        extract_metadata_from_djvu(&dir, &mut md, &ImportSettings::default());
+        assert_eq!(md[0].title, "The Title");
+        assert_eq!(md[0].author, "The Author");
+        assert_eq!(md[0].year, "1999");
+    }
+}
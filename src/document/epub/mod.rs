@@ -4,13 +4,18 @@ use std::path::{Path, PathBuf};
 use std::borrow::Cow;
 use std::collections::{HashMap, BTreeSet};
 use zip::ZipArchive;
+use regex::Regex;
+use lazy_static::lazy_static;
+use chrono::{DateTime, Local};
 use failure::{Error, format_err};
 use crate::framebuffer::Pixmap;
 use crate::helpers::{Normalize, decode_entities};
+use crate::metadata::{Metadata, Direction, normalize_year, normalize_language, file_modified};
+use crate::settings::{ImportSettings, CategoryProvider};
 use crate::document::{Document, Location, TextLocation, TocEntry, BoundedText, chapter_from_uri};
 use crate::unit::pt_to_px;
 use crate::geom::{Rectangle, Edge, CycleDir};
-use super::html::dom::Node;
+use super::html::dom::{Node, ElementData};
 use super::html::engine::{Page, Engine, ResourceFetcher};
 use super::html::layout::{StyleData, LoopContext};
 use super::html::layout::{RootData, DrawState, DrawCommand, TextCommand, ImageCommand};
@@ -20,9 +25,14 @@ use super::html::xml::XmlParser;
 
 const VIEWER_STYLESHEET: &str = "css/epub.css";
 const USER_STYLESHEET: &str = "css/epub-user.css";
+const CONTAINER_PATH: &str = "META-INF/container.xml";
 
 type UriCache = HashMap<String, usize>;
 
+lazy_static! {
+    static ref KOBO_SPAN: Regex = Regex::new(r#"<span class="koboSpan"[^>]*>(.*?)</span>"#).unwrap();
+}
+
 impl ResourceFetcher for ZipArchive<File> {
     fn fetch(&mut self, name: &str) -> Result<Vec<u8>, Error> {
         let mut file = self.by_name(name)?;
@@ -40,6 +50,7 @@ pub struct EpubDocument {
     spine: Vec<Chunk>,
     cache: HashMap<usize, Vec<Page>>,
     ignore_document_css: bool,
+    defects: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -53,63 +64,71 @@ unsafe impl Sync for EpubDocument {}
 
 impl EpubDocument {
     pub fn new<P: AsRef<Path>>(path: P) -> Result<EpubDocument, Error> {
+        let doc = EpubDocument::new_lenient(path)?;
+
+        if doc.spine.is_empty() {
+            return Err(format_err!("The spine is empty."));
+        }
+
+        Ok(doc)
+    }
+
+    // Recovers what it can from malformed packages. The problems encountered
+    // along the way are available through `defects`.
+    pub fn new_lenient<P: AsRef<Path>>(path: P) -> Result<EpubDocument, Error> {
         let file = File::open(path)?;
         let mut archive = ZipArchive::new(file)?;
-
-        let opf_path = {
-            let mut zf = archive.by_name("META-INF/container.xml")?;
-            let mut text = String::new();
-            zf.read_to_string(&mut text)?;
-            let root = XmlParser::new(&text).parse();
-            root.find("rootfile")
-                .and_then(|e| e.attr("full-path"))
-                .map(String::from)
-        }.ok_or_else(|| format_err!("Can't get the OPF path."))?;
+        let mut defects = Vec::new();
+
+        let opf_path = container_rootfile(&mut archive, &mut defects).or_else(|| {
+            let name = archive.file_names()
+                              .find(|name| name.to_lowercase().ends_with(".opf"))
+                              .map(String::from);
+            if let Some(ref name) = name {
+                defects.push(format!("Found the OPF by scanning the archive: '{}'.", name));
+            }
+            name
+        }).ok_or_else(|| format_err!("Can't get the OPF path."))?;
 
         let parent = Path::new(&opf_path).parent()
                           .unwrap_or_else(|| Path::new(""));
 
-        let text = {
-            let mut zf = archive.by_name(&opf_path)?;
-            let mut text = String::new();
-            zf.read_to_string(&mut text)?;
-            text
-        };
+        let text = read_xml(&mut archive, &opf_path, &mut defects)?;
 
-        let info = XmlParser::new(&text).parse();
+        let mut info = XmlParser::new(&text).parse();
+        strip_prefix(&mut info, "opf:");
         let mut spine = Vec::new();
 
-        {
-            let manifest = info.find("manifest")
-                                  .ok_or_else(|| format_err!("The manifest is missing."))?;
-
-            let children = info.find("spine")
-                                  .and_then(Node::children)
-                                  .ok_or_else(|| format_err!("The spine is missing."))?;
+        if let Some(manifest) = info.find("manifest") {
+            if let Some(children) = info.find("spine").and_then(Node::children) {
+                for child in children {
+                    let vertebra_opt = child.attr("idref").and_then(|idref| {
+                        manifest.find_by_id(idref)
+                    }).and_then(|entry| {
+                        entry.attr("href")
+                    }).and_then(|href| {
+                        let href_path = parent.join(&href.replace("%20", " ").replace("&amp;", "&"));
+                        href_path.to_str().and_then(|path| {
+                            archive.by_name(path).map_err(|e| {
+                                eprintln!("Can't retrieve '{}' from the archive: {}.", path, e)
+                            // We're assuming that the size of the spine is less than 4 GiB.
+                            }).map(|zf| (zf.size() as usize, path.to_string())).ok()
+                        })
+                    });
 
-            for child in children {
-                let vertebra_opt = child.attr("idref").and_then(|idref| {
-                    manifest.find_by_id(idref)
-                }).and_then(|entry| {
-                    entry.attr("href")
-                }).and_then(|href| {
-                    let href_path = parent.join(&href.replace("%20", " ").replace("&amp;", "&"));
-                    href_path.to_str().and_then(|path| {
-                        archive.by_name(path).map_err(|e| {
-                            eprintln!("Can't retrieve '{}' from the archive: {}.", path, e)
-                        // We're assuming that the size of the spine is less than 4 GiB.
-                        }).map(|zf| (zf.size() as usize, path.to_string())).ok()
-                    })
-                });
-
-                if let Some((size, path)) = vertebra_opt {
-                    spine.push(Chunk { path, size });
+                    if let Some((size, path)) = vertebra_opt {
+                        spine.push(Chunk { path, size });
+                    }
                 }
+            } else {
+                defects.push("The spine is missing.".to_string());
             }
+        } else {
+            defects.push("The manifest is missing.".to_string());
         }
 
-        if spine.is_empty() {
-            return Err(format_err!("The spine is empty."));
+        if info.find("metadata").is_none() {
+            defects.push("The metadata are missing.".to_string());
         }
 
         Ok(EpubDocument {
@@ -120,9 +139,14 @@ impl EpubDocument {
             spine,
             cache: HashMap::new(),
             ignore_document_css: false,
+            defects,
         })
     }
 
+    pub fn defects(&self) -> &[String] {
+        &self.defects
+    }
+
     fn offset(&self, index: usize) -> usize {
         self.spine.iter().take(index).map(|c| c.size).sum()
     }
@@ -426,7 +450,7 @@ impl EpubDocument {
             .and_then(Node::children)
             .map(|children| {
                 for child in children {
-                    if child.tag_name() == Some("dc:subject") {
+                    if child.tag_name().map_or(false, |name| same_name(name, "dc:subject")) {
                         if let Some(subject) = child.text().map(|text| decode_entities(text)) {
                             // Pipe separated list of BISAC categories
                             if subject.contains(" / ") {
@@ -513,16 +537,162 @@ impl EpubDocument {
         None
     }
 
+    fn read_document(&mut self, name: &str) -> Option<(Node, PathBuf)> {
+        let mut text = String::new();
+        {
+            let mut zf = self.archive.by_name(name).ok()?;
+            zf.read_to_string(&mut text).ok()?;
+        }
+        let dir = Path::new(name).parent()
+                      .unwrap_or_else(|| Path::new(""))
+                      .to_path_buf();
+        Some((XmlParser::new(&text).parse(), dir))
+    }
+
+    fn ncx_document(&mut self) -> Option<(Node, PathBuf)> {
+        let name = self.info.find("spine").and_then(|spine| {
+            spine.attr("toc")
+        }).and_then(|toc_id| {
+            self.info.find("manifest")
+                .and_then(|manifest| manifest.find_by_id(toc_id))
+                .and_then(|entry| entry.attr("href"))
+        }).map(|href| {
+            self.parent.join(href).normalize()
+                .to_string_lossy().into_owned()
+        })?;
+
+        self.read_document(&name)
+    }
+
+    fn nav_document(&mut self) -> Option<(Node, PathBuf)> {
+        let name = self.info.find("manifest")
+                       .and_then(Node::children)
+                       .and_then(|children| children.iter().find(|child| {
+                           child.attr("properties")
+                                .map_or(false, |p| p.split_whitespace().any(|v| v == "nav"))
+                       }))
+                       .and_then(|entry| entry.attr("href"))
+                       .map(|href| {
+                           self.parent.join(href).normalize()
+                               .to_string_lossy().into_owned()
+                       })?;
+
+        self.read_document(&name)
+    }
+
+    // The location where the reading should begin, as declared by the landmarks or the guide.
+    pub fn start_location(&mut self) -> Option<TextLocation> {
+        let uri = self.nav_document().and_then(|(root, nav_dir)| {
+            find_nav(&root, "landmarks")
+                .and_then(|nav| find_link_by_type(nav, "bodymatter"))
+                .and_then(|href| nav_dir.join(href).normalize().to_str().map(String::from))
+        }).or_else(|| {
+            self.info.find("guide")
+                .and_then(Node::children)
+                .and_then(|children| children.iter().find(|child| {
+                    child.tag_name() == Some("reference") &&
+                    child.attr("type").map_or(false, |t| t.eq_ignore_ascii_case("text"))
+                }))
+                .and_then(|child| child.attr("href"))
+                .and_then(|href| self.parent.join(href).normalize().to_str().map(String::from))
+        })?;
+
+        let offset = if uri.contains('#') {
+            let mut cache = HashMap::new();
+            self.resolve_link(&uri, &mut cache)?
+        } else {
+            self.vertebra_coordinates_from_name(&uri)
+                .map(|(_, start_offset)| start_offset)?
+        };
+
+        Some(TextLocation::Dynamic(offset))
+    }
+
+    fn metadata_node(&self, key: &str) -> Option<&Node> {
+        self.info.find("metadata")
+            .and_then(Node::children)
+            .and_then(|children| children.iter().find(|child| child.tag_name() == Some(key))
+                                         .or_else(|| children.iter().find(|child| {
+                                             child.tag_name().map_or(false, |name| same_name(name, key))
+                                         })))
+    }
+
+    // Like `metadata`, but also accepts values wrapped in markup, such as the spans
+    // inserted by Kobo in kepubs.
+    fn metadata_text(&self, key: &str) -> Option<String> {
+        self.metadata_node(key)
+            .map(text_content)
+            .map(|text| KOBO_SPAN.replace_all(&text, "$1").into_owned())
+            .filter(|text| !text.is_empty())
+    }
+
     pub fn series(&self) -> Option<String> {
         self.metadata_by_name("calibre:series")
     }
 
+    // The first title in another language than the one of the book.
+    pub fn original_title(&self) -> Option<String> {
+        let children = self.info.find("metadata").and_then(Node::children)?;
+        let mut titles = children.iter().filter(|child| {
+            child.tag_name().map_or(false, |name| same_name(name, "dc:title"))
+        });
+        let main_title = titles.next()?;
+        let language = self.language().or_else(|| main_title.attr("xml:lang").map(String::from));
+        let primary_tag = |lang: &str| lang.split(|c| c == '-' || c == '_').next().unwrap_or_default().to_lowercase();
+        titles.find(|title| title.attr("xml:lang").map_or(false, |lang| {
+                  language.as_ref().map_or(true, |language| primary_tag(lang) != primary_tag(language))
+              }))
+              .map(text_content)
+              .map(|text| text.trim().to_string())
+              .filter(|text| !text.is_empty())
+    }
+
+    // The contributors with the *trl* MARC relator role, given either by an *opf:role*
+    // attribute or by a *role* meta element that refines the contributor.
+    pub fn translators(&self) -> Vec<String> {
+        let children = match self.info.find("metadata").and_then(Node::children) {
+            Some(children) => children,
+            None => return Vec::new(),
+        };
+        let has_role = |node: &Node| node.attributes().map_or(false, |attrs| {
+            attrs.iter().any(|(key, value)| local_name(key) == "role" && value.trim() == "trl")
+        });
+        let refined_role = |id: &str| children.iter().any(|child| {
+            child.tag_name() == Some("meta") &&
+            child.attr("refines").map_or(false, |r| r.trim_start_matches('#') == id) &&
+            child.attr("property") == Some("role") &&
+            child.text().or_else(|| child.child(0).and_then(Node::text))
+                 .map_or(false, |text| text.trim() == "trl")
+        });
+        children.iter().filter(|child| {
+            child.tag_name().map_or(false, |name| same_name(name, "dc:contributor") ||
+                                                  same_name(name, "dc:creator")) &&
+            (has_role(child) || child.attr("id").map_or(false, |id| refined_role(id)))
+        }).map(text_content)
+          .map(|text| text.trim().to_string())
+          .filter(|text| !text.is_empty())
+          .collect()
+    }
+
+    // The last modification date of the publication, from *dcterms:modified*.
+    pub fn modified(&self) -> Option<DateTime<Local>> {
+        self.info.find("metadata")
+            .and_then(Node::children)
+            .and_then(|children| children.iter()
+                                         .find(|child| child.tag_name() == Some("meta") &&
+                                                       child.attr("property") == Some("dcterms:modified")))
+            .and_then(|child| child.children().and_then(|c| c.get(0)))
+            .and_then(|child| child.text())
+            .and_then(|text| DateTime::parse_from_rfc3339(text.trim()).ok())
+            .map(|date| date.with_timezone(&Local))
+    }
+
     pub fn series_index(&self) -> Option<String> {
         self.metadata_by_name("calibre:series_index")
     }
 
     pub fn description(&self) -> Option<String> {
-        self.metadata("dc:description")
+        self.metadata_text("dc:description")
     }
 
     pub fn publisher(&self) -> Option<String> {
@@ -534,7 +704,7 @@ impl EpubDocument {
     }
 
     pub fn year(&self) -> Option<String> {
-        self.metadata("dc:date").map(|s| s.chars().take(4).collect())
+        self.metadata("dc:date").map(|s| normalize_year(&s))
     }
 }
 
@@ -549,28 +719,8 @@ impl Document for EpubDocument {
     }
 
     fn toc(&mut self) -> Option<Vec<TocEntry>> {
-        let name = self.info.find("spine").and_then(|spine| {
-            spine.attr("toc")
-        }).and_then(|toc_id| {
-            self.info.find("manifest")
-                .and_then(|manifest| manifest.find_by_id(toc_id))
-                .and_then(|entry| entry.attr("href"))
-        }).map(|href| {
-            self.parent.join(href).normalize()
-                .to_string_lossy().into_owned()
-        })?;
-
-        let toc_dir = Path::new(&name).parent()
-                           .unwrap_or_else(|| Path::new(""));
+        let (root, toc_dir) = self.ncx_document()?;
 
-        let mut text = String::new();
-        if let Ok(mut zf) = self.archive.by_name(&name) {
-            zf.read_to_string(&mut text).ok()?;
-        } else {
-            return None;
-        }
-
-        let root = XmlParser::new(&text).parse();
         root.find("navMap").map(|map| {
             let mut cache = HashMap::new();
             let mut index = 0;
@@ -781,8 +931,38 @@ impl Document for EpubDocument {
         self.cache.clear();
     }
 
+    fn set_hyphenation(&mut self, enabled: bool, language: Option<&str>) {
+        self.engine.set_hyphenation(enabled, language);
+        self.cache.clear();
+    }
+
+    fn set_paragraph_spacing(&mut self, spacing: Option<f32>) {
+        self.engine.paragraph_spacing = spacing;
+        self.cache.clear();
+    }
+
+    fn set_text_indent(&mut self, indent: Option<f32>) {
+        self.engine.text_indent = indent;
+        self.cache.clear();
+    }
+
+    fn set_font_weight(&mut self, weight: Option<f32>) {
+        self.engine.font_weight = weight;
+        self.cache.clear();
+    }
+
+    fn set_letter_spacing(&mut self, spacing: Option<f32>) {
+        self.engine.letter_spacing = spacing;
+        self.cache.clear();
+    }
+
+    fn set_direction(&mut self, direction: Direction) {
+        self.engine.direction = direction;
+        self.cache.clear();
+    }
+
     fn title(&self) -> Option<String> {
-        self.metadata("dc:title")
+        self.metadata_text("dc:title")
     }
 
     fn author(&self) -> Option<String> {
@@ -791,9 +971,7 @@ impl Document for EpubDocument {
     }
 
     fn metadata(&self, key: &str) -> Option<String> {
-        self.info.find("metadata")
-            .and_then(Node::children)
-            .and_then(|children| children.iter().find(|child| child.tag_name() == Some(key)))
+        self.metadata_node(key)
             .and_then(|child| child.children().and_then(|c| c.get(0)))
             .and_then(|child| child.text().map(|s| decode_entities(s).into_owned()))
     }
@@ -805,4 +983,357 @@ impl Document for EpubDocument {
     fn has_synthetic_page_numbers(&self) -> bool {
         true
     }
+
+    fn page_list(&mut self) -> Vec<(String, TextLocation)> {
+        let mut targets = Vec::new();
+
+        if let Some((root, nav_dir)) = self.nav_document() {
+            if let Some(nav) = find_nav(&root, "page-list") {
+                collect_links(nav, &nav_dir, &mut targets);
+            }
+        }
+
+        // EPUB 2 fallback.
+        if targets.is_empty() {
+            if let Some((root, ncx_dir)) = self.ncx_document() {
+                if let Some(children) = root.find("pageList").and_then(Node::children) {
+                    for child in children {
+                        if child.tag_name() != Some("pageTarget") {
+                            continue;
+                        }
+                        let label = child.find("navLabel")
+                                         .and_then(|label| label.find("text"))
+                                         .map(text_content)
+                                         .unwrap_or_default();
+                        let uri = child.find("content")
+                                       .and_then(|content| content.attr("src"))
+                                       .and_then(|src| ncx_dir.join(src).normalize()
+                                                              .to_str().map(String::from));
+                        if let Some(uri) = uri {
+                            targets.push((label, uri));
+                        }
+                    }
+                }
+            }
+        }
+
+        self.engine.load_fonts();
+        let mut cache = HashMap::new();
+
+        targets.into_iter().filter_map(|(label, uri)| {
+            if label.is_empty() {
+                return None;
+            }
+            let offset = cache.get(&uri).cloned()
+                              .or_else(|| self.resolve_link(&uri, &mut cache))?;
+            Some((label, TextLocation::Dynamic(offset)))
+        }).collect()
+    }
+}
+
+// Compares two tag names, ignoring the namespace prefixes, which are
+// sometimes missing or undeclared in the wild.
+fn same_name(a: &str, b: &str) -> bool {
+    a == b || local_name(a) == local_name(b)
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+fn strip_prefix(node: &mut Node, prefix: &str) {
+    if let Node::Element(ElementData { ref mut name, ref mut children, .. }) = node {
+        if name.starts_with(prefix) {
+            *name = name[prefix.len()..].to_string();
+        }
+        for child in children {
+            strip_prefix(child, prefix);
+        }
+    }
+}
+
+// Reads an XML file from the archive, skipping anything before the first tag.
+fn read_xml(archive: &mut ZipArchive<File>, name: &str, defects: &mut Vec<String>) -> Result<String, Error> {
+    let mut buf = Vec::new();
+    archive.by_name(name)?.read_to_end(&mut buf)?;
+    let text = String::from_utf8_lossy(&buf);
+
+    if let Cow::Owned(..) = text {
+        defects.push(format!("'{}' isn't valid UTF-8.", name));
+    }
+
+    let start_index = text.find('<').unwrap_or(0);
+
+    if !text[..start_index].trim_start_matches('\u{feff}').trim().is_empty() {
+        defects.push(format!("Skipped {} stray bytes at the beginning of '{}'.", start_index, name));
+    }
+
+    Ok(text[start_index..].to_string())
+}
+
+// Looks for a file in the archive, ignoring the case if there's no exact match.
+fn find_file(archive: &ZipArchive<File>, name: &str, defects: &mut Vec<String>) -> Option<String> {
+    if archive.file_names().any(|n| n == name) {
+        return Some(name.to_string());
+    }
+
+    let found = archive.file_names()
+                       .find(|n| n.eq_ignore_ascii_case(name))
+                       .map(String::from);
+
+    if let Some(ref found) = found {
+        defects.push(format!("Wrong case for '{}': '{}'.", name, found));
+    } else {
+        defects.push(format!("Can't find '{}' in the archive.", name));
+    }
+
+    found
+}
+
+fn container_rootfile(archive: &mut ZipArchive<File>, defects: &mut Vec<String>) -> Option<String> {
+    let name = find_file(archive, CONTAINER_PATH, defects)?;
+    let text = read_xml(archive, &name, defects).ok()?;
+    let root = XmlParser::new(&text).parse();
+    let opf_path = root.find("rootfile")
+                       .and_then(|e| e.attr("full-path"))
+                       .map(String::from);
+
+    if opf_path.is_none() {
+        defects.push("The container doesn't reference any package.".to_string());
+    }
+
+    opf_path.and_then(|path| find_file(archive, &path, defects))
+}
+
+fn text_content(node: &Node) -> String {
+    fn text_content_aux(node: &Node, buf: &mut String) {
+        match node {
+            Node::Element(..) => {
+                if let Some(children) = node.children() {
+                    for child in children {
+                        text_content_aux(child, buf);
+                    }
+                }
+            },
+            _ => {
+                if let Some(text) = node.text() {
+                    buf.push_str(&decode_entities(text));
+                }
+            },
+        }
+    }
+
+    let mut buf = String::new();
+    text_content_aux(node, &mut buf);
+    buf.trim().to_string()
+}
+
+fn find_nav<'a>(node: &'a Node, kind: &str) -> Option<&'a Node> {
+    if node.tag_name() == Some("nav") &&
+       node.attr("epub:type").map_or(false, |t| t.split_whitespace().any(|v| v == kind)) {
+        return Some(node);
+    }
+    node.children().and_then(|children| {
+        children.iter().filter_map(|child| find_nav(child, kind)).next()
+    })
+}
+
+fn find_link_by_type<'a>(node: &'a Node, kind: &str) -> Option<&'a str> {
+    if node.tag_name() == Some("a") &&
+       node.attr("epub:type").map_or(false, |t| t.split_whitespace().any(|v| v == kind)) {
+        return node.attr("href");
+    }
+    node.children().and_then(|children| {
+        children.iter().filter_map(|child| find_link_by_type(child, kind)).next()
+    })
+}
+
+// Collects the labels and normalized targets of the links inside a nav element.
+fn collect_links(node: &Node, nav_dir: &Path, links: &mut Vec<(String, String)>) {
+    if node.tag_name() == Some("a") {
+        if let Some(uri) = node.attr("href").and_then(|href| nav_dir.join(href).normalize()
+                                                                    .to_str().map(String::from)) {
+            links.push((text_content(node), uri));
+        }
+        return;
+    }
+    if let Some(children) = node.children() {
+        for child in children {
+            collect_links(child, nav_dir, links);
+        }
+    }
+}
+
+pub fn extract_metadata_from_epub(dir: &Path, metadata: &mut Metadata, settings: &ImportSettings) {
+    let subjects_as_categories = settings.category_providers.contains(&CategoryProvider::Subject);
+
+    for info in metadata {
+        if info.file.kind != "epub" {
+            continue;
+        }
+
+        let path = dir.join(&info.file.path);
+
+        match EpubDocument::new_lenient(&path) {
+            Ok(mut doc) => {
+                let modified = doc.modified().or_else(|| file_modified(&path));
+                // Only a new revision of an already extracted entry is extracted again. The entries
+                // extracted before the revisions were recorded, or filled from a sidecar, keep their fields.
+                if !info.title.is_empty() && (info.modified.is_none() || info.modified == modified) {
+                    if info.modified.is_none() {
+                        info.modified = modified;
+                    }
+                    continue;
+                }
+                info.modified = modified;
+                for defect in doc.defects() {
+                    eprintln!("{}: {}", info.file.path.display(), defect);
+                }
+                info.title = doc.title().unwrap_or_default();
+                info.author = doc.author().unwrap_or_default();
+                info.year = doc.year().unwrap_or_default();
+                info.publisher = doc.publisher().unwrap_or_default();
+                info.series = doc.series().unwrap_or_default();
+                if !info.series.is_empty() {
+                    info.number = doc.series_index().unwrap_or_default();
+                }
+                info.language = normalize_language(&doc.language().unwrap_or_default());
+                info.original_title = doc.original_title().unwrap_or_default();
+                info.translator = doc.translators().join(", ");
+                info.start_location = doc.start_location();
+                if subjects_as_categories {
+                    info.categories.append(&mut doc.categories());
+                }
+                println!("{}", info.label());
+            },
+            Err(e) => eprintln!("{}: {}", info.file.path.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::{Info, FileInfo, make_query};
+    use chrono::{TimeZone, Utc};
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/epub").join(name)
+    }
+
+    fn check_partial(name: &str, readable: bool) {
+        let doc = EpubDocument::new_lenient(fixture(name)).unwrap();
+        assert_eq!(doc.title().as_deref(), Some("The Title"));
+        assert_eq!(doc.author().as_deref(), Some("The Author"));
+        assert!(!doc.defects().is_empty());
+        assert_eq!(EpubDocument::new(fixture(name)).is_ok(), readable);
+    }
+
+    #[test]
+    fn stray_bytes() {
+        check_partial("stray-bytes.epub", true);
+    }
+
+    #[test]
+    fn container_case() {
+        check_partial("container-case.epub", true);
+    }
+
+    #[test]
+    fn missing_container() {
+        check_partial("missing-container.epub", true);
+    }
+
+    #[test]
+    fn undeclared_namespace() {
+        let doc = EpubDocument::new(fixture("undeclared-namespace.epub")).unwrap();
+        assert_eq!(doc.title().as_deref(), Some("The Title"));
+        assert_eq!(doc.author().as_deref(), Some("The Author"));
+    }
+
+    #[test]
+    fn missing_spine() {
+        check_partial("missing-spine.epub", false);
+    }
+
+    #[test]
+    fn guide_start_location() {
+        let mut doc = EpubDocument::new(fixture("guide.epub")).unwrap();
+        assert!(matches!(doc.start_location(), Some(TextLocation::Dynamic(offset)) if offset > 0));
+    }
+
+    #[test]
+    fn modified() {
+        let doc = EpubDocument::new(fixture("modified.epub")).unwrap();
+        let expected = Utc.ymd(2020, 3, 14).and_hms(15, 9, 26).with_timezone(&Local);
+        assert_eq!(doc.modified(), Some(expected));
+        let doc = EpubDocument::new(fixture("container-case.epub")).unwrap();
+        assert_eq!(doc.modified(), None);
+    }
+
+    #[test]
+    fn translations() {
+        let doc = EpubDocument::new(fixture("translated.epub")).unwrap();
+        assert_eq!(doc.title().as_deref(), Some("War and Peace"));
+        assert_eq!(doc.original_title().as_deref(), Some("Война и мир"));
+        assert_eq!(doc.translators(), vec!["Louise Maude".to_string(), "Aylmer Maude".to_string()]);
+        let doc = EpubDocument::new(fixture("modified.epub")).unwrap();
+        assert_eq!(doc.original_title(), None);
+        assert!(doc.translators().is_empty());
+    }
+
+    #[test]
+    fn kobo_spans() {
+        let doc = EpubDocument::new(fixture("kobo-spans.kepub.epub")).unwrap();
+        assert_eq!(doc.title().as_deref(), Some("The Title"));
+        assert_eq!(doc.description().as_deref(), Some("<p>The description.</p>"));
+    }
+
+    #[test]
+    fn no_start_location() {
+        let mut doc = EpubDocument::new(fixture("container-case.epub")).unwrap();
+        assert_eq!(doc.start_location(), None);
+    }
+
+    #[test]
+    fn stale_epub_metadata() {
+        let dir = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/epub"));
+        let mut md = vec![Info {
+            title: "Edited".to_string(),
+            file: FileInfo { path: PathBuf::from("modified.epub"), kind: "epub".to_string(), .. Default::default() },
+            .. Default::default()
+        }];
+        // Extracted before the revisions were recorded: the revision is only recorded.
+        extract_metadata_from_epub(dir, &mut md, &ImportSettings::default());
+        assert_eq!(md[0].title, "Edited");
+        let modified = md[0].modified;
+        assert!(modified.is_some());
+
+        extract_metadata_from_epub(dir, &mut md, &ImportSettings::default());
+        assert_eq!(md[0].title, "Edited");
+
+        // A new revision.
+        md[0].modified = Some(Local.ymd(2001, 1, 1).and_hms(0, 0, 0));
+        extract_metadata_from_epub(dir, &mut md, &ImportSettings::default());
+        assert_ne!(md[0].title, "Edited");
+        assert_eq!(md[0].modified, modified);
+    }
+
+    #[test]
+    fn translation_metadata() {
+        let dir = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/epub"));
+        let mut md = vec![Info {
+            file: FileInfo { path: PathBuf::from("translated.epub"), kind: "epub".to_string(), .. Default::default() },
+            .. Default::default()
+        }];
+        extract_metadata_from_epub(dir, &mut md, &ImportSettings::default());
+        assert_eq!(md[0].title, "War and Peace");
+        assert_eq!(md[0].original_title, "Война и мир");
+        assert_eq!(md[0].translator, "Louise Maude, Aylmer Maude");
+        assert!(md[0].is_match(&make_query("aylmer")));
+        assert!(md[0].is_match(&make_query("война")));
+
+        let json = serde_json::to_value(&Info::default()).unwrap();
+        assert!(json.get("originalTitle").is_none() && json.get("translator").is_none());
+    }
 }
@@ -0,0 +1,370 @@
+use std::io::Read;
+use std::fs::File;
+use std::path::Path;
+use std::ffi::OsStr;
+use zip::ZipArchive;
+use regex::Regex;
+use fnv::FnvHashMap;
+use lazy_static::lazy_static;
+use failure::{Error, format_err};
+use crate::helpers::decode_entities;
+use crate::metadata::{Metadata, normalize_year, normalize_language};
+use crate::settings::{ImportSettings, CategoryProvider};
+use super::html::dom::Node;
+use super::html::xml::XmlParser;
+
+// The upper half of the Windows-1251 code page, still common among FictionBook files.
+const WINDOWS_1251: [char; 64] = [
+    '\u{0402}', '\u{0403}', '\u{201A}', '\u{0453}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{20AC}', '\u{2030}', '\u{0409}', '\u{2039}', '\u{040A}', '\u{040C}', '\u{040B}', '\u{040F}',
+    '\u{0452}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{FFFD}', '\u{2122}', '\u{0459}', '\u{203A}', '\u{045A}', '\u{045C}', '\u{045B}', '\u{045F}',
+    '\u{00A0}', '\u{040E}', '\u{045E}', '\u{0408}', '\u{00A4}', '\u{0490}', '\u{00A6}', '\u{00A7}',
+    '\u{0401}', '\u{00A9}', '\u{0404}', '\u{00AB}', '\u{00AC}', '\u{00AD}', '\u{00AE}', '\u{0407}',
+    '\u{00B0}', '\u{00B1}', '\u{0406}', '\u{0456}', '\u{0491}', '\u{00B5}', '\u{00B6}', '\u{00B7}',
+    '\u{0451}', '\u{2116}', '\u{0454}', '\u{00BB}', '\u{0458}', '\u{0405}', '\u{0455}', '\u{0457}',
+];
+
+lazy_static! {
+    static ref XML_ENCODING: Regex = Regex::new(r#"^[^>]*encoding\s*=\s*["']([^"']+)["']"#).unwrap();
+
+    // The genre codes of the FictionBook 2.1 specification.
+    pub static ref GENRES: FnvHashMap<&'static str, &'static str> = [
+        ("sf_history", "Alternative History"),
+        ("sf_action", "Action Science Fiction"),
+        ("sf_epic", "Epic Science Fiction"),
+        ("sf_heroic", "Heroic Science Fiction"),
+        ("sf_detective", "Detective Science Fiction"),
+        ("sf_cyberpunk", "Cyberpunk"),
+        ("sf_space", "Space Science Fiction"),
+        ("sf_social", "Social Science Fiction"),
+        ("sf_horror", "Horror & Mystic"),
+        ("sf_humor", "Humor Science Fiction"),
+        ("sf_fantasy", "Fantasy"),
+        ("sf", "Science Fiction"),
+        ("det_classic", "Classical Detective"),
+        ("det_police", "Police Stories"),
+        ("det_action", "Action"),
+        ("det_irony", "Ironical Detective"),
+        ("det_history", "Historical Detective"),
+        ("det_espionage", "Espionage Detective"),
+        ("det_crime", "Crime Detective"),
+        ("det_political", "Political Detective"),
+        ("det_maniac", "Maniacs"),
+        ("det_hard", "Hard-boiled"),
+        ("thriller", "Thriller"),
+        ("detective", "Detective"),
+        ("prose_classic", "Classics Prose"),
+        ("prose_history", "Historical Prose"),
+        ("prose_contemporary", "Contemporary Prose"),
+        ("prose_counter", "Counterculture"),
+        ("prose_rus_classic", "Russian Classics"),
+        ("prose_su_classics", "Soviet Classics"),
+        ("love_contemporary", "Contemporary Romance"),
+        ("love_history", "Historical Romance"),
+        ("love_detective", "Detective Romance"),
+        ("love_short", "Short Romance"),
+        ("love_erotica", "Erotica"),
+        ("adv_western", "Western"),
+        ("adv_history", "History"),
+        ("adv_indian", "Indians"),
+        ("adv_maritime", "Maritime Fiction"),
+        ("adv_geo", "Travel & Geography"),
+        ("adv_animal", "Nature & Animals"),
+        ("adventure", "Adventure"),
+        ("child_tale", "Fairy Tales"),
+        ("child_verse", "Verses"),
+        ("child_prose", "Prose"),
+        ("child_sf", "Science Fiction"),
+        ("child_det", "Detectives & Thrillers"),
+        ("child_adv", "Adventures"),
+        ("child_education", "Education"),
+        ("children", "Children"),
+        ("poetry", "Poetry"),
+        ("dramaturgy", "Dramaturgy"),
+        ("antique_ant", "Antique"),
+        ("antique_european", "European"),
+        ("antique_russian", "Old Russian"),
+        ("antique_east", "Old East"),
+        ("antique_myths", "Myths, Legends & Epos"),
+        ("antique", "Antique Literature"),
+        ("sci_history", "History"),
+        ("sci_psychology", "Psychology"),
+        ("sci_culture", "Cultural Science"),
+        ("sci_religion", "Religious Studies"),
+        ("sci_philosophy", "Philosophy"),
+        ("sci_politics", "Politics"),
+        ("sci_business", "Business Literature"),
+        ("sci_juris", "Jurisprudence"),
+        ("sci_linguistic", "Linguistics"),
+        ("sci_medicine", "Medicine"),
+        ("sci_phys", "Physics"),
+        ("sci_math", "Mathematics"),
+        ("sci_chem", "Chemistry"),
+        ("sci_biology", "Biology"),
+        ("sci_tech", "Technical"),
+        ("science", "Science"),
+        ("comp_www", "Internet"),
+        ("comp_programming", "Programming"),
+        ("comp_hard", "Hardware"),
+        ("comp_soft", "Software"),
+        ("comp_db", "Databases"),
+        ("comp_osnet", "OS & Networking"),
+        ("computers", "Computers"),
+        ("ref_encyc", "Encyclopedias"),
+        ("ref_dict", "Dictionaries"),
+        ("ref_ref", "Reference"),
+        ("ref_guide", "Guidebooks"),
+        ("reference", "Reference"),
+        ("nonf_biography", "Biography & Memoirs"),
+        ("nonf_publicism", "Publicism"),
+        ("nonf_criticism", "Criticism"),
+        ("design", "Art & Design"),
+        ("nonfiction", "Nonfiction"),
+        ("religion_rel", "Religion"),
+        ("religion_esoterics", "Esoterics"),
+        ("religion_self", "Self-improvement"),
+        ("religion", "Religion"),
+        ("humor_anecdote", "Anecdote"),
+        ("humor_prose", "Humorous Prose"),
+        ("humor_verse", "Humorous Verses"),
+        ("humor", "Humor"),
+        ("home_cooking", "Cooking"),
+        ("home_pets", "Pets"),
+        ("home_crafts", "Hobbies & Crafts"),
+        ("home_entertain", "Entertaining"),
+        ("home_health", "Health"),
+        ("home_garden", "Garden"),
+        ("home_diy", "Do It Yourself"),
+        ("home_sport", "Sports"),
+        ("home_sex", "Erotica & Sex"),
+        ("home", "Home & Family"),
+    ].iter().cloned().collect();
+}
+
+// Returns the raw content of a FictionBook document, which might be
+// the single member of a ZIP archive (*.fb2.zip*).
+pub fn read_fb2<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, Error> {
+    let path = path.as_ref();
+    let mut file = File::open(path)?;
+    let mut buf = Vec::new();
+    if path.extension().and_then(OsStr::to_str).map_or(false, |ext| ext.eq_ignore_ascii_case("zip")) {
+        let mut archive = ZipArchive::new(file)?;
+        let index = (0..archive.len()).find(|&i| {
+            archive.by_index(i).ok().map_or(false, |f| f.name().to_lowercase().ends_with(".fb2"))
+        }).ok_or_else(|| format_err!("no FictionBook document found in the archive"))?;
+        archive.by_index(index)?.read_to_end(&mut buf)?;
+    } else {
+        file.read_to_end(&mut buf)?;
+    }
+    Ok(buf)
+}
+
+fn decode_text(buf: &[u8]) -> String {
+    let buf = if buf.starts_with(b"\xEF\xBB\xBF") { &buf[3..] } else { buf };
+    let head = String::from_utf8_lossy(&buf[..buf.len().min(256)]);
+    let encoding = XML_ENCODING.captures(&head)
+                               .map(|caps| caps[1].to_lowercase())
+                               .unwrap_or_default();
+    if encoding == "windows-1251" || encoding == "cp1251" {
+        buf.iter().map(|&b| match b {
+            0..=0x7F => b as char,
+            0x80..=0xBF => WINDOWS_1251[(b - 0x80) as usize],
+            _ => char::from_u32(0x0410 + (b - 0xC0) as u32).unwrap(),
+        }).collect()
+    } else {
+        String::from_utf8_lossy(buf).into_owned()
+    }
+}
+
+fn text_content(node: &Node) -> String {
+    fn text_content_aux(node: &Node, buf: &mut String) {
+        if let Some(children) = node.children() {
+            for child in children {
+                text_content_aux(child, buf);
+            }
+        } else if let Some(text) = node.text() {
+            buf.push_str(&decode_entities(text));
+        }
+    }
+    let mut buf = String::new();
+    text_content_aux(node, &mut buf);
+    buf.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn child_text(node: &Node, name: &str) -> Option<String> {
+    node.children()
+        .and_then(|children| children.iter().find(|child| child.tag_name() == Some(name)))
+        .map(text_content)
+        .filter(|text| !text.is_empty())
+}
+
+// The *description* element of a FictionBook document.
+pub struct Fb2Description {
+    root: Node,
+}
+
+impl Fb2Description {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Fb2Description, Error> {
+        let text = decode_text(&read_fb2(path)?);
+        Fb2Description::from_text(&text)
+    }
+
+    pub fn from_text(text: &str) -> Result<Fb2Description, Error> {
+        let start = text.find("<description").ok_or_else(|| format_err!("missing description"))?;
+        let end = text[start..].find("</description>")
+                               .map(|index| start + index + "</description>".len())
+                               .ok_or_else(|| format_err!("unterminated description"))?;
+        let root = XmlParser::new(&text[start..end]).parse();
+        Ok(Fb2Description { root })
+    }
+
+    fn title_info(&self) -> Option<&Node> {
+        self.root.find("title-info")
+    }
+
+    pub fn title(&self) -> Option<String> {
+        self.title_info().and_then(|node| child_text(node, "book-title"))
+            .or_else(|| self.root.find("publish-info").and_then(|node| child_text(node, "book-name")))
+    }
+
+    // The full names of the authors, assembled from their first, middle and last names.
+    pub fn authors(&self) -> Vec<String> {
+        self.title_info().and_then(Node::children).map(|children| {
+            children.iter().filter(|child| child.tag_name() == Some("author")).filter_map(|author| {
+                let name = ["first-name", "middle-name", "last-name"].iter()
+                              .filter_map(|part| child_text(author, part))
+                              .collect::<Vec<String>>().join(" ");
+                Some(name).filter(|n| !n.is_empty())
+                          .or_else(|| child_text(author, "nickname"))
+            }).collect()
+        }).unwrap_or_default()
+    }
+
+    pub fn author(&self) -> Option<String> {
+        Some(self.authors().join(", ")).filter(|a| !a.is_empty())
+    }
+
+    // The name and the number of the series.
+    pub fn sequence(&self) -> Option<(String, Option<String>)> {
+        self.title_info()
+            .and_then(Node::children)
+            .and_then(|children| children.iter().find(|child| child.tag_name() == Some("sequence")))
+            .and_then(|node| {
+                let name = node.attr("name").map(|n| decode_entities(n).trim().to_string())
+                               .filter(|n| !n.is_empty())?;
+                let number = node.attr("number").map(|n| n.trim().to_string())
+                                 .filter(|n| !n.is_empty() && n != "0");
+                Some((name, number))
+            })
+    }
+
+    // The genres, with their codes replaced by their names when known.
+    pub fn genres(&self) -> Vec<String> {
+        self.title_info().and_then(Node::children).map(|children| {
+            children.iter().filter(|child| child.tag_name() == Some("genre"))
+                    .map(text_content)
+                    .filter(|code| !code.is_empty())
+                    .map(|code| GENRES.get(code.as_str()).map_or(code.clone(), |name| name.to_string()))
+                    .collect()
+        }).unwrap_or_default()
+    }
+
+    pub fn language(&self) -> Option<String> {
+        self.title_info().and_then(|node| child_text(node, "lang"))
+    }
+
+    pub fn year(&self) -> Option<String> {
+        self.title_info()
+            .and_then(Node::children)
+            .and_then(|children| children.iter().find(|child| child.tag_name() == Some("date")))
+            .and_then(|node| node.attr("value").map(String::from)
+                                 .or_else(|| Some(text_content(node))))
+            .filter(|date| !date.is_empty())
+            .or_else(|| self.root.find("publish-info").and_then(|node| child_text(node, "year")))
+            .map(|date| normalize_year(&date))
+    }
+
+    pub fn publisher(&self) -> Option<String> {
+        self.root.find("publish-info").and_then(|node| child_text(node, "publisher"))
+    }
+}
+
+pub fn extract_metadata_from_fb2(dir: &Path, metadata: &mut Metadata, settings: &ImportSettings) {
+    let subjects_as_categories = settings.category_providers.contains(&CategoryProvider::Subject);
+
+    for info in metadata {
+        if !info.title.is_empty() || info.file.kind != "fb2" {
+            continue;
+        }
+
+        let path = dir.join(&info.file.path);
+
+        match Fb2Description::new(&path) {
+            Ok(desc) => {
+                info.title = desc.title().unwrap_or_default();
+                info.author = desc.author().unwrap_or_default();
+                info.year = desc.year().unwrap_or_default();
+                info.publisher = desc.publisher().unwrap_or_default();
+                if let Some((series, number)) = desc.sequence() {
+                    info.series = series;
+                    info.number = number.unwrap_or_default();
+                }
+                info.language = normalize_language(&desc.language().unwrap_or_default());
+                if subjects_as_categories {
+                    info.categories.extend(desc.genres());
+                }
+                println!("{}", info.label());
+            },
+            Err(e) => eprintln!("{}: {}.", info.file.path.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::metadata::{Info, FileInfo};
+    use crate::document::file_kind;
+
+    #[test]
+    fn windows_1251() {
+        let buf = b"<?xml version=\"1.0\" encoding=\"windows-1251\"?><a>\xcf\xf0\xe8\xe2\xe5\xf2 \xb9 \xa8</a>";
+        assert!(decode_text(buf).ends_with("<a>Привет № Ё</a>"));
+        let buf = "<?xml version=\"1.0\" encoding=\"UTF-8\"?><a>Привет</a>".as_bytes();
+        assert!(decode_text(buf).ends_with("<a>Привет</a>"));
+    }
+
+    #[test]
+    fn fb2_metadata() {
+        assert_eq!(file_kind("Books/Book.fb2.zip").as_deref(), Some("fb2"));
+        assert_eq!(file_kind("Books/Book.zip").as_deref(), Some("zip"));
+        let fb2_info = |name: &str| Info {
+            file: FileInfo { path: PathBuf::from(name), kind: file_kind(name).unwrap(), .. Default::default() },
+            .. Default::default()
+        };
+        let mut md = vec![fb2_info("strugatsky.fb2"), fb2_info("bulgakov.fb2.zip")];
+        let mut settings = ImportSettings::default();
+        settings.category_providers.insert(CategoryProvider::Subject);
+        extract_metadata_from_fb2(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/fb2")), &mut md, &settings);
+
+        assert_eq!(md[0].title, "Monday Begins on Saturday & Other Tales");
+        assert_eq!(md[0].author, "Arkady Natanovich Strugatsky, Boris Strugatsky");
+        assert_eq!(md[0].series, "NIICHAVO");
+        assert_eq!(md[0].number, "1");
+        assert_eq!(md[0].year, "1965");
+        assert_eq!(md[0].language, "en");
+        assert_eq!(md[0].publisher, "DAW Books");
+        assert_eq!(md[0].categories.iter().map(String::as_str).collect::<Vec<&str>>(),
+                   vec!["Humor Science Fiction", "Space Science Fiction", "unknown_code"]);
+
+        assert_eq!(md[1].title, "Мастер и Маргарита");
+        assert_eq!(md[1].author, "Михаил Афанасьевич Булгаков");
+        assert_eq!(md[1].year, "1940");
+        assert_eq!(md[1].language, "ru");
+        assert_eq!(md[1].publisher, "Художественная литература");
+        assert_eq!(md[1].file_stem(), "bulgakov");
+        assert!(md[1].categories.contains("Russian Classics"));
+    }
+}
@@ -13,6 +13,7 @@ use crate::document::{Document, Location};
 use crate::document::pdf::PdfOpener;
 use crate::unit::{mm_to_px, pt_to_px};
 use crate::geom::{Rectangle, Edge};
+use crate::metadata::Direction;
 use crate::settings::{DEFAULT_FONT_SIZE, DEFAULT_MARGIN_WIDTH, DEFAULT_TEXT_ALIGN, DEFAULT_LINE_HEIGHT};
 use super::parse::{parse_display, parse_edge, parse_float, parse_text_align, parse_text_indent, parse_width, parse_height, parse_inline_material};
 use super::parse::{parse_font_kind, parse_font_style, parse_font_weight, parse_font_size, parse_font_features, parse_font_variant, parse_letter_spacing};
@@ -53,6 +54,19 @@ pub struct Engine {
     pub dims: (u32, u32),
     // Device DPI.
     pub dpi: u16,
+    // Hyphenation of the justified paragraphs.
+    pub hyphenation: bool,
+    // Overrides the language of the elements, for hyphenation.
+    pub hyph_lang: Option<String>,
+    // In ems, override the vertical margins and the text indent of the paragraphs.
+    pub paragraph_spacing: Option<f32>,
+    pub text_indent: Option<f32>,
+    // A CSS weight, reached by emboldening the regular fonts.
+    pub font_weight: Option<f32>,
+    // In ems, overrides the letter spacing of the elements.
+    pub letter_spacing: Option<f32>,
+    // The direction of the paragraphs.
+    pub direction: Direction,
 }
 
 impl Engine {
@@ -68,6 +82,13 @@ impl Engine {
             line_height,
             dims: (DEFAULT_WIDTH, DEFAULT_HEIGHT),
             dpi: DEFAULT_DPI,
+            hyphenation: true,
+            hyph_lang: None,
+            paragraph_spacing: None,
+            text_indent: None,
+            font_weight: None,
+            letter_spacing: None,
+            direction: Direction::Ltr,
         }
     }
 
@@ -116,6 +137,17 @@ impl Engine {
         self.line_height = line_height;
     }
 
+    pub fn set_hyphenation(&mut self, enabled: bool, language: Option<&str>) {
+        self.hyphenation = enabled;
+        self.hyph_lang = language.map(String::from);
+    }
+
+    // The synthetic emboldening strength, in ems: going from a weight of 400 to 700
+    // thickens the glyphs by a 24th of an em, like FreeType does.
+    fn emboldening(&self) -> f32 {
+        self.font_weight.map_or(0.0, |weight| (weight - 400.0).max(0.0) / (300.0 * 24.0))
+    }
+
     #[inline]
     pub fn rect(&self) -> Rectangle {
         let (width, height) = self.dims;
@@ -128,7 +160,21 @@ impl Engine {
         let mut rects: Vec<Option<Rectangle>> = Vec::new();
         rects.push(None);
 
-        let props = specified_values(node, loop_context.parent, loop_context.sibling, stylesheet);
+        let mut props = specified_values(node, loop_context.parent, loop_context.sibling, stylesheet);
+
+        if let Some(spacing) = self.letter_spacing {
+            props.insert("letter-spacing".to_string(), format!("{}em", spacing));
+        }
+
+        if node.tag_name() == Some("p") {
+            if let Some(spacing) = self.paragraph_spacing {
+                props.insert("margin-top".to_string(), format!("{}em", spacing));
+                props.insert("margin-bottom".to_string(), format!("{}em", spacing));
+            }
+            if let Some(indent) = self.text_indent {
+                props.insert("text-indent".to_string(), format!("{}em", indent));
+            }
+        }
 
         style.display = props.get("display").and_then(|value| parse_display(value))
                              .unwrap_or(Display::Block);
@@ -577,7 +623,11 @@ impl Engine {
         match node {
             Node::Element(ElementData { offset, name, attributes, children }) => {
                 let mut style = StyleData::default();
-                let props = specified_values(node, parent, sibling, stylesheet);
+                let mut props = specified_values(node, parent, sibling, stylesheet);
+
+                if let Some(spacing) = self.letter_spacing {
+                    props.insert("letter-spacing".to_string(), format!("{}em", spacing));
+                }
 
                 style.font_style = parent_style.font_style;
                 style.line_height = parent_style.line_height;
@@ -825,6 +875,7 @@ impl Engine {
                                                    .get_mut(style.font_kind,
                                                             style.font_style,
                                                             style.font_weight);
+                                    font.set_direction(self.direction);
                                     font.set_size(font_size, self.dpi);
                                     font.plan(&buf, None, style.font_features.as_ref().map(Vec::as_slice))
                                 };
@@ -948,6 +999,7 @@ impl Engine {
                                            .get_mut(style.font_kind,
                                                     style.font_style,
                                                     style.font_weight);
+                            font.set_direction(self.direction);
                             font.set_size(font_size, self.dpi);
                             font.plan(&buf, None, style.font_features.as_ref().map(Vec::as_slice))
                         };
@@ -1144,8 +1196,9 @@ impl Engine {
         let mut glue_drifts = Vec::new();
 
         if bps.is_empty() {
-            let dictionary = if style.text_align == TextAlign::Justify {
-                hyph_lang(style.language.as_ref().map_or(DEFAULT_HYPH_LANG, String::as_str))
+            let dictionary = if style.text_align == TextAlign::Justify && self.hyphenation {
+                hyph_lang(self.hyph_lang.as_ref().or_else(|| style.language.as_ref())
+                              .map_or(DEFAULT_HYPH_LANG, String::as_str))
                          .and_then(|lang| HYPHENATION_PATTERNS.get(&lang))
             } else {
                 None
@@ -1242,7 +1295,14 @@ impl Engine {
             let mut epsilon: f32 = 0.0;
             let current_text_indent = if is_first_line { text_indent } else { 0 };
 
-            match style.text_align {
+            // The right to left lines are laid out from the left, then mirrored.
+            let text_align = match (self.direction, style.text_align) {
+                (Direction::Rtl, TextAlign::Left) => TextAlign::Right,
+                (Direction::Rtl, TextAlign::Right) => TextAlign::Left,
+                (_, text_align) => text_align,
+            };
+
+            match text_align {
                 TextAlign::Right => position.x = end_x - width - current_text_indent,
                 _ => position.x = start_x + current_text_indent,
             }
@@ -1255,6 +1315,8 @@ impl Engine {
                 last_index += 1;
             }
 
+            let line_start = page.len();
+
             for i in last_index..index {
                 match items[i] {
                     ParagraphItem::Box { ref data, width } => {
@@ -1425,6 +1487,24 @@ impl Engine {
                 }
             }
 
+            if self.direction == Direction::Rtl {
+                for command in page.iter_mut().skip(line_start) {
+                    match command {
+                        DrawCommand::Text(TextCommand { ref mut position, ref mut rect, .. }) |
+                        DrawCommand::Image(ImageCommand { ref mut position, ref mut rect, .. }) => {
+                            let dx = start_x + end_x - rect.min.x - rect.max.x;
+                            position.x += dx;
+                            rect.min.x += dx;
+                            rect.max.x += dx;
+                            if let Some(pr) = page_rect.as_mut() {
+                                pr.absorb(rect);
+                            }
+                        },
+                        _ => (),
+                    }
+                }
+            }
+
             last_index = index;
             is_first_line = false;
 
@@ -1462,6 +1542,7 @@ impl Engine {
                            .get_mut(element.font_kind,
                                     element.font_style,
                                     element.font_weight);
+            font.set_direction(self.direction);
             font.set_size(element.font_size, self.dpi);
             font.plan(chunk, None, element.font_features.as_ref().map(Vec::as_slice))
         };
@@ -1590,6 +1671,7 @@ impl Engine {
                     *plan = {
                         let font = self.fonts.as_mut().unwrap()
                                        .get_mut(font_kind, font_style, font_weight);
+                        font.set_direction(self.direction);
                         font.set_size(font_size, self.dpi);
                         font.plan(text, None, font_features.as_ref().map(Vec::as_slice))
                     };
@@ -1649,6 +1731,7 @@ impl Engine {
                         *plan = {
                             let font = self.fonts.as_mut().unwrap()
                                            .get_mut(font_kind, font_style, font_weight);
+                            font.set_direction(self.direction);
                             font.set_size(font_size, self.dpi);
                             font.plan(text, None, font_features.as_ref().map(Vec::as_slice))
                         };
@@ -1675,6 +1758,7 @@ impl Engine {
     pub fn render_page(&mut self, page: &[DrawCommand], resource_fetcher: &mut dyn ResourceFetcher) -> Pixmap {
         let (width, height) = self.dims;
         let mut fb = Pixmap::new(width, height);
+        let emboldening = self.emboldening();
 
         for dc in page {
             match dc {
@@ -1683,6 +1767,7 @@ impl Engine {
                     let font = self.fonts.as_mut().unwrap()
                                    .get_mut(*font_kind, *font_style, *font_weight);
                     font.set_size(*font_size, self.dpi);
+                    font.set_emboldening(emboldening);
                     font.render(&mut fb, *color, plan, *position);
                 },
                 DrawCommand::Image(ImageCommand { position, path, scale, .. }) => {
@@ -16,6 +16,7 @@ use crate::helpers::{Normalize, decode_entities};
 use crate::document::{Document, Location, TextLocation, TocEntry, BoundedText};
 use crate::unit::pt_to_px;
 use crate::geom::{Rectangle, Edge, CycleDir};
+use crate::metadata::Direction;
 use self::dom::Node;
 use self::layout::{RootData, StyleData, DrawState, LoopContext};
 use self::layout::{DrawCommand, TextCommand, ImageCommand, TextAlign};
@@ -401,6 +402,36 @@ impl Document for HtmlDocument {
         self.pages.clear();
     }
 
+    fn set_hyphenation(&mut self, enabled: bool, language: Option<&str>) {
+        self.engine.set_hyphenation(enabled, language);
+        self.pages.clear();
+    }
+
+    fn set_paragraph_spacing(&mut self, spacing: Option<f32>) {
+        self.engine.paragraph_spacing = spacing;
+        self.pages.clear();
+    }
+
+    fn set_text_indent(&mut self, indent: Option<f32>) {
+        self.engine.text_indent = indent;
+        self.pages.clear();
+    }
+
+    fn set_font_weight(&mut self, weight: Option<f32>) {
+        self.engine.font_weight = weight;
+        self.pages.clear();
+    }
+
+    fn set_letter_spacing(&mut self, spacing: Option<f32>) {
+        self.engine.letter_spacing = spacing;
+        self.pages.clear();
+    }
+
+    fn set_direction(&mut self, direction: Direction) {
+        self.engine.direction = direction;
+        self.pages.clear();
+    }
+
     fn title(&self) -> Option<String> {
         self.content.find("head")
             .and_then(Node::children)
@@ -0,0 +1,252 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::fs::File;
+use std::path::Path;
+use byteorder::{BigEndian, ByteOrder};
+use failure::{Error, format_err};
+use crate::metadata::{Metadata, normalize_year, normalize_language};
+use crate::settings::{ImportSettings, CategoryProvider};
+
+// The size of the Palm Database header, which is followed by the record list.
+const PDB_HEADER_SIZE: usize = 78;
+const PDB_RECORD_INFO_SIZE: usize = 8;
+// The size of the PalmDoc header, which is followed by the MOBI header.
+const PALMDOC_HEADER_SIZE: usize = 16;
+const EXTH_FLAG: u32 = 0x40;
+const UTF_8_ENCODING: u32 = 65001;
+
+const EXTH_AUTHOR: u32 = 100;
+const EXTH_PUBLISHER: u32 = 101;
+const EXTH_DESCRIPTION: u32 = 103;
+const EXTH_ISBN: u32 = 104;
+const EXTH_SUBJECT: u32 = 105;
+const EXTH_PUBLISHING_DATE: u32 = 106;
+const EXTH_UPDATED_TITLE: u32 = 503;
+const EXTH_LANGUAGE: u32 = 524;
+
+// The bytes 0x80 to 0x9F of the Windows-1252 code page, the others match Latin-1.
+const WINDOWS_1252: [char; 32] = [
+    '\u{20AC}', '\u{FFFD}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{FFFD}', '\u{017D}', '\u{FFFD}',
+    '\u{FFFD}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{FFFD}', '\u{017E}', '\u{0178}',
+];
+
+// The metadata found in the header of the first record of a MOBI or AZW3 file.
+#[derive(Debug, Clone, Default)]
+pub struct MobiMetadata {
+    pub title: Option<String>,
+    pub authors: Vec<String>,
+    pub publisher: Option<String>,
+    pub description: Option<String>,
+    pub isbn: Option<String>,
+    pub date: Option<String>,
+    pub subjects: Vec<String>,
+    pub language: Option<String>,
+}
+
+impl MobiMetadata {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<MobiMetadata, Error> {
+        let mut file = File::open(path)?;
+        let file_size = file.metadata()?.len();
+
+        let mut header = [0u8; PDB_HEADER_SIZE];
+        file.read_exact(&mut header)?;
+        if &header[60..68] != b"BOOKMOBI" {
+            return Err(format_err!("not a MOBI file"));
+        }
+        let pdb_name = decode_text(trim_nul(&header[..32]), false);
+        let records_count = BigEndian::read_u16(&header[76..78]) as usize;
+        if records_count == 0 {
+            return Err(format_err!("no records"));
+        }
+
+        // The offsets of the first two records give the size of the first one.
+        let mut infos = [0u8; 2 * PDB_RECORD_INFO_SIZE];
+        let infos_len = PDB_RECORD_INFO_SIZE * records_count.min(2);
+        file.read_exact(&mut infos[..infos_len])?;
+        let start = BigEndian::read_u32(&infos[0..4]) as u64;
+        let end = if records_count > 1 {
+            BigEndian::read_u32(&infos[8..12]) as u64
+        } else {
+            file_size
+        };
+        if start >= end || end > file_size {
+            return Err(format_err!("invalid record offsets"));
+        }
+
+        let mut record = Vec::with_capacity((end - start) as usize);
+        file.seek(SeekFrom::Start(start))?;
+        file.take(end - start).read_to_end(&mut record)?;
+        MobiMetadata::from_record(&record, &pdb_name)
+    }
+
+    // Parses the first record: the PalmDoc header, the MOBI header and the optional EXTH header.
+    pub fn from_record(record: &[u8], pdb_name: &str) -> Result<MobiMetadata, Error> {
+        let mobi = record.get(PALMDOC_HEADER_SIZE..)
+                         .filter(|mobi| mobi.starts_with(b"MOBI") && mobi.len() >= 116)
+                         .ok_or_else(|| format_err!("missing MOBI header"))?;
+        let mobi_header_len = BigEndian::read_u32(&mobi[4..8]) as usize;
+        let utf8 = BigEndian::read_u32(&mobi[12..16]) == UTF_8_ENCODING;
+        let exth_flags = BigEndian::read_u32(&mobi[112..116]);
+
+        let mut metadata = MobiMetadata::default();
+
+        let name_offset = BigEndian::read_u32(&mobi[68..72]) as usize;
+        let name_len = BigEndian::read_u32(&mobi[72..76]) as usize;
+        let full_name = name_offset.checked_add(name_len)
+                                   .and_then(|end| record.get(name_offset..end))
+                                   .map(|name| decode_text(name, utf8))
+                                   .filter(|name| !name.trim().is_empty());
+
+        if exth_flags & EXTH_FLAG != 0 {
+            let exth = PALMDOC_HEADER_SIZE.checked_add(mobi_header_len)
+                                          .and_then(|offset| record.get(offset..))
+                                          .filter(|exth| exth.starts_with(b"EXTH") && exth.len() >= 12)
+                                          .ok_or_else(|| format_err!("missing EXTH header"))?;
+            let exth_len = (BigEndian::read_u32(&exth[4..8]) as usize).min(exth.len());
+            let count = BigEndian::read_u32(&exth[8..12]) as usize;
+            let mut offset = 12;
+
+            for _ in 0..count {
+                if offset + 8 > exth_len {
+                    break;
+                }
+                let kind = BigEndian::read_u32(&exth[offset..offset+4]);
+                let len = BigEndian::read_u32(&exth[offset+4..offset+8]) as usize;
+                if len < 8 || len > exth_len - offset {
+                    break;
+                }
+                let value = decode_text(&exth[offset+8..offset+len], utf8).trim().to_string();
+                offset += len;
+                if value.is_empty() {
+                    continue;
+                }
+                match kind {
+                    EXTH_AUTHOR => metadata.authors.push(value),
+                    EXTH_PUBLISHER => metadata.publisher = Some(value),
+                    EXTH_DESCRIPTION => metadata.description = Some(value),
+                    EXTH_ISBN => metadata.isbn = Some(value),
+                    EXTH_SUBJECT => metadata.subjects.push(value),
+                    EXTH_PUBLISHING_DATE => metadata.date = Some(value),
+                    EXTH_UPDATED_TITLE => metadata.title = Some(value),
+                    EXTH_LANGUAGE => metadata.language = Some(value),
+                    _ => (),
+                }
+            }
+        }
+
+        if metadata.title.is_none() {
+            metadata.title = full_name.or_else(|| Some(pdb_name.replace('_', " ")))
+                                      .map(|title| title.trim().to_string())
+                                      .filter(|title| !title.is_empty());
+        }
+
+        Ok(metadata)
+    }
+}
+
+fn trim_nul(bytes: &[u8]) -> &[u8] {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or_else(|| bytes.len());
+    &bytes[..end]
+}
+
+fn decode_text(bytes: &[u8], utf8: bool) -> String {
+    if utf8 {
+        String::from_utf8_lossy(bytes).into_owned()
+    } else {
+        bytes.iter().map(|&b| match b {
+            0x80..=0x9F => WINDOWS_1252[(b - 0x80) as usize],
+            _ => b as char,
+        }).collect()
+    }
+}
+
+pub fn extract_metadata_from_mobi(dir: &Path, metadata: &mut Metadata, settings: &ImportSettings) {
+    let subjects_as_categories = settings.category_providers.contains(&CategoryProvider::Subject);
+
+    for info in metadata {
+        if !info.title.is_empty() || !["mobi", "azw3", "azw"].contains(&info.file.kind.as_str()) {
+            continue;
+        }
+
+        let path = dir.join(&info.file.path);
+
+        match MobiMetadata::new(&path) {
+            Ok(mut md) => {
+                info.title = md.title.unwrap_or_default();
+                info.author = md.authors.join(", ");
+                info.year = md.date.map(|date| normalize_year(&date)).unwrap_or_default();
+                info.publisher = md.publisher.unwrap_or_default();
+                info.isbn = md.isbn.unwrap_or_default();
+                info.description = md.description.unwrap_or_default();
+                info.language = normalize_language(&md.language.unwrap_or_default());
+                if subjects_as_categories {
+                    info.categories.extend(md.subjects.drain(..));
+                }
+                println!("{}", info.label());
+            },
+            Err(e) => eprintln!("{}: {}.", info.file.path.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::metadata::{Info, FileInfo};
+    use crate::document::file_kind;
+
+    #[test]
+    fn corrupt_records() {
+        assert!(MobiMetadata::from_record(b"", "").is_err());
+        assert!(MobiMetadata::from_record(&[0; 64], "").is_err());
+
+        let mut record = vec![0u8; PALMDOC_HEADER_SIZE];
+        record.extend_from_slice(b"MOBI");
+        record.extend_from_slice(&[0xFF; 112]);
+        assert!(MobiMetadata::from_record(&record, "A_Title").is_err());
+
+        // A lying EXTH record length stops the parsing.
+        let mut record = vec![0u8; PALMDOC_HEADER_SIZE + 232];
+        record[16..20].copy_from_slice(b"MOBI");
+        BigEndian::write_u32(&mut record[20..24], 232);
+        BigEndian::write_u32(&mut record[128..132], EXTH_FLAG);
+        record.extend_from_slice(b"EXTH");
+        record.extend_from_slice(&[0, 0, 0, 36, 0, 0, 0, 2]);
+        record.extend_from_slice(&[0, 0, 0, 100, 0, 0, 0, 12]);
+        record.extend_from_slice(b"Anon");
+        record.extend_from_slice(&[0, 0, 0, 101, 0xFF, 0xFF, 0xFF, 0xFF]);
+        let metadata = MobiMetadata::from_record(&record, "A_Title").unwrap();
+        assert_eq!(metadata.authors, vec!["Anon".to_string()]);
+        assert_eq!(metadata.publisher, None);
+        assert_eq!(metadata.title.as_deref(), Some("A Title"));
+    }
+
+    #[test]
+    fn mobi_metadata() {
+        let mobi_info = |name: &str| Info {
+            file: FileInfo { path: PathBuf::from(name), kind: file_kind(name).unwrap(), .. Default::default() },
+            .. Default::default()
+        };
+        let mut md = vec![mobi_info("exth.azw3"), mobi_info("no-exth.mobi")];
+        let mut settings = ImportSettings::default();
+        extract_metadata_from_mobi(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/mobi")), &mut md, &settings);
+
+        assert_eq!(md[0].title, "The Left Hand of Darkness — 50th Anniversary");
+        assert_eq!(md[0].author, "Ursula K. Le Guin");
+        assert_eq!(md[0].publisher, "Ace Books");
+        assert_eq!(md[0].isbn, "9780441478125");
+        assert_eq!(md[0].year, "1969");
+        assert_eq!(md[0].language, "en");
+        assert!(md[0].categories.is_empty());
+        assert_eq!(md[1].title, "Café Stories");
+        assert_eq!(md[1].author, "");
+
+        md[0].title = String::new();
+        settings.category_providers.insert(CategoryProvider::Subject);
+        extract_metadata_from_mobi(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/mobi")), &mut md, &settings);
+        assert_eq!(md[0].categories.iter().map(String::as_str).collect::<Vec<&str>>(),
+                   vec!["Gethen & Others", "Science Fiction"]);
+    }
+}
@@ -1,6 +1,11 @@
 pub mod djvu;
 pub mod pdf;
 pub mod epub;
+pub mod cbz;
+pub mod fb2;
+pub mod mobi;
+pub mod text;
+pub mod audio;
 pub mod html;
 
 mod djvulibre_sys;
@@ -16,8 +21,10 @@ use serde::{Serialize, Deserialize};
 use self::djvu::DjvuOpener;
 use self::pdf::PdfOpener;
 use self::epub::EpubDocument;
+use self::fb2::read_fb2;
+use self::audio::AUDIOBOOK_KINDS;
 use crate::geom::{Boundary, CycleDir};
-use crate::metadata::{TextAlign};
+use crate::metadata::{TextAlign, Direction};
 use crate::framebuffer::Pixmap;
 
 pub const BYTES_PER_PAGE: f64 = 2048.0;
@@ -95,6 +102,28 @@ pub trait Document: Send+Sync {
     fn set_text_align(&mut self, text_align: TextAlign);
     fn set_line_height(&mut self, line_height: f32);
 
+    // The given language overrides the one of the text.
+    fn set_hyphenation(&mut self, _enabled: bool, _language: Option<&str>) {
+    }
+
+    // In ems, override the style of the paragraphs.
+    fn set_paragraph_spacing(&mut self, _spacing: Option<f32>) {
+    }
+
+    fn set_text_indent(&mut self, _indent: Option<f32>) {
+    }
+
+    // A CSS weight, and a spacing in ems.
+    fn set_font_weight(&mut self, _weight: Option<f32>) {
+    }
+
+    fn set_letter_spacing(&mut self, _spacing: Option<f32>) {
+    }
+
+    // The direction of the paragraphs, such as the one of the language of the book.
+    fn set_direction(&mut self, _direction: Direction) {
+    }
+
     fn title(&self) -> Option<String>;
     fn author(&self) -> Option<String>;
     fn metadata(&self, key: &str) -> Option<String>;
@@ -105,6 +134,11 @@ pub trait Document: Send+Sync {
         false
     }
 
+    // Print page labels and the locations they point to.
+    fn page_list(&mut self) -> Vec<(String, TextLocation)> {
+        Vec::new()
+    }
+
     fn resolve_location(&mut self, loc: Location) -> Option<usize> {
         if self.pages_count() == 0 {
             return None;
@@ -138,9 +172,36 @@ pub trait Document: Send+Sync {
 }
 
 pub fn file_kind<P: AsRef<Path>>(path: P) -> Option<String> {
-    path.as_ref().extension()
+    let path = path.as_ref();
+    path.extension()
         .and_then(OsStr::to_str)
-        .map(str::to_lowercase)
+        .map(|ext| if ext.eq_ignore_ascii_case("zip") && is_zipped_fb2(path) {
+            "fb2".to_string()
+        } else {
+            canonical_kind(ext)
+        })
+}
+
+// Maps the extensions of a same format to a single kind.
+// Kobo's *.kepub.epub* (or *.kepub*) files are regular EPUBs.
+pub fn canonical_kind(ext: &str) -> String {
+    let ext = ext.to_lowercase();
+    match ext.as_str() {
+        "kepub" => "epub",
+        "djv" => "djvu",
+        "htm" | "xhtml" => "html",
+        "markdown" => "md",
+        "azw" | "prc" => "mobi",
+        _ => return ext,
+    }.to_string()
+}
+
+// Whether the path designates a zipped FictionBook document (*.fb2.zip*).
+fn is_zipped_fb2(path: &Path) -> bool {
+    path.file_stem().map(Path::new)
+        .and_then(Path::extension)
+        .and_then(OsStr::to_str)
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("fb2"))
 }
 
 pub trait HumanSize {
@@ -178,12 +239,22 @@ pub fn open<P: AsRef<Path>>(path: P) -> Option<Box<dyn Document>> {
                              .map_err(|e| eprintln!("{}: {}.", path.as_ref().display(), e))
                              .map(|d| Box::new(d) as Box<dyn Document>).ok()
             },
-            "djvu" | "djv" => {
+            "djvu" => {
                 DjvuOpener::new().and_then(|o| {
                     o.open(path)
                      .map(|d| Box::new(d) as Box<dyn Document>)
                 })
             },
+            "fb2" if is_zipped_fb2(path.as_ref()) => {
+                read_fb2(&path).map_err(|e| eprintln!("{}: {}.", path.as_ref().display(), e)).ok()
+                               .and_then(|buf| PdfOpener::new().and_then(|o| o.open_memory("fb2", &buf)))
+                               .map(|d| Box::new(d) as Box<dyn Document>)
+            },
+            // Audiobooks are only catalogued: there's nothing to render.
+            kind if AUDIOBOOK_KINDS.contains(&kind) => {
+                eprintln!("{}: audiobooks can't be opened.", path.as_ref().display());
+                None
+            },
             _ => {
                 PdfOpener::new().and_then(|o| {
                     o.open(path)
@@ -201,6 +272,22 @@ pub enum SimpleTocEntry {
     Container(String, TocLocation, Vec<SimpleTocEntry>),
 }
 
+impl SimpleTocEntry {
+    pub fn title(&self) -> &str {
+        match self {
+            SimpleTocEntry::Leaf(title, _) |
+            SimpleTocEntry::Container(title, _, _) => title,
+        }
+    }
+
+    pub fn children(&self) -> &[SimpleTocEntry] {
+        match self {
+            SimpleTocEntry::Leaf(..) => &[],
+            SimpleTocEntry::Container(_, _, children) => children,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum TocLocation {
@@ -208,6 +295,48 @@ pub enum TocLocation {
     Uri(String),
 }
 
+// Converts a table of contents into its simple form, skipping the entries that point outside
+// of the document and keeping at most *max_entries* entries.
+pub fn simplify_toc(toc: &[TocEntry], pages_count: usize, max_entries: usize) -> Vec<SimpleTocEntry> {
+    fn simplify_toc_aux(toc: &[TocEntry], pages_count: usize, budget: &mut usize) -> Vec<SimpleTocEntry> {
+        let mut result = Vec::new();
+
+        for entry in toc {
+            if *budget == 0 {
+                break;
+            }
+
+            let location = match entry.location {
+                Location::Exact(page) if page < pages_count => Some(TocLocation::Exact(page)),
+                Location::Uri(ref uri) => Some(TocLocation::Uri(uri.clone())),
+                _ => None,
+            };
+
+            if location.is_some() {
+                *budget -= 1;
+            }
+
+            let children = simplify_toc_aux(&entry.children, pages_count, budget);
+
+            match location {
+                Some(location) if children.is_empty() => {
+                    result.push(SimpleTocEntry::Leaf(entry.title.clone(), location));
+                },
+                Some(location) => {
+                    result.push(SimpleTocEntry::Container(entry.title.clone(), location, children));
+                },
+                // The children of an invalid entry are attached to its parent.
+                None => result.extend(children),
+            }
+        }
+
+        result
+    }
+
+    let mut budget = max_entries;
+    simplify_toc_aux(toc, pages_count, &mut budget)
+}
+
 impl From<TocLocation> for Location {
     fn from(loc: TocLocation) -> Location {
         match loc {
@@ -416,3 +545,49 @@ pub static ref RECOGNIZED_KINDS: FnvHashSet<&'static str> =
     "xps",
     ].iter().cloned().collect();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_kinds() {
+        assert_eq!(file_kind("Books/Book.PDF").as_deref(), Some("pdf"));
+        assert_eq!(file_kind("Books/Book.azw3").as_deref(), Some("azw3"));
+        assert_eq!(file_kind("Books/Book.AZW").as_deref(), Some("mobi"));
+        assert_eq!(file_kind("Books/Book.prc").as_deref(), Some("mobi"));
+        assert_eq!(file_kind("Books/Book.fb2").as_deref(), Some("fb2"));
+        assert_eq!(file_kind("Books/Book.FB2.ZIP").as_deref(), Some("fb2"));
+        assert_eq!(file_kind("Books/Book.djvu").as_deref(), Some("djvu"));
+        assert_eq!(file_kind("Books/Book.djv").as_deref(), Some("djvu"));
+        assert_eq!(file_kind("Comics/Issue.cbr").as_deref(), Some("cbr"));
+        assert_eq!(file_kind("Comics/Issue.CBZ").as_deref(), Some("cbz"));
+        assert_eq!(file_kind("Articles/Page.htm").as_deref(), Some("html"));
+        assert_eq!(file_kind("Articles/Page.xhtml").as_deref(), Some("html"));
+        assert_eq!(file_kind("Notes/Draft.markdown").as_deref(), Some("md"));
+        assert_eq!(file_kind("Audio/Book.M4B").as_deref(), Some("m4b"));
+        assert_eq!(file_kind("Audio/Book.opus").as_deref(), Some("opus"));
+        assert_eq!(file_kind("Books/README"), None);
+    }
+
+    fn entry(title: &str, page: usize, children: Vec<TocEntry>) -> TocEntry {
+        TocEntry { title: title.to_string(), location: Location::Exact(page), index: 0, children }
+    }
+
+    fn titles(toc: &[SimpleTocEntry]) -> Vec<String> {
+        toc.iter().map(|e| match e {
+            SimpleTocEntry::Leaf(title, _) => title.clone(),
+            SimpleTocEntry::Container(title, _, children) => format!("{}{:?}", title, titles(children)),
+        }).collect()
+    }
+
+    #[test]
+    fn simplify() {
+        let toc = vec![entry("a", 0, vec![entry("a1", 1, vec![]), entry("a2", 9, vec![])]),
+                       entry("b", 42, vec![entry("b1", 3, vec![])]),
+                       entry("c", 4, vec![])];
+        assert_eq!(titles(&simplify_toc(&toc, 10, 10)), vec!["a[\"a1\", \"a2\"]", "b1", "c"]);
+        assert_eq!(titles(&simplify_toc(&toc, 5, 10)), vec!["a[\"a1\"]", "b1", "c"]);
+        assert_eq!(titles(&simplify_toc(&toc, 10, 2)), vec!["a[\"a1\"]"]);
+    }
+}
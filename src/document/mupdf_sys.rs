@@ -7,6 +7,9 @@ pub const FZ_VERSION: &str = "1.16.1";
 
 pub const FZ_META_INFO_AUTHOR: &str = "info:Author";
 pub const FZ_META_INFO_TITLE: &str = "info:Title";
+pub const FZ_META_INFO_SUBJECT: &str = "info:Subject";
+pub const FZ_META_INFO_KEYWORDS: &str = "info:Keywords";
+pub const FZ_META_INFO_CREATION_DATE: &str = "info:CreationDate";
 
 pub const FZ_TEXT_PRESERVE_LIGATURES: libc::c_int = 1;
 pub const FZ_TEXT_PRESERVE_WHITESPACE: libc::c_int = 2;
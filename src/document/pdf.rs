@@ -5,14 +5,20 @@ use std::slice;
 use std::char;
 use std::rc::Rc;
 use std::path::Path;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
 use std::fs::File;
 use std::ffi::{CString, CStr};
 use std::os::unix::ffi::OsStrExt;
 use failure::Error;
-use super::{Document, Location, TextLocation, BoundedText, TocEntry};
+use lazy_static::lazy_static;
+use regex::bytes::Regex;
+use super::{Document, Location, TextLocation, BoundedText, TocEntry, simplify_toc};
 use super::{chapter, chapter_relative};
-use crate::metadata::TextAlign;
+use super::html::dom::Node;
+use super::html::xml::XmlParser;
+use crate::helpers::decode_entities;
+use crate::metadata::{Metadata, TextAlign, JUNK_TITLE, MAX_TOC_ENTRIES, normalize_year};
+use crate::settings::{ImportSettings, CategoryProvider};
 use crate::geom::{Boundary, CycleDir};
 use crate::unit::pt_to_px;
 use crate::framebuffer::Pixmap;
@@ -156,6 +162,90 @@ impl PdfDocument {
     pub fn is_protected(&self) -> bool {
         unsafe { fz_needs_password(self.ctx.0, self.doc) == 1 }
     }
+
+    pub fn subject(&self) -> Option<String> {
+        self.metadata(FZ_META_INFO_SUBJECT)
+    }
+
+    pub fn keywords(&self) -> Option<String> {
+        self.metadata(FZ_META_INFO_KEYWORDS)
+    }
+
+    pub fn creation_date(&self) -> Option<String> {
+        self.metadata(FZ_META_INFO_CREATION_DATE)
+    }
+}
+
+// The number of bytes searched for an XMP packet at each end of the file.
+const XMP_WINDOW_SIZE: u64 = 256 * 1024;
+
+lazy_static! {
+    static ref XMP_START: Regex = Regex::new(r"<x:xmpmeta").unwrap();
+    static ref XMP_END: Regex = Regex::new(r"</x:xmpmeta>").unwrap();
+}
+
+// Parses the last XMP packet found at the end of the given file, where incremental
+// updates write it, or else at its beginning. Compressed packets are ignored.
+pub fn read_xmp<P: AsRef<Path>>(path: P) -> Option<Node> {
+    let mut file = File::open(path).ok()?;
+    let size = file.metadata().ok()?.len();
+    let tail_start = size.saturating_sub(XMP_WINDOW_SIZE);
+    let mut buf = Vec::new();
+    file.seek(SeekFrom::Start(tail_start)).ok()?;
+    (&mut file).take(XMP_WINDOW_SIZE).read_to_end(&mut buf).ok()?;
+
+    if last_xmp_packet(&buf).is_none() && tail_start > 0 {
+        buf.clear();
+        file.seek(SeekFrom::Start(0)).ok()?;
+        file.take(XMP_WINDOW_SIZE.min(tail_start)).read_to_end(&mut buf).ok()?;
+    }
+
+    last_xmp_packet(&buf).map(|packet| XmlParser::new(&String::from_utf8_lossy(packet)).parse())
+}
+
+fn last_xmp_packet(buf: &[u8]) -> Option<&[u8]> {
+    let start = XMP_START.find_iter(buf).last()?.start();
+    let end = XMP_END.find(&buf[start..])?.end();
+    Some(&buf[start..start+end])
+}
+
+// The values of an XMP property, given either as an element (possibly holding
+// an *rdf:Alt*, *rdf:Bag* or *rdf:Seq*) or as an attribute of *rdf:Description*.
+pub fn xmp_values(node: &Node, name: &str) -> Vec<String> {
+    fn collect_items(node: &Node, values: &mut Vec<String>) {
+        if node.tag_name() == Some("rdf:li") {
+            if let Some(text) = node.text() {
+                values.push(decode_entities(text).trim().to_string());
+            }
+        } else if let Some(children) = node.children() {
+            for child in children {
+                collect_items(child, values);
+            }
+        }
+    }
+
+    let mut values = Vec::new();
+
+    if node.tag_name() == Some(name) {
+        collect_items(node, &mut values);
+        if values.is_empty() {
+            if let Some(text) = node.text() {
+                values.push(decode_entities(text).trim().to_string());
+            }
+        }
+    } else if let Some(value) = node.attr(name) {
+        values.push(decode_entities(value).trim().to_string());
+    } else if let Some(children) = node.children() {
+        for child in children {
+            values = xmp_values(child, name);
+            if !values.is_empty() {
+                break;
+            }
+        }
+    }
+
+    values.retain(|v| !v.is_empty());
+    values
 }
 
 impl Document for PdfDocument {
@@ -455,3 +545,117 @@ impl<'a> Drop for PdfPage<'a> {
         unsafe { fz_drop_page(self.ctx.0, self.page); }
     }
 }
+
+pub fn extract_metadata_from_pdf(dir: &Path, metadata: &mut Metadata, settings: &ImportSettings) {
+    let subjects_as_categories = settings.category_providers.contains(&CategoryProvider::Subject);
+    let opener = match PdfOpener::new() {
+        Some(opener) => opener,
+        None => {
+            eprintln!("Can't create PDF opener.");
+            return;
+        },
+    };
+
+    for info in metadata {
+        if !info.title.is_empty() || info.file.kind != "pdf" {
+            continue;
+        }
+
+        let path = dir.join(&info.file.path);
+
+        let mut doc = match opener.open(&path) {
+            Some(doc) => doc,
+            None => {
+                eprintln!("{}: can't open document.", info.file.path.display());
+                continue;
+            },
+        };
+
+        if info.toc.is_none() {
+            let pages_count = doc.pages_count();
+            info.toc = doc.toc().map(|toc| simplify_toc(&toc, pages_count, MAX_TOC_ENTRIES));
+        }
+
+        let non_empty = |value: Option<String>| value.map(|v| v.trim().to_string())
+                                                     .filter(|v| !v.is_empty());
+        let mut title = non_empty(doc.title());
+        let mut author = non_empty(doc.author());
+        let mut date = non_empty(doc.creation_date());
+        let mut subjects = Vec::new();
+        subjects.extend(non_empty(doc.subject()));
+        if let Some(keywords) = non_empty(doc.keywords()) {
+            subjects.extend(keywords.split(|c| c == ',' || c == ';')
+                                    .map(str::trim)
+                                    .filter(|k| !k.is_empty())
+                                    .map(String::from));
+        }
+
+        if title.is_none() || author.is_none() || date.is_none() || subjects.is_empty() {
+            if let Some(xmp) = read_xmp(&path) {
+                title = title.or_else(|| xmp_values(&xmp, "dc:title").into_iter().next());
+                author = author.or_else(|| Some(xmp_values(&xmp, "dc:creator").join(", "))
+                                                .filter(|a| !a.is_empty()));
+                date = date.or_else(|| xmp_values(&xmp, "xmp:CreateDate").into_iter().next());
+                if subjects.is_empty() {
+                    subjects = xmp_values(&xmp, "dc:subject");
+                }
+            }
+        }
+
+        if let Some(title) = title.filter(|t| !JUNK_TITLE.is_match(t)) {
+            info.title = title;
+        }
+        if info.author.is_empty() {
+            if let Some(author) = author {
+                info.author = author;
+            }
+        }
+        if info.year.is_empty() {
+            info.year = date.map(|d| normalize_year(&d))
+                            .filter(|y| y.len() == 4 && y.chars().all(|c| c.is_ascii_digit()))
+                            .unwrap_or_default();
+        }
+        if subjects_as_categories {
+            info.categories.extend(subjects);
+        }
+        println!("{}", info.label());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xmp_properties() {
+        let text = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF>
+            <rdf:Description xmp:CreateDate="2019-05-02T10:00:00Z"/>
+            <rdf:Description>
+                <dc:title><rdf:Alt><rdf:li xml:lang="x-default">The Title</rdf:li></rdf:Alt></dc:title>
+                <dc:creator><rdf:Seq><rdf:li>Alice</rdf:li><rdf:li>Bob</rdf:li></rdf:Seq></dc:creator>
+                <pdf:Producer>Some &amp; Tool</pdf:Producer>
+            </rdf:Description>
+        </rdf:RDF></x:xmpmeta>"#;
+        let root = XmlParser::new(text).parse();
+        assert_eq!(xmp_values(&root, "dc:title"), vec!["The Title"]);
+        assert_eq!(xmp_values(&root, "dc:creator"), vec!["Alice", "Bob"]);
+        assert_eq!(xmp_values(&root, "xmp:CreateDate"), vec!["2019-05-02T10:00:00Z"]);
+        assert_eq!(xmp_values(&root, "pdf:Producer"), vec!["Some & Tool"]);
+        assert!(xmp_values(&root, "dc:subject").is_empty());
+    }
+
+    #[test]
+    fn xmp_packets() {
+        let first = b"<x:xmpmeta><dc:title>First</dc:title></x:xmpmeta>";
+        let last = b"<x:xmpmeta><dc:title>Last</dc:title></x:xmpmeta>";
+        let mut buf = b"%PDF-1.7 ".to_vec();
+        buf.extend_from_slice(first);
+        buf.extend_from_slice(b" stream ");
+        buf.extend_from_slice(last);
+        buf.extend_from_slice(b" %%EOF");
+        assert_eq!(last_xmp_packet(&buf), Some(&last[..]));
+        // A packet cut by the end of the window.
+        assert_eq!(last_xmp_packet(&buf[..buf.len() - 20]), None);
+        assert_eq!(last_xmp_packet(b"%PDF-1.7 %%EOF"), None);
+    }
+}
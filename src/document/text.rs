@@ -0,0 +1,229 @@
+use std::io::Read;
+use std::fs::File;
+use std::path::Path;
+use std::str;
+use failure::{Error, format_err};
+use crate::metadata::{Metadata, normalize_year};
+
+// The number of bytes read at the beginning of the file.
+const HEAD_SIZE: u64 = 4096;
+const MAX_TITLE_LENGTH: usize = 96;
+const UTF_8_BOM: &[u8] = b"\xEF\xBB\xBF";
+const FIELDS: [&str; 5] = ["title", "author", "authors", "date", "year"];
+
+// The metadata found at the beginning of a plain text or Markdown document.
+#[derive(Debug, Clone, Default)]
+pub struct TextMetadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub date: Option<String>,
+}
+
+impl TextMetadata {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<TextMetadata, Error> {
+        let file = File::open(path)?;
+        let mut buf = Vec::new();
+        file.take(HEAD_SIZE).read_to_end(&mut buf)?;
+        let text = decode_head(&buf).ok_or_else(|| format_err!("binary content"))?;
+        Ok(TextMetadata::from_text(text))
+    }
+
+    pub fn from_text(text: &str) -> TextMetadata {
+        let mut metadata = TextMetadata::default();
+        let mut lines = text.lines().peekable();
+
+        match lines.peek().map(|line| line.trim_end()) {
+            // YAML front matter.
+            Some("---") => {
+                lines.next();
+                let mut key = String::new();
+                while let Some(line) = lines.next() {
+                    let line = line.trim_end();
+                    if line == "---" || line == "..." {
+                        break;
+                    }
+                    if line.trim_start().starts_with("- ") {
+                        // An item of a list, such as the authors.
+                        if key == "author" || key == "authors" {
+                            append(&mut metadata.author, unquote(&line.trim_start()[2..]));
+                        }
+                    } else if let Some((k, v)) = split_field(line) {
+                        key = k;
+                        metadata.set_field(&key, unquote(v));
+                    }
+                }
+            },
+            // Pandoc title block.
+            Some(line) if line.starts_with('%') => {
+                let mut fields = Vec::new();
+                while let Some(line) = lines.peek().filter(|line| line.starts_with('%')) {
+                    fields.push(line[1..].trim().to_string());
+                    lines.next();
+                }
+                let mut fields = fields.into_iter().map(|f| Some(f).filter(|f| !f.is_empty()));
+                metadata.title = fields.next().and_then(|f| f);
+                metadata.author = fields.next().and_then(|f| f).map(|f| f.split(';').map(str::trim)
+                                                                        .collect::<Vec<&str>>().join(", "));
+                metadata.date = fields.next().and_then(|f| f);
+            },
+            // MultiMarkdown metadata.
+            Some(line) if split_field(line).map_or(false, |(key, _)| FIELDS.contains(&key.as_str())) => {
+                while let Some((key, value)) = lines.peek().and_then(|line| split_field(line)) {
+                    metadata.set_field(&key, value.to_string());
+                    lines.next();
+                }
+            },
+            _ => (),
+        }
+
+        if metadata.title.is_none() {
+            let rest = lines.map(str::trim).filter(|line| !line.is_empty()).collect::<Vec<&str>>();
+            metadata.title = rest.iter().find(|line| line.starts_with('#'))
+                                 .map(|line| line.trim_matches('#').trim())
+                                 .or_else(|| rest.first().cloned())
+                                 .filter(|title| !title.is_empty())
+                                 .map(cap_length);
+        }
+
+        metadata
+    }
+
+    fn set_field(&mut self, key: &str, value: String) {
+        if value.is_empty() {
+            return;
+        }
+        match key {
+            "title" => self.title = Some(cap_length(&value)),
+            "author" | "authors" => append(&mut self.author, value),
+            "date" | "year" => self.date = Some(value),
+            _ => (),
+        }
+    }
+}
+
+// Returns the text at the beginning of the buffer, unless it looks like binary data.
+fn decode_head(buf: &[u8]) -> Option<&str> {
+    let buf = if buf.starts_with(UTF_8_BOM) { &buf[UTF_8_BOM.len()..] } else { buf };
+    if buf.contains(&0) {
+        return None;
+    }
+    match str::from_utf8(buf) {
+        Ok(text) => Some(text),
+        // The end of the buffer might cut a character in half.
+        Err(e) if e.error_len().is_none() => str::from_utf8(&buf[..e.valid_up_to()]).ok(),
+        Err(..) => None,
+    }
+}
+
+fn split_field(line: &str) -> Option<(String, &str)> {
+    let index = line.find(':')?;
+    let key = &line[..index];
+    if key.is_empty() || !key.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == ' ') {
+        return None;
+    }
+    Some((key.trim().to_lowercase(), line[index+1..].trim()))
+}
+
+fn unquote(value: &str) -> String {
+    value.trim().trim_matches(|c| c == '"' || c == '\'').trim().to_string()
+}
+
+fn append(field: &mut Option<String>, value: String) {
+    if value.is_empty() {
+        return;
+    }
+    match field {
+        Some(text) => {
+            text.push_str(", ");
+            text.push_str(&value);
+        },
+        None => *field = Some(value),
+    }
+}
+
+fn cap_length(title: &str) -> String {
+    match title.char_indices().nth(MAX_TITLE_LENGTH) {
+        Some((index, _)) => title[..index].trim_end().to_string(),
+        None => title.to_string(),
+    }
+}
+
+pub fn extract_metadata_from_text(dir: &Path, metadata: &mut Metadata) {
+    for info in metadata {
+        if !info.title.is_empty() || !["txt", "md", "markdown"].contains(&info.file.kind.as_str()) {
+            continue;
+        }
+
+        let path = dir.join(&info.file.path);
+
+        match TextMetadata::new(&path) {
+            Ok(md) => {
+                info.title = md.title.unwrap_or_default();
+                info.author = md.author.unwrap_or_default();
+                info.year = md.date.map(|date| normalize_year(&date)).unwrap_or_default();
+                println!("{}", info.label());
+            },
+            Err(e) => eprintln!("{}: {}.", info.file.path.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::metadata::{Info, FileInfo};
+    use crate::document::file_kind;
+
+    #[test]
+    fn headers() {
+        let md = TextMetadata::from_text("---\ntitle: \"Linear Algebra\"\nauthor:\n  - Jane Doe\n  - John Roe\ndate: 2019-09-02\n---\n# Lecture 1\n");
+        assert_eq!(md.title.as_deref(), Some("Linear Algebra"));
+        assert_eq!(md.author.as_deref(), Some("Jane Doe, John Roe"));
+        assert_eq!(md.date.as_deref(), Some("2019-09-02"));
+
+        let md = TextMetadata::from_text("Title: Topology\nAuthor: Jane Doe\n\nSome: text\n");
+        assert_eq!(md.title.as_deref(), Some("Topology"));
+        assert_eq!(md.author.as_deref(), Some("Jane Doe"));
+
+        let md = TextMetadata::from_text("% Analysis\n% Jane Doe; John Roe\n% 2020\n\nBody.\n");
+        assert_eq!(md.title.as_deref(), Some("Analysis"));
+        assert_eq!(md.author.as_deref(), Some("Jane Doe, John Roe"));
+        assert_eq!(md.date.as_deref(), Some("2020"));
+
+        let md = TextMetadata::from_text("Chapter 1: Sets\n\nA set is...\n");
+        assert_eq!(md.title.as_deref(), Some("Chapter 1: Sets"));
+
+        let md = TextMetadata::from_text("\n\nIntroduction\n\n## Groups ##\n");
+        assert_eq!(md.title.as_deref(), Some("Groups"));
+        let md = TextMetadata::from_text("\n  Notes on rings  \n\nRings are...\n");
+        assert_eq!(md.title.as_deref(), Some("Notes on rings"));
+        let md = TextMetadata::from_text(&"a".repeat(300));
+        assert_eq!(md.title.map(|t| t.len()), Some(MAX_TITLE_LENGTH));
+    }
+
+    #[test]
+    fn binary_content() {
+        assert_eq!(decode_head(b"\xEF\xBB\xBF# Title"), Some("# Title"));
+        assert_eq!(decode_head("Caf\u{e9}".as_bytes()), Some("Caf\u{e9}"));
+        assert_eq!(decode_head(&"Caf\u{e9}".as_bytes()[..4]), Some("Caf"));
+        assert_eq!(decode_head(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), None);
+        assert_eq!(decode_head(b"\xFF\xFEa\xFF"), None);
+    }
+
+    #[test]
+    fn text_metadata() {
+        let info = |name: &str| Info {
+            file: FileInfo { path: PathBuf::from(name), kind: file_kind(name).unwrap(), .. Default::default() },
+            .. Default::default()
+        };
+        let mut md = vec![info("front-matter.md"), info("bom.txt"), info("binary.txt")];
+        extract_metadata_from_text(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/text")), &mut md);
+        assert_eq!(md[0].title, "Measure Theory — Lecture Notes");
+        assert_eq!(md[0].author, "Jane Doe");
+        assert_eq!(md[0].year, "2021");
+        assert_eq!(md[1].title, "Notes de cours : topologie");
+        assert_eq!(md[1].author, "");
+        assert_eq!(md[2].title, "");
+    }
+}
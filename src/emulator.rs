@@ -17,6 +17,7 @@ mod frontlight;
 mod lightsensor;
 mod symbolic_path;
 mod trash;
+mod watcher;
 mod rtc;
 mod app;
 
@@ -51,8 +52,8 @@ use crate::view::calculator::Calculator;
 use crate::view::sketch::Sketch;
 use crate::view::common::{locate, locate_by_id, transfer_notifications, overlapping_rectangle};
 use crate::view::common::{toggle_input_history_menu, toggle_keyboard_layout_menu};
-use crate::helpers::{load_json, save_json, load_toml, save_toml};
-use crate::metadata::{Metadata, METADATA_FILENAME, auto_import};
+use crate::helpers::{load_toml, save_toml};
+use crate::metadata::{MetadataError, METADATA_FILENAME, auto_import, load_metadata, save_metadata};
 use crate::settings::{Settings, SETTINGS_PATH};
 use crate::geom::Rectangle;
 use crate::gesture::gesture_events;
@@ -71,7 +72,16 @@ const CLOCK_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
 pub fn build_context(fb: Box<dyn Framebuffer>) -> Result<Context, Error> {
     let settings = load_toml::<Settings, _>(SETTINGS_PATH)?;
     let path = settings.library_path.join(METADATA_FILENAME);
-    let mut metadata = load_json::<Metadata, _>(path)?;
+    let mut metadata = match load_metadata(&path) {
+        Ok((metadata, restoration)) => {
+            if let Some(restoration) = restoration {
+                restoration.report();
+            }
+            metadata
+        },
+        Err(MetadataError::Missing) => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
     if settings.import.startup_trigger {
         let imported_metadata = auto_import(&settings.library_path,
                                             &metadata,
@@ -198,6 +208,9 @@ impl Framebuffer for WindowCanvas {
     fn set_monochrome(&mut self, _enable: bool) {
     }
 
+    fn set_dithered(&mut self, _enable: bool) {
+    }
+
     fn inverted(&self) -> bool {
         false
     }
@@ -206,6 +219,10 @@ impl Framebuffer for WindowCanvas {
         false
     }
 
+    fn dithered(&self) -> bool {
+        false
+    }
+
     fn dims(&self) -> (u32, u32) {
         self.window().size()
     }
@@ -476,7 +493,7 @@ pub fn run() -> Result<(), Error> {
                                                   msg, &tx, &mut context);
                     view.children_mut().push(Box::new(notif) as Box<dyn View>);
                 },
-                Event::AddDocument(..) | Event::RemoveDocument(..) => {
+                Event::AddDocument(..) | Event::RemoveDocument(..) | Event::LibraryChanged(..) => {
                     if view.is::<Home>() {
                         view.handle_event(&evt, &tx, &mut bus, &mut context);
                     } else {
@@ -507,7 +524,9 @@ pub fn run() -> Result<(), Error> {
                     };
                 },
                 Event::Device(DeviceEvent::RotateScreen(n)) => {
-                    tx.send(Event::Select(EntryId::Rotate(n))).ok();
+                    if !view.rotation_locked() {
+                        tx.send(Event::Select(EntryId::Rotate(n))).ok();
+                    }
                 },
                 Event::Select(EntryId::Quit) => {
                     break 'outer;
@@ -536,7 +555,7 @@ pub fn run() -> Result<(), Error> {
     }
 
     let path = context.settings.library_path.join(&context.filename);
-    save_json(&context.metadata, path).context("Can't save metadata.")?;
+    save_metadata(&context.metadata, &path).context("Can't save metadata.")?;
 
     let path = Path::new(SETTINGS_PATH);
     save_toml(&context.settings, path).context("Can't save settings.")?;
@@ -30,6 +30,7 @@ extern {
     pub fn hb_buffer_add_utf8(buf: *mut HbBuffer, txt: *const libc::c_char, len: libc::c_int, offset: libc::c_uint, ilen: libc::c_int);
     pub fn hb_buffer_set_direction(buf: *mut HbBuffer, dir: HbDirection);
     pub fn hb_buffer_guess_segment_properties(buf: *mut HbBuffer);
+    pub fn hb_buffer_reverse(buf: *mut HbBuffer);
     pub fn hb_shape(font: *mut HbFont, buf: *mut HbBuffer, features: *const HbFeature, features_count: libc::c_uint);
     pub fn hb_feature_from_string(s: *const libc::c_char, len: libc::c_int, feature: *mut HbFeature) -> HbBool;
     pub fn hb_buffer_get_length(buf: *mut HbBuffer) -> libc::c_uint;
@@ -17,6 +17,7 @@ use failure::{Error, Fail, format_err};
 use glob::glob;
 use crate::geom::Point;
 use crate::framebuffer::Framebuffer;
+use crate::metadata::Direction;
 
 // Font sizes in 1/64th of a point
 pub const FONT_SIZES: [u32; 3] = [349, 524, 629];
@@ -973,6 +974,10 @@ pub struct Font {
     font: *mut HbFont,
     size: u32,
     dpi: u16,
+    // Synthetic emboldening strength, in ems.
+    emboldening: f32,
+    // The direction of the paragraphs.
+    direction: Direction,
     // used as truncation mark
     pub ellipsis: RenderPlan,
     // lowercase and uppercase x heights
@@ -1005,8 +1010,8 @@ impl FontOpener {
             let ellipsis = RenderPlan::default();
             let x_heights = (0, 0);
             let space_codepoint = FT_Get_Char_Index(face, ' ' as libc::c_ulong);
-            Ok(Font { lib: self.0.clone(), face, font,
-                      size: 0, dpi: 0, ellipsis, x_heights, space_codepoint })
+            Ok(Font { lib: self.0.clone(), face, font, size: 0, dpi: 0,
+                      emboldening: 0.0, direction: Direction::Ltr, ellipsis, x_heights, space_codepoint })
         }
     }
 
@@ -1021,8 +1026,8 @@ impl FontOpener {
             let font = ptr::null_mut();
             let x_heights = (0, 0);
             let space_codepoint = FT_Get_Char_Index(face, ' ' as libc::c_ulong);
-            Ok(Font { lib: self.0.clone(), face, font,
-                      size: 0, dpi: 0, ellipsis, x_heights, space_codepoint })
+            Ok(Font { lib: self.0.clone(), face, font, size: 0, dpi: 0,
+                      emboldening: 0.0, direction: Direction::Ltr, ellipsis, x_heights, space_codepoint })
         }
     }
 }
@@ -1048,6 +1053,17 @@ impl Font {
         }
     }
 
+    // The glyphs are thickened by the given strength, in ems, when rendered.
+    // The metrics are left unchanged.
+    pub fn set_emboldening(&mut self, strength: f32) {
+        self.emboldening = strength.max(0.0);
+    }
+
+    // The right to left words are then shaped in their own direction.
+    pub fn set_direction(&mut self, direction: Direction) {
+        self.direction = direction;
+    }
+
     pub fn set_size(&mut self, size: u32, dpi: u16) {
         if !self.font.is_null() && self.size == size && self.dpi == dpi {
             return;
@@ -1175,7 +1191,7 @@ impl Font {
     }
 
     #[inline]
-    unsafe fn patch(&mut self, txt: &str, features: &[HbFeature], render_plan: &mut RenderPlan, missing_glyphs: Vec<(usize, usize)>, buf: *mut HbBuffer) {
+    unsafe fn patch(&mut self, txt: &str, features: &[HbFeature], render_plan: &mut RenderPlan, missing_glyphs: Vec<(usize, usize)>, logical: bool, buf: *mut HbBuffer) {
         let mut drift = 0;
         for (mut start, mut end) in missing_glyphs.into_iter() {
             start = (start as i32 + drift).max(0) as usize;
@@ -1201,6 +1217,9 @@ impl Font {
             FT_Set_Pixel_Sizes(face, (*(*self.face).size).metrics.x_ppem as libc::c_uint, 0);
             let font = hb_ft_font_create(face, ptr::null());
             hb_shape(font, buf, features.as_ptr(), features.len() as libc::c_uint);
+            if logical && hb_buffer_get_direction(buf) == HB_DIRECTION_RTL {
+                hb_buffer_reverse(buf);
+            }
             let len = hb_buffer_get_length(buf) as usize;
             let info = hb_buffer_get_glyph_infos(buf, ptr::null_mut());
             let pos = hb_buffer_get_glyph_positions(buf, ptr::null_mut());
@@ -1233,8 +1252,11 @@ impl Font {
             hb_buffer_add_utf8(buf, txt.as_ptr() as *const libc::c_char,
                                txt.len() as libc::c_int, 0, -1);
 
+            // Unless the paragraphs are right to left, the direction isn't guessed from the script.
             // If the direction is RTL, the clusters are given in reverse order.
-            hb_buffer_set_direction(buf, HB_DIRECTION_LTR);
+            if self.direction == Direction::Ltr {
+                hb_buffer_set_direction(buf, HB_DIRECTION_LTR);
+            }
             hb_buffer_guess_segment_properties(buf);
 
             let features_vec: Vec<HbFeature> = features.map(|ftr|
@@ -1252,6 +1274,12 @@ impl Font {
             ).unwrap_or_default();
 
             hb_shape(self.font, buf, features_vec.as_ptr(), features_vec.len() as libc::c_uint);
+
+            // The glyphs are patched in logical order.
+            let reversed = hb_buffer_get_direction(buf) == HB_DIRECTION_RTL;
+            if reversed {
+                hb_buffer_reverse(buf);
+            }
  
             let len = hb_buffer_get_length(buf) as usize;
             let info = hb_buffer_get_glyph_infos(buf, ptr::null_mut());
@@ -1285,7 +1313,11 @@ impl Font {
                 render_plan.glyphs.push(glyph);
             }
 
-            self.patch(txt, &features_vec, &mut render_plan, missing_glyphs, buf);
+            self.patch(txt, &features_vec, &mut render_plan, missing_glyphs, reversed, buf);
+
+            if reversed {
+                render_plan.reverse();
+            }
 
             hb_buffer_destroy(buf);
 
@@ -1418,6 +1450,7 @@ impl Font {
         unsafe {
             let mut pos = origin;
             let mut fallback_faces = HashMap::new();
+            let strength = (self.emboldening * (*(*self.face).size).metrics.x_ppem as f32 * 64.0).round() as FtPos;
 
             for (index, glyph) in render_plan.glyphs.iter().enumerate() {
                 let face = if let Some(script) = render_plan.scripts.get(&index) {
@@ -1433,9 +1466,18 @@ impl Font {
                     self.face
                 };
 
-                FT_Load_Glyph(face, glyph.codepoint, FT_LOAD_RENDER | FT_LOAD_NO_HINTING);
-
                 let glyph_slot = (*face).glyph;
+
+                if strength > 0 {
+                    FT_Load_Glyph(face, glyph.codepoint, FT_LOAD_NO_HINTING);
+                    if (*glyph_slot).format == FT_GLYPH_FORMAT_OUTLINE {
+                        FT_Outline_Embolden(&mut (*glyph_slot).outline, strength);
+                    }
+                    FT_Render_Glyph(glyph_slot, FT_RENDER_MODE_NORMAL);
+                } else {
+                    FT_Load_Glyph(face, glyph.codepoint, FT_LOAD_RENDER | FT_LOAD_NO_HINTING);
+                }
+
                 let top_left = pos + glyph.offset + pt!((*glyph_slot).bitmap_left, -(*glyph_slot).bitmap_top);
                 let bitmap = &(*glyph_slot).bitmap;
 
@@ -1531,6 +1573,13 @@ impl RenderPlan {
         }
     }
 
+    // Switches between the logical and the visual order of the glyphs.
+    pub fn reverse(&mut self) {
+        let last = self.glyphs.len().saturating_sub(1);
+        self.scripts = self.scripts.drain().map(|(k, v)| (last - k, v)).collect();
+        self.glyphs.reverse();
+    }
+
     pub fn split_off(&mut self, index: usize, width: u32) -> RenderPlan {
         let mut next_scripts = HashMap::new();
         if !self.scripts.is_empty() {
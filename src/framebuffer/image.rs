@@ -101,6 +101,9 @@ impl Framebuffer for Pixmap {
     fn set_monochrome(&mut self, _enable: bool) {
     }
 
+    fn set_dithered(&mut self, _enable: bool) {
+    }
+
     fn inverted(&self) -> bool {
         false
     }
@@ -109,6 +112,10 @@ impl Framebuffer for Pixmap {
         false
     }
 
+    fn dithered(&self) -> bool {
+        false
+    }
+
     fn dims(&self) -> (u32, u32) {
         (self.width, self.height)
     }
@@ -33,6 +33,7 @@ pub struct KoboFramebuffer {
     token: u32,
     flags: u32,
     monochrome: bool,
+    dithered: bool,
     set_pixel_rgb: SetPixelRgb,
     get_pixel_rgb: GetPixelRgb,
     as_rgb: AsRgb,
@@ -77,6 +78,7 @@ impl KoboFramebuffer {
                    token: 1,
                    flags: 0,
                    monochrome: false,
+                   dithered: false,
                    set_pixel_rgb,
                    get_pixel_rgb,
                    as_rgb,
@@ -165,6 +167,18 @@ impl Framebuffer for KoboFramebuffer {
             waveform_mode = NTX_WFM_MODE_A2;
         }
 
+        let mut dither_mode = 0;
+        let mut quant_bit = 0;
+
+        if self.dithered {
+            if mark >= 7 {
+                dither_mode = EPDC_FLAG_USE_DITHERING_ORDERED as libc::c_int;
+                quant_bit = 7;
+            } else {
+                flags |= EPDC_FLAG_USE_DITHERING_Y4;
+            }
+        }
+
         let result = if mark >= 7 {
             let update_data = MxcfbUpdateDataV2 {
                 update_region: (*rect).into(),
@@ -173,8 +187,8 @@ impl Framebuffer for KoboFramebuffer {
                 update_marker,
                 temp: TEMP_USE_AMBIENT,
                 flags,
-                dither_mode: 0,
-                quant_bit: 0,
+                dither_mode,
+                quant_bit,
                 alt_buffer_data: MxcfbAltBufferDataV2::default(),
             };
             unsafe {
@@ -291,6 +305,14 @@ impl Framebuffer for KoboFramebuffer {
         self.monochrome
     }
 
+    fn set_dithered(&mut self, enable: bool) {
+        self.dithered = enable;
+    }
+
+    fn dithered(&self) -> bool {
+        self.dithered
+    }
+
     fn width(&self) -> u32 {
         self.var_info.xres
     }
@@ -37,8 +37,10 @@ pub trait Framebuffer {
     fn set_rotation(&mut self, n: i8) -> Result<(u32, u32), Error>;
     fn set_monochrome(&mut self, enable: bool);
     fn set_inverted(&mut self, enable: bool);
+    fn set_dithered(&mut self, enable: bool);
     fn monochrome(&self) -> bool;
     fn inverted(&self) -> bool;
+    fn dithered(&self) -> bool;
 
     fn toggle_inverted(&mut self) {
         self.set_inverted(!self.inverted());
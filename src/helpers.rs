@@ -59,6 +59,27 @@ pub fn decode_entities(text: &str) -> Cow<str> {
     Cow::Owned(buf)
 }
 
+pub fn encode_entities(text: &str) -> Cow<str> {
+    if !text.contains(|c| c == '&' || c == '<' || c == '>' || c == '"' || c == '\'') {
+        return Cow::Borrowed(text);
+    }
+
+    let mut buf = String::with_capacity(text.len() + 16);
+
+    for c in text.chars() {
+        match c {
+            '&' => buf.push_str("&amp;"),
+            '<' => buf.push_str("&lt;"),
+            '>' => buf.push_str("&gt;"),
+            '"' => buf.push_str("&quot;"),
+            '\'' => buf.push_str("&apos;"),
+            _ => buf.push(c),
+        }
+    }
+
+    Cow::Owned(buf)
+}
+
 pub fn load_json<T, P: AsRef<Path>>(path: P) -> Result<T, Error> where for<'a> T: Deserialize<'a> {
     let file = File::open(path).context("Can't open file.")?;
     serde_json::from_reader(file).context("Can't parse file.").map_err(Into::into)
@@ -122,19 +143,115 @@ impl AsciiExtension for char {
 }
 
 pub mod simple_date_format {
-    use chrono::{DateTime, Local, TimeZone};
-    use serde::{self, Deserialize, Serializer, Deserializer};
+    use chrono::{DateTime, NaiveDate, Local, TimeZone};
+    use serde::{Deserialize, Serializer, Deserializer};
 
     const FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+    const DATE_FORMAT: &str = "%Y-%m-%d";
 
     pub fn serialize<S>(date: &DateTime<Local>, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
         let s = format!("{}", date.format(FORMAT));
         serializer.serialize_str(&s)
     }
 
+    // A malformed date is read as unknown instead of failing the whole deserialization.
     pub fn deserialize<'de, D>(deserializer: D) -> Result<DateTime<Local>, D::Error> where D: Deserializer<'de> {
         let s = String::deserialize(deserializer)?;
-        Local.datetime_from_str(&s, FORMAT).map_err(serde::de::Error::custom)
+        Ok(parse(&s).unwrap_or_else(|| {
+            eprintln!("Invalid date: {}.", s);
+            unknown()
+        }))
+    }
+
+    // Stands for the missing or malformed dates, which aren't written back.
+    pub fn unknown() -> DateTime<Local> {
+        Local.timestamp(0, 0)
+    }
+
+    pub fn is_unknown(date: &DateTime<Local>) -> bool {
+        *date == unknown()
+    }
+
+    // Accepts the canonical format, RFC 3339 and dates without time.
+    pub fn parse(s: &str) -> Option<DateTime<Local>> {
+        let s = s.trim();
+        Local.datetime_from_str(s, FORMAT).ok()
+             .or_else(|| DateTime::parse_from_rfc3339(s).ok().map(|d| d.with_timezone(&Local)))
+             .or_else(|| NaiveDate::parse_from_str(s, DATE_FORMAT).ok()
+                                   .and_then(|d| Local.from_local_datetime(&d.and_hms(0, 0, 0)).earliest()))
+    }
+
+    pub mod option {
+        use chrono::{DateTime, Local};
+        use serde::{Deserialize, Serializer, Deserializer};
+
+        pub fn serialize<S>(date: &Option<DateTime<Local>>, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+            match date {
+                Some(date) => super::serialize(date, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        // A malformed date is dropped.
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<DateTime<Local>>, D::Error> where D: Deserializer<'de> {
+            Ok(Option::<String>::deserialize(deserializer)?.and_then(|s| super::parse(&s)))
+        }
+    }
+
+    pub mod vec {
+        use chrono::{DateTime, Local};
+        use serde::{Deserialize, Serializer, Deserializer};
+        use serde::ser::SerializeSeq;
+
+        pub fn serialize<S>(dates: &[DateTime<Local>], serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+            let mut seq = serializer.serialize_seq(Some(dates.len()))?;
+            for date in dates {
+                seq.serialize_element(&date.format(super::FORMAT).to_string())?;
+            }
+            seq.end()
+        }
+
+        // The malformed dates are dropped.
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<DateTime<Local>>, D::Error> where D: Deserializer<'de> {
+            Ok(Vec::<String>::deserialize(deserializer)?.iter().filter_map(|s| super::parse(s)).collect())
+        }
+    }
+}
+
+// A directory that's removed when dropped, even if the test that created it panics.
+#[cfg(test)]
+pub struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!("plato-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&path).ok();
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.0).ok();
     }
 }
 
@@ -150,5 +267,7 @@ mod tests {
         assert_eq!(decode_entities("a &#x003E; b"), "a > b");
         assert_eq!(decode_entities("a &#38; b"), "a & b");
         assert_eq!(decode_entities("a &lt; b &gt; c"), "a < b > c");
+        assert_eq!(encode_entities("a < b & \"c\""), "a &lt; b &amp; &quot;c&quot;");
+        assert_eq!(decode_entities(&encode_entities("<'&'>")), "<'&'>");
     }
 }
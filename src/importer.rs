@@ -18,15 +18,26 @@ use std::fs;
 use std::process;
 use std::io::Read;
 use std::path::Path;
+use std::sync::atomic::AtomicBool;
 use failure::{Error, ResultExt, format_err};
 use regex::Regex;
 use getopts::Options;
 use titlecase::titlecase;
 use crate::helpers::{load_json, save_json};
 use crate::settings::{ImportSettings, CategoryProvider};
-use crate::metadata::{Info, Metadata, METADATA_FILENAME, IMPORTED_MD_FILENAME};
-use crate::metadata::{import, extract_metadata_from_epub, extract_metadata_from_filename, clean_up};
-use crate::document::{open, asciify};
+use crate::metadata::{Info, Metadata, MergeStrategy, AnnotationStrategy, ExportFormat, METADATA_FILENAME, IMPORTED_MD_FILENAME};
+use crate::metadata::{import, extract_metadata_from_opf, extract_metadata_from_filename, count_pages};
+use crate::metadata::{assign_chapters, clean_up, merge_metadata, export_csv, export_opds, export_opf};
+use crate::metadata::{export_all_annotations, import_annotations, load_annotations, find_duplicate_works};
+use crate::document::{open, asciify, canonical_kind};
+use crate::document::epub::extract_metadata_from_epub;
+use crate::document::pdf::extract_metadata_from_pdf;
+use crate::document::djvu::extract_metadata_from_djvu;
+use crate::document::cbz::extract_metadata_from_cbz;
+use crate::document::fb2::extract_metadata_from_fb2;
+use crate::document::mobi::extract_metadata_from_mobi;
+use crate::document::text::extract_metadata_from_text;
+use crate::document::audio::extract_metadata_from_audio;
 
 fn run() -> Result<(), Error> {
     let args: Vec<String> = env::args().skip(1).collect();
@@ -35,24 +46,44 @@ fn run() -> Result<(), Error> {
 
     opts.optflag("h", "help", "Print this help message.");
     opts.optflag("I", "import", "Import new books.");
+    opts.optflag("S", "extract-metadata-opf", "Extract metadata from Calibre's OPF files.");
     opts.optflag("M", "extract-metadata-epub", "Extract metadata from ePUBs.");
+    opts.optflag("P", "extract-metadata-pdf", "Extract metadata from PDFs.");
+    opts.optflag("D", "extract-metadata-djvu", "Extract metadata from DjVu documents.");
+    opts.optflag("R", "extract-metadata-fb2", "Extract metadata from FictionBook documents.");
+    opts.optflag("K", "extract-metadata-mobi", "Extract metadata from MOBI and AZW3 documents.");
+    opts.optflag("T", "extract-metadata-text", "Extract metadata from plain text and Markdown documents.");
+    opts.optflag("B", "extract-metadata-cbz", "Extract metadata from comic book archives.");
+    opts.optflag("L", "extract-metadata-audio", "Extract metadata from MP3, M4B and Opus audiobooks.");
     opts.optflag("F", "extract-metadata-filename", "Extract metadata from filenames.");
     opts.optflag("C", "consolidate", "Consolidate an existing database.");
+    opts.optflag("H", "assign-chapters", "Record the chapter of each annotation.");
     opts.optflag("N", "rename", "Rename files based on their info.");
     opts.optflag("Y", "synchronize", "Synchronize libraries.");
     opts.optflag("U", "clean-up", "Remove entries with dangling paths.");
     opts.optflag("G", "merge", "Merge the imported entries into the library.");
+    opts.optflag("X", "export-opf", "Write a Calibre OPF file next to each book.");
+    opts.optflag("E", "export-csv", "Print the library catalog as CSV.");
+    opts.optflag("Q", "find-duplicates", "Print the groups of books that are likely the same work.");
     opts.optflag("Z", "initialize", "Initialize a database.");
+    opts.optflag("p", "count-pages", "Count the pages of the documents.");
     opts.optflag("t", "traverse-hidden", "Traverse hidden directories.");
     opts.optopt("a", "allowed-kinds", "Comma separated list of allowed kinds.", "ALLOWED_KINDS");
     opts.optopt("c", "category-providers", "Comma separated list of category providers.", "CATEGORY_PROVIDERS");
+    opts.optopt("s", "merge-strategy", "Which entry wins when merging: base, incoming or newest.", "STRATEGY");
+    opts.optopt("k", "annotations-strategy", "Which annotations to keep when importing: newer, replace or missing.", "STRATEGY");
+    opts.optopt("d", "path-category-depth", "Maximum depth of the categories derived from paths.", "DEPTH");
+    opts.optopt("O", "export-opds", "Print the library catalog as an OPDS feed.", "BASE_URL");
+    opts.optopt("W", "export-annotations", "Export the annotations of every book to a directory.", "DIRECTORY");
+    opts.optopt("A", "import-annotations", "Merge the annotations of a JSON export into the library.", "SOURCE");
+    opts.optopt("f", "annotations-format", "Format of the exported annotations: markdown, json, calibre-like or koreader.", "FORMAT");
     opts.optopt("i", "input", "Input file name.", "INPUT_NAME");
     opts.optopt("o", "output", "Output file name.", "OUTPUT_NAME");
 
     let matches = opts.parse(&args).context("Failed to parse the command line arguments.")?;
 
     if matches.opt_present("h") {
-        println!("{}", opts.usage("Usage: plato-import -h|-I|-M|-F|-C|-N|-U|-G|-Z|-Y [-t] [-a ALLOWED_KINDS] [-c CATEGORY_PROVIDERS] [-i INPUT_NAME] [-o OUTPUT_NAME] LIBRARY_PATH [DEST_LIBRARY_PATH]"));
+        println!("{}", opts.usage("Usage: plato-import -h|-I|-S|-M|-P|-D|-R|-K|-T|-B|-L|-F|-C|-H|-N|-U|-G|-X|-E|-Q|-Z|-Y [-t] [-p] [-a ALLOWED_KINDS] [-c CATEGORY_PROVIDERS] [-s STRATEGY] [-d DEPTH] [-O BASE_URL] [-W DIRECTORY] [-A SOURCE] [-f FORMAT] [-i INPUT_NAME] [-o OUTPUT_NAME] LIBRARY_PATH [DEST_LIBRARY_PATH]"));
         return Ok(());
     }
 
@@ -68,12 +99,16 @@ fn run() -> Result<(), Error> {
     let output_path = library_path.join(&output_name);
     let mut import_settings = ImportSettings::default();
     import_settings.traverse_hidden = matches.opt_present("t");
-    if let Some(allowed_kinds) = matches.opt_str("a").map(|v| v.split(',').map(|k| k.to_string()).collect()) {
+    import_settings.count_pages = matches.opt_present("p");
+    if let Some(allowed_kinds) = matches.opt_str("a").map(|v| v.split(',').map(canonical_kind).collect()) {
         import_settings.allowed_kinds = allowed_kinds;
     }
     if let Some(category_providers) = matches.opt_str("c").map(|v| v.split(',').filter_map(|k| CategoryProvider::from_str(k)).collect()) {
         import_settings.category_providers = category_providers;
     }
+    if let Some(depth) = matches.opt_str("d") {
+        import_settings.path_category_depth = Some(depth.parse::<usize>().context("Invalid path category depth.")?);
+    }
 
     if matches.opt_present("Z") {
         if input_path.exists() {
@@ -89,14 +124,60 @@ fn run() -> Result<(), Error> {
         let dest_library_path = matches.free.get(1).map(|s| Path::new(s))
                                        .unwrap_or(library_path);
         let dest_input_path = dest_library_path.join(input_name);
+        let strategy = match matches.opt_str("s") {
+            Some(s) => MergeStrategy::from_str(&s).ok_or_else(|| format_err!("Invalid merge strategy: {}.", s))?,
+            None => MergeStrategy::PreferBase,
+        };
         let mut metadata: Metadata = load_json(&dest_input_path)?;
-        let mut imported_metadata = load_json(&output_path)?;
-        metadata.append(&mut imported_metadata);
+        let imported_metadata = load_json(&output_path)?;
+        merge_metadata(&mut metadata, imported_metadata, strategy);
         save_json(&metadata, dest_input_path)?;
     } else if matches.opt_present("U") {
         let mut metadata = load_json(&input_path)?;
         clean_up(library_path, &mut metadata);
         save_json(&metadata, input_path)?;
+    } else if matches.opt_present("X") {
+        let metadata: Metadata = load_json(&input_path)?;
+        export_opf(library_path, &metadata);
+    } else if matches.opt_present("E") {
+        let metadata = load_json(&input_path)?;
+        print!("{}", export_csv(&metadata)?);
+    } else if matches.opt_present("Q") {
+        let metadata: Metadata = load_json(&input_path)?;
+        for (i, group) in find_duplicate_works(&metadata).iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+            for &index in group {
+                println!("{}", metadata[index].file.path.display());
+            }
+        }
+    } else if let Some(base_url) = matches.opt_str("O") {
+        let metadata = load_json(&input_path)?;
+        print!("{}", export_opds(&metadata, &base_url)?);
+    } else if let Some(directory) = matches.opt_str("W") {
+        let format = match matches.opt_str("f") {
+            Some(f) => ExportFormat::from_str(&f).ok_or_else(|| format_err!("Invalid annotations format: {}.", f))?,
+            None => ExportFormat::Markdown,
+        };
+        let metadata = load_json(&input_path)?;
+        let summary = export_all_annotations(library_path, &metadata, format, Path::new(&directory),
+                                             &AtomicBool::new(true))?;
+        println!("Exported {} annotations from {} books ({} up to date).",
+                 summary.annotations, summary.books, summary.skipped);
+    } else if let Some(source) = matches.opt_str("A") {
+        let strategy = match matches.opt_str("k") {
+            Some(s) => AnnotationStrategy::from_str(&s).ok_or_else(|| format_err!("Invalid annotations strategy: {}.", s))?,
+            None => AnnotationStrategy::KeepNewer,
+        };
+        let mut metadata: Metadata = load_json(&input_path)?;
+        let imported = match load_annotations(Path::new(&source)) {
+            Ok(imported) => imported,
+            Err(e) => return Err(e.context(format!("Can't import {}.", source)).into()),
+        };
+        let (books, annotations) = import_annotations(&mut metadata, &imported, strategy);
+        println!("Imported {} annotations into {} books.", annotations, books);
+        save_json(&metadata, input_path)?;
     } else if matches.opt_present("Y") {
         if matches.free.len() < 2 {
             return Err(format_err!("Missing required argument: destination library path."));
@@ -108,18 +189,60 @@ fn run() -> Result<(), Error> {
     } else {
         let mut metadata = load_json(&output_path)?;
 
+        if matches.opt_present("S") {
+            extract_metadata_from_opf(library_path, &mut metadata, &import_settings);
+        }
+
         if matches.opt_present("M") {
             extract_metadata_from_epub(library_path, &mut metadata, &import_settings);
         }
 
+        if matches.opt_present("P") {
+            extract_metadata_from_pdf(library_path, &mut metadata, &import_settings);
+        }
+
+        if matches.opt_present("D") {
+            extract_metadata_from_djvu(library_path, &mut metadata, &import_settings);
+        }
+
+        if matches.opt_present("R") {
+            extract_metadata_from_fb2(library_path, &mut metadata, &import_settings);
+        }
+
+        if matches.opt_present("K") {
+            extract_metadata_from_mobi(library_path, &mut metadata, &import_settings);
+        }
+
+        if matches.opt_present("T") {
+            extract_metadata_from_text(library_path, &mut metadata);
+        }
+
+        if matches.opt_present("B") {
+            extract_metadata_from_cbz(library_path, &mut metadata);
+        }
+
+        if matches.opt_present("L") {
+            extract_metadata_from_audio(library_path, &mut metadata);
+        }
+
         if matches.opt_present("F") {
-            extract_metadata_from_filename(&mut metadata);
+            extract_metadata_from_filename(&mut metadata, &import_settings);
+        }
+
+        if import_settings.count_pages {
+            count_pages(library_path, &mut metadata);
         }
 
         if matches.opt_present("C") {
             consolidate(&mut metadata);
         }
 
+        if matches.opt_present("H") {
+            for info in &mut metadata {
+                assign_chapters(info);
+            }
+        }
+
         if matches.opt_present("N") {
             rename(library_path, &mut metadata);
         }
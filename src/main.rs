@@ -16,6 +16,7 @@ mod symbolic_path;
 mod rtc;
 mod settings;
 mod trash;
+mod watcher;
 mod view;
 mod font;
 mod app;
@@ -0,0 +1,858 @@
+use std::fs;
+use std::fmt::Write;
+use std::path::Path;
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use fnv::FnvHashSet;
+use chrono::{Local, Utc, DateTime};
+use serde::Serialize;
+use failure::{Error, ResultExt};
+use crate::document::{BYTES_PER_PAGE, TextLocation};
+use crate::helpers::{simple_date_format, encode_entities};
+use crate::settings::ProgressMode;
+use super::{Info, Metadata, Annotation, AnnotationContext, HighlightStyle};
+use super::{chapter_at, opf_path, write_atomically};
+
+// The name of the file that holds all the annotations exported in JSON.
+const ANNOTATIONS_EXPORT_NAME: &str = "annotations";
+const FORBIDDEN_CHARS: &str = "/\\:*?\"<>|";
+const MAX_FILENAME_LENGTH: usize = 128;
+const RESERVED_FILENAMES: [&str; 22] = ["CON", "PRN", "AUX", "NUL",
+                                        "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+                                        "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9"];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+    // The layout of the annotations collections of Calibre's viewer. The positions are pages,
+    // not CFIs, so Calibre itself can't import it.
+    CalibreLike,
+    // The JSON layout of KOReader's exported notes.
+    KoReader,
+}
+
+impl ExportFormat {
+    pub fn from_str(s: &str) -> Option<ExportFormat> {
+        match s {
+            "markdown" | "md" => Some(ExportFormat::Markdown),
+            "json" => Some(ExportFormat::Json),
+            "calibre-like" => Some(ExportFormat::CalibreLike),
+            "koreader" => Some(ExportFormat::KoReader),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Json | ExportFormat::CalibreLike | ExportFormat::KoReader => "json",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AnnotationsExport<'a> {
+    title: String,
+    author: &'a str,
+    path: &'a Path,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    isbn: &'a str,
+    annotations: Vec<AnnotationExport<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    bookmarks: Vec<BookmarkExport<'a>>,
+}
+
+#[derive(Serialize)]
+struct BookmarkExport<'a> {
+    // Counted from one.
+    page: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct AnnotationExport<'a> {
+    id: &'a str,
+    text: &'a str,
+    note: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chapter: Option<&'a str>,
+    selection: [TextLocation; 2],
+    #[serde(with = "simple_date_format")]
+    created: DateTime<Local>,
+    #[serde(with = "simple_date_format")]
+    modified: DateTime<Local>,
+    style: HighlightStyle,
+    tags: &'a BTreeSet<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<&'a AnnotationContext>,
+}
+
+fn sorted_annotations(info: &Info) -> Vec<&Annotation> {
+    let mut annotations = info.reader.as_ref()
+                              .map(|r| r.annotations.iter().collect::<Vec<&Annotation>>())
+                              .unwrap_or_default();
+    annotations.sort_by_key(|annot| annot.selection);
+    annotations
+}
+
+fn chapter_of<'a>(info: &'a Info, annot: &'a Annotation) -> Option<&'a str> {
+    if !annot.chapter.is_empty() {
+        return Some(&annot.chapter);
+    }
+    info.toc.as_ref().and_then(|toc| chapter_at(toc, annot.selection[0].location()))
+}
+
+fn annotations_markdown(info: &Info) -> String {
+    const DATE_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+    let mut md = format!("# {}\n\n", info.title());
+    if !info.author.is_empty() {
+        md.push_str(&format!("*{}*\n\n", info.author));
+    }
+    for annot in sorted_annotations(info) {
+        md.push_str("---\n\n");
+        for line in annot.text.lines() {
+            md.push_str(&format!("> {}\n", line));
+        }
+        md.push('\n');
+        if !annot.note.is_empty() {
+            md.push_str(&format!("{}\n\n", annot.note));
+        }
+        if !annot.tags.is_empty() {
+            let tags = annot.tags.iter().map(|tag| format!("`{}`", tag)).collect::<Vec<String>>();
+            md.push_str(&format!("{}\n\n", tags.join(" ")));
+        }
+        let mut date = annot.created.format(DATE_FORMAT).to_string();
+        let modified = annot.modified.format(DATE_FORMAT).to_string();
+        if modified != date {
+            date = format!("{} (edited {})", date, modified);
+        }
+        if annot.style != HighlightStyle::default() {
+            date = format!("{} — {}", date, annot.style.name());
+        }
+        match chapter_of(info, annot) {
+            Some(chapter) => md.push_str(&format!("*{} — {}*\n\n", chapter, date)),
+            None => md.push_str(&format!("*{}*\n\n", date)),
+        }
+    }
+    let labels = info.reader.as_ref().map(|r| &r.bookmark_labels).filter(|labels| !labels.is_empty());
+    if let Some(labels) = labels {
+        md.push_str("---\n\n## Bookmarks\n\n");
+        for (page, label) in labels {
+            md.push_str(&format!("- Page {}: {}\n", page + 1, label));
+        }
+    }
+    md
+}
+
+fn annotations_export(info: &Info) -> AnnotationsExport {
+    AnnotationsExport {
+        title: info.title(),
+        author: &info.author,
+        path: &info.file.path,
+        isbn: &info.isbn,
+        annotations: sorted_annotations(info).into_iter().map(|annot| AnnotationExport {
+            id: &annot.id,
+            text: &annot.text,
+            note: &annot.note,
+            chapter: chapter_of(info, annot),
+            selection: annot.selection,
+            created: annot.created,
+            modified: annot.modified,
+            style: annot.style,
+            tags: &annot.tags,
+            context: annot.context.as_ref(),
+        }).collect(),
+        bookmarks: info.reader.as_ref().map(|r| r.bookmarks.iter().map(|&page| BookmarkExport {
+            page: page + 1,
+            label: r.bookmark_label(page),
+        }).collect()).unwrap_or_default(),
+    }
+}
+
+// A position as pages and offsets: the page, counted from one, and the index of the word
+// for static locations, or the offset of the word within the synthetic page for dynamic ones.
+#[derive(Serialize)]
+struct PagePosition {
+    page: usize,
+    offset: usize,
+}
+
+impl From<TextLocation> for PagePosition {
+    fn from(loc: TextLocation) -> PagePosition {
+        match loc {
+            TextLocation::Static(page, word) => PagePosition { page: page + 1, offset: word },
+            TextLocation::Dynamic(offset) => {
+                let page_size = BYTES_PER_PAGE as usize;
+                PagePosition { page: offset / page_size + 1, offset: offset % page_size }
+            },
+        }
+    }
+}
+
+fn utc_timestamp(date: DateTime<Local>) -> String {
+    date.with_timezone(&Utc).format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+// What the other formats can't represent.
+#[derive(Serialize)]
+struct PlatoExtra<'a> {
+    id: &'a str,
+    selection: [TextLocation; 2],
+    start: PagePosition,
+    end: PagePosition,
+    created: String,
+    style: HighlightStyle,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    tags: &'a BTreeSet<String>,
+}
+
+impl<'a> From<&'a Annotation> for PlatoExtra<'a> {
+    fn from(annot: &'a Annotation) -> PlatoExtra<'a> {
+        PlatoExtra {
+            id: &annot.id,
+            selection: annot.selection,
+            start: annot.selection[0].into(),
+            end: annot.selection[1].into(),
+            created: utc_timestamp(annot.created),
+            style: annot.style,
+            tags: &annot.tags,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Extra<'a> {
+    plato: PlatoExtra<'a>,
+}
+
+#[derive(Serialize)]
+struct CalibreCollection<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    version: u8,
+    annotations: Vec<CalibreAnnotation<'a>>,
+}
+
+#[derive(Serialize)]
+struct CalibreAnnotation<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    uuid: &'a str,
+    timestamp: String,
+    highlighted_text: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    notes: &'a str,
+    style: CalibreStyle,
+    toc_family_titles: Vec<&'a str>,
+    extra: Extra<'a>,
+}
+
+#[derive(Serialize)]
+struct CalibreStyle {
+    kind: &'static str,
+    #[serde(rename = "type")]
+    kind_type: &'static str,
+    which: &'static str,
+}
+
+// Calibre has no shades of gray, only colors and decorations.
+fn calibre_style(style: HighlightStyle) -> CalibreStyle {
+    let (kind, which) = match style {
+        HighlightStyle::Underline => ("decoration", "wavy"),
+        _ => ("color", "yellow"),
+    };
+    CalibreStyle { kind, kind_type: "builtin", which }
+}
+
+fn calibre_export(info: &Info) -> CalibreCollection {
+    CalibreCollection {
+        kind: "plato_annotation_collection",
+        version: 1,
+        annotations: sorted_annotations(info).into_iter().map(|annot| CalibreAnnotation {
+            kind: "highlight",
+            uuid: &annot.id,
+            timestamp: utc_timestamp(annot.modified),
+            highlighted_text: &annot.text,
+            notes: &annot.note,
+            style: calibre_style(annot.style),
+            toc_family_titles: chapter_of(info, annot).into_iter().collect(),
+            extra: Extra { plato: annot.into() },
+        }).collect(),
+    }
+}
+
+#[derive(Serialize)]
+struct KoReaderNotes<'a> {
+    title: String,
+    author: &'a str,
+    file: &'a Path,
+    number_of_pages: usize,
+    entries: Vec<[KoReaderClipping<'a>; 1]>,
+}
+
+#[derive(Serialize)]
+struct KoReaderClipping<'a> {
+    sort: &'static str,
+    page: usize,
+    time: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chapter: Option<&'a str>,
+    text: &'a str,
+    #[serde(skip_serializing_if = "str::is_empty")]
+    note: &'a str,
+    drawer: &'static str,
+    extra: Extra<'a>,
+}
+
+fn koreader_drawer(style: HighlightStyle) -> &'static str {
+    match style {
+        HighlightStyle::Gray | HighlightStyle::Light => "lighten",
+        HighlightStyle::Dark => "invert",
+        HighlightStyle::Underline => "underscore",
+    }
+}
+
+fn koreader_export(info: &Info) -> KoReaderNotes {
+    KoReaderNotes {
+        title: info.title(),
+        author: &info.author,
+        file: &info.file.path,
+        number_of_pages: info.pages_count().unwrap_or(0),
+        entries: sorted_annotations(info).into_iter().map(|annot| [KoReaderClipping {
+            sort: "highlight",
+            page: PagePosition::from(annot.selection[0]).page,
+            time: annot.modified.timestamp(),
+            chapter: chapter_of(info, annot),
+            text: &annot.text,
+            note: &annot.note,
+            drawer: koreader_drawer(annot.style),
+            extra: Extra { plato: annot.into() },
+        }]).collect(),
+    }
+}
+
+fn annotations_content(info: &Info, format: ExportFormat) -> Result<String, Error> {
+    Ok(match format {
+        ExportFormat::Markdown => annotations_markdown(info),
+        ExportFormat::Json => serde_json::to_string_pretty(&annotations_export(info))?,
+        ExportFormat::CalibreLike => serde_json::to_string_pretty(&calibre_export(info))?,
+        ExportFormat::KoReader => serde_json::to_string_pretty(&koreader_export(info))?,
+    })
+}
+
+// Writes the annotations of a book, in document order, to a new file in the *out* directory.
+pub fn export_annotations(info: &Info, format: ExportFormat, out: &Path) -> Result<(), Error> {
+    let content = annotations_content(info, format)?;
+
+    fs::create_dir_all(out)?;
+    let stem = info.file_stem();
+    let extension = format.extension();
+    let mut path = out.join(format!("{}.{}", stem, extension));
+    let mut index = 2;
+    while path.exists() {
+        path = out.join(format!("{} ({}).{}", stem, index, extension));
+        index += 1;
+    }
+    fs::write(&path, content)?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExportSummary {
+    pub books: usize,
+    pub annotations: usize,
+    // The books whose export, left by a previous run, was still up to date.
+    pub skipped: usize,
+    pub interrupted: bool,
+}
+
+// Makes a file name out of a title that is valid on the FAT file systems of SD cards.
+fn sanitize_filename(name: &str) -> String {
+    let mut name: String = name.chars()
+                               .map(|c| if c.is_control() || FORBIDDEN_CHARS.contains(c) { '_' } else { c })
+                               .collect();
+    if let Some((index, _)) = name.char_indices().nth(MAX_FILENAME_LENGTH) {
+        name.truncate(index);
+    }
+    let name = name.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    if name.is_empty() {
+        return "Untitled".to_string();
+    }
+    let base = name.split('.').next().unwrap().trim_end();
+    if RESERVED_FILENAMES.iter().any(|r| r.eq_ignore_ascii_case(base)) {
+        format!("_{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+// Exports the annotations of every annotated book to *out*, which is relative to *dir*.
+// In JSON, all the books are written at once to a single file. In the other formats,
+// each book gets its own file, named after its title, and the files that are newer than
+// the book's annotations are kept: an interrupted export resumes where it stopped.
+// The export stops, between two books, as soon as *running* is false.
+pub fn export_all_annotations(dir: &Path, metadata: &Metadata, format: ExportFormat, out: &Path, running: &AtomicBool) -> Result<ExportSummary, Error> {
+    let out = dir.join(out);
+    fs::create_dir_all(&out)?;
+    let mut summary = ExportSummary::default();
+    let mut names = FnvHashSet::default();
+    let mut exports = Vec::new();
+
+    for info in metadata {
+        let count = info.reader.as_ref().map_or(0, |r| r.annotations.len());
+        if count == 0 {
+            continue;
+        }
+
+        if !running.load(AtomicOrdering::Relaxed) {
+            summary.interrupted = true;
+            return Ok(summary);
+        }
+
+        if format == ExportFormat::Json {
+            exports.push(annotations_export(info));
+            summary.books += 1;
+            summary.annotations += count;
+            continue;
+        }
+
+        // Names are compared case-insensitively, like FAT does.
+        let stem = sanitize_filename(&info.title());
+        let mut name = stem.clone();
+        let mut index = 2;
+        while !names.insert(name.to_lowercase()) {
+            name = format!("{} ({})", stem, index);
+            index += 1;
+        }
+
+        let path = out.join(format!("{}.{}", name, format.extension()));
+        let last_modified = info.reader.as_ref()
+                                .and_then(|r| r.annotations.iter().map(|annot| annot.modified).max());
+        let exported = fs::metadata(&path).and_then(|m| m.modified()).ok()
+                           .map(DateTime::<Local>::from);
+        if exported.is_some() && exported >= last_modified {
+            summary.skipped += 1;
+            continue;
+        }
+
+        write_atomically(&path, &annotations_content(info, format)?)
+                        .with_context(|e| format!("Can't write {}: {}.", path.display(), e))?;
+        summary.books += 1;
+        summary.annotations += count;
+    }
+
+    if format == ExportFormat::Json {
+        let path = out.join(format!("{}.{}", ANNOTATIONS_EXPORT_NAME, format.extension()));
+        write_atomically(&path, &serde_json::to_string_pretty(&exports)?)?;
+    }
+
+    Ok(summary)
+}
+
+const CSV_HEADER: [&str; 14] = ["Title", "Author", "Series", "Number", "Year", "Publisher", "ISBN",
+                                "Language", "Categories", "Kind", "Size", "Status", "Progress", "Reading Time"];
+
+// Quotes the field when it contains a delimiter, a quote or a line break (RFC 4180).
+fn csv_field(field: &str) -> Cow<str> {
+    if field.contains(|c| c == ',' || c == '"' || c == '\r' || c == '\n') {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+fn csv_record(fields: &[&str]) -> String {
+    let mut record = fields.iter().map(|f| csv_field(f)).collect::<Vec<Cow<str>>>().join(",");
+    record.push_str("\r\n");
+    record
+}
+
+pub fn export_csv(metadata: &Metadata) -> Result<String, Error> {
+    let mut csv = csv_record(&CSV_HEADER);
+    for info in metadata {
+        let categories = info.categories.iter().map(String::as_str).collect::<Vec<&str>>().join("; ");
+        let size = info.file.size.to_string();
+        let status = info.simple_status().to_string();
+        let progress = format!("{:.3}", info.progress(ProgressMode::Current));
+        let reading_time = info.total_reading_time().to_string();
+        csv.push_str(&csv_record(&[&info.title, &info.author, &info.series, &info.number,
+                                   &info.year, &info.publisher, &info.isbn, &info.language,
+                                   &categories, &info.file.kind, &size, &status, &progress,
+                                   &reading_time]));
+    }
+    Ok(csv)
+}
+
+fn mime_type(kind: &str) -> &str {
+    match kind {
+        "epub" => "application/epub+zip",
+        "pdf" => "application/pdf",
+        "djvu" => "image/vnd.djvu",
+        "cbz" => "application/vnd.comicbook+zip",
+        "fb2" => "application/x-fictionbook+xml",
+        "xps" => "application/oxps",
+        _ => "application/octet-stream",
+    }
+}
+
+// Percent-encodes every byte of the path that isn't an unreserved URI character.
+fn url_path(path: &Path) -> String {
+    let mut buf = String::new();
+    for (i, component) in path.iter().enumerate() {
+        if i > 0 {
+            buf.push('/');
+        }
+        for b in component.to_string_lossy().bytes() {
+            if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                buf.push(b as char);
+            } else {
+                buf.push_str(&format!("%{:02X}", b));
+            }
+        }
+    }
+    buf
+}
+
+// Builds an OPDS acquisition feed, with one entry per book.
+pub fn export_opds(metadata: &Metadata, base_url: &str) -> Result<String, Error> {
+    let base_url = base_url.trim_end_matches('/');
+    let mut feed = String::new();
+    writeln!(feed, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(feed, r#"<feed xmlns="http://www.w3.org/2005/Atom" xmlns:dc="http://purl.org/dc/terms/" xmlns:opds="http://opds-spec.org/2010/catalog">"#)?;
+    writeln!(feed, "  <id>{}</id>", encode_entities(base_url))?;
+    writeln!(feed, "  <title>Library</title>")?;
+    writeln!(feed, "  <updated>{}</updated>", Local::now().to_rfc3339())?;
+    writeln!(feed, r#"  <link rel="start" href="{}" type="application/atom+xml;profile=opds-catalog;kind=acquisition"/>"#,
+             encode_entities(base_url))?;
+    for info in metadata {
+        let url = format!("{}/{}", base_url, url_path(&info.file.path));
+        writeln!(feed, "  <entry>")?;
+        writeln!(feed, "    <title>{}</title>", encode_entities(&info.title()))?;
+        writeln!(feed, "    <id>{}</id>", encode_entities(&url))?;
+        writeln!(feed, "    <updated>{}</updated>", info.added.to_rfc3339())?;
+        if !info.author.is_empty() {
+            writeln!(feed, "    <author><name>{}</name></author>", encode_entities(&info.author))?;
+        }
+        if !info.year.is_empty() {
+            writeln!(feed, "    <dc:issued>{}</dc:issued>", encode_entities(&info.year))?;
+        }
+        if !info.language.is_empty() {
+            writeln!(feed, "    <dc:language>{}</dc:language>", encode_entities(&info.language))?;
+        }
+        for category in &info.categories {
+            let category = encode_entities(category);
+            writeln!(feed, r#"    <category term="{}" label="{}"/>"#, category, category)?;
+        }
+        writeln!(feed, r#"    <link rel="http://opds-spec.org/acquisition" href="{}" type="{}"/>"#,
+                 encode_entities(&url), mime_type(&info.file.kind))?;
+        writeln!(feed, "  </entry>")?;
+    }
+    writeln!(feed, "</feed>")?;
+    Ok(feed)
+}
+
+// Writes the OPF file of each book next to it. Returns the number of files written.
+pub fn export_opf(dir: &Path, metadata: &Metadata) -> usize {
+    let mut count = 0;
+    for info in metadata {
+        let path = dir.join(opf_path(&info.file.path));
+        match fs::write(&path, info.to_opf()) {
+            Ok(()) => count += 1,
+            Err(e) => eprintln!("Can't write {}: {}.", path.display(), e),
+        }
+    }
+    count
+}
+
+impl Info {
+    // Produces a *metadata.opf* file readable by Calibre.
+    pub fn to_opf(&self) -> String {
+        let mut opf = String::new();
+        opf.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        opf.push_str("<package xmlns=\"http://www.idpf.org/2007/opf\" unique-identifier=\"plato_id\" version=\"2.0\">\n");
+        opf.push_str("  <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:opf=\"http://www.idpf.org/2007/opf\">\n");
+        let mut element = |name: &str, attributes: &str, text: &str| {
+            if !text.is_empty() {
+                opf.push_str(&format!("    <{0}{1}>{2}</{0}>\n", name, attributes, encode_entities(text)));
+            }
+        };
+        element("dc:identifier", " id=\"plato_id\" opf:scheme=\"plato\"", &self.file.path.to_string_lossy());
+        element("dc:title", "", &self.title);
+        for author in self.author.split(", ") {
+            element("dc:creator", " opf:role=\"aut\"", author);
+        }
+        element("dc:date", "", &self.year);
+        element("dc:publisher", "", &self.publisher);
+        element("dc:description", "", &self.description);
+        element("dc:language", "", &self.language);
+        element("dc:identifier", " opf:scheme=\"ISBN\"", &self.isbn);
+        for category in &self.categories {
+            element("dc:subject", "", category);
+        }
+        if !self.series.is_empty() {
+            opf.push_str(&format!("    <meta name=\"calibre:series\" content=\"{}\"/>\n", encode_entities(&self.series)));
+            if !self.number.is_empty() {
+                opf.push_str(&format!("    <meta name=\"calibre:series_index\" content=\"{}\"/>\n", encode_entities(&self.number)));
+            }
+        }
+        opf.push_str("  </metadata>\n");
+        opf.push_str("</package>\n");
+        opf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use chrono::TimeZone;
+    use crate::helpers::{TempDir, decode_entities};
+    use crate::document::{SimpleTocEntry, TocLocation};
+    use crate::document::html::dom::Node;
+    use crate::document::html::xml::XmlParser;
+    use crate::metadata::{FileInfo, ReaderInfo};
+
+    #[test]
+    fn csv_export() {
+        let mut md = vec![
+            Info {
+                title: "Cats, Dogs and \"Other\" Animals".to_string(),
+                author: "Jane Doe".to_string(),
+                year: "1999".to_string(),
+                categories: ["Nature", "Pets"].iter().map(|c| c.to_string()).collect(),
+                file: FileInfo { path: PathBuf::from("cats.epub"), kind: "epub".to_string(), size: 1024, .. Default::default() },
+                reader: Some(ReaderInfo { current_page: 25, pages_count: 100, .. Default::default() }),
+                .. Default::default()
+            },
+            Info {
+                title: "Dune".to_string(),
+                series: "Dune".to_string(),
+                number: "1".to_string(),
+                file: FileInfo { path: PathBuf::from("dune.pdf"), kind: "pdf".to_string(), size: 2048, .. Default::default() },
+                .. Default::default()
+            },
+        ];
+        let csv = export_csv(&md).unwrap();
+        let lines: Vec<&str> = csv.split("\r\n").collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "Title,Author,Series,Number,Year,Publisher,ISBN,Language,Categories,Kind,Size,Status,Progress,Reading Time");
+        assert_eq!(lines[1], "\"Cats, Dogs and \"\"Other\"\" Animals\",Jane Doe,,,1999,,,,Nature; Pets,epub,1024,Reading,0.250,0");
+        assert_eq!(lines[2], "Dune,,Dune,1,,,,,,pdf,2048,New,0.000,0");
+        assert_eq!(lines[3], "");
+
+        md[1].title = "Line\nBreak".to_string();
+        md[1].reader = Some(ReaderInfo { finished: true, .. Default::default() });
+        let csv = export_csv(&md).unwrap();
+        assert!(csv.ends_with("\"Line\nBreak\",,Dune,1,,,,,,pdf,2048,Finished,1.000,0\r\n"));
+    }
+
+    #[test]
+    fn opds_export() {
+        let md = vec![
+            Info {
+                title: "Tom & Jerry <Unabridged>".to_string(),
+                author: "Joseph \"Joe\" Barbera".to_string(),
+                year: "1940".to_string(),
+                categories: ["Cartoons", "Cats & Mice"].iter().map(|c| c.to_string()).collect(),
+                file: FileInfo { path: PathBuf::from("Cartoons/Tom & Jerry.epub"), kind: "epub".to_string(), .. Default::default() },
+                .. Default::default()
+            },
+            Info {
+                title: "Dune".to_string(),
+                file: FileInfo { path: PathBuf::from("dune.pdf"), kind: "pdf".to_string(), .. Default::default() },
+                .. Default::default()
+            },
+        ];
+        let feed = export_opds(&md, "http://example.com/library/").unwrap();
+        assert!(feed.starts_with("<?xml"));
+        assert_eq!(feed.matches("<entry>").count(), 2);
+        assert_eq!(feed.matches("</entry>").count(), 2);
+        assert!(feed.contains("<title>Tom &amp; Jerry &lt;Unabridged&gt;</title>"));
+        assert!(feed.contains("<name>Joseph &quot;Joe&quot; Barbera</name>"));
+        assert!(feed.contains(r#"<category term="Cats &amp; Mice" label="Cats &amp; Mice"/>"#));
+        assert!(feed.contains(r#"href="http://example.com/library/Cartoons/Tom%20%26%20Jerry.epub" type="application/epub+zip""#));
+        assert!(feed.contains("<dc:issued>1940</dc:issued>"));
+
+        let root = XmlParser::new(&feed).parse();
+        let feed_node = root.find("feed").unwrap();
+        let entries: Vec<&Node> = feed_node.children().unwrap().iter()
+                                           .filter(|child| child.tag_name() == Some("entry")).collect();
+        assert_eq!(entries.len(), 2);
+        let title = entries[0].find("title").and_then(|t| t.child(0)).and_then(Node::text).unwrap();
+        assert_eq!(decode_entities(title), "Tom & Jerry <Unabridged>");
+    }
+
+    #[test]
+    fn annotations_export() {
+        let annotation = |start: usize, text: &str, note: &str| Annotation {
+            id: format!("walden-{}", start),
+            text: text.to_string(),
+            note: note.to_string(),
+            selection: [TextLocation::Static(start, 0), TextLocation::Static(start, 9)],
+            chapter: String::new(),
+            created: Local.ymd(2020, 3, 14).and_hms(15, 9, 0),
+            modified: Local.ymd(2020, 3, 14).and_hms(15, 9, 0),
+            style: HighlightStyle::default(),
+            tags: BTreeSet::new(),
+            context: None,
+            dangling: false,
+        };
+        let mut info = Info {
+            title: "Walden".to_string(),
+            author: "Henry David Thoreau".to_string(),
+            file: FileInfo { path: PathBuf::from("Thoreau/walden.pdf"), kind: "pdf".to_string(), .. Default::default() },
+            toc: Some(vec![SimpleTocEntry::Leaf("Economy".to_string(), TocLocation::Exact(2)),
+                           SimpleTocEntry::Container("Solitude".to_string(), TocLocation::Exact(40),
+                                                     vec![SimpleTocEntry::Leaf("Visitors".to_string(), TocLocation::Exact(50))])]),
+            reader: Some(ReaderInfo {
+                annotations: vec![annotation(55, "Second\nline", ""), annotation(1, "First", ""),
+                                  annotation(3, "Woods", "Why I went.")],
+                .. Default::default()
+            }),
+            .. Default::default()
+        };
+        info.reader.as_mut().unwrap().annotations[2].style = HighlightStyle::Dark;
+
+        let out = TempDir::new("annotations");
+        export_annotations(&info, ExportFormat::Markdown, &out).unwrap();
+        export_annotations(&info, ExportFormat::Markdown, &out).unwrap();
+        export_annotations(&info, ExportFormat::Json, &out).unwrap();
+        let md = fs::read_to_string(out.join("walden.md")).unwrap();
+        assert!(out.join("walden (2).md").exists());
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(out.join("walden.json")).unwrap()).unwrap();
+
+        assert!(md.starts_with("# Walden\n\n*Henry David Thoreau*\n\n"));
+        let first = md.find("> First").unwrap();
+        let woods = md.find("> Woods\n\nWhy I went.\n\n*Economy — 2020-03-14 15:09 — dark*").unwrap();
+        let second = md.find("> Second\n> line\n\n*Visitors — 2020-03-14 15:09*").unwrap();
+        assert!(first < woods && woods < second);
+        assert!(md.contains("> First\n\n*2020-03-14 15:09*"));
+
+        let annotations = json["annotations"].as_array().unwrap();
+        assert_eq!(json["title"], "Walden");
+        assert_eq!(annotations.len(), 3);
+        assert_eq!(annotations[1]["note"], "Why I went.");
+        assert_eq!(annotations[2]["chapter"], "Visitors");
+        assert!(annotations[0].get("chapter").is_none());
+        assert_eq!(annotations[0]["style"], "gray");
+        assert_eq!(annotations[1]["style"], "dark");
+    }
+
+    #[test]
+    fn bookmarks_export() {
+        let mut reader = ReaderInfo::default();
+        reader.add_bookmark(4, None);
+        reader.add_bookmark(9, Some("Chapter 2".to_string()));
+        reader.add_bookmark(120, Some("Appendix".to_string()));
+        let info = Info { title: "Walden".to_string(), reader: Some(reader), .. Default::default() };
+        let export = serde_json::to_value(&super::annotations_export(&info)).unwrap();
+        assert_eq!(export["bookmarks"][1]["page"], 10);
+        assert_eq!(export["bookmarks"][1]["label"], "Chapter 2");
+        assert!(export["bookmarks"][0].get("label").is_none());
+        assert!(annotations_markdown(&info).contains("## Bookmarks\n\n- Page 10: Chapter 2\n- Page 121: Appendix\n"));
+    }
+
+    #[test]
+    fn bulk_annotations_export() {
+        let annotated = |path: &str, title: &str, count: usize| Info {
+            title: title.to_string(),
+            file: FileInfo { path: PathBuf::from(path), kind: "epub".to_string(), .. Default::default() },
+            reader: Some(ReaderInfo {
+                annotations: (0..count).map(|i| Annotation {
+                    id: format!("{}-{}", path, i),
+                    text: format!("Passage {}", i),
+                    note: String::new(),
+                    selection: [TextLocation::Dynamic(i), TextLocation::Dynamic(i + 8)],
+                    chapter: String::new(),
+                    created: Local.ymd(2020, 3, 14).and_hms(15, 9, 0),
+                    modified: Local.ymd(2020, 3, 14).and_hms(15, 9, 0),
+                    style: HighlightStyle::default(),
+                    tags: BTreeSet::new(),
+                    context: None,
+                    dangling: false,
+                }).collect(),
+                .. Default::default()
+            }),
+            .. Default::default()
+        };
+        let md = vec![annotated("a.epub", "Either/Or: A Fragment", 2),
+                      annotated("b.epub", "Unread", 0),
+                      annotated("c.epub", "con", 1),
+                      annotated("d.epub", "Either/Or: a fragment", 3),
+                      annotated("e.epub", "...", 1)];
+
+        let dir = TempDir::new("bulk-annotations");
+        let out = Path::new("notes");
+        let stopped = AtomicBool::new(false);
+        let summary = export_all_annotations(&dir, &md, ExportFormat::Markdown, out, &stopped).unwrap();
+        assert!(summary.interrupted);
+        assert_eq!(summary.books, 0);
+
+        let running = AtomicBool::new(true);
+        let summary = export_all_annotations(&dir, &md, ExportFormat::Markdown, out, &running).unwrap();
+        assert_eq!((summary.books, summary.annotations, summary.skipped), (4, 7, 0));
+        let mut names: Vec<String> = fs::read_dir(dir.join(out)).unwrap()
+                                       .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+                                       .collect();
+        names.sort();
+        assert_eq!(names, vec!["Either_Or_ A Fragment.md", "Either_Or_ a fragment (2).md",
+                               "Untitled.md", "_con.md"]);
+
+        fs::remove_file(dir.join(out).join("_con.md")).unwrap();
+        let summary = export_all_annotations(&dir, &md, ExportFormat::Markdown, out, &running).unwrap();
+        assert_eq!((summary.books, summary.annotations, summary.skipped), (1, 1, 3));
+
+        let summary = export_all_annotations(&dir, &md, ExportFormat::Json, out, &running).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(dir.join(out).join("annotations.json")).unwrap()).unwrap();
+        assert_eq!(summary.books, 4);
+        assert_eq!(json.as_array().map(Vec::len), Some(4));
+        assert_eq!(json[3]["annotations"][0]["text"], "Passage 0");
+    }
+
+    #[test]
+    fn foreign_annotation_formats() {
+        let date = |day: u32| Local.from_utc_datetime(&chrono::NaiveDate::from_ymd(2020, 3, day).and_hms(14, 9, 0));
+        let annotation = |id: &str, sel: [TextLocation; 2], text: &str, note: &str, day: u32| Annotation {
+            id: id.to_string(),
+            text: text.to_string(),
+            note: note.to_string(),
+            selection: sel,
+            created: date(day),
+            modified: date(day + 1),
+            .. Default::default()
+        };
+        let mut info = Info {
+            title: "Walden".to_string(),
+            author: "Henry David Thoreau".to_string(),
+            file: FileInfo { path: PathBuf::from("Thoreau/walden.epub"), kind: "epub".to_string(), .. Default::default() },
+            toc: Some(vec![SimpleTocEntry::Leaf("Economy".to_string(), TocLocation::Exact(4096))]),
+            reader: Some(ReaderInfo {
+                pages_count: 240,
+                annotations: vec![annotation("b", [TextLocation::Dynamic(5000), TextLocation::Dynamic(5040)],
+                                             "I went to the woods", "Why I went.", 10),
+                                  annotation("a", [TextLocation::Dynamic(120), TextLocation::Dynamic(180)],
+                                             "When I wrote the following pages", "", 3)],
+                .. Default::default()
+            }),
+            .. Default::default()
+        };
+        {
+            let annotations = &mut info.reader.as_mut().unwrap().annotations;
+            annotations[0].style = HighlightStyle::Underline;
+            annotations[0].tags.insert("nature".to_string());
+        }
+
+        let goldens = [(ExportFormat::CalibreLike, concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/annotations/calibre-like.json")),
+                       (ExportFormat::KoReader, concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/annotations/koreader.json"))];
+        for &(format, golden) in &goldens {
+            let content = annotations_content(&info, format).unwrap();
+            let actual: serde_json::Value = serde_json::from_str(&content).unwrap();
+            let expected: serde_json::Value = serde_json::from_str(&fs::read_to_string(golden).unwrap()).unwrap();
+            assert_eq!(actual, expected, "{}", golden);
+        }
+    }
+}
@@ -0,0 +1,6143 @@
+mod export;
+
+use std::fs;
+use std::io;
+use std::mem;
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::ffi::OsStr;
+use std::borrow::Cow;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeSet, BTreeMap};
+use std::hash::Hasher;
+use fnv::{FnvHashMap, FnvHashSet, FnvHasher};
+use chrono::{Local, DateTime, Datelike, Timelike};
+use serde::{Serialize, Deserialize, Deserializer};
+use lazy_static::lazy_static;
+use septem::Roman;
+use regex::Regex;
+use rand::{Rng, thread_rng};
+use failure::{Error, Fail, ResultExt, format_err};
+use crate::document::{Document, Location, BoundedText, SimpleTocEntry, TocLocation, TextLocation, simplify_toc, open, asciify};
+use crate::document::djvu::extract_metadata_from_djvu;
+use crate::document::epub::extract_metadata_from_epub;
+use crate::document::cbz::extract_metadata_from_cbz;
+use crate::document::fb2::extract_metadata_from_fb2;
+use crate::document::mobi::extract_metadata_from_mobi;
+use crate::document::text::extract_metadata_from_text;
+use crate::document::audio::extract_metadata_from_audio;
+use crate::document::html::dom::Node;
+use crate::document::html::xml::XmlParser;
+use crate::document::pdf::{PdfOpener, extract_metadata_from_pdf};
+use crate::helpers::{simple_date_format, decode_entities, AsciiExtension};
+use crate::settings::{ImportSettings, CategoryProvider, ReaderDefaults, ProgressMode, ContrastPresetsSettings};
+use crate::document::{file_kind, canonical_kind};
+use crate::symbolic_path::{self, SymbolicPath};
+
+pub use self::export::{ExportFormat, export_annotations, export_all_annotations};
+pub use self::export::{export_csv, export_opds, export_opf};
+
+pub const METADATA_FILENAME: &str = ".metadata.json";
+pub const IMPORTED_MD_FILENAME: &str = ".metadata-imported.json";
+pub const TRASH_NAME: &str = ".trash";
+pub const ANNOTATIONS_DIRNAME: &str = ".annotations";
+// The pseudo-category of the books without categories, it isn't a valid category name.
+pub const UNCATEGORIZED: &str = "";
+// The inner extensions of *name.kepub.epub* and *name.fb2.zip*.
+const STEM_SUFFIXES: [&str; 2] = [".kepub", ".fb2"];
+// The number of characters kept on each side of a selection.
+const ANCHOR_CONTEXT_LENGTH: usize = 32;
+// The number of pages searched, on each side, for a drifted annotation.
+const RELOCATION_RADIUS: usize = 3;
+// The number of characters shown on each side of a match within an annotation.
+const EXCERPT_RADIUS: usize = 40;
+// Put between the notes of merged annotations.
+const NOTE_SEPARATOR: &str = "\n\n";
+// The markup that might separate two consecutive words of a reflowable document, in bytes.
+const WORD_GAP_SLACK: usize = 8;
+// The number of reading sessions kept per book, the oldest are dropped.
+const MAX_SESSIONS: usize = 256;
+
+pub const DEFAULT_CONTRAST_EXPONENT: f32 = 1.0;
+pub const DEFAULT_CONTRAST_GRAY: f32 = 224.0;
+pub const MIN_CONTRAST_EXPONENT: f32 = 1.0;
+pub const MAX_CONTRAST_EXPONENT: f32 = 5.0;
+pub const MAX_CONTRAST_GRAY: f32 = 255.0;
+// Font weights are CSS weights, the ones above the regular weight are synthesized.
+pub const MIN_FONT_WEIGHT: f32 = 400.0;
+pub const MAX_FONT_WEIGHT: f32 = 900.0;
+// In ems.
+pub const MAX_LETTER_SPACING: f32 = 0.5;
+// The custom zoom factors are relative to the width of the screen.
+pub const MIN_ZOOM_FACTOR: f32 = 0.5;
+pub const MAX_ZOOM_FACTOR: f32 = 4.0;
+// The name of the metadata files saved by Calibre alongside the books.
+pub const OPF_SIDECAR_NAME: &str = "metadata.opf";
+// The maximum number of entries of the imported tables of contents.
+pub const MAX_TOC_ENTRIES: usize = 2048;
+
+pub type Metadata = Vec<Info>;
+
+// Maps the paths of the entries to their indices. It's a snapshot of the metadata:
+// it must be rebuilt after entries are inserted, removed or reordered.
+pub type PathIndex = FnvHashMap<PathBuf, usize>;
+
+pub fn path_index(metadata: &Metadata) -> PathIndex {
+    metadata.iter().enumerate()
+            .map(|(index, info)| (info.file.path.clone(), index))
+            .collect()
+}
+
+// Maps the normalized ISBNs to the indices of the entries. When several entries share
+// an ISBN, the first one is indexed and the indices of the others are returned aside.
+pub fn index_by_isbn(metadata: &Metadata) -> (FnvHashMap<String, usize>, Vec<usize>) {
+    let mut index = FnvHashMap::default();
+    let mut duplicates = Vec::new();
+    for (i, info) in metadata.iter().enumerate() {
+        if let Some(isbn) = info.normalized_isbn() {
+            if index.contains_key(&isbn) {
+                duplicates.push(i);
+            } else {
+                index.insert(isbn, i);
+            }
+        }
+    }
+    (index, duplicates)
+}
+
+// Returns the ISBN-13 form of a valid ISBN-10 or ISBN-13, without separators.
+pub fn normalize_isbn(isbn: &str) -> Option<String> {
+    let digits: String = isbn.chars()
+                             .filter(|c| !c.is_whitespace() && *c != '-')
+                             .map(|c| c.to_ascii_uppercase())
+                             .collect();
+    match digits.len() {
+        10 => {
+            let sum = digits.chars().enumerate().try_fold(0, |sum, (i, c)| {
+                let value = match c {
+                    'X' if i == 9 => 10,
+                    _ => c.to_digit(10)?,
+                };
+                Some(sum + (10 - i as u32) * value)
+            })?;
+            if sum % 11 != 0 {
+                return None;
+            }
+            let mut isbn = format!("978{}", &digits[..9]);
+            isbn.push(isbn13_check_digit(&isbn)?);
+            Some(isbn)
+        },
+        13 => {
+            if !digits.starts_with("978") && !digits.starts_with("979") {
+                return None;
+            }
+            if isbn13_check_digit(&digits[..12])? != digits.chars().last()? {
+                return None;
+            }
+            Some(digits)
+        },
+        _ => None,
+    }
+}
+
+fn isbn13_check_digit(digits: &str) -> Option<char> {
+    let sum = digits.chars().enumerate().try_fold(0, |sum, (i, c)| {
+        Some(sum + if i % 2 == 0 { 1 } else { 3 } * c.to_digit(10)?)
+    })?;
+    std::char::from_digit((10 - sum % 10) % 10, 10)
+}
+
+pub fn find_by_path<'a>(metadata: &'a Metadata, path: &Path) -> Option<&'a Info> {
+    metadata.iter().find(|info| info.file.path == path)
+}
+
+pub fn find_by_path_mut<'a>(metadata: &'a mut Metadata, path: &Path) -> Option<&'a mut Info> {
+    metadata.iter_mut().find(|info| info.file.path == path)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Info {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub title: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub subtitle: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub author: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub narrator: String,
+    // The title of the work in its original language, for translations.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub original_title: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub translator: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub year: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub language: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub publisher: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub series: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub edition: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub volume: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub number: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub isbn: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub categories: BTreeSet<String>,
+    pub file: FileInfo,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reader: Option<ReaderInfo>,
+    // The template of the reader settings, until the book is first opened.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reader_defaults: Option<ReaderDefaults>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub toc: Option<Vec<SimpleTocEntry>>,
+    // The number of pages known before the document is opened, in the unit
+    // of `ReaderInfo::pages_count`, which takes precedence once it's known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pages: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_location: Option<TextLocation>,
+    #[serde(skip_serializing_if = "Option::is_none", with = "simple_date_format::option")]
+    pub modified: Option<DateTime<Local>>,
+    #[serde(default = "simple_date_format::unknown", skip_serializing_if = "simple_date_format::is_unknown", with = "simple_date_format")]
+    pub added: DateTime<Local>,
+}
+
+// The descriptive fields of `Info` edited at once: only the given ones are changed.
+#[derive(Debug, Clone, Default)]
+pub struct InfoPatch {
+    pub title: Option<String>,
+    pub subtitle: Option<String>,
+    pub author: Option<String>,
+    pub narrator: Option<String>,
+    pub original_title: Option<String>,
+    pub translator: Option<String>,
+    pub year: Option<String>,
+    pub language: Option<String>,
+    pub publisher: Option<String>,
+    pub series: Option<String>,
+    pub edition: Option<String>,
+    pub volume: Option<String>,
+    pub number: Option<String>,
+    pub isbn: Option<String>,
+    pub description: Option<String>,
+    pub categories: Option<BTreeSet<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct FileInfo {
+    pub path: PathBuf,
+    #[serde(deserialize_with = "deserialize_kind")]
+    pub kind: String,
+    pub size: u64,
+    // A digest of the file's content, when it has been computed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+}
+
+impl Default for FileInfo {
+    fn default() -> Self {
+        FileInfo {
+            path: PathBuf::default(),
+            kind: String::default(),
+            size: u64::default(),
+            checksum: None,
+        }
+    }
+}
+
+impl FileInfo {
+    // A short name for the files derived from this one, such as thumbnails.
+    // It only depends on the checksum, when known, so that it survives the moves of the file.
+    pub fn cache_key(&self) -> String {
+        let mut hasher = FnvHasher::default();
+        match self.checksum {
+            Some(ref checksum) => hasher.write(checksum.as_bytes()),
+            None => {
+                hasher.write(&self.size.to_le_bytes());
+                hasher.write(self.path.to_string_lossy().as_bytes());
+            },
+        }
+        format!("{:016x}", hasher.finish())
+    }
+}
+
+pub fn thumbnail_path(cache_dir: &Path, file: &FileInfo) -> PathBuf {
+    cache_dir.join(format!("{}.png", file.cache_key()))
+}
+
+// The defaults are given per field: the default annotation has a new identifier,
+// which would be wasted on the stored ones.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Annotation {
+    // Identifies the annotation across devices, whatever its later edits.
+    // Missing from older files, see *restore_legacy_fields*.
+    #[serde(default)]
+    pub id: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub note: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub text: String,
+    #[serde(default = "default_selection")]
+    pub selection: [TextLocation; 2],
+    // The title of the innermost chapter containing the start of the selection.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub chapter: String,
+    // Set once, when the highlight is made. Missing from older files.
+    #[serde(default = "simple_date_format::unknown", skip_serializing_if = "simple_date_format::is_unknown", with = "simple_date_format")]
+    pub created: DateTime<Local>,
+    #[serde(default = "simple_date_format::unknown", skip_serializing_if = "simple_date_format::is_unknown", with = "simple_date_format")]
+    pub modified: DateTime<Local>,
+    #[serde(default, skip_serializing_if = "HighlightStyle::is_default")]
+    pub style: HighlightStyle,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+    // The text around the selection, used to find it again after a layout change.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<AnnotationContext>,
+    // Set on annotations whose selection doesn't fit the document, or couldn't be found again.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub dangling: bool,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AnnotationContext {
+    pub before: String,
+    pub after: String,
+}
+
+// The ways a highlight can be drawn on a grayscale screen.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HighlightStyle {
+    Gray,
+    Light,
+    Dark,
+    Underline,
+}
+
+impl Default for HighlightStyle {
+    fn default() -> Self {
+        HighlightStyle::Gray
+    }
+}
+
+impl HighlightStyle {
+    pub const ALL: [HighlightStyle; 4] = [HighlightStyle::Gray, HighlightStyle::Light,
+                                          HighlightStyle::Dark, HighlightStyle::Underline];
+
+    pub fn from_str(s: &str) -> Option<HighlightStyle> {
+        HighlightStyle::ALL.iter().cloned().find(|style| style.name() == s)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HighlightStyle::Gray => "gray",
+            HighlightStyle::Light => "light",
+            HighlightStyle::Dark => "dark",
+            HighlightStyle::Underline => "underline",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            HighlightStyle::Gray => "Gray",
+            HighlightStyle::Light => "Light",
+            HighlightStyle::Dark => "Dark",
+            HighlightStyle::Underline => "Underline",
+        }
+    }
+
+    pub fn next(self) -> HighlightStyle {
+        let index = HighlightStyle::ALL.iter().position(|&style| style == self).unwrap();
+        HighlightStyle::ALL[(index + 1) % HighlightStyle::ALL.len()]
+    }
+
+    fn is_default(&self) -> bool {
+        *self == HighlightStyle::default()
+    }
+}
+
+impl Default for Annotation {
+    fn default() -> Self {
+        let now = Local::now();
+        Annotation {
+            id: new_annotation_id(),
+            note: String::new(),
+            text: String::new(),
+            selection: default_selection(),
+            chapter: String::new(),
+            created: now,
+            modified: now,
+            style: HighlightStyle::default(),
+            tags: BTreeSet::new(),
+            context: None,
+            dangling: false,
+        }
+    }
+}
+
+fn default_selection() -> [TextLocation; 2] {
+    [TextLocation::Dynamic(0), TextLocation::Dynamic(1)]
+}
+
+// The annotations are deserialized through this function wherever they're stored.
+fn deserialize_annotations<'de, D>(deserializer: D) -> Result<Vec<Annotation>, D::Error> where D: Deserializer<'de> {
+    let mut annotations = Vec::<Annotation>::deserialize(deserializer)?;
+    for annot in &mut annotations {
+        annot.restore_legacy_fields();
+    }
+    Ok(annotations)
+}
+
+// A random version 4 UUID.
+fn new_annotation_id() -> String {
+    let mut bytes: [u8; 16] = thread_rng().gen();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+// The identifier given to an annotation stored without one. It only depends on what
+// the stored annotation holds, so the copies of a file get the same identifiers.
+fn legacy_annotation_id(selection: [TextLocation; 2], text: &str, created: DateTime<Local>) -> String {
+    let mut hasher = FnvHasher::default();
+    // The JSON form of the selection doesn't depend on the width of `usize`.
+    hasher.write(serde_json::to_string(&selection).unwrap_or_default().as_bytes());
+    hasher.write(text.as_bytes());
+    hasher.write(created.format("%Y-%m-%d %H:%M:%S").to_string().as_bytes());
+    format!("{:016x}", hasher.finish())
+}
+
+// Whether the identifier was generated by *new_annotation_id*, rather than derived
+// from an older file or given by another application.
+fn is_generated_id(id: &str) -> bool {
+    id.len() == 36 && id.char_indices().all(|(i, c)| {
+        if [8, 13, 18, 23].contains(&i) { c == '-' } else { c.is_ascii_hexdigit() }
+    })
+}
+
+// The position of the annotation that matches *annot*: by identifier, or by *same*
+// when one of the two identifiers wasn't generated, since two generated identifiers
+// that differ designate different annotations.
+fn matching_annotation<F>(annotations: &[Annotation], annot: &Annotation, same: F) -> Option<usize>
+                         where F: Fn(&Annotation) -> bool {
+    let generated = is_generated_id(&annot.id);
+    annotations.iter().position(|a| a.id == annot.id)
+               .or_else(|| annotations.iter().position(|a| !(generated && is_generated_id(&a.id)) && same(a)))
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AnnotationDate {
+    Created,
+    Modified,
+}
+
+impl Annotation {
+    // Fills the fields that older files don't have: the creation date is the modification
+    // date, and the identifier is derived from the stored fields.
+    fn restore_legacy_fields(&mut self) {
+        if simple_date_format::is_unknown(&self.created) {
+            self.created = self.modified;
+        } else if simple_date_format::is_unknown(&self.modified) {
+            self.modified = self.created;
+        }
+        if self.id.is_empty() {
+            self.id = legacy_annotation_id(self.selection, &self.text, self.created);
+        }
+    }
+
+    pub fn date(&self, which: AnnotationDate) -> DateTime<Local> {
+        match which {
+            AnnotationDate::Created => self.created,
+            AnnotationDate::Modified => self.modified,
+        }
+    }
+
+    // Returns whether the tag wasn't already there.
+    pub fn add_tag(&mut self, tag: &str) -> bool {
+        let tag = normalize_tag(tag);
+        !tag.is_empty() && self.tags.insert(tag)
+    }
+
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        self.tags.remove(&normalize_tag(tag))
+    }
+}
+
+// Tags are lowercase, and their words are separated by single spaces.
+pub fn normalize_tag(tag: &str) -> String {
+    tag.split_whitespace().collect::<Vec<&str>>().join(" ").to_lowercase()
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+fn is_zero_usize(value: &usize) -> bool {
+    *value == 0
+}
+
+fn push_bounded(stack: &mut Vec<usize>, page: usize, max_depth: usize) {
+    if stack.last() != Some(&page) {
+        stack.push(page);
+    }
+    if stack.len() > max_depth {
+        let excess = stack.len() - max_depth;
+        stack.drain(..excess);
+    }
+}
+
+// The largest fraction of a page dimension that can be cropped.
+const MAX_CROPPED_FRACTION: f32 = 0.9;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Margin {
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+    pub left: f32,
+}
+
+impl Margin {
+    pub fn new(top: f32, right: f32, bottom: f32, left: f32) -> Margin {
+        Margin { top, right, bottom, left }.clamped()
+    }
+
+    // Brings each side within `0.0..=1.0` and shrinks opposite sides proportionally
+    // so that at least a tenth of the page remains in each dimension.
+    pub fn clamped(&self) -> Margin {
+        let side = |value: f32| if value.is_nan() { 0.0 } else { value.max(0.0).min(1.0) };
+        let pair = |a: f32, b: f32| {
+            let (a, b) = (side(a), side(b));
+            let sum = a + b;
+            if sum > MAX_CROPPED_FRACTION {
+                let factor = MAX_CROPPED_FRACTION / sum;
+                (a * factor, b * factor)
+            } else {
+                (a, b)
+            }
+        };
+        let (top, bottom) = pair(self.top, self.bottom);
+        let (left, right) = pair(self.left, self.right);
+        Margin { top, right, bottom, left }
+    }
+
+    // Moves each side *padding* closer to the edge of the page.
+    pub fn padded(&self, padding: f32) -> Margin {
+        let side = |value: f32| (value - padding).max(0.0);
+        Margin::new(side(self.top), side(self.right), side(self.bottom), side(self.left))
+    }
+
+    // Shrinks opposite sides proportionally so that they crop at most *max_fraction* of the page.
+    pub fn limited(&self, max_fraction: f32) -> Margin {
+        let max_fraction = max_fraction.max(0.0).min(MAX_CROPPED_FRACTION);
+        let pair = |a: f32, b: f32| {
+            let sum = a + b;
+            if sum > max_fraction {
+                (a * max_fraction / sum, b * max_fraction / sum)
+            } else {
+                (a, b)
+            }
+        };
+        let (top, bottom) = pair(self.top, self.bottom);
+        let (left, right) = pair(self.left, self.right);
+        Margin { top, right, bottom, left }
+    }
+}
+
+impl Default for Margin {
+    fn default() -> Margin {
+        Margin::new(0.0, 0.0, 0.0, 0.0)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PageScheme {
+    Any,
+    EvenOdd,
+    // The first page is cropped on its own, the others as in `EvenOdd`.
+    Cover,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CroppingMargins {
+    Any(Margin),
+    EvenOdd([Margin; 2]),
+    // The margins of the cover, of the even pages and of the odd pages.
+    CoverEvenOdd([Margin; 3]),
+    // The margins of the pages that don't follow the base margins.
+    Exceptions {
+        base: Box<CroppingMargins>,
+        #[serde(deserialize_with = "deserialize_page_exceptions")]
+        pages: BTreeMap<usize, Margin>,
+    },
+}
+
+impl CroppingMargins {
+    pub fn margin(&self, index: usize) -> &Margin {
+        match *self {
+            CroppingMargins::Any(ref margin) => margin,
+            CroppingMargins::EvenOdd(ref pair) => &pair[index % 2],
+            CroppingMargins::CoverEvenOdd(ref triple) => &triple[cover_even_odd_slot(index)],
+            CroppingMargins::Exceptions { ref base, ref pages } => pages.get(&index).unwrap_or_else(|| base.margin(index)),
+        }
+    }
+
+    pub fn margin_mut(&mut self, index: usize) -> &mut Margin {
+        match *self {
+            CroppingMargins::Any(ref mut margin) => margin,
+            CroppingMargins::EvenOdd(ref mut pair) => &mut pair[index % 2],
+            CroppingMargins::CoverEvenOdd(ref mut triple) => &mut triple[cover_even_odd_slot(index)],
+            CroppingMargins::Exceptions { ref mut base, ref mut pages } => {
+                match pages.get_mut(&index) {
+                    Some(margin) => margin,
+                    None => base.margin_mut(index),
+                }
+            },
+        }
+    }
+
+    // The exceptions are kept, only the base margins follow the new scheme.
+    pub fn apply(&mut self, index: usize, scheme: PageScheme) {
+        if let CroppingMargins::Exceptions { ref mut base, .. } = *self {
+            base.apply(index, scheme);
+            return;
+        }
+
+        let margin = self.margin(index).clamped();
+
+        match scheme {
+            PageScheme::Any => *self = CroppingMargins::Any(margin),
+            PageScheme::EvenOdd => *self = CroppingMargins::EvenOdd([margin.clone(), margin]),
+            PageScheme::Cover => *self = CroppingMargins::CoverEvenOdd([margin.clone(), margin.clone(), margin]),
+        }
+    }
+
+    pub fn has_exception(&self, index: usize) -> bool {
+        match *self {
+            CroppingMargins::Exceptions { ref pages, .. } => pages.contains_key(&index),
+            _ => false,
+        }
+    }
+
+    // Gives the page at *index* its own margin, initialized with its current one.
+    pub fn add_exception(&mut self, index: usize) {
+        let margin = self.margin(index).clone();
+        if let CroppingMargins::Exceptions { ref mut pages, .. } = *self {
+            pages.insert(index, margin);
+            return;
+        }
+        let base = mem::replace(self, CroppingMargins::Any(Margin::default()));
+        let mut pages = BTreeMap::new();
+        pages.insert(index, margin);
+        *self = CroppingMargins::Exceptions { base: Box::new(base), pages };
+    }
+
+    pub fn clear_exception(&mut self, index: usize) {
+        let is_empty = match *self {
+            CroppingMargins::Exceptions { ref mut pages, .. } => {
+                pages.remove(&index);
+                pages.is_empty()
+            },
+            _ => false,
+        };
+        if is_empty {
+            self.clear_exceptions();
+        }
+    }
+
+    pub fn clear_exceptions(&mut self) {
+        if let CroppingMargins::Exceptions { ref mut base, .. } = *self {
+            let base = mem::replace(base.as_mut(), CroppingMargins::Any(Margin::default()));
+            *self = base;
+        }
+    }
+
+    pub fn clamp(&mut self) {
+        match *self {
+            CroppingMargins::Any(ref mut margin) => *margin = margin.clamped(),
+            CroppingMargins::EvenOdd(ref mut pair) => {
+                for margin in pair.iter_mut() {
+                    *margin = margin.clamped();
+                }
+            },
+            CroppingMargins::CoverEvenOdd(ref mut triple) => {
+                for margin in triple.iter_mut() {
+                    *margin = margin.clamped();
+                }
+            },
+            CroppingMargins::Exceptions { ref mut base, ref mut pages } => {
+                base.clamp();
+                for margin in pages.values_mut() {
+                    *margin = margin.clamped();
+                }
+            },
+        }
+    }
+
+    pub fn scheme(&self) -> PageScheme {
+        match *self {
+            CroppingMargins::Any(..) => PageScheme::Any,
+            CroppingMargins::EvenOdd(..) => PageScheme::EvenOdd,
+            CroppingMargins::CoverEvenOdd(..) => PageScheme::Cover,
+            CroppingMargins::Exceptions { ref base, .. } => base.scheme(),
+        }
+    }
+
+    pub fn is_split(&self) -> bool {
+        match *self {
+            CroppingMargins::Any(..) => false,
+            _ => true,
+        }
+    }
+}
+
+// The keys of a map are strings in JSON, and the untagged representation can't parse them as numbers.
+fn deserialize_page_exceptions<'de, D>(deserializer: D) -> Result<BTreeMap<usize, Margin>, D::Error> where D: Deserializer<'de> {
+    Ok(BTreeMap::<String, Margin>::deserialize(deserializer)?
+                .into_iter()
+                .filter_map(|(k, v)| k.parse().ok().map(|k| (k, v)))
+                .collect())
+}
+
+fn cover_even_odd_slot(index: usize) -> usize {
+    if index == 0 {
+        0
+    } else {
+        1 + index % 2
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct HyphenationSettings {
+    pub enabled: bool,
+    // Overrides the language of the text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+impl Default for HyphenationSettings {
+    fn default() -> Self {
+        HyphenationSettings {
+            enabled: true,
+            language: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TextAlign {
+    Justify,
+    Left,
+    Right,
+    Center,
+}
+
+impl TextAlign {
+    pub fn icon_name(&self) -> &str {
+        match self {
+            TextAlign::Justify => "align-justify",
+            TextAlign::Left => "align-left",
+            TextAlign::Right => "align-right",
+            TextAlign::Center => "align-center",
+        }
+    }
+}
+
+impl fmt::Display for TextAlign {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+    Ltr,
+    Rtl,
+}
+
+impl Direction {
+    // The direction of the main script of the given language.
+    pub fn from_language(language: &str) -> Direction {
+        if RIGHT_TO_LEFT_LANGUAGES.contains(&normalize_language(language).as_str()) {
+            Direction::Rtl
+        } else {
+            Direction::Ltr
+        }
+    }
+
+    // The alignment used when none was chosen.
+    pub fn default_text_align(self, text_align: TextAlign) -> TextAlign {
+        match (self, text_align) {
+            (Direction::Rtl, TextAlign::Justify) => TextAlign::Justify,
+            (Direction::Rtl, _) => TextAlign::Right,
+            (Direction::Ltr, _) => text_align,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ReaderInfo {
+    #[serde(default = "simple_date_format::unknown", skip_serializing_if = "simple_date_format::is_unknown", with = "simple_date_format")]
+    pub opened: DateTime<Local>,
+    pub current_page: usize,
+    // The location kept on screen through the changes of layout, on the current page.
+    // Only set for the documents with synthetic page numbers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_location: Option<TextLocation>,
+    // The furthest page ever reached, unless the progress was reset since.
+    #[serde(skip_serializing_if = "is_zero_usize")]
+    pub furthest_page: usize,
+    pub pages_count: usize,
+    pub finished: bool,
+    // When the book was finished. Cleared when the book is read again.
+    #[serde(skip_serializing_if = "Option::is_none", with = "simple_date_format::option", alias = "finishedDate")]
+    pub finished_at: Option<DateTime<Local>>,
+    // Every date at which the book was finished, oldest first. Kept when the book is read again.
+    #[serde(skip_serializing_if = "Vec::is_empty", with = "simple_date_format::vec")]
+    pub finish_history: Vec<DateTime<Local>>,
+    #[serde(skip_serializing_if = "Option::is_none", deserialize_with = "deserialize_zoom_mode")]
+    pub zoom_mode: Option<ZoomMode>,
+    // The number of page turns between two full refreshes, overrides the refresh rate settings.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_every: Option<u8>,
+    // Overrides the inversion of the display.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inverted: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_offset: Option<i32>,
+    // The horizontal position within the page, for the zoom modes that pan sideways.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub left_offset: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none", deserialize_with = "deserialize_rotation")]
+    pub rotation: Option<i8>,
+    // The rotation is then always applied, and the gyroscope is ignored.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotation_lock: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cropping_margins: Option<CroppingMargins>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub margin_width: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub screen_margin_width: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub font_family: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub font_size: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text_align: Option<TextAlign>,
+    // Overrides the direction derived from the language of the book.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub direction: Option<Direction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hyphenation: Option<HyphenationSettings>,
+    // In ems, override the style of the paragraphs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paragraph_spacing: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text_indent: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none", deserialize_with = "deserialize_font_weight")]
+    pub font_weight: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none", deserialize_with = "deserialize_letter_spacing")]
+    pub letter_spacing: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_height: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none", deserialize_with = "deserialize_contrast_exponent")]
+    pub contrast_exponent: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none", deserialize_with = "deserialize_contrast_gray")]
+    pub contrast_gray: Option<f32>,
+    // The exponent and the gray are derived from the preset, unless it's custom.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contrast_preset: Option<ContrastPreset>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dither: Option<DitherMode>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub page_names: BTreeMap<usize, String>,
+    // The index of the page numbered one. The pages before it are front matter.
+    // A negative offset numbers the first page after the missing ones, as in excerpts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_offset: Option<i32>,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub bookmarks: BTreeSet<usize>,
+    // The labels of some of the bookmarks.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub bookmark_labels: BTreeMap<usize, String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", deserialize_with = "deserialize_annotations")]
+    pub annotations: Vec<Annotation>,
+    // The layout against which the dynamic locations of the annotations were last checked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anchored_layout: Option<String>,
+    // The layout against which the print page labels were last resolved into page names.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page_names_layout: Option<String>,
+    // In seconds.
+    #[serde(skip_serializing_if = "is_zero")]
+    pub reading_time: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sessions: Vec<Session>,
+    // The pages jumped from, the most recent last.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub history: Vec<usize>,
+    // The pages left by going back, the most recent last.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub forward_history: Vec<usize>,
+}
+
+// A stretch of reading without long pauses.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    #[serde(default = "simple_date_format::unknown", skip_serializing_if = "simple_date_format::is_unknown", with = "simple_date_format")]
+    pub start: DateTime<Local>,
+    #[serde(default = "simple_date_format::unknown", skip_serializing_if = "simple_date_format::is_unknown", with = "simple_date_format")]
+    pub end: DateTime<Local>,
+    pub start_page: usize,
+    pub end_page: usize,
+}
+
+impl Session {
+    // In seconds.
+    pub fn duration(&self) -> u64 {
+        self.end.signed_duration_since(self.start).num_seconds().max(0) as u64
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub enum ZoomMode {
+    FitToPage,
+    FitToWidth,
+    FitToHeight,
+    // A multiple of the fit to width scale.
+    Custom(f32),
+}
+
+impl ZoomMode {
+    pub fn clamped(self) -> ZoomMode {
+        match self {
+            ZoomMode::Custom(factor) => {
+                ZoomMode::Custom(clamp_value(factor, MIN_ZOOM_FACTOR, MAX_ZOOM_FACTOR).unwrap_or(1.0))
+            },
+            _ => self,
+        }
+    }
+
+    // Whether the page is shown through a window that moves across it, one screen at a time.
+    pub fn is_windowed(self) -> bool {
+        matches!(self, ZoomMode::FitToHeight | ZoomMode::Custom(..))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ContrastPreset {
+    Off,
+    Light,
+    Medium,
+    Aggressive,
+    Custom,
+}
+
+impl ContrastPreset {
+    pub const ALL: [ContrastPreset; 5] = [ContrastPreset::Off, ContrastPreset::Light, ContrastPreset::Medium,
+                                          ContrastPreset::Aggressive, ContrastPreset::Custom];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ContrastPreset::Off => "Off",
+            ContrastPreset::Light => "Light",
+            ContrastPreset::Medium => "Medium",
+            ContrastPreset::Aggressive => "Aggressive",
+            ContrastPreset::Custom => "Custom",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DitherMode {
+    Off,
+    Ordered,
+}
+
+fn normalize_rotation(n: i64) -> i8 {
+    n.rem_euclid(4) as i8
+}
+
+// NaN means that the value is unknown.
+fn clamp_value(value: f32, min: f32, max: f32) -> Option<f32> {
+    if value.is_nan() {
+        None
+    } else {
+        Some(value.max(min).min(max))
+    }
+}
+
+fn deserialize_rotation<'de, D>(deserializer: D) -> Result<Option<i8>, D::Error> where D: Deserializer<'de> {
+    Ok(Option::<i64>::deserialize(deserializer)?.map(normalize_rotation))
+}
+
+// Migrates the kinds stored before they were canonicalized.
+fn deserialize_kind<'de, D>(deserializer: D) -> Result<String, D::Error> where D: Deserializer<'de> {
+    Ok(canonical_kind(&String::deserialize(deserializer)?))
+}
+
+fn deserialize_zoom_mode<'de, D>(deserializer: D) -> Result<Option<ZoomMode>, D::Error> where D: Deserializer<'de> {
+    Ok(Option::<ZoomMode>::deserialize(deserializer)?.map(ZoomMode::clamped))
+}
+
+fn deserialize_contrast_exponent<'de, D>(deserializer: D) -> Result<Option<f32>, D::Error> where D: Deserializer<'de> {
+    Ok(Option::<f32>::deserialize(deserializer)?
+              .and_then(|e| clamp_value(e, MIN_CONTRAST_EXPONENT, MAX_CONTRAST_EXPONENT)))
+}
+
+fn deserialize_contrast_gray<'de, D>(deserializer: D) -> Result<Option<f32>, D::Error> where D: Deserializer<'de> {
+    Ok(Option::<f32>::deserialize(deserializer)?
+              .and_then(|g| clamp_value(g, 0.0, MAX_CONTRAST_GRAY)))
+}
+
+fn deserialize_font_weight<'de, D>(deserializer: D) -> Result<Option<f32>, D::Error> where D: Deserializer<'de> {
+    Ok(Option::<f32>::deserialize(deserializer)?
+              .and_then(|w| clamp_value(w, MIN_FONT_WEIGHT, MAX_FONT_WEIGHT)))
+}
+
+fn deserialize_letter_spacing<'de, D>(deserializer: D) -> Result<Option<f32>, D::Error> where D: Deserializer<'de> {
+    Ok(Option::<f32>::deserialize(deserializer)?
+              .and_then(|s| clamp_value(s, 0.0, MAX_LETTER_SPACING)))
+}
+
+impl ReaderInfo {
+    // Quarter turns, in 0..4.
+    pub fn set_rotation(&mut self, n: i8) {
+        self.rotation = Some(normalize_rotation(n as i64));
+    }
+
+    // The rotation the book is locked to, if any.
+    pub fn locked_rotation(&self) -> Option<i8> {
+        self.rotation.filter(|_| self.rotation_lock == Some(true))
+    }
+
+    pub fn set_contrast_exponent(&mut self, exponent: f32) {
+        self.contrast_exponent = clamp_value(exponent, MIN_CONTRAST_EXPONENT, MAX_CONTRAST_EXPONENT);
+    }
+
+    pub fn set_contrast_gray(&mut self, gray: f32) {
+        self.contrast_gray = clamp_value(gray, 0.0, MAX_CONTRAST_GRAY);
+    }
+
+    pub fn set_font_weight(&mut self, weight: f32) {
+        self.font_weight = clamp_value(weight, MIN_FONT_WEIGHT, MAX_FONT_WEIGHT);
+    }
+
+    pub fn set_letter_spacing(&mut self, spacing: f32) {
+        self.letter_spacing = clamp_value(spacing, 0.0, MAX_LETTER_SPACING);
+    }
+
+    // Fills the exponent and the gray from the preset of the book.
+    pub fn apply_contrast_preset(&mut self, presets: &ContrastPresetsSettings) {
+        let values = match self.contrast_preset.and_then(|preset| presets.values(preset)) {
+            Some(values) => values,
+            None => return,
+        };
+        if (values.exponent - DEFAULT_CONTRAST_EXPONENT).abs() > f32::EPSILON {
+            self.set_contrast_exponent(values.exponent);
+            if (values.gray - DEFAULT_CONTRAST_GRAY).abs() > f32::EPSILON {
+                self.set_contrast_gray(values.gray);
+            } else {
+                self.contrast_gray = None;
+            }
+        } else {
+            self.contrast_exponent = None;
+            self.contrast_gray = None;
+        }
+    }
+
+    pub fn dithered(&self) -> bool {
+        self.dither.map_or(false, |dither| dither != DitherMode::Off)
+    }
+
+    // The indices of the annotations, in document order.
+    pub fn annotations_sorted(&self) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..self.annotations.len()).collect();
+        indices.sort_by_key(|&i| self.annotations[i].selection);
+        indices
+    }
+
+    // Sets the fields that are still unset to the values of the template.
+    pub fn apply_defaults(&mut self, defaults: &ReaderDefaults) {
+        if self.font_family.is_none() {
+            self.font_family = defaults.font_family.clone();
+        }
+        self.font_size = self.font_size.or(defaults.font_size);
+        self.text_align = self.text_align.or(defaults.text_align);
+        self.line_height = self.line_height.or(defaults.line_height);
+        self.margin_width = self.margin_width.or(defaults.margin_width);
+        self.screen_margin_width = self.screen_margin_width.or(defaults.screen_margin_width);
+        self.contrast_exponent = self.contrast_exponent.or(defaults.contrast_exponent);
+        self.contrast_gray = self.contrast_gray.or(defaults.contrast_gray);
+    }
+
+    // Brings the state in line with a document of *pages_count* pages, which might be a shorter
+    // edition of the one the state was recorded with. The locations of reflowable documents are
+    // byte offsets, hence *pages_count* is then the length of the text.
+    // The annotations beyond the end are kept, but marked as dangling.
+    pub fn sanitize(&mut self, pages_count: usize) -> StateAdjustments {
+        let mut adjustments = StateAdjustments::default();
+
+        if pages_count > 0 && self.current_page >= pages_count {
+            adjustments.current_page = Some(self.current_page);
+            self.current_page = pages_count - 1;
+        }
+        self.furthest_page = self.furthest_page.min(pages_count.saturating_sub(1));
+        for page in self.history.iter_mut().chain(self.forward_history.iter_mut()) {
+            *page = (*page).min(pages_count.saturating_sub(1));
+        }
+        self.history.dedup();
+        self.forward_history.dedup();
+
+        adjustments.bookmarks = self.bookmarks.split_off(&pages_count).len();
+        let bookmarks = &self.bookmarks;
+        self.bookmark_labels.retain(|page, _| bookmarks.contains(page));
+        adjustments.page_names = self.page_names.split_off(&pages_count).len();
+        if let Some(offset) = self.page_offset.as_mut() {
+            *offset = (*offset).min(pages_count.saturating_sub(1) as i32);
+        }
+
+        for annot in &mut self.annotations {
+            let resolvable = is_resolvable(annot.selection, Some(pages_count));
+            if !resolvable && !annot.dangling {
+                annot.dangling = true;
+                adjustments.annotations += 1;
+            // The dynamic locations are checked against the text by *relocate_annotations*.
+            } else if resolvable && annot.dangling && matches!(annot.selection[0], TextLocation::Static(..)) {
+                annot.dangling = false;
+            }
+        }
+
+        self.pages_count = pages_count;
+        adjustments
+    }
+
+    // Records a jump away from *from*, forgetting the pages that were left by going back.
+    pub fn push_jump(&mut self, from: usize, max_depth: usize) {
+        self.forward_history.clear();
+        push_bounded(&mut self.history, from, max_depth);
+    }
+
+    pub fn go_back(&mut self, current: usize, max_depth: usize) -> Option<usize> {
+        let page = self.history.pop()?;
+        push_bounded(&mut self.forward_history, current, max_depth);
+        Some(page)
+    }
+
+    pub fn go_forward(&mut self, current: usize, max_depth: usize) -> Option<usize> {
+        let page = self.forward_history.pop()?;
+        push_bounded(&mut self.history, current, max_depth);
+        Some(page)
+    }
+
+    pub fn reach_page(&mut self, page: usize) {
+        self.furthest_page = self.furthest_page.max(page);
+    }
+
+    pub fn reset_progress(&mut self) {
+        self.furthest_page = self.current_page;
+    }
+
+    // The entries written before the furthest page was tracked have none.
+    pub fn progress_page(&self, mode: ProgressMode) -> usize {
+        match mode {
+            ProgressMode::Current => self.current_page,
+            ProgressMode::Furthest => self.furthest_page.max(self.current_page),
+        }
+    }
+
+    pub fn set_finished(&mut self, finished: bool) {
+        if !finished {
+            self.finished_at = None;
+        } else if !self.finished {
+            let now = Local::now();
+            self.finished_at = Some(now);
+            self.finish_history.push(now);
+        }
+        self.finished = finished;
+    }
+
+    // The dates at which the book was finished, including the one recorded before the history was kept.
+    pub fn finish_dates(&self) -> impl Iterator<Item=DateTime<Local>> + '_ {
+        let legacy = self.finished_at.filter(|date| !self.finish_history.contains(date));
+        legacy.into_iter().chain(self.finish_history.iter().cloned())
+    }
+
+    // Starts a reading session, when the book is opened.
+    pub fn begin_session(&mut self, now: DateTime<Local>, page: usize) {
+        self.sessions.push(Session { start: now, end: now, start_page: page, end_page: page });
+        self.trim_sessions();
+    }
+
+    // The reading time isn't affected.
+    fn trim_sessions(&mut self) {
+        if self.sessions.len() > MAX_SESSIONS {
+            let excess = self.sessions.len() - MAX_SESSIONS;
+            self.sessions.drain(..excess);
+        }
+    }
+
+    // Counts the time elapsed since the last activity as reading time, unless it exceeds
+    // *idle_threshold* seconds: the reader was then away, and a new session starts.
+    pub fn record_activity(&mut self, now: DateTime<Local>, page: usize, idle_threshold: u64) {
+        let elapsed = match self.sessions.last() {
+            Some(session) => now.signed_duration_since(session.end).num_seconds(),
+            None => {
+                self.begin_session(now, page);
+                return;
+            },
+        };
+
+        if elapsed < 0 || elapsed as u64 > idle_threshold {
+            let last_page = self.sessions.last().map(|s| s.end_page).unwrap_or(page);
+            self.drop_empty_session();
+            self.begin_session(now, last_page);
+        } else {
+            self.reading_time += elapsed as u64;
+        }
+
+        if let Some(session) = self.sessions.last_mut() {
+            session.end = now;
+            session.end_page = page;
+        }
+    }
+
+    // Closes the current session, when the book is closed.
+    pub fn end_session(&mut self, now: DateTime<Local>, page: usize, idle_threshold: u64) {
+        self.record_activity(now, page, idle_threshold);
+        self.drop_empty_session();
+    }
+
+    fn drop_empty_session(&mut self) {
+        if self.sessions.last().map_or(false, |s| s.duration() == 0) {
+            self.sessions.pop();
+        }
+    }
+
+    pub fn progress(&self) -> f32 {
+        self.progress_at(self.current_page)
+    }
+
+    // The pages before the first numbered page count as nothing.
+    pub fn progress_at(&self, page: usize) -> f32 {
+        let start = self.page_offset().max(0) as usize;
+        page.saturating_sub(start) as f32 / self.pages_count.saturating_sub(start) as f32
+    }
+
+    // The offset can't go past the last page.
+    pub fn page_offset(&self) -> i64 {
+        self.page_offset.map_or(0, |offset| (offset as i64).min(self.pages_count.saturating_sub(1) as i64))
+    }
+
+    // The number of the page at *index*, unless it's front matter.
+    pub fn page_number(&self, index: usize) -> Option<usize> {
+        let number = index as i64 - self.page_offset() + 1;
+        Some(number as usize).filter(|_| number > 0)
+    }
+
+    // The page numbered *number*.
+    pub fn page_index(&self, number: usize) -> Option<usize> {
+        let index = number as i64 - 1 + self.page_offset();
+        Some(index as usize).filter(|&i| index >= 0 && i < self.pages_count)
+    }
+
+    pub fn annotations_with_style(&self, style: HighlightStyle) -> impl Iterator<Item=&Annotation> {
+        self.annotations.iter().filter(move |annot| annot.style == style)
+    }
+
+    // The annotations in chronological order.
+    pub fn annotations_by_date(&self, which: AnnotationDate) -> Vec<&Annotation> {
+        let mut annotations = self.annotations.iter().collect::<Vec<&Annotation>>();
+        annotations.sort_by_key(|annot| annot.date(which));
+        annotations
+    }
+
+    pub fn add_bookmark(&mut self, page: usize, label: Option<String>) {
+        self.bookmarks.insert(page);
+        match label.filter(|label| !label.trim().is_empty()) {
+            Some(label) => { self.bookmark_labels.insert(page, label.trim().to_string()); },
+            None => { self.bookmark_labels.remove(&page); },
+        }
+    }
+
+    // An empty label removes the label. Returns false if there's no bookmark at *page*.
+    pub fn rename_bookmark(&mut self, page: usize, label: &str) -> bool {
+        if !self.bookmarks.contains(&page) {
+            return false;
+        }
+        self.add_bookmark(page, Some(label.to_string()));
+        true
+    }
+
+    pub fn remove_bookmark(&mut self, page: usize) -> bool {
+        self.bookmark_labels.remove(&page);
+        self.bookmarks.remove(&page)
+    }
+
+    pub fn bookmark_label(&self, page: usize) -> Option<&str> {
+        self.bookmark_labels.get(&page).map(String::as_str)
+    }
+
+    // A page name marks the start of a run: the following pages are numbered after it.
+    // The name is looked up as is, then regardless of the case, then as a number within
+    // the runs. A number that falls in no run is the raw page number, counted from one.
+    pub fn page_for_name(&self, name: &str) -> Option<usize> {
+        let name = name.trim();
+        if let Some(index) = self.named_page(name) {
+            return Some(index);
+        }
+
+        // The closest run start at or before *a*, and the page of *a* within that run.
+        fn within_runs<'a>(a: u32, starts: impl Iterator<Item=(u32, &'a usize)>) -> Option<usize> {
+            starts.filter(|(b, _)| *b <= a)
+                  .max_by(|x, y| x.0.cmp(&y.0))
+                  .map(|(b, i)| *i + (a - b) as usize)
+        }
+
+        if let Ok(a) = name.parse::<u32>() {
+            within_runs(a, self.page_names.iter().filter_map(|(i, s)| s.parse::<u32>().ok().map(|b| (b, i))))
+                .or_else(|| self.page_index(a as usize))
+                .or_else(|| Some((a as usize).saturating_sub(1)))
+        } else if let Some(a) = name.chars().next().filter(|_| name.chars().count() == 1)
+                                    .and_then(|c| c.to_alphabetic_digit()) {
+            within_runs(a, self.page_names.iter().filter_map(|(i, s)| {
+                s.chars().next().and_then(|c| c.to_alphabetic_digit()).map(|c| (c, i))
+            }))
+        } else if let Ok(a) = name.parse::<Roman>() {
+            within_runs(*a, self.page_names.iter().filter_map(|(i, s)| s.parse::<Roman>().ok().map(|b| (*b, i))))
+        } else {
+            None
+        }
+    }
+
+    // The page with the given name, regardless of the case if there's no exact match.
+    pub fn named_page(&self, name: &str) -> Option<usize> {
+        self.page_names.iter().find(|(_, s)| s.as_str() == name)
+            .or_else(|| self.page_names.iter().find(|(_, s)| s.to_lowercase() == name.to_lowercase()))
+            .map(|(i, _)| *i)
+    }
+
+    // Replaces the page names with runs that start at the given pages and count from one.
+    pub fn number_pages(&mut self, runs: &[(usize, PageNumberStyle)]) {
+        self.page_names.clear();
+        for &(index, style) in runs {
+            self.page_names.insert(index, style.format(1));
+        }
+    }
+
+    // The start of the last modified annotation.
+    pub fn latest_annotation_location(&self) -> Option<TextLocation> {
+        self.annotations.iter().filter(|annot| !annot.dangling)
+            .max_by_key(|annot| annot.modified)
+            .map(|annot| annot.selection[0])
+    }
+
+    pub fn last_annotated(&self, which: AnnotationDate) -> Option<DateTime<Local>> {
+        self.annotations.iter().map(|annot| annot.date(which)).max()
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PageNumberStyle {
+    Arabic,
+    LowerRoman,
+    UpperRoman,
+    // A, B, …, Z, as for appendices.
+    UpperAlpha,
+}
+
+impl PageNumberStyle {
+    pub fn format(self, number: u32) -> String {
+        match self {
+            PageNumberStyle::Arabic => number.to_string(),
+            PageNumberStyle::LowerRoman => Roman::from(number).map(Roman::to_lowercase)
+                                                 .unwrap_or_else(|_| number.to_string()),
+            PageNumberStyle::UpperRoman => Roman::from(number).map(Roman::to_uppercase)
+                                                 .unwrap_or_else(|_| number.to_string()),
+            PageNumberStyle::UpperAlpha => std::char::from_u32(64 + number).filter(char::is_ascii_uppercase)
+                                                .map_or_else(|| number.to_string(), String::from),
+        }
+    }
+}
+
+// What *ReaderInfo::sanitize* changed.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct StateAdjustments {
+    // The former current page, if it was out of range.
+    pub current_page: Option<usize>,
+    pub bookmarks: usize,
+    pub page_names: usize,
+    pub annotations: usize,
+}
+
+impl StateAdjustments {
+    pub fn is_empty(&self) -> bool {
+        self.current_page.is_none() && self.bookmarks == 0 &&
+        self.page_names == 0 && self.annotations == 0
+    }
+}
+
+impl fmt::Display for StateAdjustments {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if self.current_page.is_some() {
+            parts.push("current page moved back".to_string());
+        }
+        for &(count, what, how) in &[(self.bookmarks, "bookmark", "removed"),
+                                     (self.page_names, "page name", "removed"),
+                                     (self.annotations, "annotation", "orphaned")] {
+            if count > 0 {
+                parts.push(format!("{} {}{} {}", count, what, if count > 1 { "s" } else { "" }, how));
+            }
+        }
+        write!(f, "{}", parts.join(", "))
+    }
+}
+
+impl Default for ReaderInfo {
+    fn default() -> Self {
+        ReaderInfo {
+            opened: Local::now(),
+            current_page: 0,
+            current_location: None,
+            furthest_page: 0,
+            pages_count: 1,
+            finished: false,
+            finished_at: None,
+            finish_history: Vec::new(),
+            zoom_mode: None,
+            refresh_every: None,
+            inverted: None,
+            top_offset: None,
+            left_offset: None,
+            rotation: None,
+            rotation_lock: None,
+            cropping_margins: None,
+            margin_width: None,
+            screen_margin_width: None,
+            font_family: None,
+            font_size: None,
+            text_align: None,
+            direction: None,
+            hyphenation: None,
+            paragraph_spacing: None,
+            text_indent: None,
+            font_weight: None,
+            letter_spacing: None,
+            line_height: None,
+            contrast_exponent: None,
+            contrast_gray: None,
+            contrast_preset: None,
+            dither: None,
+            page_names: BTreeMap::new(),
+            page_offset: None,
+            bookmarks: BTreeSet::new(),
+            bookmark_labels: BTreeMap::new(),
+            annotations: Vec::new(),
+            anchored_layout: None,
+            page_names_layout: None,
+            reading_time: 0,
+            sessions: Vec::new(),
+            history: Vec::new(),
+            forward_history: Vec::new(),
+        }
+    }
+}
+
+impl Default for Info {
+    fn default() -> Self {
+        Info {
+            title: String::default(),
+            subtitle: String::default(),
+            author: String::default(),
+            narrator: String::default(),
+            original_title: String::default(),
+            translator: String::default(),
+            year: String::default(),
+            language: String::default(),
+            publisher: String::default(),
+            series: String::default(),
+            edition: String::default(),
+            volume: String::default(),
+            number: String::default(),
+            isbn: String::default(),
+            description: String::default(),
+            categories: BTreeSet::new(),
+            file: FileInfo::default(),
+            added: Local::now(),
+            reader: None,
+            reader_defaults: None,
+            toc: None,
+            pages: None,
+            start_location: None,
+            modified: None,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum Status {
+    New,
+    Reading(f32),
+    Finished,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SimpleStatus {
+    New,
+    Reading,
+    Finished,
+}
+
+impl fmt::Display for SimpleStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl Info {
+    // The date at which the book was finished, if it still is.
+    pub fn finished_date(&self) -> Option<DateTime<Local>> {
+        self.reader.as_ref().filter(|r| r.finished).and_then(|r| r.finished_at)
+    }
+
+    // The language of the hyphenation patterns, when the book has hyphenation settings.
+    pub fn hyphenation_language(&self) -> Option<&str> {
+        let hyphenation = self.reader.as_ref().and_then(|r| r.hyphenation.as_ref())?;
+        hyphenation.language.as_deref()
+                   .or_else(|| Some(self.language.as_str()).filter(|l| !l.is_empty()))
+    }
+
+    pub fn effective_inverted(&self, global: bool) -> bool {
+        self.reader.as_ref().and_then(|r| r.inverted).unwrap_or(global)
+    }
+
+    // In seconds.
+    pub fn total_reading_time(&self) -> u64 {
+        self.reader.as_ref().map_or(0, |r| r.reading_time)
+    }
+
+    pub fn status(&self) -> Status {
+        self.status_by(ProgressMode::Current)
+    }
+
+    pub fn status_by(&self, mode: ProgressMode) -> Status {
+        if let Some(ref r) = self.reader {
+            if r.finished {
+                Status::Finished
+            } else {
+                Status::Reading(r.progress_at(r.progress_page(mode)))
+            }
+        } else {
+            Status::New
+        }
+    }
+
+    pub fn progress(&self, mode: ProgressMode) -> f32 {
+        match self.status_by(mode) {
+            Status::New => 0.0,
+            Status::Reading(progress) => progress,
+            Status::Finished => 1.0,
+        }
+    }
+
+    pub fn simple_status(&self) -> SimpleStatus {
+        if let Some(ref r) = self.reader {
+            if r.finished {
+                SimpleStatus::Finished
+            } else {
+                SimpleStatus::Reading
+            }
+        } else {
+            SimpleStatus::New
+        }
+    }
+
+    // Whether the book belongs to *category*, or to one of its descendants.
+    pub fn is_in_category(&self, category: &str) -> bool {
+        if category == UNCATEGORIZED {
+            return self.categories.is_empty();
+        }
+        self.categories.iter().any(|c| c == category || c.is_descendant_of(category))
+    }
+
+    // The books that were never opened have no reader info, and are new.
+    pub fn has_status(&self, status: SimpleStatus) -> bool {
+        self.simple_status() == status
+    }
+
+    pub fn file_stem(&self) -> String {
+        let stem = self.file.path.file_stem().unwrap().to_string_lossy();
+        let len = stem.len();
+        for suffix in &STEM_SUFFIXES {
+            if len > suffix.len() && stem.is_char_boundary(len - suffix.len()) &&
+               stem[len - suffix.len()..].eq_ignore_ascii_case(suffix) {
+                return stem[..len - suffix.len()].to_string();
+            }
+        }
+        stem.into_owned()
+    }
+
+    pub fn normalized_isbn(&self) -> Option<String> {
+        normalize_isbn(&self.isbn)
+    }
+
+    pub fn author(&self) -> &str {
+        if self.author.is_empty() {
+            "Unknown Author"
+        } else {
+            &self.author
+        }
+    }
+
+    pub fn title(&self) -> String {
+        if self.title.is_empty() {
+            return self.file_stem();
+        }
+
+        let mut title = self.title.clone();
+
+        if !self.number.is_empty() && self.series.is_empty() {
+            title = format!("{} #{}", title, self.number);
+        }
+
+        if !self.volume.is_empty() {
+            title = format!("{} — vol. {}", title, self.volume);
+        }
+
+        if !self.subtitle.is_empty() {
+            title = if self.subtitle.chars().next().unwrap().is_alphanumeric() &&
+                       title.chars().last().unwrap().is_alphanumeric() {
+                format!("{}: {}", title, self.subtitle)
+            } else {
+                format!("{} {}", title, self.subtitle)
+            };
+        }
+
+        if !self.series.is_empty() && !self.number.is_empty() {
+            title = format!("{} ({} #{})", title, self.series, self.number);
+        }
+
+        title
+    }
+
+    // The entries of the table of contents, in document order, with their depths.
+    pub fn flatten_toc(&self) -> Vec<(usize, &SimpleTocEntry)> {
+        fn walk<'a>(entries: &'a [SimpleTocEntry], depth: usize, result: &mut Vec<(usize, &'a SimpleTocEntry)>) {
+            for entry in entries {
+                result.push((depth, entry));
+                walk(entry.children(), depth + 1, result);
+            }
+        }
+
+        let mut result = Vec::new();
+        if let Some(ref toc) = self.toc {
+            walk(toc, 0, &mut result);
+        }
+        result
+    }
+
+    // Whether an entry of the table of contents satisfies *f*, stopping at the first one.
+    fn any_toc_entry<F>(&self, f: F) -> bool where F: Fn(&SimpleTocEntry) -> bool {
+        fn walk<F>(entries: &[SimpleTocEntry], f: &F) -> bool where F: Fn(&SimpleTocEntry) -> bool {
+            entries.iter().any(|entry| f(entry) || walk(entry.children(), f))
+        }
+
+        self.toc.as_ref().map_or(false, |toc| walk(toc, &f))
+    }
+
+    // The entries whose depth, as given by *flatten_toc*, is at most *max_depth*.
+    pub fn toc_to_depth(&self, max_depth: usize) -> Vec<&SimpleTocEntry> {
+        self.flatten_toc().into_iter()
+            .filter(|&(depth, _)| depth <= max_depth)
+            .map(|(_, entry)| entry)
+            .collect()
+    }
+
+    pub fn search_toc(&self, query: &Regex) -> Vec<&SimpleTocEntry> {
+        self.flatten_toc().into_iter()
+            .map(|(_, entry)| entry)
+            .filter(|entry| query.is_match(entry.title()))
+            .collect()
+    }
+
+    #[inline]
+    pub fn is_match(&self, query: &Option<Regex>) -> bool {
+        self.is_match_with(query, false)
+    }
+
+    // When *fold* is set, the punctuation of the fields is folded before matching.
+    pub fn is_match_with(&self, query: &Option<Regex>, fold: bool) -> bool {
+        if let Some(ref query) = *query {
+            let matches = |text: &str| if fold {
+                query.is_match(&fold_punctuation(text))
+            } else {
+                query.is_match(text)
+            };
+            matches(&self.title) ||
+            matches(&self.subtitle) ||
+            matches(&self.author) ||
+            matches(&self.narrator) ||
+            matches(&self.original_title) ||
+            matches(&self.translator) ||
+            matches(&self.series) ||
+            self.categories.iter().any(|c| matches(c)) ||
+            self.any_toc_entry(|entry| matches(entry.title())) ||
+            self.file.path.to_str().map(|s| matches(s)).unwrap_or(false)
+        } else {
+            true
+        }
+    }
+
+    // TODO: handle the following case: *Walter M. Miller Jr.*?
+    // NOTE: e.g.: John Le Carré: the space between *Le* and *Carré* is a non-breaking space
+    pub fn alphabetic_author(&self) -> &str {
+        self.author().split(',').next()
+                     .and_then(|a| a.split(' ').last())
+                     .unwrap_or_default()
+    }
+
+    // The year as a number, from the same formats as the ones read on import.
+    pub fn year_number(&self) -> Option<i32> {
+        Some(normalize_year(&self.year)).filter(|y| y.len() == 4)
+                                         .and_then(|y| y.parse().ok())
+    }
+
+    // Such as *1950s*.
+    pub fn decade(&self) -> Option<String> {
+        self.year_number().map(|y| format!("{}s", y - y % 10))
+    }
+
+    // Such as *20th century*, the years ending in *00* starting a new century.
+    pub fn century(&self) -> Option<String> {
+        self.year_number().map(|y| {
+            let n = y / 100 + 1;
+            let suffix = match (n % 10, n % 100) {
+                (_, 11..=13) => "th",
+                (1, _) => "st",
+                (2, _) => "nd",
+                (3, _) => "rd",
+                _ => "th",
+            };
+            format!("{}{} century", n, suffix)
+        })
+    }
+
+    // The direction set for this book, or the one of its language.
+    pub fn text_direction(&self) -> Direction {
+        self.reader.as_ref().and_then(|r| r.direction)
+            .unwrap_or_else(|| Direction::from_language(&self.language))
+    }
+
+    pub fn alphabetic_title(&self) -> &str {
+        let mut start = 0;
+        if let Some(re) = TITLE_PREFIXES.get(normalize_language(&self.language).as_str()) {
+            if let Some(m) = re.find(&self.title) {
+                start = m.end()
+            }
+        }
+        &self.title[start..]
+    }
+
+    // The reading state, the table of contents and the dates are left untouched.
+    pub fn apply_patch(&mut self, patch: InfoPatch) {
+        if let Some(title) = patch.title {
+            self.title = title;
+        }
+        if let Some(author) = patch.author {
+            self.author = author;
+        }
+        if let Some(language) = patch.language {
+            self.language = language;
+        }
+        let fields = [(patch.subtitle, &mut self.subtitle),
+                      (patch.narrator, &mut self.narrator),
+                      (patch.original_title, &mut self.original_title),
+                      (patch.translator, &mut self.translator),
+                      (patch.year, &mut self.year),
+                      (patch.publisher, &mut self.publisher),
+                      (patch.series, &mut self.series),
+                      (patch.edition, &mut self.edition),
+                      (patch.volume, &mut self.volume),
+                      (patch.number, &mut self.number),
+                      (patch.isbn, &mut self.isbn),
+                      (patch.description, &mut self.description)];
+        for (value, field) in fields {
+            if let Some(value) = value {
+                *field = value;
+            }
+        }
+        if let Some(categories) = patch.categories {
+            self.categories = categories;
+        }
+    }
+
+    pub fn label(&self) -> String {
+        format!("{} · {}", self.title(), self.author())
+    }
+
+    pub fn pages_count(&self) -> Option<usize> {
+        self.reader.as_ref().map(|r| r.pages_count).or(self.pages)
+    }
+}
+
+fn is_quote(c: char) -> bool {
+    matches!(c, '\'' | '"' | '`' | '´' | '‘' | '’' | '‚' | '‛' | '“' | '”' | '„' | '‟')
+}
+
+fn is_dash(c: char) -> bool {
+    matches!(c, '-' | '‐' | '‑' | '‒' | '–' | '—' | '―')
+}
+
+// Removes the quotes, turns the dashes into spaces and collapses the runs of whitespace.
+// Most fields have nothing to fold, and are returned as they are.
+pub fn fold_punctuation(text: &str) -> Cow<str> {
+    let mut space = true;
+    let unfolded = text.chars().all(|c| {
+        let ok = !is_quote(c) && !is_dash(c) && (c == ' ' || !c.is_whitespace()) && !(space && c == ' ');
+        space = c == ' ';
+        ok
+    }) && !space;
+    if unfolded {
+        Cow::Borrowed(text)
+    } else {
+        Cow::Owned(fold_punctuation_except(text, |_| false))
+    }
+}
+
+// The characters for which *verbatim* returns true are kept as they are.
+fn fold_punctuation_except<F>(text: &str, mut verbatim: F) -> String where F: FnMut(char) -> bool {
+    let mut folded = String::with_capacity(text.len());
+    let mut space = false;
+    for c in text.chars() {
+        if !verbatim(c) {
+            if is_quote(c) {
+                continue;
+            }
+            if is_dash(c) || c.is_whitespace() {
+                space = true;
+                continue;
+            }
+        }
+        if space && !folded.is_empty() {
+            folded.push(' ');
+        }
+        space = false;
+        folded.push(c);
+    }
+    folded
+}
+
+// Only folds the literal text of the query: the escape sequences and the character classes,
+// e.g. *[a-z]*, are left untouched.
+fn fold_query(text: &str) -> String {
+    let (mut escaped, mut class) = (false, false);
+    fold_punctuation_except(text, |c| {
+        let verbatim = escaped || class || c == '\\' || c == '[';
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '[' {
+            class = true;
+        } else if c == ']' {
+            class = false;
+        }
+        verbatim
+    })
+}
+
+pub fn make_query_with(text: &str, fold: bool) -> Option<Regex> {
+    if fold {
+        make_query(&fold_query(text))
+    } else {
+        make_query(text)
+    }
+}
+
+// A leading *=* only matches whole fields: *=Dune* doesn't match *Dune Messiah*.
+pub fn make_query(text: &str) -> Option<Regex> {
+    let any = Regex::new(r"^(\.*|\s)$").unwrap();
+
+    if any.is_match(text) {
+        return None;
+    }
+
+    let (text, exact) = match text.strip_prefix('=') {
+        Some(rest) if !rest.trim().is_empty() => (rest.trim(), true),
+        _ => (text, false),
+    };
+
+    let text = text.replace('a', "[aáàâä]")
+                   .replace('e', "[eéèêë]")
+                   .replace('i', "[iíìîï]")
+                   .replace('o', "[oóòôö]")
+                   .replace('u', "[uúùûü]")
+                   .replace('c', "[cç]")
+                   .replace("ae", "(ae|æ)")
+                   .replace("oe", "(oe|œ)");
+    let text = if exact { format!("^(?:{})$", text) } else { text };
+    Regex::new(&format!("(?i){}", text))
+          .map_err(|e| eprintln!("{}", e))
+          .ok()
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SortMethod {
+    Opened,
+    Added,
+    Progress,
+    Author,
+    Title,
+    Year,
+    Size,
+    Kind,
+    Pages,
+    FileName,
+    FilePath,
+    Annotations,
+    Bookmarks,
+    Annotated,
+    AnnotationModified,
+    Finished,
+    Category,
+}
+
+// Where the entries that lack the sorted value, such as the unopened books, are placed.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum NullOrder {
+    First,
+    Last,
+}
+
+impl SortMethod {
+    // The placement of the missing values that the comparator gives for the given order.
+    pub fn null_order(self, reverse_order: bool) -> NullOrder {
+        let last = match self {
+            SortMethod::Category => true,
+            _ => false,
+        };
+        if last != reverse_order {
+            NullOrder::Last
+        } else {
+            NullOrder::First
+        }
+    }
+
+    // Whether the sorted value is missing from the given entry.
+    fn lacks_value(self, info: &Info) -> bool {
+        match self {
+            SortMethod::Opened => info.reader.is_none(),
+            SortMethod::Pages => info.pages_count().is_none(),
+            SortMethod::Annotated => info.reader.as_ref().and_then(|r| r.last_annotated(AnnotationDate::Created)).is_none(),
+            SortMethod::AnnotationModified => info.reader.as_ref().and_then(|r| r.last_annotated(AnnotationDate::Modified)).is_none(),
+            SortMethod::Finished => info.finished_date().is_none(),
+            SortMethod::Category => info.categories.is_empty(),
+            _ => false,
+        }
+    }
+
+    pub fn reverse_order(self) -> bool {
+        match self {
+            SortMethod::Author |
+            SortMethod::Title |
+            SortMethod::Category |
+            SortMethod::Kind |
+            SortMethod::FileName |
+            SortMethod::FilePath => false,
+            _ => true,
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        match *self {
+            SortMethod::Opened => "Date Opened",
+            SortMethod::Added => "Date Added",
+            SortMethod::Progress => "Progress",
+            SortMethod::Author => "Author",
+            SortMethod::Title => "Title",
+            SortMethod::Year => "Year",
+            SortMethod::Size => "File Size",
+            SortMethod::Kind => "File Type",
+            SortMethod::Pages => "Pages",
+            SortMethod::FileName => "File Name",
+            SortMethod::FilePath => "File Path",
+            SortMethod::Annotations => "Annotations",
+            SortMethod::Bookmarks => "Bookmarks",
+            SortMethod::Annotated => "Date Annotated",
+            SortMethod::AnnotationModified => "Annotation Modified",
+            SortMethod::Finished => "Date Finished",
+            SortMethod::Category => "Category",
+        }
+    }
+
+    pub fn title(self) -> String {
+        format!("Sort by: {}", self.label())
+    }
+}
+
+pub fn sort(md: &mut Metadata, sort_method: SortMethod, reverse_order: bool) {
+    sort_with(md, sort_method, reverse_order, ProgressMode::Current, sort_method.null_order(reverse_order));
+}
+
+// The entries that lack the sorted value are placed according to *null_order*, whatever the order.
+pub fn sort_with(md: &mut Metadata, sort_method: SortMethod, reverse_order: bool, progress_mode: ProgressMode, null_order: NullOrder) {
+    // The alphabetic keys are costly to compute: compute them once per entry.
+    match sort_method {
+        SortMethod::Author => {
+            return sort_by_cached_key(md, |info| info.alphabetic_author().to_string(), reverse_order);
+        },
+        SortMethod::Title => {
+            return sort_by_cached_key(md, |info| info.alphabetic_title().to_string(), reverse_order);
+        },
+        _ => (),
+    }
+
+    let sort_fn: fn(&Info, &Info) -> Ordering = match sort_method {
+        SortMethod::Opened => sort_opened,
+        SortMethod::Added => sort_added,
+        SortMethod::Progress => match progress_mode {
+            ProgressMode::Current => sort_progress,
+            ProgressMode::Furthest => sort_furthest_progress,
+        },
+        SortMethod::Author => sort_author,
+        SortMethod::Title => sort_title,
+        SortMethod::Year => sort_year,
+        SortMethod::Size => sort_size,
+        SortMethod::Kind => sort_kind,
+        SortMethod::Pages => sort_pages,
+        SortMethod::FileName => sort_filename,
+        SortMethod::FilePath => sort_filepath,
+        SortMethod::Annotations => sort_annotations,
+        SortMethod::Bookmarks => sort_bookmarks,
+        SortMethod::Annotated => sort_annotated,
+        SortMethod::AnnotationModified => sort_annotation_modified,
+        SortMethod::Finished => sort_finished,
+        SortMethod::Category => sort_category,
+    };
+    let null_ordering = match null_order {
+        NullOrder::First => Ordering::Less,
+        NullOrder::Last => Ordering::Greater,
+    };
+    md.sort_by(|a, b| {
+        match (sort_method.lacks_value(a), sort_method.lacks_value(b)) {
+            (true, false) => null_ordering,
+            (false, true) => null_ordering.reverse(),
+            _ if reverse_order => sort_fn(a, b).reverse(),
+            _ => sort_fn(a, b),
+        }
+    });
+}
+
+// Orders otherwise equal entries, so that the result doesn't depend on the input order.
+fn sort_tie_break(i1: &Info, i2: &Info) -> Ordering {
+    i1.added.cmp(&i2.added)
+      .then_with(|| i1.file.path.cmp(&i2.file.path))
+}
+
+fn sort_by_cached_key<K, F>(md: &mut Metadata, key: F, reverse_order: bool) where K: Ord, F: Fn(&Info) -> K {
+    if reverse_order {
+        md.sort_by_cached_key(|info| Reverse(key(info)));
+    } else {
+        md.sort_by_cached_key(key);
+    }
+}
+
+pub fn sort_opened(i1: &Info, i2: &Info) -> Ordering {
+    match (&i1.reader, &i2.reader) {
+        (&None, &None) => Ordering::Equal,
+        (&None, &Some(_)) => Ordering::Less,
+        (&Some(_), &None) => Ordering::Greater,
+        (&Some(ref r1), &Some(ref r2)) => r1.opened.cmp(&r2.opened),
+    }.then_with(|| sort_tie_break(i1, i2))
+}
+
+pub fn sort_pages(i1: &Info, i2: &Info) -> Ordering {
+    i1.pages_count().cmp(&i2.pages_count())
+}
+
+pub fn sort_annotations(i1: &Info, i2: &Info) -> Ordering {
+    let count = |info: &Info| info.reader.as_ref().map_or(0, |r| r.annotations.len());
+    count(i1).cmp(&count(i2))
+}
+
+fn sort_last_annotated(i1: &Info, i2: &Info, which: AnnotationDate) -> Ordering {
+    let last = |info: &Info| info.reader.as_ref().and_then(|r| r.last_annotated(which));
+    last(i1).cmp(&last(i2))
+            .then_with(|| sort_tie_break(i1, i2))
+}
+
+pub fn sort_annotated(i1: &Info, i2: &Info) -> Ordering {
+    sort_last_annotated(i1, i2, AnnotationDate::Created)
+}
+
+pub fn sort_annotation_modified(i1: &Info, i2: &Info) -> Ordering {
+    sort_last_annotated(i1, i2, AnnotationDate::Modified)
+}
+
+// The books that aren't finished come first.
+pub fn sort_finished(i1: &Info, i2: &Info) -> Ordering {
+    i1.finished_date().cmp(&i2.finished_date())
+      .then_with(|| sort_tie_break(i1, i2))
+}
+
+// The *n* books that were finished last, the latest first, including the ones being read again.
+pub fn recently_finished(metadata: &Metadata, n: usize) -> Vec<&Info> {
+    let last_finished = |info: &Info| info.reader.as_ref().and_then(|r| r.finish_dates().max());
+    let mut books = metadata.iter().filter_map(|info| last_finished(info).map(|date| (date, info)))
+                            .collect::<Vec<(DateTime<Local>, &Info)>>();
+    books.sort_by(|(d1, i1), (d2, i2)| d2.cmp(d1).then_with(|| sort_tie_break(i1, i2)));
+    books.into_iter().take(n).map(|(_, info)| info).collect()
+}
+
+// A book read again counts in every year it was finished. The books finished before the
+// date was recorded count in the year they were last opened.
+pub fn books_finished_in_year(metadata: &Metadata, year: i32) -> usize {
+    metadata.iter().filter(|info| {
+        info.reader.as_ref().map_or(false, |r| {
+            let mut dates = r.finish_dates().peekable();
+            if dates.peek().is_none() {
+                r.finished && r.opened.year() == year
+            } else {
+                dates.any(|date| date.year() == year)
+            }
+        })
+    }).count()
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum TimelineEvent {
+    Added,
+    Opened,
+    Finished,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TimelineEntry {
+    pub date: DateTime<Local>,
+    pub title: String,
+    pub author: String,
+    pub event: TimelineEvent,
+}
+
+// The additions, last openings and completions of the books, oldest first.
+pub fn reading_timeline(metadata: &Metadata) -> Vec<TimelineEntry> {
+    let mut entries = Vec::new();
+
+    for info in metadata {
+        let mut push = |date, event| entries.push(TimelineEntry {
+            date,
+            title: info.title(),
+            author: info.author.clone(),
+            event,
+        });
+        push(info.added, TimelineEvent::Added);
+        if let Some(r) = info.reader.as_ref() {
+            push(r.opened, TimelineEvent::Opened);
+            for date in r.finish_dates() {
+                push(date, TimelineEvent::Finished);
+            }
+        }
+    }
+
+    entries.sort_by(|a, b| a.date.cmp(&b.date)
+                            .then_with(|| a.event.cmp(&b.event))
+                            .then_with(|| a.title.cmp(&b.title)));
+    entries
+}
+
+pub fn filter_by_status(metadata: &Metadata, status: SimpleStatus) -> Vec<&Info> {
+    metadata.iter().filter(|info| info.has_status(status)).collect()
+}
+
+// Keeps the books that belong to all the included categories and to none of the excluded ones.
+pub fn filter_by_categories<'a, S: AsRef<str>>(metadata: &'a Metadata, include: &[S], exclude: &[S]) -> Vec<&'a Info> {
+    metadata.iter().filter(|info| {
+        include.iter().all(|c| info.is_in_category(c.as_ref())) &&
+        !exclude.iter().any(|c| info.is_in_category(c.as_ref()))
+    }).collect()
+}
+
+// Applies *f* once to each of the selected books. The indices beyond the end are ignored.
+// Returns the number of books changed.
+pub fn apply_to_indices(metadata: &mut Metadata, indices: &[usize], f: impl Fn(&mut Info)) -> usize {
+    let indices: BTreeSet<usize> = indices.iter().cloned().filter(|&i| i < metadata.len()).collect();
+    for &index in &indices {
+        f(&mut metadata[index]);
+    }
+    indices.len()
+}
+
+pub fn set_author_for(metadata: &mut Metadata, indices: &[usize], author: &str) -> usize {
+    apply_to_indices(metadata, indices, |info| info.author = author.to_string())
+}
+
+pub fn set_publisher_for(metadata: &mut Metadata, indices: &[usize], publisher: &str) -> usize {
+    apply_to_indices(metadata, indices, |info| info.publisher = publisher.to_string())
+}
+
+pub fn add_category_for(metadata: &mut Metadata, indices: &[usize], category: &str) -> usize {
+    apply_to_indices(metadata, indices, |info| { info.categories.insert(category.to_string()); })
+}
+
+// Unless *merge* is set, the renaming can't turn *old*, or one of its descendants,
+// into a category that already exists.
+pub fn check_category_rename(metadata: &Metadata, old: &str, new: &str, merge: bool) -> Result<(), Error> {
+    if !symbolic_path::is_valid(new) {
+        return Err(format_err!("invalid category name: {}", new));
+    }
+    if merge {
+        return Ok(());
+    }
+    let moved = |c: &str| c == old || c.is_descendant_of(old);
+    let existing: FnvHashSet<&str> = metadata.iter().flat_map(|info| info.categories.iter())
+                                             .map(String::as_str).filter(|c| !moved(c)).collect();
+    let conflict = metadata.iter().flat_map(|info| info.categories.iter())
+                           .filter_map(|c| symbolic_path::rename(c, old, new))
+                           .find(|c| existing.contains(c.as_str()));
+    match conflict {
+        Some(c) => Err(format_err!("the category {} already exists", c)),
+        None => Ok(()),
+    }
+}
+
+// Renames *old* and its descendants. Returns the number of books changed.
+pub fn rename_category(metadata: &mut Metadata, old: &str, new: &str, merge: bool) -> Result<usize, Error> {
+    check_category_rename(metadata, old, new, merge)?;
+    if old == new {
+        return Ok(0);
+    }
+    let mut count = 0;
+    for info in metadata.iter_mut() {
+        if info.categories.iter().any(|c| symbolic_path::rename(c, old, new).is_some()) {
+            info.categories = info.categories.iter()
+                                  .map(|c| symbolic_path::rename(c, old, new).unwrap_or_else(|| c.clone()))
+                                  .collect();
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+fn check_category_cycle(old: &str, new: &str) -> Result<(), Error> {
+    if new.is_descendant_of(old) {
+        return Err(format_err!("can't move {} under itself", old));
+    }
+    Ok(())
+}
+
+// Moves the subtree of *old* to *new*, e.g. from *SF* to *Fiction.SF*.
+// Returns the number of books changed.
+pub fn move_category(metadata: &mut Metadata, old: &str, new: &str) -> Result<usize, Error> {
+    check_category_cycle(old, new)?;
+    rename_category(metadata, old, new, false)
+}
+
+// Merges the subtree of *from* into the one of *into*, and removes *from*.
+// Returns the number of books changed.
+pub fn merge_categories(metadata: &mut Metadata, from: &str, into: &str) -> Result<usize, Error> {
+    check_category_cycle(from, into)?;
+    rename_category(metadata, from, into, true)
+}
+
+// Returns the number of books of each category. If *descendants* is set, a book
+// also counts for the ancestors of its categories, but only once per category.
+pub fn category_counts(metadata: &Metadata, descendants: bool) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for info in metadata {
+        let mut categories: BTreeSet<&str> = info.categories.iter().map(String::as_str).collect();
+        if descendants {
+            let ancestors: Vec<&str> = categories.iter().flat_map(|c| c.ancestors()).collect();
+            categories.extend(ancestors);
+        }
+        for c in categories {
+            *counts.entry(c.to_string()).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+// Returns the indices of the books of the given category, or of its descendants
+// if *descendants* is set.
+pub fn books_in_category(metadata: &Metadata, category: &str, descendants: bool) -> Vec<usize> {
+    metadata.iter().enumerate().filter(|(_, info)| {
+        info.categories.iter().any(|c| c == category || (descendants && c.is_descendant_of(category)))
+    }).map(|(index, _)| index).collect()
+}
+
+// Returns the number of books of each kind, the kinds being normalized like the
+// ones of new files.
+pub fn kind_counts(metadata: &Metadata) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for info in metadata.iter().filter(|info| !info.file.kind.is_empty()) {
+        *counts.entry(canonical_kind(&info.file.kind)).or_insert(0) += 1;
+    }
+    counts
+}
+
+// The minimum similarity of the titles, and of the authors, of two editions of a work.
+const WORK_SIMILARITY_THRESHOLD: f32 = 0.85;
+
+// Groups the entries that are likely the same work: they share an ISBN, or have similar
+// titles and authors, whatever their formats. Only the groups of several entries are returned.
+pub fn find_duplicate_works(metadata: &Metadata) -> Vec<Vec<usize>> {
+    let mut parents: Vec<usize> = (0..metadata.len()).collect();
+
+    fn root(parents: &mut Vec<usize>, mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+
+    let (index, _) = index_by_isbn(metadata);
+    for (i, info) in metadata.iter().enumerate() {
+        if let Some(&j) = info.normalized_isbn().and_then(|isbn| index.get(&isbn)) {
+            let (a, b) = (root(&mut parents, i), root(&mut parents, j));
+            parents[a] = b;
+        }
+    }
+
+    let keys: Vec<(Vec<(char, char)>, Vec<(char, char)>)> = metadata.iter().map(|info| {
+        let mut author_words: Vec<String> = work_key(&info.author).split(' ').map(String::from).collect();
+        author_words.sort();
+        (bigrams(&work_key(&info.title)), bigrams(&author_words.join(" ")))
+    }).collect();
+
+    // Without titles and authors on both sides, only the ISBNs are compared.
+    let comparable = |info: &Info| !info.title.trim().is_empty() && !info.author.trim().is_empty();
+    for i in 0..metadata.len() {
+        if !comparable(&metadata[i]) {
+            continue;
+        }
+        for j in i+1..metadata.len() {
+            if !comparable(&metadata[j]) || root(&mut parents, i) == root(&mut parents, j) {
+                continue;
+            }
+            if dice_coefficient(&keys[i].0, &keys[j].0) >= WORK_SIMILARITY_THRESHOLD &&
+               dice_coefficient(&keys[i].1, &keys[j].1) >= WORK_SIMILARITY_THRESHOLD {
+                let (a, b) = (root(&mut parents, i), root(&mut parents, j));
+                parents[a] = b;
+            }
+        }
+    }
+
+    let mut groups: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for i in 0..metadata.len() {
+        groups.entry(root(&mut parents, i)).or_default().push(i);
+    }
+    let mut groups: Vec<Vec<usize>> = groups.into_iter().map(|(_, g)| g)
+                                            .filter(|g| g.len() > 1).collect();
+    groups.sort_by_key(|g| g[0]);
+    groups
+}
+
+// Lowercase ASCII words separated by single spaces.
+fn work_key(text: &str) -> String {
+    asciify(&fold_punctuation(text)).to_lowercase()
+                                    .split(|c: char| !c.is_alphanumeric())
+                                    .filter(|w| !w.is_empty())
+                                    .collect::<Vec<&str>>().join(" ")
+}
+
+fn bigrams(text: &str) -> Vec<(char, char)> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut pairs: Vec<(char, char)> = chars.windows(2).map(|w| (w[0], w[1])).collect();
+    pairs.sort_unstable();
+    pairs
+}
+
+// The Sørensen–Dice coefficient of two sorted multisets of bigrams.
+fn dice_coefficient(a: &[(char, char)], b: &[(char, char)]) -> f32 {
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    let (mut i, mut j, mut common) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            Ordering::Less => i += 1,
+            Ordering::Greater => j += 1,
+            Ordering::Equal => {
+                common += 1;
+                i += 1;
+                j += 1;
+            },
+        }
+    }
+    2.0 * common as f32 / (a.len() + b.len()) as f32
+}
+
+// Returns the number of books finished in the given year, and the goal.
+pub fn reading_goal_progress(metadata: &Metadata, year: i32, goal: usize) -> (usize, usize) {
+    (books_finished_in_year(metadata, year), goal)
+}
+
+pub fn sort_bookmarks(i1: &Info, i2: &Info) -> Ordering {
+    let count = |info: &Info| info.reader.as_ref().map_or(0, |r| r.bookmarks.len());
+    count(i1).cmp(&count(i2))
+}
+
+pub fn sort_added(i1: &Info, i2: &Info) -> Ordering {
+    i1.added.cmp(&i2.added)
+}
+
+// FIXME: 'Z'.cmp('É') equals Ordering::Less
+pub fn sort_author(i1: &Info, i2: &Info) -> Ordering {
+    i1.alphabetic_author().cmp(i2.alphabetic_author())
+}
+
+pub fn sort_title(i1: &Info, i2: &Info) -> Ordering {
+    i1.alphabetic_title().cmp(i2.alphabetic_title())
+}
+
+// The uncategorized books come last.
+pub fn sort_category(i1: &Info, i2: &Info) -> Ordering {
+    let key = |info: &Info| info.categories.iter().next().map(|c| asciify(c).to_lowercase());
+    match (key(i1), key(i2)) {
+        (Some(c1), Some(c2)) => c1.cmp(&c2),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+// Ordering: Finished < New < Reading
+pub fn sort_progress(i1: &Info, i2: &Info) -> Ordering {
+    sort_progress_by(i1, i2, ProgressMode::Current)
+}
+
+pub fn sort_furthest_progress(i1: &Info, i2: &Info) -> Ordering {
+    sort_progress_by(i1, i2, ProgressMode::Furthest)
+}
+
+fn sort_progress_by(i1: &Info, i2: &Info, mode: ProgressMode) -> Ordering {
+    match (i1.status_by(mode), i2.status_by(mode)) {
+        (Status::Finished, Status::Finished) => Ordering::Equal,
+        (Status::New, Status::New) => Ordering::Equal,
+        (Status::New, Status::Finished) => Ordering::Greater,
+        (Status::Finished, Status::New) => Ordering::Less,
+        (Status::New, Status::Reading(..)) => Ordering::Less,
+        (Status::Reading(..), Status::New) => Ordering::Greater,
+        (Status::Finished, Status::Reading(..)) => Ordering::Less,
+        (Status::Reading(..), Status::Finished) => Ordering::Greater,
+        (Status::Reading(p1), Status::Reading(p2)) => p1.partial_cmp(&p2)
+                                                        .unwrap_or(Ordering::Equal),
+    }.then_with(|| sort_tie_break(i1, i2))
+}
+
+pub fn sort_size(i1: &Info, i2: &Info) -> Ordering {
+    i1.file.size.cmp(&i2.file.size)
+}
+
+pub fn sort_kind(i1: &Info, i2: &Info) -> Ordering {
+    i1.file.kind.cmp(&i2.file.kind)
+}
+
+pub fn sort_year(i1: &Info, i2: &Info) -> Ordering {
+    i1.year.cmp(&i2.year)
+}
+
+pub fn sort_filename(i1: &Info, i2: &Info) -> Ordering {
+    i1.file.path.file_name().cmp(&i2.file.path.file_name())
+}
+
+pub fn sort_filepath(i1: &Info, i2: &Info) -> Ordering {
+    i1.file.path.cmp(&i2.file.path)
+}
+
+// The position of a book within its series, such as *3*, *2.5* or *#3*.
+fn series_number(info: &Info) -> Option<f32> {
+    info.number.trim().trim_start_matches('#').parse::<f32>().ok()
+        .filter(|n| n.is_finite())
+}
+
+// Groups the indices of the books by series, in reading order. The books without a number
+// come last. The books that aren't part of a series are grouped under the empty string.
+pub fn group_by_series(metadata: &Metadata) -> BTreeMap<String, Vec<usize>> {
+    let mut groups: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+
+    for (index, info) in metadata.iter().enumerate() {
+        groups.entry(info.series.trim().to_string()).or_default().push(index);
+    }
+
+    for indices in groups.values_mut() {
+        indices.sort_by(|&a, &b| {
+            let (i1, i2) = (&metadata[a], &metadata[b]);
+            match (series_number(i1), series_number(i2)) {
+                (Some(n1), Some(n2)) => n1.partial_cmp(&n2).unwrap_or(Ordering::Equal),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }.then_with(|| i1.title.cmp(&i2.title))
+             .then_with(|| sort_tie_break(i1, i2))
+        });
+    }
+
+    groups
+}
+
+// The title of the last table of contents entry, with an exact location, at or before *location*.
+fn chapter_at(toc: &[SimpleTocEntry], location: usize) -> Option<&str> {
+    fn walk<'a>(entries: &'a [SimpleTocEntry], location: usize, result: &mut Option<(usize, &'a str)>) {
+        for entry in entries {
+            let (title, loc, children) = match entry {
+                SimpleTocEntry::Leaf(title, loc) => (title, loc, None),
+                SimpleTocEntry::Container(title, loc, children) => (title, loc, Some(children)),
+            };
+            if let TocLocation::Exact(n) = *loc {
+                if n <= location && result.map_or(true, |(m, _)| n >= m) {
+                    *result = Some((n, title.as_str()));
+                }
+            }
+            if let Some(children) = children {
+                walk(children, location, result);
+            }
+        }
+    }
+
+    let mut result = None;
+    walk(toc, location, &mut result);
+    result.map(|(_, title)| title)
+}
+
+// Records the chapter of the annotations that don't know it yet, using the table of contents
+// of the book. Only the entries with an exact location are considered.
+// Returns the number of annotations that were updated.
+pub fn assign_chapters(info: &mut Info) -> usize {
+    let toc = match info.toc {
+        Some(ref toc) => toc,
+        None => return 0,
+    };
+    let mut count = 0;
+    if let Some(ref mut r) = info.reader {
+        for annot in r.annotations.iter_mut().filter(|annot| annot.chapter.is_empty()) {
+            if let Some(title) = chapter_at(toc, annot.selection[0].location()) {
+                annot.chapter = title.to_string();
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+// Writes to a temporary file first, so that an interruption never leaves a truncated file behind.
+fn write_atomically(path: &Path, content: &str) -> Result<(), Error> {
+    let name = path.file_name().ok_or_else(|| format_err!("missing file name"))?;
+    let temp_path = path.with_file_name(format!(".{}.part", name.to_string_lossy()));
+    fs::write(&temp_path, content)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AnnotationStrategy {
+    // The imported annotations replace the existing ones.
+    Replace,
+    // The imported annotations replace the older ones with the same selection.
+    KeepNewer,
+    // Only the imported annotations with a new selection or text are added.
+    AddMissing,
+}
+
+impl AnnotationStrategy {
+    pub fn from_str(s: &str) -> Option<AnnotationStrategy> {
+        match s {
+            "replace" => Some(AnnotationStrategy::Replace),
+            "newer" => Some(AnnotationStrategy::KeepNewer),
+            "missing" => Some(AnnotationStrategy::AddMissing),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AnnotationsImport {
+    Book(BookAnnotations),
+    Library(Vec<BookAnnotations>),
+}
+
+// The annotations of a book, as written by *export_annotations* in JSON.
+#[derive(Deserialize)]
+pub struct BookAnnotations {
+    #[serde(default)]
+    title: String,
+    // Older exports only have a title.
+    #[serde(default)]
+    path: Option<PathBuf>,
+    #[serde(default)]
+    isbn: String,
+    #[serde(deserialize_with = "deserialize_annotations")]
+    annotations: Vec<Annotation>,
+}
+
+impl BookAnnotations {
+    // The index of the book of the library the annotations belong to: the one with the same
+    // path, or else the only one with the same ISBN, or else the only one with the same title.
+    fn target(&self, metadata: &Metadata) -> Option<usize> {
+        let only = |matches: &dyn Fn(&Info) -> bool| {
+            let mut indices = metadata.iter().enumerate().filter(|(_, info)| matches(info)).map(|(i, _)| i);
+            indices.next().filter(|_| indices.next().is_none())
+        };
+        self.path.as_ref()
+            .and_then(|path| metadata.iter().position(|info| info.file.path == *path))
+            .or_else(|| Some(&self.isbn).filter(|isbn| !isbn.is_empty())
+                                        .and_then(|isbn| only(&|info| info.isbn == *isbn)))
+            .or_else(|| only(&|info| info.title() == self.title))
+    }
+}
+
+// Reads a file written by *export_annotations* or *export_all_annotations* in JSON.
+pub fn load_annotations(source: &Path) -> Result<Vec<BookAnnotations>, Error> {
+    let content = fs::read_to_string(source)?;
+    Ok(match serde_json::from_str(&content)? {
+        AnnotationsImport::Book(book) => vec![book],
+        AnnotationsImport::Library(books) => books,
+    })
+}
+
+// The annotations, across the library, that have the given tag. They're given as pairs of
+// indices, of the book and of the annotation, in document order within each book.
+pub fn annotations_with_tag(metadata: &Metadata, tag: &str) -> Vec<(usize, usize)> {
+    let tag = normalize_tag(tag);
+    let mut result = Vec::new();
+    for (book_index, info) in metadata.iter().enumerate() {
+        if let Some(ref reader) = info.reader {
+            result.extend(reader.annotations_sorted().into_iter()
+                                .filter(|&i| reader.annotations[i].tags.contains(&tag))
+                                .map(|i| (book_index, i)));
+        }
+    }
+    result
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AnnotationField {
+    Text,
+    Note,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AnnotationHit {
+    pub book: usize,
+    pub annotation: usize,
+    pub field: AnnotationField,
+    // The text around the match, and the position of the match within it.
+    pub excerpt: String,
+    pub highlight: Range<usize>,
+}
+
+// Narrows the books searched. A category also accepts its subcategories.
+#[derive(Debug, Clone, Default)]
+pub struct AnnotationFilter {
+    pub category: Option<String>,
+    pub status: Option<SimpleStatus>,
+}
+
+impl AnnotationFilter {
+    fn accepts(&self, info: &Info) -> bool {
+        self.status.map_or(true, |status| info.has_status(status)) &&
+        self.category.as_ref().map_or(true, |categ| {
+            info.categories.iter().any(|c| c == categ || c.is_descendant_of(categ))
+        })
+    }
+}
+
+fn excerpt(text: &str, range: Range<usize>) -> (String, Range<usize>) {
+    let start = text[..range.start].char_indices().rev().nth(EXCERPT_RADIUS - 1).map_or(0, |(i, _)| i);
+    let end = text[range.end..].char_indices().nth(EXCERPT_RADIUS).map_or(text.len(), |(i, _)| range.end + i);
+    let mut result = String::new();
+    if start > 0 {
+        result.push('…');
+    }
+    let offset = result.len() as isize - start as isize;
+    result.push_str(&text[start..end]);
+    if end < text.len() {
+        result.push('…');
+    }
+    let highlight = (range.start as isize + offset) as usize..(range.end as isize + offset) as usize;
+    (result, highlight)
+}
+
+// The annotations of a book that match *query*, which is usually built by `make_query`,
+// in document order. Each annotation yields at most one hit for its text and one for its note.
+pub fn search_book_annotations(book: usize, info: &Info, query: &Regex) -> Vec<AnnotationHit> {
+    let reader = match info.reader {
+        Some(ref reader) => reader,
+        None => return Vec::new(),
+    };
+    let mut hits = Vec::new();
+
+    for annotation in reader.annotations_sorted() {
+        let annot = &reader.annotations[annotation];
+        for &(field, text) in &[(AnnotationField::Text, &annot.text), (AnnotationField::Note, &annot.note)] {
+            if let Some(m) = query.find(text) {
+                let (excerpt, highlight) = excerpt(text, m.range());
+                hits.push(AnnotationHit { book, annotation, field, excerpt, highlight });
+            }
+        }
+    }
+
+    hits
+}
+
+// The annotations, across the books accepted by *filter*, that match *query*.
+// The hits are grouped by book, and in document order within each book.
+pub fn search_annotations(metadata: &Metadata, query: &Regex, filter: &AnnotationFilter) -> Vec<AnnotationHit> {
+    metadata.iter().enumerate()
+            .filter(|(_, info)| filter.accepts(info))
+            .flat_map(|(book, info)| search_book_annotations(book, info, query))
+            .collect()
+}
+
+// Whether *next*, which doesn't start before *prev*, overlaps it or starts right after it.
+fn touches(prev: &Annotation, next: &Annotation) -> bool {
+    let end = prev.selection[1];
+    let start = next.selection[0];
+    if start <= end {
+        return true;
+    }
+    match (end, start) {
+        (TextLocation::Static(p1, w1), TextLocation::Static(p2, w2)) => p1 == p2 && w2 == w1 + 1,
+        // Dynamic locations are the offsets of the words: the gap is estimated from the last word.
+        (TextLocation::Dynamic(o1), TextLocation::Dynamic(o2)) => {
+            let last_word = prev.text.split_whitespace().last().map_or(0, str::len);
+            o2 - o1 <= last_word + WORD_GAP_SLACK
+        },
+        _ => false,
+    }
+}
+
+// Appends *next* to *text*, without repeating the words they have in common.
+fn join_texts(text: &str, next: &str) -> String {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let next_words: Vec<&str> = next.split_whitespace().collect();
+    let max_overlap = words.len().min(next_words.len());
+    let overlap = (0..=max_overlap).rev()
+                                   .find(|&n| words[words.len()-n..] == next_words[..n])
+                                   .unwrap_or(0);
+    words.iter().chain(&next_words[overlap..]).cloned().collect::<Vec<&str>>().join(" ")
+}
+
+fn merge_into(merged: &mut Annotation, annot: Annotation) {
+    if annot.selection[1] > merged.selection[1] {
+        merged.text = join_texts(&merged.text, &annot.text);
+        merged.selection[1] = annot.selection[1];
+        merged.context = match (merged.context.take(), annot.context) {
+            (Some(first), Some(last)) => Some(AnnotationContext { before: first.before, after: last.after }),
+            _ => None,
+        };
+    }
+    if !annot.note.is_empty() && !merged.note.contains(&annot.note) {
+        if !merged.note.is_empty() {
+            merged.note.push_str(NOTE_SEPARATOR);
+        }
+        merged.note.push_str(&annot.note);
+    }
+    merged.created = merged.created.min(annot.created);
+    merged.modified = merged.modified.max(annot.modified);
+    merged.tags.extend(annot.tags);
+    // The first explicit style wins.
+    if merged.style.is_default() {
+        merged.style = annot.style;
+    }
+}
+
+// Merges the annotations whose selections overlap or follow each other. The merged annotation
+// takes the place of the first of its parts in the list. Dangling annotations are left alone.
+// Returns the number of annotations that were absorbed.
+pub fn merge_overlapping_annotations(reader: &mut ReaderInfo) -> usize {
+    // The groups of annotations to merge, in document order.
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    // The member of the current group that ends last.
+    let mut last: Option<usize> = None;
+
+    for index in reader.annotations_sorted() {
+        let annot = &reader.annotations[index];
+        if annot.dangling {
+            continue;
+        }
+        match (last, groups.last_mut()) {
+            (Some(l), Some(group)) if touches(&reader.annotations[l], annot) => {
+                group.push(index);
+                if annot.selection[1] > reader.annotations[l].selection[1] {
+                    last = Some(index);
+                }
+            },
+            _ => {
+                groups.push(vec![index]);
+                last = Some(index);
+            },
+        }
+    }
+
+    groups.retain(|group| group.len() > 1);
+    if groups.is_empty() {
+        return 0;
+    }
+
+    let mut count = 0;
+    let mut annotations: Vec<Option<Annotation>> = reader.annotations.drain(..).map(Some).collect();
+    for group in groups {
+        count += group.len() - 1;
+        let root = *group.iter().min().unwrap();
+        let mut parts = group.iter().map(|&i| annotations[i].take().unwrap());
+        let mut merged = parts.next().unwrap();
+        for part in parts {
+            merge_into(&mut merged, part);
+        }
+        annotations[root] = Some(merged);
+    }
+    reader.annotations = annotations.into_iter().flatten().collect();
+
+    count
+}
+
+// Whether the selection could have been made in the document, as far as its size tells.
+fn is_resolvable(selection: [TextLocation; 2], pages_count: Option<usize>) -> bool {
+    let [start, end] = selection;
+    let same_kind = match (start, end) {
+        (TextLocation::Static(..), TextLocation::Static(..)) |
+        (TextLocation::Dynamic(..), TextLocation::Dynamic(..)) => true,
+        _ => false,
+    };
+    same_kind && start <= end && pages_count.map_or(true, |count| count == 0 || end.location() < count)
+}
+
+// The text around *sel*, within *words*, which are in document order.
+pub fn annotation_context<'a, I>(words: I, sel: [TextLocation; 2]) -> AnnotationContext
+                                where I: IntoIterator<Item=&'a BoundedText> {
+    let mut before = Vec::new();
+    let mut after = Vec::new();
+    for word in words {
+        if word.location < sel[0] {
+            before.push(word.text.as_str());
+        } else if word.location > sel[1] {
+            after.push(word.text.as_str());
+        }
+    }
+    let before = before.join(" ");
+    let after = after.join(" ");
+    let start = before.char_indices().rev().nth(ANCHOR_CONTEXT_LENGTH - 1).map_or(0, |(i, _)| i);
+    let end = after.char_indices().nth(ANCHOR_CONTEXT_LENGTH).map_or(after.len(), |(i, _)| i);
+    AnnotationContext {
+        before: before[start..].to_string(),
+        after: after[..end].to_string(),
+    }
+}
+
+// The text without the characters that depend on the layout.
+fn compact_text(text: &str) -> String {
+    text.chars().filter(|c| !c.is_whitespace() && *c != '\u{00AD}').collect()
+}
+
+fn common_prefix_len<A, B>(a: A, b: B) -> usize where A: Iterator<Item=char>, B: Iterator<Item=char> {
+    a.zip(b).take_while(|(x, y)| x == y).count()
+}
+
+// The words of a page, and its location, as returned by *Document::words*.
+type PageWords = Option<(Vec<BoundedText>, usize)>;
+
+// The words of the page at *location* and of the *radius* pages around it.
+fn words_around(words_at: &mut dyn FnMut(Location) -> PageWords, location: usize, radius: usize) -> Option<Vec<BoundedText>> {
+    let (mut words, current) = words_at(Location::Exact(location))?;
+    let mut loc = current;
+    for _ in 0..radius {
+        match words_at(Location::Previous(loc)) {
+            Some((mut previous, l)) => {
+                previous.append(&mut words);
+                words = previous;
+                loc = l;
+            },
+            None => break,
+        }
+    }
+    let mut loc = current;
+    for _ in 0..radius {
+        match words_at(Location::Next(loc)) {
+            Some((next, l)) => {
+                words.extend(next);
+                loc = l;
+            },
+            None => break,
+        }
+    }
+    Some(words)
+}
+
+fn selected_text(words: &[BoundedText], [start, end]: [TextLocation; 2]) -> String {
+    words.iter().filter(|w| w.location >= start && w.location <= end)
+         .map(|w| compact_text(&w.text)).collect()
+}
+
+// Looks, within *words*, for the text of the annotation, and returns the locations of the
+// first and last words of the occurrence whose surroundings best match the stored context.
+// Ties go to the occurrence closest to the stored selection.
+fn find_anchor(words: &[BoundedText], annot: &Annotation) -> Option<[TextLocation; 2]> {
+    let needle = compact_text(&annot.text);
+    if needle.is_empty() {
+        return None;
+    }
+
+    let mut haystack = String::new();
+    let mut bounds = Vec::with_capacity(words.len());
+    for word in words {
+        let start = haystack.len();
+        haystack.push_str(&compact_text(&word.text));
+        bounds.push((start, haystack.len()));
+    }
+
+    let (before, after) = annot.context.as_ref()
+                               .map(|c| (compact_text(&c.before), compact_text(&c.after)))
+                               .unwrap_or_default();
+    let origin = annot.selection[0].location();
+    let mut best: Option<(usize, usize, [TextLocation; 2])> = None;
+
+    for (start, _) in haystack.match_indices(&needle) {
+        let end = start + needle.len();
+        let first = bounds.iter().position(|&(s, _)| s == start);
+        let last = bounds.iter().rposition(|&(_, e)| e == end);
+        if let (Some(first), Some(last)) = (first, last) {
+            let score = common_prefix_len(before.chars().rev(), haystack[..start].chars().rev()) +
+                        common_prefix_len(after.chars(), haystack[end..].chars());
+            let location = words[first].location.location();
+            let distance = if location > origin { location - origin } else { origin - location };
+            let better = best.map_or(true, |(s, d, _)| score > s || (score == s && distance < d));
+            if better {
+                best = Some((score, distance, [words[first].location, words[last].location]));
+            }
+        }
+    }
+
+    best.map(|(_, _, sel)| sel)
+}
+
+// Checks that the dynamic locations of the annotations still point to their text, and
+// moves those that drifted. The annotations whose text can't be found around their former
+// location are marked as dangling. Returns the number of annotations that were moved.
+pub fn relocate_annotations(doc: &mut dyn Document, reader: &mut ReaderInfo) -> usize {
+    relocate_annotations_with(&mut |loc| doc.words(loc), reader)
+}
+
+fn relocate_annotations_with(words_at: &mut dyn FnMut(Location) -> PageWords, reader: &mut ReaderInfo) -> usize {
+    let mut count = 0;
+
+    for annot in &mut reader.annotations {
+        let is_dynamic = annot.selection.iter().all(|loc| matches!(loc, TextLocation::Dynamic(..)));
+        if !is_dynamic || annot.text.is_empty() {
+            continue;
+        }
+
+        let text = compact_text(&annot.text);
+        let location = annot.selection[0].location();
+
+        // The page of the annotation is enough to tell that it didn't drift.
+        if let Some((words, _)) = words_at(Location::Exact(location)) {
+            if selected_text(&words, annot.selection) == text {
+                annot.dangling = false;
+                continue;
+            }
+        }
+
+        let words = match words_around(words_at, location, RELOCATION_RADIUS) {
+            Some(words) => words,
+            None => {
+                annot.dangling = true;
+                continue;
+            },
+        };
+
+        // The selection might span several pages.
+        if selected_text(&words, annot.selection) == text {
+            annot.dangling = false;
+            continue;
+        }
+
+        match find_anchor(&words, annot) {
+            Some(sel) => {
+                annot.selection = sel;
+                annot.dangling = false;
+                count += 1;
+            },
+            None => annot.dangling = true,
+        }
+    }
+
+    count
+}
+
+// Merges the annotations of *books*, read by *load_annotations*, into those of the library.
+// Returns the number of books, and of annotations, that were added or updated.
+pub fn import_annotations(metadata: &mut Metadata, books: &[BookAnnotations], strategy: AnnotationStrategy) -> (usize, usize) {
+    let (mut books_count, mut annotations_count) = (0, 0);
+    for book in books {
+        let index = match book.target(metadata) {
+            Some(index) => index,
+            None => {
+                eprintln!("Can't find the book of {}.", book.title);
+                continue;
+            },
+        };
+        let count = merge_annotations(&mut metadata[index], book.annotations.clone(), strategy);
+        if count > 0 {
+            books_count += 1;
+            annotations_count += count;
+        }
+    }
+    (books_count, annotations_count)
+}
+
+fn merge_annotations(info: &mut Info, mut imported: Vec<Annotation>, strategy: AnnotationStrategy) -> usize {
+    if imported.is_empty() && info.reader.is_none() {
+        return 0;
+    }
+
+    let pages_count = info.pages_count();
+    for annot in &mut imported {
+        annot.dangling = !is_resolvable(annot.selection, pages_count);
+        annot.tags = annot.tags.iter().map(|tag| normalize_tag(tag))
+                          .filter(|tag| !tag.is_empty()).collect();
+    }
+
+    let reader = info.reader.get_or_insert_with(ReaderInfo::default);
+    let mut count = 0;
+
+    match strategy {
+        AnnotationStrategy::Replace => {
+            count = imported.len();
+            reader.annotations = imported;
+        },
+        AnnotationStrategy::KeepNewer => {
+            for annot in imported {
+                let index = matching_annotation(&reader.annotations, &annot, |a| a.selection == annot.selection);
+                match index.map(|index| &mut reader.annotations[index]) {
+                    Some(a) => if annot.modified > a.modified {
+                        let created = a.created.min(annot.created);
+                        *a = annot;
+                        a.created = created;
+                        count += 1;
+                    },
+                    None => {
+                        reader.annotations.push(annot);
+                        count += 1;
+                    },
+                }
+            }
+        },
+        AnnotationStrategy::AddMissing => {
+            for annot in imported {
+                if matching_annotation(&reader.annotations, &annot,
+                                       |a| a.selection == annot.selection && a.text == annot.text).is_none() {
+                    reader.annotations.push(annot);
+                    count += 1;
+                }
+            }
+        },
+    }
+
+    count
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MergeStrategy {
+    PreferBase,
+    PreferIncoming,
+    Newest,
+}
+
+impl MergeStrategy {
+    pub fn from_str(s: &str) -> Option<MergeStrategy> {
+        match s {
+            "base" => Some(MergeStrategy::PreferBase),
+            "incoming" => Some(MergeStrategy::PreferIncoming),
+            "newest" => Some(MergeStrategy::Newest),
+            _ => None,
+        }
+    }
+}
+
+// Merges the incoming entries into the base entries. The entries are matched by path,
+// by checksum when both sides have one, or else by file name and size when the file was
+// moved. A base entry is merged at most once, the unmatched entries are appended.
+pub fn merge_metadata(base: &mut Metadata, incoming: Metadata, strategy: MergeStrategy) {
+    let index = path_index(base);
+    let checksums: FnvHashMap<String, usize> = base.iter().enumerate()
+                                                   .filter_map(|(i, info)| info.file.checksum.clone().map(|c| (c, i)))
+                                                   .collect();
+    let count = base.len();
+    let mut merged = FnvHashSet::default();
+    for info in incoming {
+        let position = index.get(&info.file.path).cloned()
+                            .filter(|i| !merged.contains(i))
+                            .or_else(|| info.file.checksum.as_ref()
+                                            .and_then(|c| checksums.get(c).cloned())
+                                            .filter(|i| !merged.contains(i)))
+                            .or_else(|| (0..count).find(|i| !merged.contains(i) &&
+                                                            base[*i].file.size == info.file.size &&
+                                                            base[*i].file.path.file_name() == info.file.path.file_name() &&
+                                                            (base[*i].file.checksum.is_none() || info.file.checksum.is_none())));
+        match position {
+            Some(position) => {
+                merged.insert(position);
+                merge_info(&mut base[position], info, strategy);
+            },
+            None => base.push(info),
+        }
+    }
+}
+
+// Copies the descriptive fields of *other* into the empty fields of *info*.
+fn fill_empty_fields(info: &mut Info, other: &Info) {
+    let fill = |field: &mut String, other: &String| {
+        if field.is_empty() {
+            *field = other.clone();
+        }
+    };
+
+    if info.title.is_empty() {
+        info.title = other.title.clone();
+    }
+    if info.author.is_empty() {
+        info.author = other.author.clone();
+    }
+    if info.language.is_empty() {
+        info.language = other.language.clone();
+    }
+    fill(&mut info.subtitle, &other.subtitle);
+    fill(&mut info.narrator, &other.narrator);
+    fill(&mut info.original_title, &other.original_title);
+    fill(&mut info.translator, &other.translator);
+    fill(&mut info.year, &other.year);
+    fill(&mut info.publisher, &other.publisher);
+    fill(&mut info.series, &other.series);
+    fill(&mut info.edition, &other.edition);
+    fill(&mut info.volume, &other.volume);
+    fill(&mut info.number, &other.number);
+    fill(&mut info.isbn, &other.isbn);
+    fill(&mut info.description, &other.description);
+    info.pages = info.pages.or(other.pages);
+}
+
+// The fields of the preferred entry win, unless they're empty.
+// Categories, bookmarks, page names and annotations are united.
+fn merge_info(base: &mut Info, incoming: Info, strategy: MergeStrategy) {
+    let opened = |info: &Info| info.reader.as_ref().map(|r| r.opened);
+    let prefer_incoming = match strategy {
+        MergeStrategy::PreferBase => false,
+        MergeStrategy::PreferIncoming => true,
+        MergeStrategy::Newest => opened(&incoming) > opened(base),
+    };
+
+    let current = mem::replace(base, Info::default());
+    let (mut primary, secondary) = if prefer_incoming {
+        (incoming, current)
+    } else {
+        (current, incoming)
+    };
+
+    fill_empty_fields(&mut primary, &secondary);
+    primary.categories.extend(secondary.categories);
+    primary.toc = primary.toc.or(secondary.toc);
+    primary.reader_defaults = primary.reader_defaults.or(secondary.reader_defaults);
+    primary.start_location = primary.start_location.or(secondary.start_location);
+    primary.modified = primary.modified.max(secondary.modified);
+    primary.added = primary.added.min(secondary.added);
+
+    primary.reader = match (primary.reader, secondary.reader) {
+        (Some(mut r1), Some(r2)) => {
+            r1.opened = r1.opened.max(r2.opened);
+            r1.finished_at = r1.finished_at.max(r2.finished_at);
+            r1.finish_history.extend(r2.finish_history);
+            r1.finish_history.sort();
+            r1.finish_history.dedup();
+            r1.bookmarks.extend(r2.bookmarks);
+            for (index, label) in r2.bookmark_labels {
+                r1.bookmark_labels.entry(index).or_insert(label);
+            }
+            for (index, name) in r2.page_names {
+                r1.page_names.entry(index).or_insert(name);
+            }
+            for annot in r2.annotations {
+                let index = matching_annotation(&r1.annotations, &annot, |a| a.selection == annot.selection);
+                match index.map(|index| &mut r1.annotations[index]) {
+                    Some(a) => if annot.modified > a.modified {
+                        let created = a.created.min(annot.created);
+                        *a = annot;
+                        a.created = created;
+                    },
+                    None => r1.annotations.push(annot),
+                }
+            }
+            // The sessions of the other device add up. The ones older than a full history
+            // might have been trimmed from it, and would then be counted twice.
+            let trimmed_before = r1.sessions.first().map(|s| s.start)
+                                   .filter(|_| r1.sessions.len() >= MAX_SESSIONS);
+            for session in r2.sessions {
+                if trimmed_before.map_or(false, |start| session.start < start) {
+                    continue;
+                }
+                if !r1.sessions.iter().any(|s| s.start == session.start) {
+                    r1.reading_time += session.duration();
+                    r1.sessions.push(session);
+                }
+            }
+            r1.sessions.sort_by_key(|s| s.start);
+            r1.trim_sessions();
+            Some(r1)
+        },
+        (r1, r2) => r1.or(r2),
+    };
+
+    *base = primary;
+}
+
+// The ISO 639-1 code, the ISO 639-2 codes, bibliographic then terminologic, and the English
+// and native names of the common languages.
+const LANGUAGES: [(&str, &str, &str, &str, &str); 35] = [
+    ("ar", "ara", "ara", "arabic", "العربية"),
+    ("ca", "cat", "cat", "catalan", "català"),
+    ("cs", "cze", "ces", "czech", "čeština"),
+    ("da", "dan", "dan", "danish", "dansk"),
+    ("de", "ger", "deu", "german", "deutsch"),
+    ("el", "gre", "ell", "greek", "ελληνικά"),
+    ("en", "eng", "eng", "english", "english"),
+    ("eo", "epo", "epo", "esperanto", "esperanto"),
+    ("es", "spa", "spa", "spanish", "español"),
+    ("fa", "per", "fas", "persian", "فارسی"),
+    ("fi", "fin", "fin", "finnish", "suomi"),
+    ("fr", "fre", "fra", "french", "français"),
+    ("he", "heb", "heb", "hebrew", "עברית"),
+    ("hr", "hrv", "hrv", "croatian", "hrvatski"),
+    ("hu", "hun", "hun", "hungarian", "magyar"),
+    ("it", "ita", "ita", "italian", "italiano"),
+    ("ja", "jpn", "jpn", "japanese", "日本語"),
+    ("ko", "kor", "kor", "korean", "한국어"),
+    ("la", "lat", "lat", "latin", "latina"),
+    ("nl", "dut", "nld", "dutch", "nederlands"),
+    ("no", "nor", "nor", "norwegian", "norsk"),
+    ("pl", "pol", "pol", "polish", "polski"),
+    ("pt", "por", "por", "portuguese", "português"),
+    ("ro", "rum", "ron", "romanian", "română"),
+    ("ru", "rus", "rus", "russian", "русский"),
+    ("sk", "slo", "slk", "slovak", "slovenčina"),
+    ("sl", "slv", "slv", "slovenian", "slovenščina"),
+    ("sr", "srp", "srp", "serbian", "српски"),
+    ("sv", "swe", "swe", "swedish", "svenska"),
+    ("tr", "tur", "tur", "turkish", "türkçe"),
+    ("uk", "ukr", "ukr", "ukrainian", "українська"),
+    ("ur", "urd", "urd", "urdu", "اردو"),
+    ("vi", "vie", "vie", "vietnamese", "tiếng việt"),
+    ("yi", "yid", "yid", "yiddish", "ייִדיש"),
+    ("zh", "chi", "zho", "chinese", "中文"),
+];
+
+// The languages whose main script is written from right to left.
+const RIGHT_TO_LEFT_LANGUAGES: [&str; 9] = ["ar", "dv", "fa", "he", "ps", "sd", "ug", "ur", "yi"];
+
+// Turns language names and tags, such as *English*, *eng* or *en-US*, into ISO 639-1 codes.
+// The unknown languages are kept, in lowercase.
+pub fn normalize_language(language: &str) -> String {
+    let language = language.trim().to_lowercase();
+    let primary = language.split(|c| c == '-' || c == '_').next().unwrap_or_default();
+    LANGUAGES.iter()
+             .find(|&&(code, bib, term, name, native)| {
+                 code == primary || bib == primary || term == primary ||
+                 name == language || native == language
+             })
+             .map_or(language.clone(), |l| l.0.to_string())
+}
+
+lazy_static! {
+    pub static ref TITLE_PREFIXES: FnvHashMap<&'static str, Regex> = {
+        let mut p = FnvHashMap::default();
+        p.insert("", Regex::new(r"^(The|An?)\s").unwrap());
+        p.insert("en", Regex::new(r"^(The|An?)\s").unwrap());
+        p.insert("fr", Regex::new(r"^(Les?\s|La\s|L['’]|Une?\s|Des?\s|Du\s)").unwrap());
+        p
+    };
+
+    // Titles set by the tools that produced the document, rather than by its author.
+    pub static ref JUNK_TITLE: Regex = Regex::new(r"(?i)^\s*$|^(microsoft (word|excel|powerpoint) - |untitled\b|title$|document\d*$)|\.(docx?|odt|rtf|txt|tex|dvi|ps|pdf|pptx?|xlsx?|indd|qxd)$").unwrap();
+
+    pub static ref FOUR_DIGIT_YEAR: Regex = Regex::new(r"(?:^|\D)(\d{4})").unwrap();
+
+    pub static ref TWO_DIGIT_YEAR: Regex = Regex::new(r"^\s*['’]?(\d{2})\s*$").unwrap();
+
+    pub static ref RESERVED_DIRECTORIES: FnvHashSet<&'static str> = [
+        TRASH_NAME,
+        ANNOTATIONS_DIRNAME,
+    ].iter().cloned().collect();
+}
+
+#[derive(Fail, Debug)]
+pub enum MetadataError {
+    #[fail(display = "The file is missing.")]
+    Missing,
+    // No backup could be read. The corrupt file is kept, unless it couldn't be copied.
+    #[fail(display = "The file is corrupt: {}.", reason)]
+    Corrupt { reason: String, kept: Option<PathBuf> },
+}
+
+impl MetadataError {
+    // Logs a corrupt file and returns the notice shown to the user.
+    pub fn report(&self) -> Option<String> {
+        match self {
+            MetadataError::Missing => None,
+            MetadataError::Corrupt { kept, .. } => {
+                eprintln!("Can't load metadata: {}", self);
+                Some(match kept {
+                    Some(kept) => format!("The metadata is corrupt, it was kept as {}.",
+                                          kept.file_name().unwrap_or_default().to_string_lossy()),
+                    None => "The metadata is corrupt.".to_string(),
+                })
+            },
+        }
+    }
+}
+
+// The metadata was read from the backup of a corrupt file.
+#[derive(Debug, Clone)]
+pub struct Restoration {
+    // Where the corrupt file was copied, if it could be.
+    pub kept: Option<PathBuf>,
+}
+
+impl Restoration {
+    // Logs the restoration and returns the notice shown to the user.
+    pub fn report(&self) -> String {
+        match self.kept {
+            Some(ref kept) => eprintln!("The metadata was restored from its backup, the corrupt file was kept as {}.", kept.display()),
+            None => eprintln!("The metadata was restored from its backup."),
+        }
+        "The corrupt metadata was restored from its backup.".to_string()
+    }
+}
+
+// Where the previous version of a metadata file is kept when it's saved.
+pub fn metadata_backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map(OsStr::to_os_string).unwrap_or_default();
+    name.push(".bak");
+    path.with_file_name(name)
+}
+
+fn read_metadata(path: &Path) -> io::Result<Metadata> {
+    let buf = fs::read(path)?;
+    serde_json::from_slice(&buf).map_err(Into::into)
+}
+
+// A corrupt file is copied aside, then replaced by its backup.
+// Returns the metadata and, if it comes from the backup, how it was restored.
+pub fn load_metadata(path: &Path) -> Result<(Metadata, Option<Restoration>), MetadataError> {
+    let reason = match read_metadata(path) {
+        Ok(metadata) => return Ok((metadata, None)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Err(MetadataError::Missing),
+        Err(e) => e.to_string(),
+    };
+
+    let name = format!("{}.corrupt-{}", path.file_name().unwrap_or_default().to_string_lossy(),
+                       Local::now().format("%Y%m%d-%H%M%S"));
+    let mut kept_path = path.with_file_name(&name);
+    let mut index = 2;
+    while kept_path.exists() {
+        kept_path = path.with_file_name(format!("{}-{}", name, index));
+        index += 1;
+    }
+    let kept = fs::copy(path, &kept_path)
+                 .map_err(|e| eprintln!("Can't copy {}: {}.", path.display(), e))
+                 .ok().map(|_| kept_path);
+
+    let backup_path = metadata_backup_path(path);
+    match read_metadata(&backup_path) {
+        Ok(metadata) => {
+            // Without a copy, the corrupt file is left in place.
+            if kept.is_some() {
+                fs::copy(&backup_path, path)
+                   .map_err(|e| eprintln!("Can't restore {}: {}.", path.display(), e)).ok();
+            }
+            Ok((metadata, Some(Restoration { kept })))
+        },
+        Err(_) => Err(MetadataError::Corrupt { reason, kept }),
+    }
+}
+
+// Whether the file holds valid JSON, without building its values.
+fn is_parsable(path: &Path) -> bool {
+    fs::read(path).ok()
+      .map_or(false, |buf| serde_json::from_slice::<serde::de::IgnoredAny>(&buf).is_ok())
+}
+
+// The previous file becomes the backup.
+pub fn save_metadata(metadata: &Metadata, path: &Path) -> Result<(), Error> {
+    save_metadata_with(metadata, path, true)
+}
+
+// The compact form is smaller, the pretty one is easier to edit and compare.
+pub fn save_metadata_with(metadata: &Metadata, path: &Path, pretty: bool) -> Result<(), Error> {
+    // A corrupt file would replace the backup that can restore it.
+    if path.exists() {
+        if is_parsable(path) {
+            fs::copy(path, metadata_backup_path(path))
+               .map_err(|e| eprintln!("Can't back up {}: {}.", path.display(), e)).ok();
+        } else {
+            eprintln!("{} is corrupt, it won't replace its backup.", path.display());
+        }
+    }
+    let text = if pretty {
+        serde_json::to_string_pretty(metadata)?
+    } else {
+        serde_json::to_string(metadata)?
+    };
+    write_atomically(path, &text)
+}
+
+pub fn auto_import(dir: &Path, metadata: &Metadata, settings: &ImportSettings) -> Result<Metadata, Error> {
+    let mut imported_metadata = import(dir, metadata, settings)?;
+    extract_metadata(dir, &mut imported_metadata, settings);
+    Ok(imported_metadata)
+}
+
+// Imports the given files, which are assumed to be new and allowed.
+pub fn import_files(dir: &Path, files: Vec<FileInfo>, settings: &ImportSettings) -> Metadata {
+    let mut imported_metadata = files.into_iter().map(|file_info| new_info(file_info, settings)).collect();
+    extract_metadata(dir, &mut imported_metadata, settings);
+    imported_metadata
+}
+
+// The files were modified: the fields derived from their content are extracted again.
+// The EPUB entries are extracted again when their revision changed.
+pub fn refresh_files(dir: &Path, metadata: &mut Metadata, settings: &ImportSettings) {
+    for info in metadata.iter_mut() {
+        info.pages = None;
+        info.toc = None;
+    }
+    extract_metadata(dir, metadata, settings);
+}
+
+fn extract_metadata(dir: &Path, imported_metadata: &mut Metadata, settings: &ImportSettings) {
+    extract_metadata_from_opf(dir, imported_metadata, settings);
+    extract_metadata_from_epub(dir, imported_metadata, settings);
+    extract_metadata_from_pdf(dir, imported_metadata, settings);
+    extract_metadata_from_djvu(dir, imported_metadata, settings);
+    extract_metadata_from_cbz(dir, imported_metadata);
+    extract_metadata_from_fb2(dir, imported_metadata, settings);
+    extract_metadata_from_mobi(dir, imported_metadata, settings);
+    extract_metadata_from_text(dir, imported_metadata);
+    extract_metadata_from_audio(dir, imported_metadata);
+    if settings.count_pages {
+        count_pages(dir, imported_metadata);
+    }
+}
+
+pub fn import(dir: &Path, metadata: &Metadata, settings: &ImportSettings) -> Result<Metadata, Error> {
+    let known = path_index(metadata);
+    let mut metadata = Vec::new();
+
+    walk_files(dir, dir, settings.traverse_hidden, &mut |file_info| {
+        if !known.contains_key(&file_info.path) && settings.allowed_kinds.contains(&file_info.kind) {
+            metadata.push(new_info(file_info, settings));
+        }
+    })?;
+
+    Ok(metadata)
+}
+
+fn new_info(file_info: FileInfo, settings: &ImportSettings) -> Info {
+    println!("{}", file_info.path.display());
+    let mut info = Info::default();
+    info.file = file_info;
+    if !settings.reader_defaults.is_empty() {
+        info.reader_defaults = Some(settings.reader_defaults.clone());
+    }
+    if settings.category_providers.contains(&CategoryProvider::Path) {
+        let categ = category_from_path(&info.file.path, settings.path_category_depth);
+        if !categ.is_empty() {
+            info.categories = [categ].iter().cloned().collect();
+        }
+    }
+    info
+}
+
+// Each parent directory of the file, up to the given depth, becomes a component of the category.
+// Both separators are recognized, so that the categories don't depend on the system that created
+// the path.
+fn category_from_path(path: &Path, depth: Option<usize>) -> String {
+    let path = path.to_string_lossy();
+    let mut names: Vec<&str> = path.split(|c| c == '/' || c == '\\')
+                                   .filter(|name| !name.is_empty() && *name != ".")
+                                   .collect();
+    names.pop();
+    if let Some(depth) = depth {
+        names.truncate(depth);
+    }
+    symbolic_path::encode(names)
+}
+
+// The OPF file of a book, named after it, so that the books of a directory don't share it.
+fn opf_path(path: &Path) -> PathBuf {
+    path.with_extension("opf")
+}
+
+// Reads the OPF files written by *export_opf*, or the *metadata.opf* files that
+// Calibre saves next to the books it exports.
+pub fn extract_metadata_from_opf(dir: &Path, metadata: &mut Metadata, settings: &ImportSettings) {
+    let subjects_as_categories = settings.category_providers.contains(&CategoryProvider::Subject);
+
+    for info in metadata {
+        if !info.title.is_empty() {
+            continue;
+        }
+
+        let parent = match info.file.path.parent() {
+            Some(parent) => parent,
+            None => continue,
+        };
+
+        let path = match [dir.join(opf_path(&info.file.path)), dir.join(parent).join(OPF_SIDECAR_NAME)]
+                             .iter().find(|path| path.exists()) {
+            Some(path) => path.clone(),
+            None => continue,
+        };
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                eprintln!("{}: {}.", path.display(), e);
+                continue;
+            },
+        };
+
+        if let Err(e) = read_opf(&text, info, subjects_as_categories) {
+            eprintln!("{}: {}.", path.display(), e);
+            continue;
+        }
+
+        println!("{}", info.label());
+    }
+}
+
+fn read_opf(text: &str, info: &mut Info, subjects_as_categories: bool) -> Result<(), Error> {
+    let root = XmlParser::new(text).parse();
+    let children = root.find("metadata").and_then(Node::children)
+                       .ok_or_else(|| format_err!("missing metadata"))?;
+
+    let texts = |name: &str| -> Vec<String> {
+        children.iter().filter(|child| child.tag_name() == Some(name))
+                .filter_map(|child| child.text().map(|text| decode_entities(text).trim().to_string()))
+                .filter(|text| !text.is_empty())
+                .collect()
+    };
+    let meta = |name: &str| -> Option<String> {
+        children.iter().find(|child| child.tag_name() == Some("meta") && child.attr("name") == Some(name))
+                .and_then(|child| child.attr("content").map(|content| decode_entities(content).trim().to_string()))
+                .filter(|content| !content.is_empty())
+    };
+
+    info.title = texts("dc:title").into_iter().next().unwrap_or_default();
+    info.author = texts("dc:creator").join(", ");
+    // Calibre represents undefined dates by the first year of the first century.
+    info.year = texts("dc:date").first().map(|date| normalize_year(date))
+                                .filter(|year| !year.starts_with('0'))
+                                .unwrap_or_default();
+    info.publisher = texts("dc:publisher").into_iter().next().unwrap_or_default();
+    info.description = texts("dc:description").into_iter().next().unwrap_or_default();
+    info.language = normalize_language(&texts("dc:language").into_iter().next().unwrap_or_default());
+    info.isbn = children.iter().filter(|child| child.tag_name() == Some("dc:identifier"))
+                        .find_map(|child| {
+                            let text = child.text().map(|text| text.trim())?;
+                            if child.attr("opf:scheme").map_or(false, |scheme| scheme.eq_ignore_ascii_case("isbn")) {
+                                Some(text.to_string())
+                            } else if text.len() > 9 && text[..9].eq_ignore_ascii_case("urn:isbn:") {
+                                Some(text[9..].to_string())
+                            } else {
+                                None
+                            }
+                        }).unwrap_or_default();
+    if let Some(series) = meta("calibre:series") {
+        info.series = series;
+        info.number = meta("calibre:series_index").map(|index| {
+            index.trim_end_matches(".0").to_string()
+        }).unwrap_or_default();
+    }
+    if subjects_as_categories {
+        info.categories.extend(texts("dc:subject"));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MetadataQuery {
+    // The normalized ISBN of the book, if it has a valid one.
+    pub isbn: Option<String>,
+    pub title: String,
+    pub author: String,
+}
+
+impl MetadataQuery {
+    pub fn new(info: &Info) -> Option<MetadataQuery> {
+        let isbn = info.normalized_isbn();
+        if isbn.is_none() && info.title.is_empty() {
+            return None;
+        }
+        Some(MetadataQuery {
+            isbn,
+            title: info.title.clone(),
+            author: info.author.clone(),
+        })
+    }
+}
+
+// A source of metadata, such as an online catalog.
+pub trait MetadataProvider {
+    fn lookup(&self, query: &MetadataQuery) -> Result<Option<Info>, Error>;
+}
+
+// A provider that knows nothing.
+pub struct NoProvider;
+
+impl MetadataProvider for NoProvider {
+    fn lookup(&self, _query: &MetadataQuery) -> Result<Option<Info>, Error> {
+        Ok(None)
+    }
+}
+
+// Fills the empty fields of the books that have an ISBN or a title with the provider's metadata.
+pub fn enrich(metadata: &mut Metadata, provider: &dyn MetadataProvider, settings: &ImportSettings) {
+    let subjects_as_categories = settings.category_providers.contains(&CategoryProvider::Subject);
+
+    for info in metadata {
+        let query = match MetadataQuery::new(info) {
+            Some(query) => query,
+            None => continue,
+        };
+
+        match provider.lookup(&query) {
+            Ok(Some(found)) => {
+                fill_empty_fields(info, &found);
+                if subjects_as_categories {
+                    info.categories.extend(found.categories);
+                }
+                println!("{}", info.label());
+            },
+            Ok(None) => (),
+            Err(e) => eprintln!("{}: {}.", info.file.path.display(), e),
+        }
+    }
+}
+
+// Opens the documents whose number of pages is unknown to count them. For reflowable
+// documents, the count is an estimate based on the default layout.
+pub fn count_pages(dir: &Path, metadata: &mut Metadata) {
+    for info in metadata {
+        if info.pages.is_some() || info.reader.is_some() {
+            continue;
+        }
+
+        let path = dir.join(&info.file.path);
+
+        match open(&path) {
+            Some(doc) => info.pages = Some(doc.pages_count()).filter(|&n| n > 0),
+            None => eprintln!("{}: can't open document.", info.file.path.display()),
+        }
+    }
+}
+
+// Replaces the table of contents of a PDF by its outline.
+pub fn rebuild_toc(dir: &Path, info: &mut Info) -> Result<(), Error> {
+    if info.file.kind != "pdf" {
+        return Err(format_err!("Unsupported kind: {}.", info.file.kind));
+    }
+
+    let opener = PdfOpener::new().ok_or_else(|| format_err!("Can't create PDF opener."))?;
+    let mut doc = opener.open(dir.join(&info.file.path))
+                        .ok_or_else(|| format_err!("Can't open {}.", info.file.path.display()))?;
+    let pages_count = doc.pages_count();
+    info.toc = doc.toc().map(|toc| simplify_toc(&toc, pages_count, MAX_TOC_ENTRIES));
+
+    Ok(())
+}
+
+// Extracts a four-digit year from a date: two-digit years are placed within the century that
+// ends ten years from now.
+pub fn normalize_year(date: &str) -> String {
+    if let Some(caps) = FOUR_DIGIT_YEAR.captures(date) {
+        return caps[1].to_string();
+    }
+
+    if let Some(caps) = TWO_DIGIT_YEAR.captures(date) {
+        let short_year: i32 = caps[1].parse().unwrap();
+        let limit = Local::now().year() + 10;
+        let mut year = limit - limit.rem_euclid(100) + short_year;
+        if year > limit {
+            year -= 100;
+        }
+        return year.to_string();
+    }
+
+    date.to_string()
+}
+
+pub fn extract_metadata_from_filename(metadata: &mut Metadata, settings: &ImportSettings) {
+    for info in metadata {
+        if !info.title.is_empty() {
+            continue;
+        }
+
+        parse_filename(info, settings.title_case);
+    }
+}
+
+const KNOWN_EXTENSIONS: [&str; 22] = ["epub", "kepub", "pdf", "djvu", "djv", "cbz", "fb2", "xps", "oxps",
+                                       "html", "htm", "xhtml", "txt", "md", "mobi", "azw", "azw3", "prc",
+                                       "m4b", "mp3", "opus", "zip"];
+
+// Removes the trailing file extensions, and the separators that preceded them.
+// The dotted words that aren't extensions are kept: *Mr. Mercedes* stays as is.
+pub fn strip_known_extensions(text: &str) -> &str {
+    let mut result = text.trim_end();
+    while let Some(index) = result.rfind('.') {
+        let ext = &result[index+1..];
+        if ext.is_empty() || !KNOWN_EXTENSIONS.iter().any(|known| known.eq_ignore_ascii_case(ext)) {
+            break;
+        }
+        result = result[..index].trim_end_matches(|c: char| c.is_whitespace() || c == '-' || c == '_' || c == '.');
+    }
+    if result.is_empty() {
+        text.trim_end()
+    } else {
+        result
+    }
+}
+
+// The words that stay lowercase within a title.
+const SMALL_WORDS: [&str; 15] = ["a", "an", "and", "as", "at", "but", "by", "for", "in",
+                                 "nor", "of", "on", "or", "the", "to"];
+
+// Capitalizes the words of a lowercase or uppercase text. The small words stay lowercase,
+// unless they start or end the text, or follow a colon.
+// The uppercase words of a text that isn't all uppercase are taken for acronyms and kept,
+// roman numerals are always uppercase.
+pub fn title_case(text: &str) -> String {
+    let shouting = !text.chars().any(char::is_lowercase);
+    let words: Vec<&str> = text.split(' ').collect();
+    let last_index = words.iter().rposition(|w| !w.is_empty()).unwrap_or(0);
+    let mut after_colon = true;
+    let mut result = Vec::with_capacity(words.len());
+
+    for (index, word) in words.iter().enumerate() {
+        let core = word.trim_matches(|c: char| !c.is_alphanumeric());
+        let lower = core.to_lowercase();
+        let is_edge = after_colon || index == last_index;
+        if !core.is_empty() {
+            after_colon = word.ends_with(':');
+        }
+
+        if core.is_empty() {
+            result.push(word.to_string());
+        } else if !is_edge && SMALL_WORDS.contains(&lower.as_str()) {
+            result.push(word.to_lowercase());
+        } else if is_roman_numeral(&lower) {
+            result.push(word.to_uppercase());
+        } else if !shouting && core.chars().any(char::is_uppercase) {
+            result.push(word.to_string());
+        } else {
+            let mut capitalized = String::with_capacity(word.len());
+            let mut first = true;
+            for c in word.chars() {
+                if first && c.is_alphanumeric() {
+                    capitalized.extend(c.to_uppercase());
+                    first = false;
+                } else {
+                    capitalized.extend(c.to_lowercase());
+                }
+            }
+            result.push(capitalized);
+        }
+    }
+
+    result.join(" ")
+}
+
+// Only the numerals made of *i*, *v* and *x* are recognized: *mix* or *did* are words.
+fn is_roman_numeral(word: &str) -> bool {
+    word.len() > 1 && word.chars().all(|c| c == 'i' || c == 'v' || c == 'x') &&
+    word.parse::<Roman>().ok().map_or(false, |r| r.to_lowercase() == word)
+}
+
+pub fn parse_filename(info: &mut Info, title_case_enabled: bool) {
+    if let Some(filename) = info.file.path.file_name().and_then(OsStr::to_str).map(String::from) {
+        let mut start_index = 0;
+
+        if filename.starts_with('(') {
+            start_index += 1;
+            if let Some(index) = filename[start_index..].find(')') {
+                info.series = filename[start_index..start_index+index].trim_end().to_string();
+                start_index += index + 1;
+            }
+        }
+
+        if let Some(index) = filename[start_index..].find("- ") {
+            info.author = filename[start_index..start_index+index].trim().to_string();
+            start_index += index + 1;
+        }
+
+        let title_start = start_index;
+
+        if let Some(index) = filename[start_index..].find('_') {
+            info.title = filename[start_index..start_index+index].trim_start().to_string();
+            start_index += index + 1;
+        }
+
+        if let Some(index) = filename[start_index..].find('-') {
+            if title_start == start_index {
+                info.title = filename[start_index..start_index+index].trim_start().to_string();
+            } else {
+                info.subtitle = filename[start_index..start_index+index].trim_start().to_string();
+            }
+            start_index += index + 1;
+        }
+
+        if let Some(index) = filename[start_index..].find('(') {
+            info.publisher = filename[start_index..start_index+index].trim_end().to_string();
+            start_index += index + 1;
+        }
+
+        if let Some(index) = filename[start_index..].find(')') {
+            info.year = normalize_year(&filename[start_index..start_index+index]);
+        }
+
+        let title = strip_known_extensions(&info.title).to_string();
+        if title.len() != info.title.len() {
+            info.title = title;
+        }
+        info.subtitle = strip_known_extensions(&info.subtitle).to_string();
+
+        if title_case_enabled {
+            let title = title_case(&info.title);
+            info.title = title;
+            info.subtitle = title_case(&info.subtitle);
+            let author = title_case(&info.author);
+            info.author = author;
+        }
+
+        println!("{}", info.label());
+    }
+}
+
+pub fn clean_up(dir: &Path, metadata: &mut Metadata) {
+    metadata.retain(|info| {
+        let path = &info.file.path;
+        if !dir.join(path).exists() {
+            println!("{}", path.display());
+            false
+        } else {
+            true
+        }
+    });
+}
+
+// The modification time of the file, with the precision of the stored dates.
+pub fn file_modified(path: &Path) -> Option<DateTime<Local>> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+      .map(DateTime::<Local>::from)
+      .and_then(|date| date.with_nanosecond(0))
+}
+
+fn find_files(root: &Path, dir: &Path, traverse_hidden: bool) -> Result<Vec<FileInfo>, Error> {
+    let mut result = Vec::new();
+    walk_files(root, dir, traverse_hidden, &mut |file_info| result.push(file_info))?;
+    Ok(result)
+}
+
+// Hands the files to *f* as they're found, instead of collecting them.
+fn walk_files<F: FnMut(FileInfo)>(root: &Path, dir: &Path, traverse_hidden: bool, f: &mut F) -> Result<(), Error> {
+    for entry in fs::read_dir(dir).context("Can't read directory.")? {
+        let entry = entry.context("Can't read directory entry.")?;
+        let path = entry.path();
+
+        if path.is_dir() {
+            if let Some(name) = entry.file_name().to_str() {
+                if (!traverse_hidden && name.starts_with('.')) || RESERVED_DIRECTORIES.contains(name) {
+                    continue;
+                }
+            }
+            walk_files(root, path.as_path(), traverse_hidden, f)?;
+        } else {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let relat = path.strip_prefix(root).unwrap().to_path_buf();
+            let kind = file_kind(path).unwrap_or_default();
+            let size = entry.metadata().map(|m| m.len()).unwrap_or_default();
+
+            f(FileInfo {
+                path: relat,
+                kind,
+                size,
+                checksum: None,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use crate::helpers::TempDir;
+    use crate::geom::Rectangle;
+    use crate::document::epub::EpubDocument;
+
+    // Reads an annotation the way the stored ones are read.
+    fn stored_annotation(json: &str) -> Annotation {
+        let reader: ReaderInfo = serde_json::from_str(&format!(r#"{{"annotations": [{}]}}"#, json)).unwrap();
+        reader.annotations.into_iter().next().unwrap()
+    }
+
+    fn info_with_counts(name: &str, counts: Option<(usize, usize)>) -> Info {
+        Info {
+            file: FileInfo {
+                path: PathBuf::from(name),
+                .. Default::default()
+            },
+            reader: counts.map(|(annotations, bookmarks)| {
+                ReaderInfo {
+                    annotations: vec![Annotation::default(); annotations],
+                    bookmarks: (0..bookmarks).collect(),
+                    .. Default::default()
+                }
+            }),
+            .. Default::default()
+        }
+    }
+
+    fn paths(md: &Metadata) -> Vec<&str> {
+        md.iter().map(|info| info.file.path.to_str().unwrap()).collect()
+    }
+
+    fn info_with_status(name: &str, added: &str, status: SimpleStatus) -> Info {
+        Info {
+            file: FileInfo {
+                path: PathBuf::from(name),
+                .. Default::default()
+            },
+            added: Local.datetime_from_str(added, "%Y-%m-%d %H:%M:%S").unwrap(),
+            reader: match status {
+                SimpleStatus::New => None,
+                SimpleStatus::Reading => Some(ReaderInfo::default()),
+                SimpleStatus::Finished => Some(ReaderInfo { finished: true, .. Default::default() }),
+            },
+            .. Default::default()
+        }
+    }
+
+    #[test]
+    fn status_buckets() {
+        let mut md = vec![info_with_status("a", "2020-01-01 00:00:00", SimpleStatus::New),
+                          info_with_status("b", "2020-01-01 00:00:00", SimpleStatus::Reading),
+                          info_with_status("c", "2020-01-01 00:00:00", SimpleStatus::Finished),
+                          info_with_status("d", "2020-01-01 00:00:00", SimpleStatus::Reading)];
+        md[0].reader_defaults = Some(ReaderDefaults { font_size: Some(9.5), .. Default::default() });
+        let names = |status| filter_by_status(&md, status).iter()
+                                                          .map(|info| info.file.path.to_str().unwrap())
+                                                          .collect::<Vec<&str>>();
+        assert_eq!(names(SimpleStatus::New), vec!["a"]);
+        assert_eq!(names(SimpleStatus::Reading), vec!["b", "d"]);
+        assert_eq!(names(SimpleStatus::Finished), vec!["c"]);
+        assert!(filter_by_status(&Vec::new(), SimpleStatus::New).is_empty());
+    }
+
+    #[test]
+    fn sort_ties_are_deterministic() {
+        let md = vec![info_with_status("d", "2020-01-02 00:00:00", SimpleStatus::New),
+                      info_with_status("b", "2020-01-01 00:00:00", SimpleStatus::Finished),
+                      info_with_status("c", "2020-01-01 00:00:00", SimpleStatus::New),
+                      info_with_status("a", "2020-01-01 00:00:00", SimpleStatus::Finished),
+                      info_with_status("e", "2020-01-01 00:00:00", SimpleStatus::New)];
+        let mut rev = md.clone();
+        rev.reverse();
+
+        for md in &mut [md.clone(), rev.clone()] {
+            sort(md, SortMethod::Progress, false);
+            assert_eq!(paths(md), vec!["a", "b", "c", "e", "d"]);
+        }
+
+        for md in &mut [md, rev] {
+            md.retain(|info| info.reader.is_none());
+            sort(md, SortMethod::Opened, false);
+            assert_eq!(paths(md), vec!["c", "e", "d"]);
+        }
+    }
+
+    #[test]
+    fn timeline() {
+        let date = |s: &str| Local.datetime_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap();
+        let mut finished = info_with_status("b", "2020-01-01 00:00:00", SimpleStatus::Finished);
+        finished.title = "Flatland".to_string();
+        finished.author = "Edwin A. Abbott".to_string();
+        if let Some(r) = finished.reader.as_mut() {
+            r.opened = date("2020-03-01 00:00:00");
+            r.finished_at = Some(date("2020-02-01 00:00:00"));
+        }
+        let mut reading = info_with_status("a", "2020-01-15 00:00:00", SimpleStatus::Reading);
+        reading.reader.as_mut().unwrap().opened = date("2020-01-20 00:00:00");
+        let new = info_with_status("c", "2020-01-01 00:00:00", SimpleStatus::New);
+
+        let entries = reading_timeline(&vec![new, reading, finished]);
+        let summary = entries.iter().map(|e| (e.title.as_str(), e.event)).collect::<Vec<_>>();
+        assert_eq!(summary, vec![("Flatland", TimelineEvent::Added),
+                                 ("c", TimelineEvent::Added),
+                                 ("a", TimelineEvent::Added),
+                                 ("a", TimelineEvent::Opened),
+                                 ("Flatland", TimelineEvent::Finished),
+                                 ("Flatland", TimelineEvent::Opened)]);
+        assert_eq!(entries[0].author, "Edwin A. Abbott");
+        assert_eq!(entries[4].date, date("2020-02-01 00:00:00"));
+        assert!(reading_timeline(&Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn batch_edits() {
+        let mut md: Metadata = ["a", "b", "c", "d"].iter()
+                                                   .map(|name| info_with_status(name, "2020-01-01 00:00:00", SimpleStatus::New))
+                                                   .collect();
+        md[3].publisher = "Dover".to_string();
+        assert_eq!(set_publisher_for(&mut md, &[0, 2, 2, 7], "Penguin"), 2);
+        let publishers = md.iter().map(|info| info.publisher.as_str()).collect::<Vec<&str>>();
+        assert_eq!(publishers, vec!["Penguin", "", "Penguin", "Dover"]);
+
+        assert_eq!(set_author_for(&mut md, &[1], "Cormac McCarthy"), 1);
+        assert_eq!(md[1].alphabetic_author(), "McCarthy");
+        assert_eq!(md[0].author, "");
+
+        assert_eq!(add_category_for(&mut md, &[1, 3], "Fiction"), 2);
+        assert!(md[3].categories.contains("Fiction"));
+        assert!(md[0].categories.is_empty() && md[2].categories.is_empty());
+        assert_eq!(apply_to_indices(&mut md, &[], |info| info.year = "1900".to_string()), 0);
+        assert_eq!(apply_to_indices(&mut md, &[4, 9], |info| info.year = "1900".to_string()), 0);
+        assert!(md.iter().all(|info| info.year.is_empty()));
+    }
+
+    #[test]
+    fn unopened_books_placement() {
+        let opened = |name: &str, date: &str| {
+            let mut info = info_with_status(name, "2020-01-01 00:00:00", SimpleStatus::Reading);
+            info.reader.as_mut().unwrap().opened = Local.datetime_from_str(date, "%Y-%m-%d %H:%M:%S").unwrap();
+            info
+        };
+        let md = vec![info_with_status("a", "2020-01-01 00:00:00", SimpleStatus::New),
+                      opened("b", "2021-03-01 00:00:00"),
+                      info_with_status("c", "2020-01-02 00:00:00", SimpleStatus::New),
+                      opened("d", "2021-01-01 00:00:00")];
+
+        // The unopened books are still sorted among themselves.
+        for &(reverse_order, unopened, opened) in &[(false, ["a", "c"], ["d", "b"]),
+                                                    (true, ["c", "a"], ["b", "d"])] {
+            let mut first = md.clone();
+            sort_with(&mut first, SortMethod::Opened, reverse_order, ProgressMode::Current, NullOrder::First);
+            assert_eq!(paths(&first), [&unopened[..], &opened[..]].concat());
+            let mut last = md.clone();
+            sort_with(&mut last, SortMethod::Opened, reverse_order, ProgressMode::Current, NullOrder::Last);
+            assert_eq!(paths(&last), [&opened[..], &unopened[..]].concat());
+        }
+
+        // The default placement matches the comparator.
+        let mut md = md;
+        sort(&mut md, SortMethod::Opened, true);
+        assert_eq!(paths(&md), ["b", "d", "c", "a"]);
+        assert_eq!(SortMethod::Opened.null_order(false), NullOrder::First);
+        assert_eq!(SortMethod::Category.null_order(false), NullOrder::Last);
+        assert_eq!(SortMethod::Category.null_order(true), NullOrder::First);
+    }
+
+    #[test]
+    fn cached_key_sort_matches_comparator() {
+        let names = ["Zola", "Émile", "The Road", "a", "Le Carré", "Balzac", "The Road"];
+        let md: Metadata = names.iter().enumerate().map(|(i, name)| {
+            Info {
+                title: name.to_string(),
+                author: format!("{}, {}", names[names.len() - 1 - i], name),
+                file: FileInfo { path: PathBuf::from(i.to_string()), .. Default::default() },
+                .. Default::default()
+            }
+        }).collect();
+
+        for &(method, sort_fn) in &[(SortMethod::Author, sort_author as fn(&Info, &Info) -> Ordering),
+                                    (SortMethod::Title, sort_title)] {
+            for &reverse_order in &[false, true] {
+                let mut expected = md.clone();
+                if reverse_order {
+                    expected.sort_by(|a, b| sort_fn(a, b).reverse());
+                } else {
+                    expected.sort_by(sort_fn);
+                }
+                let mut actual = md.clone();
+                sort(&mut actual, method, reverse_order);
+                assert_eq!(paths(&actual), paths(&expected));
+            }
+        }
+
+        let count = std::cell::Cell::new(0);
+        let mut actual = md.clone();
+        sort_by_cached_key(&mut actual, |info| {
+            count.set(count.get() + 1);
+            info.alphabetic_title().to_string()
+        }, false);
+        assert_eq!(count.get(), md.len());
+    }
+
+    #[test]
+    fn language_normalization() {
+        for language in &["English", "eng", "en-US", " EN_gb ", "en"] {
+            assert_eq!(normalize_language(language), "en");
+        }
+        assert_eq!(normalize_language("fre"), "fr");
+        assert_eq!(normalize_language("fra"), "fr");
+        assert_eq!(normalize_language("Français"), "fr");
+        assert_eq!(normalize_language("zh-Hant-TW"), "zh");
+        assert_eq!(normalize_language("Klingon"), "klingon");
+        assert_eq!(normalize_language(""), "");
+
+        let mut info = Info { title: "La Peste".to_string(), language: "fra".to_string(), .. Default::default() };
+        assert_eq!(info.alphabetic_title(), "Peste");
+        info.language = "en-GB".to_string();
+        info.title = "The Plague".to_string();
+        assert_eq!(info.alphabetic_title(), "Plague");
+    }
+
+    #[test]
+    fn category_renaming() {
+        let mut md = vec![
+            Info { categories: ["Fiction.SF".to_string()].iter().cloned().collect(), .. Default::default() },
+            Info { categories: ["Fiction.SF.Classics".to_string(), "Poetry".to_string()].iter().cloned().collect(), .. Default::default() },
+            Info { categories: ["Fiction.SFX".to_string()].iter().cloned().collect(), .. Default::default() },
+            Info { categories: ["Fiction.Fantasy".to_string()].iter().cloned().collect(), .. Default::default() },
+        ];
+        assert_eq!(rename_category(&mut md, "Fiction.SF", "Fiction.ScienceFiction", false).unwrap(), 2);
+        assert!(md[0].categories.contains("Fiction.ScienceFiction"));
+        assert!(md[1].categories.contains("Fiction.ScienceFiction.Classics"));
+        assert!(md[1].categories.contains("Poetry"));
+        assert!(md[2].categories.contains("Fiction.SFX"));
+
+        assert!(rename_category(&mut md, "Fiction.ScienceFiction", "Fiction.Fantasy", false).is_err());
+        assert!(rename_category(&mut md, "Poetry", "Fiction..Verse", true).is_err());
+        assert!(md[0].categories.contains("Fiction.ScienceFiction"));
+        assert_eq!(rename_category(&mut md, "Fiction.ScienceFiction", "Fiction.Fantasy", true).unwrap(), 2);
+        assert!(md[1].categories.contains("Fiction.Fantasy.Classics"));
+        assert_eq!(rename_category(&mut md, "Fiction", "Books\\.Fiction", false).unwrap(), 4);
+        assert!(md[3].categories.contains("Books\\.Fiction.Fantasy"));
+    }
+
+    #[test]
+    fn category_moving() {
+        let mut md = vec![
+            Info { categories: ["SF".to_string(), "SF.Space.Opera".to_string()].iter().cloned().collect(), .. Default::default() },
+            Info { categories: ["SF.Space.Opera.Classics".to_string()].iter().cloned().collect(), .. Default::default() },
+            Info { categories: ["Fiction.SF".to_string(), "SF".to_string()].iter().cloned().collect(), .. Default::default() },
+            Info { categories: ["Poetry".to_string()].iter().cloned().collect(), .. Default::default() },
+        ];
+        assert!(move_category(&mut md, "SF", "SF.Space.SF").is_err());
+        assert!(merge_categories(&mut md, "SF", "SF.Space").is_err());
+        assert!(move_category(&mut md, "SF", "Fiction.SF").is_err());
+        assert_eq!(move_category(&mut md, "SF.Space", "Space").unwrap(), 2);
+        assert!(md[0].categories.contains("Space.Opera"));
+        assert!(md[1].categories.contains("Space.Opera.Classics"));
+
+        assert_eq!(merge_categories(&mut md, "SF", "Fiction.SF").unwrap(), 2);
+        assert_eq!(md[0].categories.iter().collect::<Vec<_>>(), vec!["Fiction.SF", "Space.Opera"]);
+        assert_eq!(md[2].categories.iter().collect::<Vec<_>>(), vec!["Fiction.SF"]);
+        assert_eq!(move_category(&mut md, "Space.Opera", "Fiction.SF.Space.Opera").unwrap(), 2);
+        assert!(md[1].categories.contains("Fiction.SF.Space.Opera.Classics"));
+        assert_eq!(merge_categories(&mut md, "Fiction.SF.Space.Opera", "Fiction.SF").unwrap(), 2);
+        assert_eq!(md[0].categories.len(), 1);
+        assert!(md[1].categories.contains("Fiction.SF.Classics"));
+        assert_eq!(merge_categories(&mut md, "Fiction.SF", "Fiction.SF").unwrap(), 0);
+        assert!(md[3].categories.contains("Poetry"));
+    }
+
+    #[test]
+    fn category_filtering() {
+        let md: Metadata = [&["Work.Reports"][..], &["Work", "Fiction"], &["Fiction.SF"], &["Workshop"], &[]].iter().map(|categs| {
+            Info { categories: categs.iter().map(|c| c.to_string()).collect(), .. Default::default() }
+        }).collect();
+        let indices = |books: Vec<&Info>| books.iter().map(|b| md.iter().position(|i| std::ptr::eq(i, *b)).unwrap()).collect::<Vec<usize>>();
+        let none: &[&str] = &[];
+        assert_eq!(indices(filter_by_categories(&md, none, &["Work"])), vec![2, 3, 4]);
+        assert_eq!(indices(filter_by_categories(&md, &["Fiction"], &["Work"])), vec![2]);
+        assert_eq!(indices(filter_by_categories(&md, &["Work"], &["Work.Reports"])), vec![1]);
+        assert_eq!(indices(filter_by_categories(&md, &[UNCATEGORIZED], none)), vec![4]);
+        assert_eq!(indices(filter_by_categories(&md, none, &[UNCATEGORIZED, "Fiction"])), vec![0, 3]);
+        assert!(filter_by_categories(&md, &["Work"], &["Work"]).is_empty());
+    }
+
+    #[test]
+    fn category_index() {
+        let md = vec![
+            Info { categories: ["Fiction.SF".to_string(), "Fiction.SF.Classics".to_string()].iter().cloned().collect(), .. Default::default() },
+            Info { categories: ["Fiction.Fantasy".to_string()].iter().cloned().collect(), .. Default::default() },
+            Info { categories: ["Fiction".to_string(), "Poetry".to_string()].iter().cloned().collect(), .. Default::default() },
+            Info::default(),
+        ];
+        let counts = category_counts(&md, false);
+        assert_eq!(counts.get("Fiction"), Some(&1));
+        assert_eq!(counts.get("Fiction.SF"), Some(&1));
+        assert_eq!(counts.get("Fiction.SF.Classics"), Some(&1));
+        assert_eq!(counts.len(), 5);
+        let counts = category_counts(&md, true);
+        assert_eq!(counts.get("Fiction"), Some(&3));
+        assert_eq!(counts.get("Fiction.SF"), Some(&1));
+        assert_eq!(counts.get("Poetry"), Some(&1));
+        assert_eq!(counts.len(), 5);
+
+        assert_eq!(books_in_category(&md, "Fiction", false), vec![2]);
+        assert_eq!(books_in_category(&md, "Fiction", true), vec![0, 1, 2]);
+        assert_eq!(books_in_category(&md, "Fiction.SF.Classics", true), vec![0]);
+        assert!(books_in_category(&md, "Fict", true).is_empty());
+    }
+
+    #[test]
+    fn kind_index() {
+        let md: Metadata = ["epub", "EPUB", "kepub", "pdf", "cbz", "Pdf", ""].iter().map(|kind| {
+            Info { file: FileInfo { kind: kind.to_string(), .. Default::default() }, .. Default::default() }
+        }).collect();
+        let counts = kind_counts(&md);
+        assert_eq!(counts.get("epub"), Some(&3));
+        assert_eq!(counts.get("pdf"), Some(&2));
+        assert_eq!(counts.get("cbz"), Some(&1));
+        assert_eq!(counts.len(), 3);
+
+        let file: FileInfo = serde_json::from_str(r#"{"path": "a.azw", "kind": "azw", "size": 0}"#).unwrap();
+        assert_eq!(file.kind, "mobi");
+        let file: FileInfo = serde_json::from_str(r#"{"path": "b", "kind": "", "size": 0}"#).unwrap();
+        assert_eq!(file.kind, "");
+    }
+
+    #[test]
+    fn info_patch() {
+        let added = Local.ymd(2019, 3, 4).and_hms(10, 0, 0);
+        let mut info = Info {
+            title: "Emma".to_string(),
+            author: "Austen, Jane".to_string(),
+            publisher: "Penguin".to_string(),
+            added,
+            reader: Some(ReaderInfo { current_page: 42, bookmarks: [7].iter().cloned().collect(), .. Default::default() }),
+            toc: Some(Vec::new()),
+            .. Default::default()
+        };
+        info.apply_patch(InfoPatch {
+            title: Some("The Watsons".to_string()),
+            author: Some("Jane Austen".to_string()),
+            .. Default::default()
+        });
+        assert_eq!(info.title, "The Watsons");
+        assert_eq!(info.author, "Jane Austen");
+        assert_eq!(info.publisher, "Penguin");
+        assert_eq!(info.added, added);
+        assert!(info.toc.is_some());
+        let reader = info.reader.as_ref().unwrap();
+        assert_eq!(reader.current_page, 42);
+        assert!(reader.bookmarks.contains(&7));
+        assert_eq!(info.alphabetic_title(), "Watsons");
+
+        info.apply_patch(InfoPatch { publisher: Some(String::new()), .. Default::default() });
+        assert_eq!(info.publisher, "");
+        assert_eq!(info.title, "The Watsons");
+    }
+
+    #[test]
+    fn page_offset() {
+        let mut reader = ReaderInfo { pages_count: 206, page_offset: Some(6), .. Default::default() };
+        assert_eq!(reader.page_number(0), None);
+        assert_eq!(reader.page_number(5), None);
+        assert_eq!(reader.page_number(6), Some(1));
+        assert_eq!(reader.page_index(1), Some(6));
+        assert_eq!(reader.page_index(200), Some(205));
+        assert_eq!(reader.page_index(201), None);
+        assert_eq!(reader.progress_at(3), 0.0);
+        assert_eq!(reader.progress_at(106), 0.5);
+        assert_eq!(reader.page_for_name("1"), Some(6));
+
+        // The names win over the numbers.
+        reader.page_names.insert(2, "1".to_string());
+        assert_eq!(reader.named_page("1"), Some(2));
+        assert_eq!(reader.page_for_name("1"), Some(2));
+        assert_eq!(reader.page_index(1), Some(6));
+
+        // An excerpt that starts on the page 301 of the book.
+        reader.page_offset = Some(-300);
+        assert_eq!(reader.page_number(0), Some(301));
+        assert_eq!(reader.page_index(301), Some(0));
+        assert_eq!(reader.page_index(300), None);
+        assert_eq!(reader.progress_at(103), 0.5);
+
+        reader.page_offset = Some(500);
+        assert_eq!(reader.page_offset(), 205);
+        assert_eq!(reader.page_number(205), Some(1));
+        reader.sanitize(100);
+        assert_eq!(reader.page_offset, Some(99));
+
+        let info = Info { reader: Some(ReaderInfo { current_page: 56, pages_count: 106,
+                                                    page_offset: Some(6), .. Default::default() }),
+                          .. Default::default() };
+        assert_eq!(info.progress(ProgressMode::Current), 0.5);
+    }
+
+    #[test]
+    fn metadata_loading() {
+        let dir = TempDir::new("metadata");
+        let path = dir.join(METADATA_FILENAME);
+
+        assert!(matches!(load_metadata(&path), Err(MetadataError::Missing)));
+
+        let metadata = vec![Info { title: "Walden".to_string(), .. Default::default() }];
+        save_metadata(&metadata, &path).unwrap();
+        assert!(!metadata_backup_path(&path).exists());
+        let (loaded, restoration) = load_metadata(&path).unwrap();
+        assert_eq!(loaded[0].title, "Walden");
+        assert!(restoration.is_none());
+
+        // The second save backs up the first one.
+        let metadata = vec![Info { title: "Emma".to_string(), .. Default::default() }];
+        save_metadata(&metadata, &path).unwrap();
+        fs::write(&path, "[{\"title\": \"Emma\",").unwrap();
+        let (loaded, restoration) = load_metadata(&path).unwrap();
+        assert_eq!(loaded[0].title, "Walden");
+        let kept = restoration.and_then(|r| r.kept).unwrap();
+        assert_eq!(fs::read_to_string(&kept).unwrap(), "[{\"title\": \"Emma\",");
+        assert_eq!(load_metadata(&path).unwrap().0[0].title, "Walden");
+
+        // The backup survives the saves that follow a corruption it couldn't repair.
+        fs::write(&path, "[{").unwrap();
+        save_metadata(&metadata, &path).unwrap();
+        assert_eq!(load_metadata(&path).unwrap().0[0].title, "Emma");
+        assert_eq!(read_metadata(&metadata_backup_path(&path)).unwrap()[0].title, "Walden");
+
+        fs::write(&path, "{").unwrap();
+        fs::remove_file(metadata_backup_path(&path)).unwrap();
+        match load_metadata(&path) {
+            Err(MetadataError::Corrupt { kept: Some(kept), .. }) => {
+                assert_eq!(fs::read_to_string(&kept).unwrap(), "{");
+            },
+            result => panic!("unexpected result: {:?}", result.map(|(_, restoration)| restoration)),
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{");
+
+    }
+
+    #[test]
+    fn metadata_forms() {
+        let dir = TempDir::new("metadata-forms");
+        let metadata = vec![
+            Info { title: "Walden".to_string(), categories: ["Essays".to_string()].iter().cloned().collect(), .. Default::default() },
+            Info { title: "Emma".to_string(), reader: Some(ReaderInfo { current_page: 12, pages_count: 300, .. Default::default() }), .. Default::default() },
+        ];
+        let expected = serde_json::to_value(&metadata).unwrap();
+        let mut sizes = Vec::new();
+
+        for &pretty in &[true, false] {
+            let path = dir.join(format!("{}-{}", pretty, METADATA_FILENAME));
+            save_metadata_with(&metadata, &path, pretty).unwrap();
+            sizes.push(fs::metadata(&path).unwrap().len());
+            let (loaded, _) = load_metadata(&path).unwrap();
+            assert_eq!(serde_json::to_value(&loaded).unwrap(), expected);
+        }
+
+        assert!(sizes[0] > sizes[1]);
+    }
+
+    #[test]
+    fn text_direction() {
+        assert_eq!(Direction::from_language("Arabic"), Direction::Rtl);
+        assert_eq!(Direction::from_language("he-IL"), Direction::Rtl);
+        assert_eq!(Direction::from_language("per"), Direction::Rtl);
+        assert_eq!(Direction::from_language("en"), Direction::Ltr);
+        assert_eq!(Direction::from_language(""), Direction::Ltr);
+
+        assert_eq!(Direction::Rtl.default_text_align(TextAlign::Left), TextAlign::Right);
+        assert_eq!(Direction::Rtl.default_text_align(TextAlign::Justify), TextAlign::Justify);
+        assert_eq!(Direction::Ltr.default_text_align(TextAlign::Center), TextAlign::Center);
+
+        let mut info = Info { language: "fas".to_string(), .. Default::default() };
+        assert_eq!(info.text_direction(), Direction::Rtl);
+        info.reader = Some(ReaderInfo { direction: Some(Direction::Ltr), .. Default::default() });
+        assert_eq!(info.text_direction(), Direction::Ltr);
+    }
+
+    #[test]
+    fn escaped_path_categories() {
+        let categ = category_from_path(Path::new("Manuals/Plato v0.8/Kobo/guide.pdf"), None);
+        assert_eq!(categ, "Manuals.Plato v0\\.8.Kobo");
+        assert_eq!(symbolic_path::decode(&categ), vec!["Manuals", "Plato v0.8", "Kobo"]);
+        assert_eq!(category_from_path(Path::new("guide.pdf"), None), "");
+    }
+
+    #[test]
+    fn cross_platform_path_categories() {
+        let unix = category_from_path(Path::new("Science/Physics/Quantum/book.epub"), None);
+        let windows = category_from_path(Path::new("Science\\Physics\\Quantum\\book.epub"), None);
+        assert_eq!(unix, "Science.Physics.Quantum");
+        assert_eq!(windows, unix);
+        assert_eq!(category_from_path(Path::new("./Science\\Physics/Quantum\\book.epub"), None), unix);
+    }
+
+    #[test]
+    fn kepub_file_stem() {
+        let stem = |path: &str| Info {
+            file: FileInfo { path: PathBuf::from(path), .. Default::default() },
+            .. Default::default()
+        }.file_stem();
+        assert_eq!(stem("Books/Book.kepub.epub"), "Book");
+        assert_eq!(stem("Books/Book.KEPUB.epub"), "Book");
+        assert_eq!(stem("Books/Book.epub"), "Book");
+        assert_eq!(stem("Books/.kepub.epub"), ".kepub");
+        assert_eq!(file_kind("Books/Book.kepub.epub").as_deref(), Some("epub"));
+        assert_eq!(file_kind("Books/Book.kepub").as_deref(), Some("epub"));
+    }
+
+    #[test]
+    fn opf_metadata() {
+        let info = |name: &str| Info {
+            file: FileInfo { path: PathBuf::from(name), kind: file_kind(name).unwrap(), .. Default::default() },
+            .. Default::default()
+        };
+        let mut md = vec![info("Ann Leckie/Ancillary Justice (12)/Ancillary Justice - Ann Leckie.epub"),
+                          info("orphan.pdf")];
+        let mut settings = ImportSettings::default();
+        settings.category_providers.insert(CategoryProvider::Subject);
+        extract_metadata_from_opf(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/opf")), &mut md, &settings);
+
+        assert_eq!(md[0].title, "Ancillary Justice");
+        assert_eq!(md[0].author, "Ann Leckie, John Doe");
+        assert_eq!(md[0].series, "Imperial Radch");
+        assert_eq!(md[0].number, "1");
+        assert_eq!(md[0].publisher, "Orbit & Co");
+        assert_eq!(md[0].year, "2013");
+        assert_eq!(md[0].isbn, "9780316246620");
+        assert_eq!(md[0].language, "en");
+        assert!(md[0].description.starts_with("<p>On a remote, icy planet"));
+        assert!(md[0].categories.contains("Science Fiction"));
+        assert!(md[0].categories.contains("Space Opera"));
+        assert!(md[1].title.is_empty());
+    }
+
+    #[test]
+    fn opf_round_trip() {
+        let info = Info {
+            title: "Ancillary Sword".to_string(),
+            author: "Ann Leckie, John <Doe>".to_string(),
+            year: "2014".to_string(),
+            publisher: "Orbit & Co".to_string(),
+            language: "en".to_string(),
+            isbn: "9780316246651".to_string(),
+            series: "Imperial \"Radch\"".to_string(),
+            number: "2".to_string(),
+            categories: ["Science Fiction", "Space Opera"].iter().map(|c| c.to_string()).collect(),
+            file: FileInfo { path: PathBuf::from("Ann Leckie/Ancillary Sword.epub"), kind: "epub".to_string(), .. Default::default() },
+            .. Default::default()
+        };
+        let opf = info.to_opf();
+        assert!(opf.contains("<dc:creator opf:role=\"aut\">John &lt;Doe&gt;</dc:creator>"));
+
+        let mut imported = Info { file: info.file.clone(), .. Default::default() };
+        read_opf(&opf, &mut imported, true).unwrap();
+        assert_eq!(imported.title, info.title);
+        assert_eq!(imported.author, info.author);
+        assert_eq!(imported.year, info.year);
+        assert_eq!(imported.publisher, info.publisher);
+        assert_eq!(imported.language, info.language);
+        assert_eq!(imported.isbn, info.isbn);
+        assert_eq!(imported.series, info.series);
+        assert_eq!(imported.number, info.number);
+        assert_eq!(imported.categories, info.categories);
+        assert_eq!(imported.description, "");
+
+        // The exported files are found by the importer, even with several books per directory.
+        let dir = TempDir::new("opf-export");
+        fs::create_dir(dir.join("Ann Leckie")).unwrap();
+        let mut other = info.clone();
+        other.title = "Ancillary Mercy".to_string();
+        other.file.path = PathBuf::from("Ann Leckie/Ancillary Mercy.kepub.epub");
+        assert_eq!(export_opf(&dir, &vec![info.clone(), other.clone()]), 2);
+        assert!(dir.join("Ann Leckie/Ancillary Mercy.kepub.opf").exists());
+        let mut md = vec![Info { file: info.file.clone(), .. Default::default() },
+                          Info { file: other.file.clone(), .. Default::default() }];
+        let mut settings = ImportSettings::default();
+        settings.category_providers.insert(CategoryProvider::Subject);
+        extract_metadata_from_opf(&dir, &mut md, &settings);
+        assert_eq!(md[0].title, info.title);
+        assert_eq!(md[1].title, other.title);
+        assert_eq!(md[1].categories, info.categories);
+    }
+
+    #[test]
+    fn path_category_depth() {
+        let path = Path::new("Science/Physics/Quantum/book.epub");
+        assert_eq!(category_from_path(path, Some(1)), "Science");
+        assert_eq!(category_from_path(path, Some(2)), "Science.Physics");
+        assert_eq!(category_from_path(path, Some(4)), "Science.Physics.Quantum");
+        assert_eq!(category_from_path(path, None), "Science.Physics.Quantum");
+    }
+
+    #[test]
+    fn year_buckets() {
+        let info = |year: &str| Info { year: year.to_string(), .. Default::default() };
+        assert_eq!(info("1951").decade().as_deref(), Some("1950s"));
+        assert_eq!(info("2000").decade().as_deref(), Some("2000s"));
+        assert_eq!(info("1999-12-31").decade().as_deref(), Some("1990s"));
+        assert_eq!(info("").decade(), None);
+        assert_eq!(info("unknown").decade(), None);
+        assert_eq!(info("1951").century().as_deref(), Some("20th century"));
+        assert_eq!(info("2000").century().as_deref(), Some("21st century"));
+        assert_eq!(info("1066").century().as_deref(), Some("11th century"));
+        assert_eq!(info("1250").century().as_deref(), Some("13th century"));
+        assert_eq!(info("").century(), None);
+    }
+
+    #[test]
+    fn normalize_years() {
+        assert_eq!(normalize_year("1999-01-01"), "1999");
+        assert_eq!(normalize_year("2020-03-14T15:09:26Z"), "2020");
+        assert_eq!(normalize_year("D:20200314150926+01'00'"), "2020");
+        assert_eq!(normalize_year("March 5, 1999"), "1999");
+        assert_eq!(normalize_year("'99"), "1999");
+        assert_eq!(normalize_year("’05"), "2005");
+        assert_eq!(normalize_year("99"), "1999");
+        assert_eq!(normalize_year("MCMXCIX"), "MCMXCIX");
+        assert_eq!(normalize_year("n.d."), "n.d.");
+        assert_eq!(normalize_year(""), "");
+    }
+
+    #[test]
+    fn junk_titles() {
+        for title in &["Microsoft Word - final.docx", "untitled", "Document1", "report.tex", "  "] {
+            assert!(JUNK_TITLE.is_match(title), "{}", title);
+        }
+        for title in &["The Road", "Untitledness", "A Document Model"] {
+            assert!(!JUNK_TITLE.is_match(title), "{}", title);
+        }
+    }
+
+    #[test]
+    fn epub_with_opf_sidecar() {
+        let dir = TempDir::new("epub-opf");
+        let book_dir = dir.join("Ann Leckie/Ancillary Justice (12)");
+        fs::create_dir_all(&book_dir).unwrap();
+        fs::copy(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/epub/modified.epub"),
+                 book_dir.join("book.epub")).unwrap();
+        fs::copy(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/opf/Ann Leckie/Ancillary Justice (12)/metadata.opf"),
+                 book_dir.join(OPF_SIDECAR_NAME)).unwrap();
+        let mut md = vec![Info {
+            file: FileInfo { path: PathBuf::from("Ann Leckie/Ancillary Justice (12)/book.epub"),
+                             kind: "epub".to_string(), .. Default::default() },
+            .. Default::default()
+        }];
+        // In the order of extract_metadata.
+        let settings = ImportSettings::default();
+        extract_metadata_from_opf(&dir, &mut md, &settings);
+        extract_metadata_from_epub(&dir, &mut md, &settings);
+        assert_eq!(md[0].title, "Ancillary Justice");
+        assert_eq!(md[0].author, "Ann Leckie, John Doe");
+        assert!(md[0].modified.is_some());
+
+        extract_metadata_from_opf(&dir, &mut md, &settings);
+        extract_metadata_from_epub(&dir, &mut md, &settings);
+        assert_eq!(md[0].title, "Ancillary Justice");
+    }
+
+    #[test]
+    fn pages_estimate() {
+        let dir = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/epub"));
+        let mut md = vec![info_with_counts("guide.epub", None), info_with_counts("modified.epub", Some((0, 0)))];
+        count_pages(dir, &mut md);
+        let pages_count = EpubDocument::new(dir.join("guide.epub")).unwrap().pages_count();
+        assert_eq!(md[0].pages, Some(pages_count));
+        assert_eq!(md[0].pages_count(), Some(pages_count));
+        assert_eq!(md[1].pages, None);
+
+        md[0].reader = Some(ReaderInfo { pages_count: 7, .. Default::default() });
+        assert_eq!(md[0].pages_count(), Some(7));
+    }
+
+    #[test]
+    fn latest_annotation() {
+        let annotation = |start: usize, day: u32, dangling: bool| Annotation {
+            selection: [TextLocation::Dynamic(start), TextLocation::Dynamic(start + 8)],
+            modified: Local.ymd(2020, 3, day).and_hms(12, 0, 0),
+            dangling,
+            .. Default::default()
+        };
+        let mut reader = ReaderInfo::default();
+        assert_eq!(reader.latest_annotation_location(), None);
+        reader.annotations = vec![annotation(900, 2, false), annotation(100, 9, false), annotation(5000, 12, true)];
+        assert_eq!(reader.latest_annotation_location(), Some(TextLocation::Dynamic(100)));
+    }
+
+    #[test]
+    fn current_location() {
+        let legacy: ReaderInfo = serde_json::from_str(r#"{"opened": "2020-01-01 00:00:00", "currentPage": 4096}"#).unwrap();
+        assert_eq!((legacy.current_page, legacy.current_location), (4096, None));
+        assert!(!serde_json::to_string(&legacy).unwrap().contains("currentLocation"));
+
+        let reader = ReaderInfo { current_page: 4096, current_location: Some(TextLocation::Dynamic(4500)), .. Default::default() };
+        let parsed: ReaderInfo = serde_json::from_str(&serde_json::to_string(&reader).unwrap()).unwrap();
+        assert_eq!(parsed.current_location, Some(TextLocation::Dynamic(4500)));
+    }
+
+    #[test]
+    fn page_names_lookup() {
+        let mut reader = ReaderInfo::default();
+        // Front matter i–xii, then the body from the 13th page.
+        reader.number_pages(&[(0, PageNumberStyle::LowerRoman), (12, PageNumberStyle::Arabic)]);
+        assert_eq!(reader.page_names.get(&0).map(String::as_str), Some("i"));
+        assert_eq!(reader.page_names.get(&12).map(String::as_str), Some("1"));
+        assert_eq!(reader.page_for_name("iv"), Some(3));
+        assert_eq!(reader.page_for_name("IV"), Some(3));
+        assert_eq!(reader.page_for_name("214"), Some(225));
+
+        reader.number_pages(&[(8, PageNumberStyle::Arabic), (300, PageNumberStyle::UpperAlpha)]);
+        assert_eq!(reader.page_for_name("1"), Some(8));
+        assert_eq!(reader.page_for_name("C"), Some(302));
+        reader.page_names.insert(150, "Plate III".to_string());
+        assert_eq!(reader.page_for_name("plate iii"), Some(150));
+        assert_eq!(reader.page_for_name("Plate III"), Some(150));
+        assert_eq!(reader.page_for_name("Plate IV"), None);
+
+        // A number before the first run is a raw page number.
+        reader.number_pages(&[]);
+        reader.page_names.insert(10, "17".to_string());
+        assert_eq!(reader.page_for_name("17"), Some(10));
+        assert_eq!(reader.page_for_name("20"), Some(13));
+        assert_eq!(reader.page_for_name("5"), Some(4));
+        assert_eq!(reader.page_for_name("0"), Some(0));
+
+        assert_eq!(PageNumberStyle::UpperRoman.format(14), "XIV");
+        assert_eq!(PageNumberStyle::UpperAlpha.format(2), "B");
+        assert_eq!(PageNumberStyle::UpperAlpha.format(27), "27");
+    }
+
+    #[test]
+    fn bookmark_labels() {
+        let mut reader = ReaderInfo::default();
+        reader.add_bookmark(4, Some(" resume here for book club ".to_string()));
+        reader.add_bookmark(9, None);
+        reader.add_bookmark(120, Some("Appendix".to_string()));
+        assert_eq!(reader.bookmark_label(4), Some("resume here for book club"));
+        assert_eq!(reader.bookmark_label(9), None);
+
+        assert!(reader.rename_bookmark(9, "Chapter 2"));
+        assert!(!reader.rename_bookmark(10, "Nowhere"));
+        assert!(reader.bookmark_labels.get(&10).is_none());
+        assert!(reader.rename_bookmark(4, ""));
+        assert_eq!(reader.bookmark_label(4), None);
+        assert!(reader.bookmarks.contains(&4));
+
+        // Older files only have the bare set of bookmarks.
+        let json = serde_json::to_string(&reader).unwrap();
+        let parsed: ReaderInfo = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.bookmark_label(9), Some("Chapter 2"));
+        let legacy: ReaderInfo = serde_json::from_str(r#"{"opened": "2020-01-01 00:00:00", "bookmarks": [2, 3]}"#).unwrap();
+        assert_eq!(legacy.bookmarks.len(), 2);
+        assert!(legacy.bookmark_labels.is_empty());
+
+        reader.sanitize(100);
+        assert_eq!(reader.bookmark_label(120), None);
+        assert!(reader.remove_bookmark(9));
+        assert!(!reader.remove_bookmark(9));
+        assert!(reader.bookmark_labels.is_empty());
+    }
+
+    #[test]
+    fn highlight_styles() {
+        let mut style = HighlightStyle::default();
+        for _ in 0..HighlightStyle::ALL.len() {
+            assert_eq!(HighlightStyle::from_str(style.name()), Some(style));
+            style = style.next();
+        }
+        assert_eq!(style, HighlightStyle::default());
+        assert_eq!(HighlightStyle::Underline.next(), HighlightStyle::Gray);
+        assert_eq!(HighlightStyle::from_str("bold"), None);
+
+        let old = r#"{"text":"Call me Ishmael.","selection":[3,19],"modified":"2019-12-01 10:00:00"}"#;
+        let annot: Annotation = serde_json::from_str(old).unwrap();
+        assert_eq!(annot.style, HighlightStyle::Gray);
+        assert!(!serde_json::to_string(&annot).unwrap().contains("style"));
+
+        let mut reader = ReaderInfo::default();
+        reader.annotations = vec![annot.clone(), annot.clone(), annot];
+        reader.annotations[1].style = HighlightStyle::Dark;
+        let json = serde_json::to_string(&reader.annotations[1]).unwrap();
+        assert!(json.contains(r#""style":"dark""#));
+        assert_eq!(reader.annotations_with_style(HighlightStyle::Dark).count(), 1);
+        assert_eq!(reader.annotations_with_style(HighlightStyle::Gray).count(), 2);
+        assert_eq!(reader.annotations_with_style(HighlightStyle::Underline).count(), 0);
+    }
+
+    #[test]
+    fn annotations_round_trip() {
+        let annotation = |page: usize, text: &str, day: u32| Annotation {
+            text: text.to_string(),
+            selection: [TextLocation::Static(page, 0), TextLocation::Static(page, 12)],
+            modified: Local.ymd(2020, 5, day).and_hms(8, 0, 0),
+            .. Default::default()
+        };
+        let mut info = Info {
+            title: "Flatland".to_string(),
+            file: FileInfo { path: PathBuf::from("flatland.pdf"), kind: "pdf".to_string(), .. Default::default() },
+            reader: Some(ReaderInfo {
+                pages_count: 90,
+                annotations: vec![annotation(4, "Of the Nature of Flatland", 1),
+                                  annotation(12, "Of the Climate and Houses", 2),
+                                  annotation(30, "Concerning the Inhabitants", 3)],
+                .. Default::default()
+            }),
+            .. Default::default()
+        };
+        let original = info.reader.as_ref().unwrap().annotations.clone();
+
+        let out = TempDir::new("annotations-import");
+        export_annotations(&info, ExportFormat::Json, &out).unwrap();
+        let source = out.join("flatland.json");
+        let mut json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&source).unwrap()).unwrap();
+        {
+            let annotations = json["annotations"].as_array_mut().unwrap();
+            annotations[1]["note"] = "Edited on a computer.".into();
+            annotations[1]["modified"] = "2020-06-01 10:00:00".into();
+            annotations[2]["note"] = "Stale edit.".into();
+            annotations[2]["modified"] = "2019-01-01 10:00:00".into();
+            let mut added = annotations[0].clone();
+            added["selection"] = serde_json::json!([[120, 0], [120, 5]]);
+            added["text"] = "A Square".into();
+            added["id"] = "a-square".into();
+            annotations.push(added);
+        }
+        fs::write(&source, serde_json::to_string(&json).unwrap()).unwrap();
+
+        let books = load_annotations(&source).unwrap();
+        let mut newer = vec![info.clone()];
+        assert_eq!(import_annotations(&mut newer, &books, AnnotationStrategy::KeepNewer), (1, 2));
+        let annotations = &newer[0].reader.as_ref().unwrap().annotations;
+        assert_eq!(annotations.len(), 4);
+        assert_eq!(annotations[0].text, original[0].text);
+        assert_eq!(annotations[0].modified, original[0].modified);
+        assert_eq!(annotations[1].note, "Edited on a computer.");
+        assert_eq!(annotations[2].note, "");
+        assert!(annotations[3].dangling);
+        assert!(annotations[..3].iter().all(|a| !a.dangling));
+
+        let mut missing = vec![info.clone()];
+        assert_eq!(import_annotations(&mut missing, &books, AnnotationStrategy::AddMissing), (1, 1));
+        assert_eq!(missing[0].reader.as_ref().unwrap().annotations[1].note, "");
+
+        // The path wins over the title, and a title shared by several books matches none.
+        let mut edition = info.clone();
+        edition.file.path = PathBuf::from("flatland.epub");
+        edition.reader = None;
+        let mut library = vec![edition, info.clone()];
+        assert_eq!(import_annotations(&mut library, &books, AnnotationStrategy::Replace), (1, 4));
+        assert!(library[0].reader.is_none());
+        let annotations = &library[1].reader.as_ref().unwrap().annotations;
+        assert_eq!(annotations[2].note, "Stale edit.");
+        assert_eq!(annotations.iter().map(|a| a.selection).collect::<Vec<_>>()[..3],
+                   original.iter().map(|a| a.selection).collect::<Vec<_>>()[..]);
+
+        json.as_object_mut().unwrap().remove("path");
+        fs::write(&source, serde_json::to_string(&json).unwrap()).unwrap();
+        let legacy = load_annotations(&source).unwrap();
+        assert_eq!(import_annotations(&mut library, &legacy, AnnotationStrategy::Replace), (0, 0));
+        library[0].title = "Sphereland".to_string();
+        assert_eq!(import_annotations(&mut library, &legacy, AnnotationStrategy::Replace), (1, 4));
+        library[1].title = "Sphereland".to_string();
+        library[1].isbn = "9780061972157".to_string();
+        json["isbn"] = library[1].isbn.clone().into();
+        fs::write(&source, serde_json::to_string(&json).unwrap()).unwrap();
+        let legacy = load_annotations(&source).unwrap();
+        assert_eq!(import_annotations(&mut library, &legacy, AnnotationStrategy::Replace), (1, 4));
+        assert!(library[0].reader.is_none());
+    }
+
+    #[test]
+    fn annotation_dates() {
+        let old = r#"{"text":"Call me Ishmael.","selection":[3,19],"modified":"2019-12-01 10:00:00"}"#;
+        let annot = stored_annotation(old);
+        assert_eq!(annot.created, Local.ymd(2019, 12, 1).and_hms(10, 0, 0));
+        assert_eq!(annot.created, annot.modified);
+        let new = r#"{"selection":[3,19],"created":"2019-11-02 08:30:00","modified":"2019-12-01 10:00:00"}"#;
+        let annot: Annotation = serde_json::from_str(new).unwrap();
+        assert_eq!(annot.created, Local.ymd(2019, 11, 2).and_hms(8, 30, 0));
+        let json = serde_json::to_string(&annot).unwrap();
+        assert!(json.contains(r#""created":"2019-11-02 08:30:00","modified":"2019-12-01 10:00:00""#));
+
+        let annotation = |start: usize, created: u32, modified: u32| Annotation {
+            selection: [TextLocation::Dynamic(start), TextLocation::Dynamic(start + 5)],
+            created: Local.ymd(2020, 6, created).and_hms(12, 0, 0),
+            modified: Local.ymd(2020, 6, modified).and_hms(12, 0, 0),
+            .. Default::default()
+        };
+        let reader = |annotations: Vec<Annotation>| Some(ReaderInfo { annotations, .. Default::default() });
+        let mut md = vec![info_with_counts("a", None), info_with_counts("b", None), info_with_counts("c", None)];
+        md[0].reader = reader(vec![annotation(10, 1, 20), annotation(0, 5, 6)]);
+        md[1].reader = reader(vec![annotation(0, 9, 10)]);
+        let r = md[0].reader.as_ref().unwrap();
+        let starts = |annotations: Vec<&Annotation>| annotations.iter().map(|a| a.selection[0].location()).collect::<Vec<usize>>();
+        assert_eq!(starts(r.annotations_by_date(AnnotationDate::Created)), vec![10, 0]);
+        assert_eq!(starts(r.annotations_by_date(AnnotationDate::Modified)), vec![0, 10]);
+
+        sort(&mut md, SortMethod::Annotated, true);
+        assert_eq!(paths(&md), vec!["b", "a", "c"]);
+        sort(&mut md, SortMethod::AnnotationModified, true);
+        assert_eq!(paths(&md), vec!["a", "b", "c"]);
+
+        let out = TempDir::new("annotation-dates");
+        let mut info = md[0].clone();
+        info.title = "Moby-Dick".to_string();
+        export_annotations(&info, ExportFormat::Markdown, &out).unwrap();
+        export_annotations(&info, ExportFormat::Json, &out).unwrap();
+        let text = fs::read_to_string(out.join("a.md")).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(out.join("a.json")).unwrap()).unwrap();
+        assert!(text.contains("*2020-06-01 12:00 (edited 2020-06-20 12:00)*"));
+        assert_eq!(json["annotations"][1]["created"], "2020-06-01 12:00:00");
+        assert_eq!(json["annotations"][1]["modified"], "2020-06-20 12:00:00");
+
+        let mut base = md[0].clone();
+        let mut incoming = md[0].clone();
+        {
+            let annot = &mut incoming.reader.as_mut().unwrap().annotations[1];
+            annot.created = Local.ymd(2020, 6, 3).and_hms(12, 0, 0);
+            annot.modified = Local.ymd(2020, 6, 7).and_hms(12, 0, 0);
+            annot.note = "Edited elsewhere.".to_string();
+        }
+        merge_info(&mut base, incoming, MergeStrategy::PreferBase);
+        let annot = &base.reader.as_ref().unwrap().annotations[1];
+        assert_eq!(annot.note, "Edited elsewhere.");
+        assert_eq!(annot.created, Local.ymd(2020, 6, 3).and_hms(12, 0, 0));
+    }
+
+    #[test]
+    fn annotation_tags() {
+        let annotation = |start: usize, tags: &[&str]| {
+            let mut annot = Annotation {
+                text: format!("Passage {}", start),
+                selection: [TextLocation::Dynamic(start), TextLocation::Dynamic(start + 10)],
+                modified: Local.ymd(2020, 4, 1).and_hms(9, 0, 0),
+                .. Default::default()
+            };
+            for tag in tags {
+                annot.add_tag(tag);
+            }
+            annot
+        };
+
+        let mut annot = annotation(0, &["  Quote ", "quote", "Look  Up", ""]);
+        assert_eq!(annot.tags.iter().map(String::as_str).collect::<Vec<_>>(), vec!["look up", "quote"]);
+        assert!(!annot.add_tag("QUOTE"));
+        assert!(annot.remove_tag(" Look up"));
+        assert!(!annot.remove_tag("todo"));
+        assert!(serde_json::to_string(&annot).unwrap().contains(r#""tags":["quote"]"#));
+        assert!(!serde_json::to_string(&annotation(0, &[])).unwrap().contains("tags"));
+
+        let book = |name: &str, annotations: Vec<Annotation>| Info {
+            title: name.to_string(),
+            file: FileInfo { path: PathBuf::from(format!("{}.epub", name)), kind: "epub".to_string(), .. Default::default() },
+            reader: Some(ReaderInfo { pages_count: 1000, annotations, .. Default::default() }),
+            .. Default::default()
+        };
+        let md = vec![book("Emma", vec![annotation(300, &["vocab"]), annotation(20, &["Vocab", "todo"]),
+                                        annotation(100, &["quote"])]),
+                      book("Persuasion", vec![]),
+                      book("Sanditon", vec![annotation(50, &["todo"]), annotation(5, &["VOCAB"])])];
+        assert_eq!(annotations_with_tag(&md, "vocab"), vec![(0, 1), (0, 0), (2, 1)]);
+        assert_eq!(annotations_with_tag(&md, " TODO"), vec![(0, 1), (2, 0)]);
+        assert!(annotations_with_tag(&md, "missing").is_empty());
+
+        let out = TempDir::new("annotation-tags");
+        export_annotations(&md[0], ExportFormat::Markdown, &out).unwrap();
+        export_annotations(&md[0], ExportFormat::Json, &out).unwrap();
+        let text = fs::read_to_string(out.join("Emma.md")).unwrap();
+        assert!(text.contains("> Passage 20\n\n`todo` `vocab`\n\n"));
+
+        let source = out.join("Emma.json");
+        let mut json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&source).unwrap()).unwrap();
+        assert_eq!(json["annotations"][0]["tags"], serde_json::json!(["todo", "vocab"]));
+        json["annotations"][1]["tags"] = serde_json::json!(["Quote", " Favorite  Lines "]);
+        fs::write(&source, serde_json::to_string(&json).unwrap()).unwrap();
+        let mut library = vec![md[0].clone()];
+        import_annotations(&mut library, &load_annotations(&source).unwrap(), AnnotationStrategy::Replace);
+        let annotations = &library[0].reader.as_ref().unwrap().annotations;
+        assert_eq!(annotations[0].tags, md[0].reader.as_ref().unwrap().annotations[1].tags);
+        assert_eq!(annotations[1].tags.iter().map(String::as_str).collect::<Vec<_>>(), vec!["favorite lines", "quote"]);
+    }
+
+    #[test]
+    fn isbn_index() {
+        assert_eq!(normalize_isbn("0-441-47812-3").as_deref(), Some("9780441478125"));
+        assert_eq!(normalize_isbn("080442957X").as_deref(), Some("9780804429573"));
+        assert_eq!(normalize_isbn("978 0 316 24662 0").as_deref(), Some("9780316246620"));
+        assert_eq!(normalize_isbn("0-441-47812-4"), None);
+        assert_eq!(normalize_isbn("9780316246621"), None);
+        assert_eq!(normalize_isbn("X804429570"), None);
+        assert_eq!(normalize_isbn(""), None);
+
+        let with_isbn = |name: &str, isbn: &str| {
+            let mut info = info_with_counts(name, None);
+            info.isbn = isbn.to_string();
+            info
+        };
+        let md = vec![with_isbn("a.epub", "0441478123"),
+                      with_isbn("b.epub", ""),
+                      with_isbn("c.epub", "9780316246620"),
+                      with_isbn("d.epub", "978-0-441-47812-5"),
+                      with_isbn("e.epub", "not an isbn")];
+        let (index, duplicates) = index_by_isbn(&md);
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.get("9780441478125"), Some(&0));
+        assert_eq!(index.get("9780316246620"), Some(&2));
+        assert_eq!(duplicates, vec![3]);
+    }
+
+    #[test]
+    fn duplicate_works() {
+        let book = |name: &str, title: &str, author: &str, isbn: &str| Info {
+            title: title.to_string(),
+            author: author.to_string(),
+            isbn: isbn.to_string(),
+            file: FileInfo { path: PathBuf::from(name), .. Default::default() },
+            .. Default::default()
+        };
+        let md = vec![book("a.epub", "Les Misérables", "Victor Hugo", ""),
+                      book("b.pdf", "Les Miserables.", "Hugo, Victor", ""),
+                      book("c.epub", "Dune", "Frank Herbert", "0441478123"),
+                      book("d.djvu", "Notre-Dame de Paris", "Victor Hugo", ""),
+                      book("e.pdf", "Dune Messiah", "Frank Herbert", ""),
+                      book("f.epub", "Arrakis", "", "978-0-441-47812-5"),
+                      book("g.epub", "Les Misérables", "Jane Doe", ""),
+                      book("h.pdf", "", "", ""),
+                      book("i.epub", "", "", ""),
+                      book("j.epub", "Notebooks", "", ""),
+                      book("k.pdf", "Notebooks", "", "")];
+        assert_eq!(find_duplicate_works(&md), vec![vec![0, 1], vec![2, 5]]);
+        assert!(find_duplicate_works(&Vec::new()).is_empty());
+    }
+
+    struct FakeProvider;
+
+    impl MetadataProvider for FakeProvider {
+        fn lookup(&self, query: &MetadataQuery) -> Result<Option<Info>, Error> {
+            let mut info = Info::default();
+            match (query.isbn.as_deref(), query.title.as_str()) {
+                (Some("9780441478125"), _) => {
+                    info.title = "The Left Hand of Darkness".to_string();
+                    info.author = "Ursula K. Le Guin".to_string();
+                    info.year = "1969".to_string();
+                    info.publisher = "Ace Books".to_string();
+                    info.categories.insert("Science Fiction".to_string());
+                },
+                (_, "Dune") => {
+                    info.title = "Dune".to_string();
+                    info.author = "Frank Herbert".to_string();
+                    info.language = "en".to_string();
+                },
+                (_, "Offline") => return Err(format_err!("network unreachable")),
+                _ => return Ok(None),
+            }
+            Ok(Some(info))
+        }
+    }
+
+    #[test]
+    fn metadata_enrichment() {
+        let book = |name: &str, title: &str, isbn: &str| {
+            let mut info = info_with_counts(name, None);
+            info.title = title.to_string();
+            info.isbn = isbn.to_string();
+            info
+        };
+        let mut md = vec![book("a.epub", "", "0-441-47812-3"),
+                          book("b.epub", "Dune", ""),
+                          book("c.epub", "", ""),
+                          book("d.epub", "Offline", ""),
+                          book("e.epub", "Unknown", "")];
+        md[0].year = "1976".to_string();
+        md[1].author = "F. Herbert".to_string();
+
+        let mut settings = ImportSettings::default();
+        settings.category_providers.insert(CategoryProvider::Subject);
+        enrich(&mut md, &FakeProvider, &settings);
+
+        assert_eq!(md[0].title, "The Left Hand of Darkness");
+        assert_eq!(md[0].year, "1976");
+        assert_eq!(md[0].publisher, "Ace Books");
+        assert!(md[0].categories.contains("Science Fiction"));
+        assert_eq!(md[1].author, "F. Herbert");
+        assert_eq!(md[1].language, "en");
+        assert!(md[2].title.is_empty());
+        assert!(md[3].author.is_empty());
+        assert!(md[4].author.is_empty());
+
+        let mut md = vec![book("a.epub", "", "0441478123")];
+        enrich(&mut md, &NoProvider, &settings);
+        assert!(md[0].title.is_empty());
+    }
+
+    #[test]
+    fn thumbnail_keys() {
+        let file = |path: &str, size: u64, checksum: Option<&str>| FileInfo {
+            path: PathBuf::from(path),
+            kind: "epub".to_string(),
+            size,
+            checksum: checksum.map(String::from),
+        };
+        let a = file("Books/dune.epub", 1024, Some("d41d8cd98f00b204"));
+        let b = file("Herbert/Dune.epub", 2048, Some("d41d8cd98f00b204"));
+        let c = file("Books/dune.epub", 1024, Some("9e107d9d372bb682"));
+        assert_eq!(a.cache_key(), b.cache_key());
+        assert_ne!(a.cache_key(), c.cache_key());
+        assert_eq!(a.cache_key().len(), 16);
+
+        let d = file("Books/dune.epub", 1024, None);
+        assert_eq!(d.cache_key(), file("Books/dune.epub", 1024, None).cache_key());
+        assert_ne!(d.cache_key(), file("Herbert/Dune.epub", 1024, None).cache_key());
+        assert_ne!(d.cache_key(), file("Books/dune.epub", 1025, None).cache_key());
+        assert_ne!(d.cache_key(), a.cache_key());
+
+        let cache_dir = Path::new("/mnt/onboard/.thumbnails");
+        assert_eq!(thumbnail_path(cache_dir, &a), thumbnail_path(cache_dir, &b));
+        assert_eq!(thumbnail_path(cache_dir, &a), cache_dir.join(format!("{}.png", a.cache_key())));
+    }
+
+    #[test]
+    fn lookup_by_path() {
+        let mut md = vec![info_with_counts("a.epub", None), info_with_counts("b/c.pdf", None)];
+        let index = path_index(&md);
+        assert_eq!(index.get(Path::new("b/c.pdf")), Some(&1));
+        assert_eq!(index.get(Path::new("c.pdf")), None);
+        assert_eq!(find_by_path(&md, Path::new("a.epub")).map(|info| info.file.kind.as_str()), Some(""));
+        assert!(find_by_path(&md, Path::new("missing.epub")).is_none());
+        find_by_path_mut(&mut md, Path::new("b/c.pdf")).unwrap().title = "C".to_string();
+        assert_eq!(md[1].title, "C");
+        assert!(find_by_path_mut(&mut md, Path::new("b")).is_none());
+    }
+
+    #[test]
+    fn merge() {
+        // The copies of an annotation on two devices share its identifier.
+        let annotation = |page: usize, note: &str, day: u32| Annotation {
+            id: format!("annotation-{}", page),
+            note: note.to_string(),
+            selection: [TextLocation::Dynamic(page), TextLocation::Dynamic(page + 10)],
+            modified: Local.ymd(2020, 1, day).and_hms(0, 0, 0),
+            .. Default::default()
+        };
+        let book = |title: &str, author: &str, category: &str, reader: ReaderInfo| Info {
+            title: title.to_string(),
+            author: author.to_string(),
+            categories: [category].iter().map(|c| c.to_string()).collect(),
+            file: FileInfo { path: PathBuf::from("Books/book.epub"), kind: "epub".to_string(), size: 1024, .. Default::default() },
+            reader: Some(reader),
+            .. Default::default()
+        };
+        let base_book = book("Book", "", "Novels", ReaderInfo {
+            opened: Local.ymd(2020, 1, 10).and_hms(0, 0, 0),
+            current_page: 12,
+            bookmarks: [1].iter().cloned().collect(),
+            annotations: vec![annotation(100, "a", 1), annotation(200, "b", 1)],
+            .. Default::default()
+        });
+        let incoming_book = book("", "Jane Doe", "Favorites", ReaderInfo {
+            opened: Local.ymd(2020, 1, 20).and_hms(0, 0, 0),
+            current_page: 42,
+            bookmarks: [5].iter().cloned().collect(),
+            annotations: vec![annotation(200, "b'", 2), annotation(300, "c", 1)],
+            .. Default::default()
+        });
+        let mut moved_book = info_with_counts("Other/moved.pdf", None);
+        moved_book.file.size = 7;
+        let mut moved_incoming = info_with_counts("Elsewhere/moved.pdf", None);
+        moved_incoming.file.size = 7;
+        moved_incoming.title = "Moved".to_string();
+
+        for &(strategy, current_page) in &[(MergeStrategy::Newest, 42), (MergeStrategy::PreferBase, 12)] {
+            let mut base = vec![base_book.clone(), moved_book.clone()];
+            let incoming = vec![incoming_book.clone(), moved_incoming.clone(), info_with_counts("new.epub", None)];
+            merge_metadata(&mut base, incoming, strategy);
+
+            assert_eq!(paths(&base), vec!["Books/book.epub", "Other/moved.pdf", "new.epub"]);
+            let info = &base[0];
+            assert_eq!(info.title, "Book");
+            assert_eq!(info.author, "Jane Doe");
+            assert_eq!(info.categories.iter().map(String::as_str).collect::<Vec<&str>>(), vec!["Favorites", "Novels"]);
+            let reader = info.reader.as_ref().unwrap();
+            assert_eq!(reader.current_page, current_page);
+            assert_eq!(reader.opened, Local.ymd(2020, 1, 20).and_hms(0, 0, 0));
+            assert_eq!(reader.bookmarks.iter().cloned().collect::<Vec<usize>>(), vec![1, 5]);
+            let mut notes = reader.annotations.iter().map(|a| a.note.as_str()).collect::<Vec<&str>>();
+            notes.sort();
+            assert_eq!(notes, vec!["a", "b'", "c"]);
+            assert_eq!(base[1].title, "Moved");
+        }
+
+        // Two copies of the same file are matched by checksum, each with a distinct entry.
+        let copy = |path: &str, checksum: Option<&str>, title: &str| {
+            let mut info = info_with_counts(path, None);
+            info.file.size = 7;
+            info.file.checksum = checksum.map(String::from);
+            info.title = title.to_string();
+            info
+        };
+        let mut base = vec![copy("a/book.pdf", Some("1"), ""), copy("b/book.pdf", Some("2"), "")];
+        let incoming = vec![copy("c/book.pdf", Some("2"), "Two"), copy("d/book.pdf", Some("3"), "Three"),
+                            copy("e/book.pdf", None, "Unknown"), copy("f/book.pdf", None, "Other")];
+        merge_metadata(&mut base, incoming, MergeStrategy::Newest);
+        assert_eq!(paths(&base), vec!["a/book.pdf", "b/book.pdf", "d/book.pdf", "f/book.pdf"]);
+        assert_eq!(base[0].title, "Unknown");
+        assert_eq!(base[1].title, "Two");
+    }
+
+    #[test]
+    fn lenient_dates() {
+        let parse = |added: &str| -> Info {
+            serde_json::from_str(&format!(r#"{{"title": "T", "added": "{}"}}"#, added)).unwrap()
+        };
+        let expected = Local.ymd(2020, 3, 14).and_hms(15, 9, 26);
+        assert_eq!(parse("2020-03-14 15:09:26").added, expected);
+        assert_eq!(parse(&expected.to_rfc3339()).added, expected);
+        assert_eq!(parse("2020-03-14").added, Local.ymd(2020, 3, 14).and_hms(0, 0, 0));
+        let info = parse("14/03/2020 at noon");
+        assert_eq!(info.title, "T");
+        assert!(simple_date_format::is_unknown(&info.added));
+        // The malformed dates aren't replaced by made up ones.
+        let json = serde_json::to_string(&info).unwrap();
+        assert!(!json.contains("added"));
+
+        let md: Metadata = serde_json::from_str(r#"[{"title": "A", "added": "garbage"},
+                                                    {"title": "B", "added": "2020-03-14",
+                                                     "reader": {"opened": "garbage"}}]"#).unwrap();
+        assert_eq!(md.len(), 2);
+        let json = serde_json::to_string(&md[1]).unwrap();
+        assert!(json.contains("added") && !json.contains("opened"));
+        let info: Info = serde_json::from_str(r#"{"title": "T", "modified": "garbage"}"#).unwrap();
+        assert_eq!(info.modified, None);
+
+        let annot = stored_annotation(r#"{"text": "T", "created": "2020-03-14", "modified": "garbage"}"#);
+        assert_eq!(annot.modified, annot.created);
+    }
+
+    #[test]
+    fn modified_round_trip() {
+        let info = Info::default();
+        let json = serde_json::to_string(&info).unwrap();
+        assert!(!json.contains("modified"));
+        let modified = Local.ymd(2020, 3, 14).and_hms(15, 9, 26);
+        let info = Info { modified: Some(modified), .. Default::default() };
+        let json = serde_json::to_string(&info).unwrap();
+        let info: Info = serde_json::from_str(&json).unwrap();
+        assert_eq!(info.modified, Some(modified));
+    }
+
+    #[test]
+    fn sort_by_annotations() {
+        let mut md = vec![info_with_counts("a", Some((2, 0))),
+                          info_with_counts("b", None),
+                          info_with_counts("c", Some((5, 1))),
+                          info_with_counts("d", Some((0, 3)))];
+        let method = SortMethod::Annotations;
+        sort(&mut md, method, method.reverse_order());
+        assert_eq!(paths(&md), vec!["c", "a", "b", "d"]);
+        sort(&mut md, method, false);
+        assert_eq!(paths(&md), vec!["b", "d", "a", "c"]);
+    }
+
+    #[test]
+    fn sort_by_bookmarks() {
+        let mut md = vec![info_with_counts("a", Some((2, 0))),
+                          info_with_counts("b", None),
+                          info_with_counts("c", Some((5, 1))),
+                          info_with_counts("d", Some((0, 3)))];
+        let method = SortMethod::Bookmarks;
+        sort(&mut md, method, method.reverse_order());
+        assert_eq!(paths(&md), vec!["d", "c", "a", "b"]);
+    }
+
+    #[test]
+    fn margin_clamping() {
+        let margin = Margin::new(-0.2, 1.5, f32::NAN, 0.25);
+        assert_eq!((margin.top, margin.bottom), (0.0, 0.0));
+        assert!((margin.left + margin.right - MAX_CROPPED_FRACTION).abs() < 1e-6);
+        assert!(margin.right > margin.left);
+
+        let margin = Margin::new(0.6, 0.1, 0.6, f32::INFINITY);
+        assert!((margin.top - 0.45).abs() < 1e-6 && (margin.bottom - 0.45).abs() < 1e-6);
+        assert!((margin.left + margin.right - MAX_CROPPED_FRACTION).abs() < 1e-6);
+
+        let corrupt: CroppingMargins = serde_json::from_str(r#"[{"top": -1.0, "right": 0.1, "bottom": 0.2, "left": 0.1},
+                                                              {"top": 0.1, "right": 7.0, "bottom": 0.2, "left": 0.1}]"#).unwrap();
+        let mut margins = corrupt.clone();
+        margins.clamp();
+        assert_eq!(margins.margin(0).top, 0.0);
+        assert!(margins.margin(1).right <= MAX_CROPPED_FRACTION);
+
+        let mut margins = corrupt;
+        margins.apply(1, PageScheme::EvenOdd);
+        let (even, odd) = (margins.margin(0), margins.margin(1));
+        assert_eq!((even.top, even.right, even.bottom, even.left),
+                   (odd.top, odd.right, odd.bottom, odd.left));
+        assert!(even.left + even.right <= MAX_CROPPED_FRACTION + 1e-6);
+    }
+
+    #[test]
+    fn annotation_ids() {
+        let first = Annotation::default();
+        let second = Annotation::default();
+        assert_ne!(first.id, second.id);
+        assert_eq!(first.id.len(), 36);
+        assert_eq!(&first.id[14..15], "4");
+
+        let json = serde_json::to_string(&first).unwrap();
+        let annot: Annotation = serde_json::from_str(&json).unwrap();
+        assert_eq!(annot.id, first.id);
+
+        // Two copies of an old file get the same identifiers.
+        let legacy = r#"{"text": "Flatland", "selection": [[3, 0], [3, 8]], "modified": "2019-04-02 10:00:00"}"#;
+        let copy_a = stored_annotation(legacy);
+        let copy_b = stored_annotation(legacy);
+        assert!(!copy_a.id.is_empty());
+        assert_eq!(copy_a.id, copy_b.id);
+        let other = stored_annotation(&legacy.replace("[3, 8]", "[3, 9]"));
+        assert_ne!(other.id, copy_a.id);
+
+        // The identifier wins over the selection and the text.
+        let mut edited = copy_b.clone();
+        edited.text = "Flatland: A Romance".to_string();
+        edited.selection = [TextLocation::Static(3, 0), TextLocation::Static(3, 19)];
+        let annotations = vec![Annotation::default(), copy_a];
+        assert_eq!(matching_annotation(&annotations, &edited, |a| a.selection == edited.selection), Some(1));
+        edited.id = "unknown".to_string();
+        assert_eq!(matching_annotation(&annotations, &edited, |a| a.text == "Flatland"), Some(1));
+        assert_eq!(matching_annotation(&annotations, &edited, |a| a.selection == edited.selection), None);
+
+        // Two annotations with generated identifiers are never matched by their content.
+        assert!(is_generated_id(&first.id));
+        assert!(!is_generated_id(&copy_b.id));
+        let twin = Annotation { text: first.text.clone(), selection: first.selection, .. Default::default() };
+        assert_eq!(matching_annotation(&[first.clone()], &twin, |a| a.selection == twin.selection), None);
+        assert_eq!(matching_annotation(&[first.clone()], &copy_b, |a| a.selection == first.selection), Some(0));
+    }
+
+    #[test]
+    fn cover_margins() {
+        let margin = |v: f32| Margin::new(v, v, v, v);
+        let mut margins = CroppingMargins::CoverEvenOdd([margin(0.3), margin(0.1), margin(0.2)]);
+        assert_eq!(margins.margin(0).top, 0.3);
+        assert_eq!(margins.margin(1).top, 0.2);
+        assert_eq!(margins.margin(2).top, 0.1);
+        assert_eq!(margins.margin(3).top, 0.2);
+        assert!(margins.is_split());
+        assert_eq!(margins.scheme(), PageScheme::Cover);
+
+        margins.margin_mut(4).left = 0.05;
+        assert_eq!(margins.margin(2).left, 0.05);
+        assert_eq!(margins.margin(0).left, 0.3);
+
+        // The untagged variants are told apart by their shapes.
+        let json = serde_json::to_string(&margins).unwrap();
+        let parsed: CroppingMargins = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.scheme(), PageScheme::Cover);
+        let pair: CroppingMargins = serde_json::from_str(r#"[{"top": 0.1, "right": 0.1, "bottom": 0.1, "left": 0.1},
+                                                            {"top": 0.2, "right": 0.2, "bottom": 0.2, "left": 0.2}]"#).unwrap();
+        assert_eq!(pair.scheme(), PageScheme::EvenOdd);
+        let single: CroppingMargins = serde_json::from_str(r#"{"top": 0.1, "right": 0.1, "bottom": 0.1, "left": 0.1}"#).unwrap();
+        assert_eq!(single.scheme(), PageScheme::Any);
+
+        let mut applied = pair.clone();
+        applied.apply(1, PageScheme::Cover);
+        assert_eq!((applied.margin(0).top, applied.margin(1).top, applied.margin(2).top), (0.2, 0.2, 0.2));
+        applied.apply(0, PageScheme::EvenOdd);
+        assert_eq!(applied.scheme(), PageScheme::EvenOdd);
+    }
+
+    #[test]
+    fn margin_padding() {
+        let margin = Margin::new(0.3, 0.1, 0.5, 0.02).padded(0.05);
+        assert_eq!((margin.right, margin.left), (0.05, 0.0));
+        let margin = margin.limited(0.4);
+        assert!((margin.top + margin.bottom - 0.4).abs() < 1e-5);
+        assert!((margin.top / margin.bottom - 0.25 / 0.45).abs() < 1e-5);
+        assert_eq!(margin.right, 0.05);
+    }
+
+    #[test]
+    fn margin_exceptions() {
+        let margin = |v: f32| Margin::new(v, v, v, v);
+        let mut margins = CroppingMargins::EvenOdd([margin(0.1), margin(0.2)]);
+        margins.add_exception(3);
+        assert!(margins.has_exception(3));
+        assert!(!margins.has_exception(1));
+        assert_eq!(margins.margin(3).top, 0.2);
+        assert_eq!(margins.scheme(), PageScheme::EvenOdd);
+
+        // An exception wins over the even/odd margins.
+        margins.margin_mut(3).top = 0.3;
+        margins.margin_mut(1).top = 0.25;
+        assert_eq!((margins.margin(1).top, margins.margin(3).top, margins.margin(5).top), (0.25, 0.3, 0.25));
+        assert_eq!(margins.margin(2).top, 0.1);
+
+        // Changing the scheme keeps the exceptions.
+        margins.apply(0, PageScheme::Any);
+        assert_eq!(margins.scheme(), PageScheme::Any);
+        assert_eq!((margins.margin(1).top, margins.margin(3).top), (0.1, 0.3));
+
+        let json = serde_json::to_string(&margins).unwrap();
+        let mut parsed: CroppingMargins = serde_json::from_str(&json).unwrap();
+        assert!(parsed.has_exception(3));
+        assert_eq!((parsed.margin(2).top, parsed.margin(3).top), (0.1, 0.3));
+
+        parsed.add_exception(6);
+        parsed.clear_exception(3);
+        assert!(!parsed.has_exception(3));
+        assert!(parsed.has_exception(6));
+        parsed.clear_exception(6);
+        assert!(matches!(parsed, CroppingMargins::Any(..)));
+
+        margins.add_exception(8);
+        margins.clear_exceptions();
+        assert!(matches!(margins, CroppingMargins::Any(..)));
+        assert_eq!(margins.margin(3).top, 0.1);
+    }
+
+    // A reflowable document whose words are *spacing* bytes apart, from *shift*.
+    // Pages of words whose offsets depend on the layout.
+    struct PagedWords {
+        words: Vec<&'static str>,
+        words_per_page: usize,
+        shift: usize,
+        spacing: usize,
+        pages_read: usize,
+    }
+
+    impl PagedWords {
+        fn words(&mut self, loc: Location) -> PageWords {
+            let pages_count = (self.words.len() + self.words_per_page - 1) / self.words_per_page;
+            let page_of = |offset: usize| (offset.saturating_sub(self.shift) / self.spacing / self.words_per_page).min(pages_count - 1);
+            let page = match loc {
+                Location::Exact(offset) => Some(page_of(offset)),
+                Location::Previous(offset) => page_of(offset).checked_sub(1),
+                Location::Next(offset) => Some(page_of(offset) + 1).filter(|&p| p < pages_count),
+                _ => None,
+            }?;
+            self.pages_read += 1;
+            let start = page * self.words_per_page;
+            let end = (start + self.words_per_page).min(self.words.len());
+            Some(((start..end).map(|i| BoundedText {
+                text: self.words[i].to_string(),
+                rect: Rectangle::default().into(),
+                location: TextLocation::Dynamic(self.shift + i * self.spacing),
+            }).collect(), self.shift + start * self.spacing))
+        }
+    }
+
+    #[test]
+    fn annotation_relocation() {
+        let text = "It was the best of times it was the worst of times it was the age of wisdom \
+                    it was the age of foolishness it was the epoch of belief it was the epoch of incredulity";
+        let mut doc = PagedWords {
+            words: text.split_whitespace().collect(),
+            words_per_page: 6,
+            shift: 0,
+            spacing: 10,
+            pages_read: 0,
+        };
+
+        // Select the second "it was the age".
+        let words = words_around(&mut |loc| doc.words(loc), 0, text.len()).unwrap();
+        assert_eq!(words.len(), doc.words.len());
+        let sel = [TextLocation::Dynamic(180), TextLocation::Dynamic(210)];
+        let context = annotation_context(&words, sel);
+        assert_eq!(context.before, "f times it was the age of wisdom");
+        assert_eq!(context.after, "of foolishness it was the epoch ");
+        let annot = |sel: [TextLocation; 2], text: &str, context: Option<AnnotationContext>| Annotation {
+            text: text.to_string(),
+            selection: sel,
+            context,
+            .. Default::default()
+        };
+        let mut reader = ReaderInfo {
+            annotations: vec![annot(sel, "it was the age", Some(context)),
+                              annot([TextLocation::Dynamic(20), TextLocation::Dynamic(30)], "the best", None),
+                              annot([TextLocation::Dynamic(40), TextLocation::Dynamic(50)], "vanished text", None),
+                              annot([TextLocation::Static(1, 0), TextLocation::Static(1, 3)], "Static", None)],
+            .. Default::default()
+        };
+
+        // Nothing moved: only the pages of the annotations are read, and the pages
+        // that follow the first one for the missing text.
+        doc.pages_read = 0;
+        assert_eq!(relocate_annotations_with(&mut |loc| doc.words(loc), &mut reader), 0);
+        assert_eq!(doc.pages_read, 3 + 1 + RELOCATION_RADIUS);
+        assert_eq!(reader.annotations[0].selection, sel);
+        assert!(!reader.annotations[0].dangling);
+        assert!(reader.annotations[2].dangling);
+
+        // The layout changed: every offset is shifted, and the first annotation is found
+        // through its context rather than at the closest occurrence of its text.
+        doc.shift = 25;
+        doc.spacing = 11;
+        assert_eq!(relocate_annotations_with(&mut |loc| doc.words(loc), &mut reader), 2);
+        let annotations = &reader.annotations;
+        assert_eq!(annotations[0].selection, [TextLocation::Dynamic(25 + 18 * 11), TextLocation::Dynamic(25 + 21 * 11)]);
+        assert_eq!(annotations[1].selection, [TextLocation::Dynamic(25 + 2 * 11), TextLocation::Dynamic(25 + 3 * 11)]);
+        assert!(annotations[2].dangling);
+        assert_eq!(annotations[2].selection, [TextLocation::Dynamic(40), TextLocation::Dynamic(50)]);
+        assert_eq!(annotations[3].selection, [TextLocation::Static(1, 0), TextLocation::Static(1, 3)]);
+    }
+
+    #[test]
+    fn toc_search() {
+        let toc = fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/toc/nested.json")).unwrap();
+        let info = Info {
+            title: "The Country of the Blind".to_string(),
+            toc: Some(serde_json::from_str(&toc).unwrap()),
+            .. Default::default()
+        };
+
+        let flat = info.flatten_toc();
+        assert_eq!(flat.iter().map(|(depth, entry)| format!("{} {}", depth, entry.title())).collect::<Vec<String>>(),
+                   vec!["0 Preface", "0 Part One: The Country of the Blind", "1 Chapter 1. Nunez",
+                        "1 Chapter 2. The Valley", "2 The Wall", "2 The Llamas",
+                        "0 Part Two: The Door in the Wall", "1 Chapter 3. The Green Door", "0 Afterword"]);
+
+        let titles = |query: &str| info.search_toc(&Regex::new(query).unwrap()).iter()
+                                       .map(|entry| entry.title()).collect::<Vec<&str>>();
+        assert_eq!(titles("(?i)wall"), vec!["The Wall", "Part Two: The Door in the Wall"]);
+        assert_eq!(titles("Llamas"), vec!["The Llamas"]);
+        assert!(titles("Nobody").is_empty());
+
+        let outline = |max_depth| info.toc_to_depth(max_depth).iter()
+                                      .map(|entry| entry.title()).collect::<Vec<&str>>();
+        assert_eq!(outline(0), vec!["Preface", "Part One: The Country of the Blind",
+                                    "Part Two: The Door in the Wall", "Afterword"]);
+        assert_eq!(outline(1), vec!["Preface", "Part One: The Country of the Blind", "Chapter 1. Nunez",
+                                    "Chapter 2. The Valley", "Part Two: The Door in the Wall",
+                                    "Chapter 3. The Green Door", "Afterword"]);
+        assert_eq!(outline(2).len(), flat.len());
+
+        assert!(info.is_match(&Regex::new("Green Door").ok()));
+        assert!(!info.is_match(&Regex::new("Red Door").ok()));
+        assert!(Info::default().flatten_toc().is_empty());
+    }
+
+    #[test]
+    fn finished_dates() {
+        let mut reader = ReaderInfo::default();
+        assert_eq!(reader.finished_at, None);
+        reader.set_finished(true);
+        let date = reader.finished_at.unwrap();
+        reader.set_finished(true);
+        assert_eq!(reader.finished_at, Some(date));
+        reader.set_finished(false);
+        assert_eq!(reader.finished_at, None);
+        assert_eq!(reader.finish_history, vec![date]);
+        reader.set_finished(true);
+        assert!(reader.finished_at.unwrap() >= date);
+        assert_eq!(reader.finish_history.len(), 2);
+
+        // Older files don't have the date, or only the last one.
+        let reader: ReaderInfo = serde_json::from_str(r#"{"finished": true}"#).unwrap();
+        assert!(reader.finished);
+        assert_eq!(reader.finished_at, None);
+        let reader: ReaderInfo = serde_json::from_str(r#"{"finished": true, "finishedDate": "2020-06-03 21:00:00"}"#).unwrap();
+        assert_eq!(reader.finish_dates().collect::<Vec<_>>(), vec![Local.ymd(2020, 6, 3).and_hms(21, 0, 0)]);
+
+        let book = |name: &str, finished: bool, day: Option<u32>| Info {
+            file: FileInfo { path: PathBuf::from(name), .. Default::default() },
+            reader: Some(ReaderInfo {
+                finished,
+                finished_at: day.filter(|_| finished).map(|d| Local.ymd(2020, 6, d).and_hms(21, 0, 0)),
+                finish_history: day.map(|d| Local.ymd(2020, 6, d).and_hms(21, 0, 0)).into_iter().collect(),
+                .. Default::default()
+            }),
+            .. Default::default()
+        };
+        let md = vec![book("a.epub", true, Some(3)), book("b.epub", false, Some(9)),
+                      book("c.epub", true, Some(12)), book("d.epub", true, None),
+                      Info::default(), book("e.epub", true, Some(7))];
+        let json = serde_json::to_value(&md[0].reader).unwrap();
+        assert_eq!(json["finishedAt"], "2020-06-03 21:00:00");
+        assert_eq!(json["finishHistory"], serde_json::json!(["2020-06-03 21:00:00"]));
+
+        // The book being read again is still among the recently finished ones.
+        let recent = recently_finished(&md, 2);
+        assert_eq!(recent.iter().map(|info| info.file.path.to_str().unwrap()).collect::<Vec<&str>>(),
+                   vec!["c.epub", "b.epub"]);
+        assert_eq!(recently_finished(&md, 10).len(), 4);
+
+        let mut md = md;
+        sort(&mut md, SortMethod::Finished, true);
+        assert_eq!(paths(&md)[..3], ["c.epub", "e.epub", "a.epub"]);
+    }
+
+    #[test]
+    fn reading_goals() {
+        let book = |finished: bool, finish_history: Vec<DateTime<Local>>, opened: DateTime<Local>| Info {
+            reader: Some(ReaderInfo {
+                finished,
+                finished_at: finish_history.last().cloned().filter(|_| finished),
+                finish_history,
+                opened,
+                .. Default::default()
+            }),
+            .. Default::default()
+        };
+        let date = |y: i32, m: u32| Local.ymd(y, m, 15).and_hms(20, 0, 0);
+        let md = vec![book(true, vec![date(2019, 12)], date(2020, 2)),
+                      book(true, vec![date(2020, 1)], date(2020, 1)),
+                      book(true, vec![date(2019, 5), date(2020, 11)], date(2020, 11)),
+                      book(true, Vec::new(), date(2020, 6)),
+                      book(false, vec![date(2020, 3)], date(2020, 9)),
+                      book(false, Vec::new(), date(2020, 4)),
+                      Info::default()];
+        assert_eq!(books_finished_in_year(&md, 2019), 2);
+        assert_eq!(books_finished_in_year(&md, 2020), 4);
+        assert_eq!(books_finished_in_year(&md, 2021), 0);
+        assert_eq!(reading_goal_progress(&md, 2020, 52), (4, 52));
+    }
+
+    #[test]
+    fn refresh_every() {
+        let json = serde_json::to_value(&ReaderInfo::default()).unwrap();
+        assert!(json.get("refreshEvery").is_none());
+        let reader: ReaderInfo = serde_json::from_str(r#"{"refreshEvery": 1}"#).unwrap();
+        assert_eq!(reader.refresh_every, Some(1));
+    }
+
+    #[test]
+    fn exact_queries() {
+        let book = |title: &str| Info { title: title.to_string(), .. Default::default() };
+        let (dune, messiah) = (book("Dune"), book("Dune Messiah"));
+        let query = make_query("dune");
+        assert!(dune.is_match(&query) && messiah.is_match(&query));
+        let query = make_query("=dune");
+        assert!(dune.is_match(&query) && !messiah.is_match(&query));
+        assert!(book("Düne").is_match(&make_query("= dune ")));
+        assert!(book("a=b").is_match(&make_query("=")));
+        assert!(!book("Dune").is_match(&make_query("=une")));
+    }
+
+    #[test]
+    fn punctuation_folding() {
+        assert_eq!(fold_punctuation(" Don’t  stop—the \"music\" "), "Dont stop the music");
+        let book = |title: &str| Info { title: title.to_string(), .. Default::default() };
+        let (dont, dash) = (book("Don't Panic"), book("Hyphen\u{2014}dash  and the rest"));
+        assert!(dont.is_match_with(&make_query_with("dont", true), true));
+        assert!(dash.is_match_with(&make_query_with("hyphen dash", true), true));
+        assert!(dash.is_match_with(&make_query_with("hyphen-dash and", true), true));
+        assert!(dont.is_match_with(&make_query_with("=don’t panic", true), true));
+        assert!(!dont.is_match_with(&make_query_with("dont", false), false));
+        assert!(!dash.is_match_with(&make_query_with("hyphen dash", false), false));
+        assert!(dont.is_match_with(&make_query_with("don't", false), false));
+
+        assert!(matches!(fold_punctuation("Don Quixote"), Cow::Borrowed(..)));
+        assert!(matches!(fold_punctuation("Don  Quixote"), Cow::Owned(..)));
+        assert!(matches!(fold_punctuation("Don Quixote "), Cow::Owned(..)));
+        assert_eq!(fold_query(r#"[a-z]'s \- "x"-y"#), r#"[a-z]s \- x y"#);
+        assert!(book("Hyphen-8").is_match_with(&make_query_with("[n-z]-[0-9]", true), true));
+        assert!(!book("Hyphen 8").is_match_with(&make_query_with(r"hyphen\-8", true), true));
+    }
+
+    #[test]
+    fn hyphenation_languages() {
+        let mut info = Info { language: "fr".to_string(), reader: Some(ReaderInfo::default()), .. Default::default() };
+        assert_eq!(info.hyphenation_language(), None);
+        let reader: ReaderInfo = serde_json::from_str(r#"{"hyphenation": {"enabled": false}}"#).unwrap();
+        assert_eq!(reader.hyphenation, Some(HyphenationSettings { enabled: false, language: None }));
+        info.reader = Some(reader);
+        assert_eq!(info.hyphenation_language(), Some("fr"));
+        info.reader.as_mut().unwrap().hyphenation.as_mut().unwrap().language = Some("de".to_string());
+        assert_eq!(info.hyphenation_language(), Some("de"));
+        info.reader.as_mut().unwrap().hyphenation = Some(HyphenationSettings::default());
+        info.language.clear();
+        assert_eq!(info.hyphenation_language(), None);
+    }
+
+    #[test]
+    fn known_extensions() {
+        assert_eq!(strip_known_extensions("Title.epub"), "Title");
+        assert_eq!(strip_known_extensions("Title - .fb2.zip "), "Title");
+        assert_eq!(strip_known_extensions("Mr. Mercedes"), "Mr. Mercedes");
+        assert_eq!(strip_known_extensions("Vol. 2.PDF"), "Vol. 2");
+        assert_eq!(strip_known_extensions("Notes on .txt"), "Notes on");
+        assert_eq!(strip_known_extensions(".epub"), ".epub");
+
+        let mut info = Info {
+            file: FileInfo { path: PathBuf::from("Dune.epub_Book One.pdf-draft.epub"), .. Default::default() },
+            .. Default::default()
+        };
+        parse_filename(&mut info, false);
+        assert_eq!(info.title, "Dune");
+        assert_eq!(info.subtitle, "Book One");
+    }
+
+    #[test]
+    fn title_casing() {
+        assert_eq!(title_case("the lord of the rings"), "The Lord of the Rings");
+        assert_eq!(title_case("a tale of two cities"), "A Tale of Two Cities");
+        assert_eq!(title_case("THE WAR OF THE WORLDS"), "The War of the Worlds");
+        assert_eq!(title_case("what the wind is for"), "What the Wind Is For");
+        assert_eq!(title_case("the history of NASA"), "The History of NASA");
+        assert_eq!(title_case("dune: the (first) book"), "Dune: The (First) Book");
+        assert_eq!(title_case("henry v and richard iii"), "Henry V and Richard III");
+        assert_eq!(title_case("HENRY IV"), "Henry IV");
+        assert_eq!(title_case("the mix of things i did"), "The Mix of Things I Did");
+        assert_eq!(title_case("jane o'neil"), "Jane O'neil");
+
+        let mut info = Info {
+            file: FileInfo { path: PathBuf::from("JANE DOE - THE ART OF WAR_ON STRATEGY - PENGUIN (2002).epub"), .. Default::default() },
+            .. Default::default()
+        };
+        parse_filename(&mut info, true);
+        assert_eq!(info.title, "The Art of War");
+        assert_eq!(info.subtitle, "On Strategy");
+        assert_eq!(info.author, "Jane Doe");
+    }
+
+    #[test]
+    fn contrast_presets() {
+        let mut presets = ContrastPresetsSettings::default();
+        let mut reader = ReaderInfo::default();
+        reader.apply_contrast_preset(&presets);
+        assert_eq!((reader.contrast_exponent, reader.contrast_gray), (None, None));
+
+        reader.contrast_preset = Some(ContrastPreset::Medium);
+        reader.apply_contrast_preset(&presets);
+        assert_eq!((reader.contrast_exponent, reader.contrast_gray), (Some(2.5), Some(192.0)));
+
+        // The table can be edited.
+        presets.medium.gray = DEFAULT_CONTRAST_GRAY;
+        reader.apply_contrast_preset(&presets);
+        assert_eq!((reader.contrast_exponent, reader.contrast_gray), (Some(2.5), None));
+
+        reader.contrast_preset = Some(ContrastPreset::Custom);
+        reader.set_contrast_gray(128.0);
+        reader.apply_contrast_preset(&presets);
+        assert_eq!((reader.contrast_exponent, reader.contrast_gray), (Some(2.5), Some(128.0)));
+
+        reader.contrast_preset = Some(ContrastPreset::Off);
+        reader.apply_contrast_preset(&presets);
+        assert_eq!((reader.contrast_exponent, reader.contrast_gray), (None, None));
+
+        assert!(!reader.dithered());
+        let reader: ReaderInfo = serde_json::from_str(r#"{"contrastPreset": "Light", "dither": "Ordered"}"#).unwrap();
+        assert_eq!(reader.contrast_preset, Some(ContrastPreset::Light));
+        assert!(reader.dithered());
+    }
+
+    #[test]
+    fn inversion_override() {
+        let mut info = Info::default();
+        assert!(info.effective_inverted(true));
+        info.reader = Some(ReaderInfo::default());
+        assert!(!info.effective_inverted(false));
+        info.reader.as_mut().unwrap().inverted = Some(true);
+        assert!(info.effective_inverted(false));
+        info.reader.as_mut().unwrap().inverted = Some(false);
+        assert!(!info.effective_inverted(true));
+
+        let json = serde_json::to_string(&info.reader).unwrap();
+        let reader: ReaderInfo = serde_json::from_str(&json).unwrap();
+        assert_eq!(reader.inverted, Some(false));
+    }
+
+    #[test]
+    fn navigation_history() {
+        let mut reader = ReaderInfo { pages_count: 100, .. Default::default() };
+        reader.push_jump(10, 3);
+        reader.push_jump(10, 3);
+        reader.push_jump(20, 3);
+        assert_eq!(reader.history, vec![10, 20]);
+
+        assert_eq!(reader.go_back(80, 3), Some(20));
+        assert_eq!(reader.go_back(20, 3), Some(10));
+        assert_eq!(reader.go_back(10, 3), None);
+        assert_eq!(reader.forward_history, vec![80, 20]);
+        assert_eq!(reader.go_forward(10, 3), Some(20));
+        assert_eq!(reader.history, vec![10]);
+
+        // A new jump forgets the way forward.
+        reader.push_jump(20, 3);
+        assert!(reader.forward_history.is_empty());
+        reader.push_jump(30, 3);
+        reader.push_jump(40, 3);
+        assert_eq!(reader.history, vec![20, 30, 40]);
+
+        let json = serde_json::to_value(&ReaderInfo::default()).unwrap();
+        assert!(json.get("history").is_none());
+
+        reader.history = vec![20, 60, 90];
+        reader.sanitize(60);
+        assert_eq!(reader.history, vec![20, 59]);
+    }
+
+    #[test]
+    fn category_sort() {
+        let book = |name: &str, categories: &[&str]| Info {
+            file: FileInfo { path: PathBuf::from(name), .. Default::default() },
+            categories: categories.iter().map(|c| c.to_string()).collect(),
+            .. Default::default()
+        };
+        let mut md = vec![book("a.epub", &[]),
+                          book("b.epub", &["Science.Physics", "Fiction"]),
+                          book("c.epub", &["Économie"]),
+                          book("d.epub", &["drama"]),
+                          book("e.epub", &["Art"])];
+        sort(&mut md, SortMethod::Category, SortMethod::Category.reverse_order());
+        assert_eq!(paths(&md), ["e.epub", "d.epub", "c.epub", "b.epub", "a.epub"]);
+        assert_eq!(SortMethod::Category.label(), "Category");
+    }
+
+    #[test]
+    fn furthest_progress() {
+        // Older files don't have the furthest page.
+        let mut reader: ReaderInfo = serde_json::from_str(r#"{"currentPage": 40, "pagesCount": 100}"#).unwrap();
+        assert_eq!(reader.furthest_page, 0);
+        assert_eq!(reader.progress_page(ProgressMode::Furthest), 40);
+
+        reader.reach_page(60);
+        reader.current_page = 10;
+        reader.reach_page(10);
+        assert_eq!(reader.furthest_page, 60);
+        assert_eq!(reader.progress_page(ProgressMode::Current), 10);
+        assert_eq!(reader.progress_page(ProgressMode::Furthest), 60);
+        reader.reset_progress();
+        assert_eq!(reader.furthest_page, 10);
+
+        let book = |name: &str, current_page: usize, furthest_page: usize| Info {
+            file: FileInfo { path: PathBuf::from(name), .. Default::default() },
+            reader: Some(ReaderInfo { current_page, furthest_page, pages_count: 100, .. Default::default() }),
+            .. Default::default()
+        };
+        let mut md = vec![book("a.epub", 10, 90), book("b.epub", 50, 50)];
+        assert_eq!(md[0].progress(ProgressMode::Current), 0.1);
+        assert_eq!(md[0].progress(ProgressMode::Furthest), 0.9);
+        sort_with(&mut md, SortMethod::Progress, true, ProgressMode::Current, NullOrder::First);
+        assert_eq!(paths(&md), ["b.epub", "a.epub"]);
+        sort_with(&mut md, SortMethod::Progress, true, ProgressMode::Furthest, NullOrder::First);
+        assert_eq!(paths(&md), ["a.epub", "b.epub"]);
+    }
+
+    #[test]
+    fn reading_sessions() {
+        let at = |h: u32, m: u32| Local.ymd(2020, 5, 1).and_hms(h, m, 0);
+        let mut reader = ReaderInfo::default();
+        reader.begin_session(at(20, 0), 10);
+        reader.record_activity(at(20, 2), 11, 300);
+        reader.record_activity(at(20, 5), 12, 300);
+        // Left open overnight.
+        reader.record_activity(at(23, 59), 13, 300);
+        reader.record_activity(at(23, 59) + chrono::Duration::seconds(90), 14, 300);
+        reader.end_session(at(23, 59) + chrono::Duration::seconds(90), 14, 300);
+
+        assert_eq!(reader.reading_time, 5 * 60 + 90);
+        assert_eq!(reader.sessions, vec![Session { start: at(20, 0), end: at(20, 5), start_page: 10, end_page: 12 },
+                                         Session { start: at(23, 59), end: at(23, 59) + chrono::Duration::seconds(90),
+                                                   start_page: 12, end_page: 14 }]);
+
+        // A book opened and closed right away leaves no trace.
+        reader.begin_session(at(23, 59), 14);
+        reader.end_session(at(23, 59), 14, 300);
+        assert_eq!(reader.sessions.len(), 2);
+
+        let info = Info { reader: Some(reader), .. Default::default() };
+        assert_eq!(info.total_reading_time(), 390);
+        let json = serde_json::to_value(&info.reader).unwrap();
+        assert_eq!(json["readingTime"], 390);
+        assert!(serde_json::to_value(&ReaderInfo::default()).unwrap().get("sessions").is_none());
+
+        let mut other = info.clone();
+        other.reader.as_mut().unwrap().begin_session(at(21, 0), 12);
+        other.reader.as_mut().unwrap().end_session(at(21, 10), 20, 900);
+        let mut merged = info.clone();
+        merge_info(&mut merged, other, MergeStrategy::PreferBase);
+        assert_eq!(merged.total_reading_time(), 390 + 600);
+        assert_eq!(merged.reader.unwrap().sessions.iter().map(|s| s.start).collect::<Vec<_>>(),
+                   vec![at(20, 0), at(21, 0), at(23, 59)]);
+
+        let mut reader = ReaderInfo::default();
+        let start = at(8, 0);
+        for i in 0..MAX_SESSIONS as i64 + 10 {
+            let now = start + chrono::Duration::hours(i);
+            reader.begin_session(now, 0);
+            reader.end_session(now + chrono::Duration::seconds(60), 1, 300);
+        }
+        assert_eq!(reader.sessions.len(), MAX_SESSIONS);
+        assert_eq!(reader.sessions[0].start, start + chrono::Duration::hours(10));
+        assert_eq!(reader.reading_time, 60 * (MAX_SESSIONS as u64 + 10));
+
+        // The trimmed sessions, still known to the other device, aren't counted twice.
+        let mut other = reader.clone();
+        other.sessions.insert(0, Session { start, end: start + chrono::Duration::seconds(60), start_page: 0, end_page: 1 });
+        let mut merged = Info { reader: Some(reader), .. Default::default() };
+        merge_info(&mut merged, Info { reader: Some(other), .. Default::default() }, MergeStrategy::PreferBase);
+        assert_eq!(merged.total_reading_time(), 60 * (MAX_SESSIONS as u64 + 10));
+    }
+
+    #[test]
+    fn series_groups() {
+        let book = |title: &str, series: &str, number: &str| Info {
+            title: title.to_string(),
+            series: series.to_string(),
+            number: number.to_string(),
+            file: FileInfo { path: PathBuf::from(format!("{}.epub", title)), .. Default::default() },
+            .. Default::default()
+        };
+        let md = vec![book("The Tombs of Atuan", "Earthsea", "2"),
+                      book("Walden", "", ""),
+                      book("Foundation and Empire", "Foundation", "2"),
+                      book("A Wizard of Earthsea", "Earthsea", "1"),
+                      book("Tales from Earthsea", "Earthsea", ""),
+                      book("Flatland", "", ""),
+                      book("The Farthest Shore", "Earthsea", "#3"),
+                      book("Foundation", "Foundation", "1"),
+                      book("The Word for World Is Forest", "Earthsea", "2.5")];
+
+        let groups = group_by_series(&md);
+        assert_eq!(groups.keys().map(String::as_str).collect::<Vec<&str>>(), vec!["", "Earthsea", "Foundation"]);
+        assert_eq!(groups[""], vec![5, 1]);
+        assert_eq!(groups["Earthsea"], vec![3, 0, 8, 6, 4]);
+        assert_eq!(groups["Foundation"], vec![7, 2]);
+        assert_eq!(groups.values().map(Vec::len).sum::<usize>(), md.len());
+    }
+
+    #[test]
+    fn annotation_chapters() {
+        let annotation = |page: usize, chapter: &str| Annotation {
+            selection: [TextLocation::Static(page, 0), TextLocation::Static(page, 3)],
+            chapter: chapter.to_string(),
+            .. Default::default()
+        };
+        let leaf = |title: &str, page: usize| SimpleTocEntry::Leaf(title.to_string(), TocLocation::Exact(page));
+        let mut info = Info {
+            toc: Some(vec![SimpleTocEntry::Container("Part I".to_string(), TocLocation::Exact(2),
+                                                     vec![leaf("Chapter 1", 2), leaf("Chapter 2", 10)]),
+                           SimpleTocEntry::Leaf("Part II".to_string(), TocLocation::Uri("#part2".to_string())),
+                           leaf("Part III", 20)]),
+            reader: Some(ReaderInfo {
+                annotations: vec![annotation(0, ""), annotation(2, ""), annotation(12, ""),
+                                  annotation(25, ""), annotation(4, "Prologue")],
+                .. Default::default()
+            }),
+            .. Default::default()
+        };
+
+        assert_eq!(assign_chapters(&mut info), 3);
+        let chapters = |info: &Info| info.reader.as_ref().unwrap().annotations.iter()
+                                         .map(|a| a.chapter.clone()).collect::<Vec<String>>();
+        assert_eq!(chapters(&info), vec!["", "Chapter 1", "Chapter 2", "Part III", "Prologue"]);
+        assert_eq!(assign_chapters(&mut info), 0);
+
+        info.toc = None;
+        info.reader.as_mut().unwrap().annotations[1].chapter.clear();
+        assert_eq!(assign_chapters(&mut info), 0);
+        assert_eq!(chapters(&info)[1], "");
+    }
+
+    #[test]
+    fn annotation_search() {
+        let annotation = |start: usize, text: &str, note: &str| Annotation {
+            text: text.to_string(),
+            note: note.to_string(),
+            selection: [TextLocation::Dynamic(start), TextLocation::Dynamic(start + 10)],
+            .. Default::default()
+        };
+        let book = |categ: &str, finished: bool, annotations: Vec<Annotation>| Info {
+            categories: [categ.to_string()].iter().cloned().collect(),
+            reader: Some(ReaderInfo { finished, annotations, .. Default::default() }),
+            .. Default::default()
+        };
+        let long = format!("{} café {}", "a".repeat(60), "b".repeat(60));
+        let metadata = vec![book("Fiction.Novels", false,
+                                 vec![annotation(50, "Nothing here", "A cafe note"),
+                                      annotation(10, "Un café noir", "Bitter")]),
+                            book("Essays", true, vec![annotation(5, &long, "")]),
+                            book("Fiction", true, vec![annotation(0, "Cafe society", "")])];
+        let query = make_query("cafe").unwrap();
+
+        let hits = search_annotations(&metadata, &query, &AnnotationFilter::default());
+        let summary: Vec<(usize, usize, AnnotationField)> = hits.iter().map(|h| (h.book, h.annotation, h.field)).collect();
+        assert_eq!(summary, vec![(0, 1, AnnotationField::Text), (0, 0, AnnotationField::Note),
+                                 (1, 0, AnnotationField::Text), (2, 0, AnnotationField::Text)]);
+        assert_eq!(&hits[0].excerpt[hits[0].highlight.clone()], "café");
+
+        let essay = &hits[2];
+        assert!(essay.excerpt.starts_with('…') && essay.excerpt.ends_with('…'));
+        assert_eq!(&essay.excerpt[essay.highlight.clone()], "café");
+        assert_eq!(essay.excerpt.chars().count(), 2 * EXCERPT_RADIUS + 4 + 2);
+
+        let fiction = AnnotationFilter { category: Some("Fiction".to_string()), .. Default::default() };
+        let books: Vec<usize> = search_annotations(&metadata, &query, &fiction).iter().map(|h| h.book).collect();
+        assert_eq!(books, vec![0, 0, 2]);
+        let finished_fiction = AnnotationFilter { status: Some(SimpleStatus::Finished), .. fiction };
+        let books: Vec<usize> = search_annotations(&metadata, &query, &finished_fiction).iter().map(|h| h.book).collect();
+        assert_eq!(books, vec![2]);
+
+        assert_eq!(search_book_annotations(1, &metadata[1], &make_query("bitter").unwrap()).len(), 0);
+        assert_eq!(search_book_annotations(0, &metadata[0], &make_query("bitter").unwrap())[0].field, AnnotationField::Note);
+    }
+
+    #[test]
+    fn imported_reader_defaults() {
+        let dir = TempDir::new("reader-defaults");
+        fs::write(dir.join("Flatland.epub"), b"Flatland").unwrap();
+
+        let mut settings = ImportSettings::default();
+        let metadata = import(&dir, &Vec::new(), &settings).unwrap();
+        assert!(metadata[0].reader_defaults.is_none());
+
+        settings.reader_defaults.font_size = Some(9.5);
+        settings.reader_defaults.contrast_gray = Some(200.0);
+        let metadata = import(&dir, &Vec::new(), &settings).unwrap();
+        let info = &metadata[0];
+        assert_eq!(info.simple_status(), SimpleStatus::New);
+        let defaults = info.reader_defaults.as_ref().unwrap();
+        assert_eq!(defaults.font_size, Some(9.5));
+
+        let mut reader = ReaderInfo { line_height: Some(1.4), contrast_gray: Some(180.0), .. Default::default() };
+        reader.apply_defaults(defaults);
+        assert_eq!(reader.font_size, Some(9.5));
+        assert_eq!(reader.line_height, Some(1.4));
+        assert_eq!(reader.contrast_gray, Some(180.0));
+        assert_eq!(reader.font_family, None);
+        assert_eq!(reader.text_align, None);
+        assert_eq!(reader.margin_width, None);
+    }
+
+    #[test]
+    fn streamed_files() {
+        let dir = TempDir::new("walk-files");
+        for sub in &["Abbott", "Abbott/Essays", ".hidden", ".trash"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        for name in &["Flatland.epub", "Abbott/Sketch.pdf", "Abbott/Essays/Dimensions.cbz",
+                      "Abbott/.Draft.pdf", ".hidden/Secret.epub", ".trash/Old.pdf"] {
+            fs::write(dir.join(name), name.as_bytes()).unwrap();
+        }
+
+        let mut streamed = Vec::new();
+        walk_files(&dir, &dir, false, &mut |info| streamed.push(info.path)).unwrap();
+        let collected: Vec<PathBuf> = find_files(&dir, &dir, false).unwrap().into_iter()
+                                      .map(|info| info.path).collect();
+        assert_eq!(streamed, collected);
+        streamed.sort();
+        assert_eq!(streamed, vec![PathBuf::from("Abbott/Essays/Dimensions.cbz"),
+                                  PathBuf::from("Abbott/Sketch.pdf"),
+                                  PathBuf::from("Flatland.epub")]);
+
+        let mut count = 0;
+        walk_files(&dir, &dir, true, &mut |_| count += 1).unwrap();
+        assert_eq!(count, 4);
+    }
+
+    #[test]
+    fn overlapping_annotations() {
+        let annotation = |sel: [usize; 2], text: &str, note: &str, day: u32| Annotation {
+            text: text.to_string(),
+            note: note.to_string(),
+            selection: [TextLocation::Static(3, sel[0]), TextLocation::Static(3, sel[1])],
+            created: Local.ymd(2020, 4, day).and_hms(9, 0, 0),
+            modified: Local.ymd(2020, 4, day).and_hms(9, 0, 0),
+            .. Default::default()
+        };
+        let mut reader = ReaderInfo {
+            annotations: vec![annotation([4, 6], "the two spheres", "", 2),
+                              annotation([0, 3], "In the case of", "Intro.", 3),
+                              annotation([2, 5], "case of the two", "Overlap.", 1),
+                              annotation([10, 12], "A Square said", "", 5),
+                              annotation([10, 12], "A Square said", "Same.", 4),
+                              annotation([20, 30], "a long passage of text that holds more words", "", 6),
+                              annotation([22, 24], "passage of text", "Inside.", 7),
+                              annotation([40, 41], "far away", "", 8)],
+            .. Default::default()
+        };
+        reader.annotations[1].tags.insert("geometry".to_string());
+        reader.annotations[2].style = HighlightStyle::Dark;
+        reader.annotations[2].tags.insert("spheres".to_string());
+
+        assert_eq!(reader.annotations_sorted(), vec![1, 2, 0, 3, 4, 5, 6, 7]);
+        assert_eq!(merge_overlapping_annotations(&mut reader), 4);
+        let annotations = &reader.annotations;
+        assert_eq!(annotations.len(), 4);
+
+        // Partial overlaps and adjacency.
+        let chain = &annotations[0];
+        assert_eq!(chain.selection, [TextLocation::Static(3, 0), TextLocation::Static(3, 6)]);
+        assert_eq!(chain.text, "In the case of the two spheres");
+        assert_eq!(chain.note, "Intro.\n\nOverlap.");
+        assert_eq!(chain.created, Local.ymd(2020, 4, 1).and_hms(9, 0, 0));
+        assert_eq!(chain.modified, Local.ymd(2020, 4, 3).and_hms(9, 0, 0));
+        assert_eq!(chain.style, HighlightStyle::Dark);
+        assert_eq!(chain.tags.len(), 2);
+
+        // Exact overlap.
+        assert_eq!(annotations[1].selection, [TextLocation::Static(3, 10), TextLocation::Static(3, 12)]);
+        assert_eq!(annotations[1].text, "A Square said");
+        assert_eq!(annotations[1].note, "Same.");
+
+        // Containment.
+        assert_eq!(annotations[2].selection, [TextLocation::Static(3, 20), TextLocation::Static(3, 30)]);
+        assert_eq!(annotations[2].text, "a long passage of text that holds more words");
+        assert_eq!(annotations[2].note, "Inside.");
+
+        assert_eq!(annotations[3].text, "far away");
+        assert_eq!(merge_overlapping_annotations(&mut reader), 0);
+
+        // Adjacent dynamic selections, apart by the length of the last word.
+        let dynamic = |start: usize, end: usize, text: &str| Annotation {
+            text: text.to_string(),
+            selection: [TextLocation::Dynamic(start), TextLocation::Dynamic(end)],
+            .. Default::default()
+        };
+        let mut reader = ReaderInfo {
+            annotations: vec![dynamic(100, 110, "Upward, not Northward"),
+                              dynamic(121, 130, "was the cry"),
+                              dynamic(300, 310, "Elsewhere")],
+            .. Default::default()
+        };
+        reader.annotations[2].dangling = true;
+        assert_eq!(merge_overlapping_annotations(&mut reader), 1);
+        assert_eq!(reader.annotations[0].text, "Upward, not Northward was the cry");
+        assert!(reader.annotations[1].dangling);
+    }
+
+    #[test]
+    fn reader_info_validation() {
+        let reader: ReaderInfo = serde_json::from_str(r#"{"rotation": 7, "contrastExponent": -2.0, "contrastGray": 400.0}"#).unwrap();
+        assert_eq!(reader.rotation, Some(3));
+        assert_eq!(reader.contrast_exponent, Some(MIN_CONTRAST_EXPONENT));
+        assert_eq!(reader.contrast_gray, Some(MAX_CONTRAST_GRAY));
+
+        let reader: ReaderInfo = serde_json::from_str(r#"{"rotation": -1, "contrastExponent": 2.5, "contrastGray": -3}"#).unwrap();
+        assert_eq!(reader.rotation, Some(3));
+        assert_eq!(reader.contrast_exponent, Some(2.5));
+        assert_eq!(reader.contrast_gray, Some(0.0));
+
+        let reader: ReaderInfo = serde_json::from_str(r#"{"rotation": 1000}"#).unwrap();
+        assert_eq!(reader.rotation, Some(0));
+        assert_eq!(reader.contrast_exponent, None);
+
+        let reader: ReaderInfo = serde_json::from_str(r#"{"rotation": 5, "rotationLock": true}"#).unwrap();
+        assert_eq!(reader.locked_rotation(), Some(1));
+        let reader: ReaderInfo = serde_json::from_str(r#"{"rotation": 2, "rotationLock": false}"#).unwrap();
+        assert_eq!(reader.locked_rotation(), None);
+        let reader: ReaderInfo = serde_json::from_str(r#"{"rotationLock": true}"#).unwrap();
+        assert_eq!(reader.locked_rotation(), None);
+        let json = serde_json::to_value(&ReaderInfo::default()).unwrap();
+        assert!(json.get("rotation").is_none() && json.get("rotationLock").is_none());
+
+        let mut reader = ReaderInfo::default();
+        reader.set_rotation(-6);
+        assert_eq!(reader.rotation, Some(2));
+        reader.set_contrast_exponent(f32::NAN);
+        assert_eq!(reader.contrast_exponent, None);
+        reader.set_contrast_exponent(9.0);
+        assert_eq!(reader.contrast_exponent, Some(MAX_CONTRAST_EXPONENT));
+        reader.set_contrast_gray(DEFAULT_CONTRAST_GRAY);
+        assert_eq!(reader.contrast_gray, Some(DEFAULT_CONTRAST_GRAY));
+
+        let reader: ReaderInfo = serde_json::from_str(r#"{"fontWeight": 1200, "letterSpacing": -0.1}"#).unwrap();
+        assert_eq!(reader.font_weight, Some(MAX_FONT_WEIGHT));
+        assert_eq!(reader.letter_spacing, Some(0.0));
+        let reader: ReaderInfo = serde_json::from_str(r#"{"fontWeight": 600, "letterSpacing": 0.05}"#).unwrap();
+        assert_eq!(reader.font_weight, Some(600.0));
+        assert_eq!(reader.letter_spacing, Some(0.05));
+
+        let reader: ReaderInfo = serde_json::from_str(r#"{"zoomMode": "FitToWidth", "topOffset": 12}"#).unwrap();
+        assert_eq!(reader.zoom_mode, Some(ZoomMode::FitToWidth));
+        assert_eq!(reader.left_offset, None);
+        let reader: ReaderInfo = serde_json::from_str(r#"{"zoomMode": {"Custom": 9.0}, "leftOffset": 40}"#).unwrap();
+        assert_eq!(reader.zoom_mode, Some(ZoomMode::Custom(MAX_ZOOM_FACTOR)));
+        assert_eq!(reader.left_offset, Some(40));
+        let reader: ReaderInfo = serde_json::from_str(r#"{"zoomMode": "FitToHeight"}"#).unwrap();
+        assert_eq!(reader.zoom_mode, Some(ZoomMode::FitToHeight));
+        assert_eq!(serde_json::to_string(&ZoomMode::Custom(2.0)).unwrap(), r#"{"Custom":2.0}"#);
+
+        let mut reader = ReaderInfo::default();
+        reader.set_font_weight(100.0);
+        assert_eq!(reader.font_weight, Some(MIN_FONT_WEIGHT));
+        reader.set_letter_spacing(2.0);
+        assert_eq!(reader.letter_spacing, Some(MAX_LETTER_SPACING));
+    }
+
+    #[test]
+    fn shorter_edition() {
+        let annotation = |page: usize| Annotation {
+            selection: [TextLocation::Static(page, 0), TextLocation::Static(page, 4)],
+            .. Default::default()
+        };
+        let mut reader = ReaderInfo {
+            current_page: 180,
+            pages_count: 200,
+            bookmarks: [3, 99, 100, 150].iter().cloned().collect(),
+            page_names: [(0, "i".to_string()), (120, "110".to_string())].iter().cloned().collect(),
+            annotations: vec![annotation(12), annotation(140), annotation(99)],
+            .. Default::default()
+        };
+
+        let adjustments = reader.sanitize(100);
+        assert_eq!(adjustments, StateAdjustments { current_page: Some(180), bookmarks: 2,
+                                                   page_names: 1, annotations: 1 });
+        assert_eq!(adjustments.to_string(), "current page moved back, 2 bookmarks removed, \
+                                             1 page name removed, 1 annotation orphaned");
+        assert_eq!(reader.current_page, 99);
+        assert_eq!(reader.pages_count, 100);
+        assert_eq!(reader.bookmarks.iter().cloned().collect::<Vec<usize>>(), vec![3, 99]);
+        assert_eq!(reader.page_names.len(), 1);
+        assert_eq!(reader.annotations.len(), 3);
+        assert_eq!(reader.annotations.iter().map(|a| a.dangling).collect::<Vec<bool>>(),
+                   vec![false, true, false]);
+        assert!(reader.sanitize(100).is_empty());
+
+        // The orphans are recovered with the original edition.
+        assert!(reader.sanitize(200).is_empty());
+        assert!(reader.annotations.iter().all(|a| !a.dangling));
+
+        let mut reader = ReaderInfo {
+            current_page: 5000,
+            annotations: vec![Annotation {
+                selection: [TextLocation::Dynamic(4000), TextLocation::Dynamic(4100)],
+                .. Default::default()
+            }],
+            .. Default::default()
+        };
+        let adjustments = reader.sanitize(4096);
+        assert_eq!(adjustments.current_page, Some(5000));
+        assert_eq!(reader.current_page, 4095);
+        assert!(reader.annotations[0].dangling);
+    }
+}
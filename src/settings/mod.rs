@@ -3,12 +3,14 @@ mod preset;
 use std::fmt::{self, Debug};
 use std::path::PathBuf;
 use std::collections::{HashSet, HashMap, BTreeMap};
-use serde::{Serialize, Deserialize};
-use crate::metadata::{SortMethod, TextAlign};
+use serde::{Serialize, Deserialize, Deserializer};
+use crate::metadata::{SortMethod, TextAlign, ZoomMode, ContrastPreset};
+use crate::metadata::{DEFAULT_CONTRAST_EXPONENT, DEFAULT_CONTRAST_GRAY};
 use crate::frontlight::LightLevels;
 use crate::color::BLACK;
 use crate::device::CURRENT_DEVICE;
 use crate::unit::mm_to_px;
+use crate::document::canonical_kind;
 
 pub use self::preset::{LightPreset, guess_frontlight};
 
@@ -96,9 +98,49 @@ impl CategoryProvider {
 pub struct ImportSettings {
     pub unshare_trigger: bool,
     pub startup_trigger: bool,
+    // Import the files as soon as they appear in the library.
+    pub watch_trigger: bool,
     pub traverse_hidden: bool,
+    #[serde(deserialize_with = "deserialize_kinds")]
     pub allowed_kinds: HashSet<String>,
     pub category_providers: HashSet<CategoryProvider>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path_category_depth: Option<usize>,
+    // Opening every imported document to count its pages is slow.
+    pub count_pages: bool,
+    // Capitalize the titles and the authors parsed from the file names.
+    pub title_case: bool,
+    // The reader settings that newly imported books start from.
+    #[serde(skip_serializing_if = "ReaderDefaults::is_empty")]
+    pub reader_defaults: ReaderDefaults,
+}
+
+// The fields left unset fall back to the reader settings.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ReaderDefaults {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub font_family: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub font_size: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text_align: Option<TextAlign>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_height: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub margin_width: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub screen_margin_width: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contrast_exponent: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contrast_gray: Option<f32>,
+}
+
+impl ReaderDefaults {
+    pub fn is_empty(&self) -> bool {
+        *self == ReaderDefaults::default()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -185,6 +227,14 @@ pub enum SecondColumn {
     Year,
 }
 
+// Which page of a book measures its progress.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProgressMode {
+    Current,
+    Furthest,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default, rename_all = "kebab-case")]
 pub struct Hook {
@@ -210,6 +260,9 @@ impl Default for Hook {
 pub struct HomeSettings {
     pub summary_size: u8,
     pub second_column: SecondColumn,
+    pub progress_mode: ProgressMode,
+    // Ignore the differences of quotes, dashes and spaces when searching.
+    pub fold_punctuation: bool,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub hooks: Vec<Hook>,
 }
@@ -221,6 +274,15 @@ pub struct RefreshRateSettings {
     pub inverted: u8,
 }
 
+// Where a book that was already opened is resumed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResumePoint {
+    LastPage,
+    LastAnnotation,
+    FurthestPage,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(default, rename_all = "kebab-case")]
 pub struct ReaderSettings {
@@ -232,6 +294,70 @@ pub struct ReaderSettings {
     pub margin_width: i32,
     pub line_height: f32,
     pub refresh_rate: RefreshRateSettings,
+    // In seconds: longer pauses between two page turns don't count as reading time.
+    pub idle_threshold: u64,
+    // The number of jumps remembered by each book.
+    pub history_size: usize,
+    pub resume_at: ResumePoint,
+    // The largest fraction of a page dimension removed by the automatic cropping.
+    pub auto_crop_limit: f32,
+    // The zoom mode of the books that don't have one yet, per kind.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub zoom_modes: BTreeMap<String, ZoomMode>,
+    pub contrast_presets: ContrastPresetsSettings,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ContrastValues {
+    pub exponent: f32,
+    pub gray: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ContrastPresetsSettings {
+    pub light: ContrastValues,
+    pub medium: ContrastValues,
+    pub aggressive: ContrastValues,
+}
+
+impl ContrastPresetsSettings {
+    // The custom preset has no values: the ones of the book are kept.
+    pub fn values(&self, preset: ContrastPreset) -> Option<ContrastValues> {
+        match preset {
+            ContrastPreset::Off => Some(ContrastValues { exponent: DEFAULT_CONTRAST_EXPONENT,
+                                                         gray: DEFAULT_CONTRAST_GRAY }),
+            ContrastPreset::Light => Some(self.light),
+            ContrastPreset::Medium => Some(self.medium),
+            ContrastPreset::Aggressive => Some(self.aggressive),
+            ContrastPreset::Custom => None,
+        }
+    }
+}
+
+impl Default for ContrastPresetsSettings {
+    fn default() -> Self {
+        ContrastPresetsSettings {
+            light: ContrastValues { exponent: 1.5, gray: 224.0 },
+            medium: ContrastValues { exponent: 2.5, gray: 192.0 },
+            aggressive: ContrastValues { exponent: 4.0, gray: 128.0 },
+        }
+    }
+}
+
+impl ReaderSettings {
+    pub fn zoom_mode(&self, kind: &str) -> ZoomMode {
+        self.zoom_modes.get(kind).map(|z| z.clamped())
+            .unwrap_or_else(|| default_zoom_for_kind(kind))
+    }
+}
+
+pub fn default_zoom_for_kind(kind: &str) -> ZoomMode {
+    match kind {
+        "epub" | "fb2" | "html" => ZoomMode::FitToWidth,
+        _ => ZoomMode::FitToPage,
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -253,6 +379,8 @@ impl Default for HomeSettings {
         HomeSettings {
             summary_size: 2,
             second_column: SecondColumn::Progress,
+            progress_mode: ProgressMode::Current,
+            fold_punctuation: false,
             hooks: Vec::new(),
         }
     }
@@ -278,19 +406,36 @@ impl Default for ReaderSettings {
             text_align: DEFAULT_TEXT_ALIGN,
             margin_width: DEFAULT_MARGIN_WIDTH,
             line_height: DEFAULT_LINE_HEIGHT,
+            idle_threshold: 300,
+            history_size: 32,
+            resume_at: ResumePoint::LastPage,
+            auto_crop_limit: 0.5,
+            zoom_modes: ["pdf", "epub"].iter().map(|k| (k.to_string(), default_zoom_for_kind(k))).collect(),
+            contrast_presets: ContrastPresetsSettings::default(),
         }
     }
 }
 
+// The kinds are compared with the canonical kinds of the files.
+fn deserialize_kinds<'de, D>(deserializer: D) -> Result<HashSet<String>, D::Error> where D: Deserializer<'de> {
+    Ok(HashSet::<String>::deserialize(deserializer)?
+                .iter().map(|k| canonical_kind(k)).collect())
+}
+
 impl Default for ImportSettings {
     fn default() -> Self {
         ImportSettings {
             unshare_trigger: true,
             startup_trigger: true,
+            watch_trigger: false,
             traverse_hidden: false,
             allowed_kinds: ["pdf", "djvu", "epub",
                             "fb2", "xps", "oxps", "cbz"].iter().map(|k| k.to_string()).collect(),
             category_providers: [CategoryProvider::Path].iter().cloned().collect(),
+            path_category_depth: None,
+            count_pages: false,
+            title_case: false,
+            reader_defaults: ReaderDefaults::default(),
         }
     }
 }
@@ -330,3 +475,30 @@ impl Default for Settings {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zoom_modes() {
+        assert_eq!(default_zoom_for_kind("pdf"), ZoomMode::FitToPage);
+        assert_eq!(default_zoom_for_kind("epub"), ZoomMode::FitToWidth);
+        assert_eq!(default_zoom_for_kind("djvu"), ZoomMode::FitToPage);
+
+        let mut settings = ReaderSettings::default();
+        assert_eq!(settings.zoom_mode("pdf"), ZoomMode::FitToPage);
+        assert_eq!(settings.zoom_mode("epub"), ZoomMode::FitToWidth);
+        settings.zoom_modes.insert("pdf".to_string(), ZoomMode::FitToWidth);
+        assert_eq!(settings.zoom_mode("pdf"), ZoomMode::FitToWidth);
+        settings.zoom_modes.clear();
+        assert_eq!(settings.zoom_mode("epub"), ZoomMode::FitToWidth);
+    }
+
+    #[test]
+    fn allowed_kinds() {
+        let settings: ImportSettings = toml::from_str("allowed-kinds = [\"htm\", \"DJV\", \"epub\"]").unwrap();
+        let kinds: HashSet<String> = ["html", "djvu", "epub"].iter().map(|k| k.to_string()).collect();
+        assert_eq!(settings.allowed_kinds, kinds);
+    }
+}
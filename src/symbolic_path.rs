@@ -1,4 +1,7 @@
+use std::borrow::Cow;
+
 pub const PATH_SEPARATOR: char = '.';
+pub const ESCAPE_CHAR: char = '\\';
 
 pub trait SymbolicPath<'a> {
     type Owned;
@@ -25,11 +28,114 @@ impl<'a> Iterator for Ancestors<'a> {
     }
 }
 
+// Escapes the separators and escape characters contained in a path component.
+pub fn escape(component: &str) -> Cow<str> {
+    if !component.contains(|c| c == PATH_SEPARATOR || c == ESCAPE_CHAR) {
+        return Cow::Borrowed(component);
+    }
+
+    let mut buf = String::with_capacity(component.len() + 1);
+
+    for c in component.chars() {
+        if c == PATH_SEPARATOR || c == ESCAPE_CHAR {
+            buf.push(ESCAPE_CHAR);
+        }
+        buf.push(c);
+    }
+
+    Cow::Owned(buf)
+}
+
+pub fn unescape(component: &str) -> Cow<str> {
+    if !component.contains(ESCAPE_CHAR) {
+        return Cow::Borrowed(component);
+    }
+
+    let mut buf = String::with_capacity(component.len());
+    let mut chars = component.chars();
+
+    while let Some(c) = chars.next() {
+        if c == ESCAPE_CHAR {
+            if let Some(next) = chars.next() {
+                buf.push(next);
+            }
+        } else {
+            buf.push(c);
+        }
+    }
+
+    Cow::Owned(buf)
+}
+
+// Builds a path from its unescaped components.
+pub fn encode<I, S>(components: I) -> String where I: IntoIterator<Item=S>, S: AsRef<str> {
+    let mut result = String::new();
+
+    for (index, component) in components.into_iter().enumerate() {
+        if index > 0 {
+            result.push(PATH_SEPARATOR);
+        }
+        result.push_str(&escape(component.as_ref()));
+    }
+
+    result
+}
+
+// Splits a path into its unescaped components.
+pub fn decode(path: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut start = 0;
+
+    for index in separators(path) {
+        result.push(unescape(&path[start..index]).into_owned());
+        start = index + PATH_SEPARATOR.len_utf8();
+    }
+
+    result.push(unescape(&path[start..]).into_owned());
+    result
+}
+
+// A path is valid if none of its components is empty, and if its last character isn't
+// a lone escape character.
+pub fn is_valid(path: &str) -> bool {
+    let trailing_escapes = path.chars().rev().take_while(|&c| c == ESCAPE_CHAR).count();
+    trailing_escapes % 2 == 0 && decode(path).iter().all(|component| !component.is_empty())
+}
+
+// Moves *path* under *new* if it's *old* or one of its descendants.
+pub fn rename(path: &str, old: &str, new: &str) -> Option<String> {
+    if path == old {
+        Some(new.to_string())
+    } else if path.is_descendant_of(old) {
+        Some(new.join(&path[old.len()+PATH_SEPARATOR.len_utf8()..]))
+    } else {
+        None
+    }
+}
+
+// The byte indices of the separators that aren't escaped.
+fn separators(path: &str) -> impl Iterator<Item=usize> + '_ {
+    let mut escaped = false;
+    path.char_indices().filter_map(move |(index, c)| {
+        if escaped {
+            escaped = false;
+            None
+        } else if c == ESCAPE_CHAR {
+            escaped = true;
+            None
+        } else if c == PATH_SEPARATOR {
+            Some(index)
+        } else {
+            None
+        }
+    })
+}
+
 impl<'a> SymbolicPath<'a> for str {
     type Owned = String;
 
     fn parent(&self) -> Option<&str> {
-        self.rfind(PATH_SEPARATOR).map(|index| &self[..index])
+        separators(self).last().map(|index| &self[..index])
     }
 
     fn ancestors(&'a self) -> Ancestors<'a> {
@@ -53,11 +159,12 @@ impl<'a> SymbolicPath<'a> for str {
 
     #[inline]
     fn is_descendant_of(&self, other: &str) -> bool {
-        self.starts_with(other) && self[other.len()..].starts_with(PATH_SEPARATOR)
+        self.starts_with(other) && separators(self).take_while(|&index| index <= other.len())
+                                                   .any(|index| index == other.len())
     }
 
     fn first_component(&self) -> &str {
-        if let Some(index) = self.find(PATH_SEPARATOR) {
+        if let Some(index) = separators(self).next() {
             &self[..index]
         } else {
             self
@@ -65,7 +172,7 @@ impl<'a> SymbolicPath<'a> for str {
     }
 
     fn last_component(&self) -> &str {
-        if let Some(index) = self.rfind(PATH_SEPARATOR) {
+        if let Some(index) = separators(self).last() {
             &self[index+1..]
         } else {
             self
@@ -73,13 +180,13 @@ impl<'a> SymbolicPath<'a> for str {
     }
 
     fn depth(&self) -> usize {
-        self.matches(PATH_SEPARATOR).count()
+        separators(self).count()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::SymbolicPath;
+    use super::{SymbolicPath, encode, decode, escape, unescape, is_valid, rename};
 
     #[test]
     fn relationships() {
@@ -99,4 +206,42 @@ mod tests {
         assert_eq!("a".depth(), 0);
         assert_eq!("a.b.c".depth(), 2);
     }
+
+    #[test]
+    fn escaped_separators() {
+        assert_eq!(escape("v1.2"), "v1\\.2");
+        assert_eq!(escape("a\\b"), "a\\\\b");
+        assert_eq!(unescape("v1\\.2"), "v1.2");
+        let path = encode(&["Manuals", "v1.2", "a\\b"]);
+        assert_eq!(path, "Manuals.v1\\.2.a\\\\b");
+        assert_eq!(decode(&path), vec!["Manuals", "v1.2", "a\\b"]);
+        assert_eq!(path.depth(), 2);
+        assert_eq!(path.first_component(), "Manuals");
+        assert_eq!(path.last_component(), "a\\\\b");
+        assert_eq!(path.parent(), Some("Manuals.v1\\.2"));
+        assert!("Manuals.v1\\.2".is_child_of("Manuals"));
+        assert!(!"Manuals.v1\\.2".is_descendant_of("Manuals.v1"));
+    }
+
+    #[test]
+    fn renaming() {
+        assert_eq!(rename("a.b", "a.b", "a.c").as_deref(), Some("a.c"));
+        assert_eq!(rename("a.b.c", "a.b", "d").as_deref(), Some("d.c"));
+        assert_eq!(rename("a.bb", "a.b", "d"), None);
+        assert_eq!(rename("a\\\\.b", "a\\\\", "d").as_deref(), Some("d.b"));
+        assert_eq!(rename("a\\.b", "a", "d"), None);
+        assert!(is_valid("a.b\\.c"));
+        assert!(is_valid("a\\\\"));
+        assert!(!is_valid(""));
+        assert!(!is_valid("a..b"));
+        assert!(!is_valid(".a"));
+        assert!(!is_valid("a\\"));
+    }
+
+    #[test]
+    fn round_trip() {
+        for path in &["", "a", "a.b.c", "a\\.b.c", "\\.\\.", "a\\\\.b", ".a..b."] {
+            assert_eq!(encode(decode(path)), *path);
+        }
+    }
 }
@@ -6,7 +6,7 @@ use crate::color::{BLACK, WHITE, READING_PROGRESS};
 use crate::color::{TEXT_NORMAL, TEXT_INVERTED_HARD};
 use crate::gesture::GestureEvent;
 use crate::metadata::{Info, Status};
-use crate::settings::SecondColumn;
+use crate::settings::{SecondColumn, ProgressMode};
 use crate::unit::scale_by_dpi;
 use crate::document::HumanSize;
 use crate::font::{Fonts, font_from_style};
@@ -21,17 +21,19 @@ pub struct Book {
     info: Info,
     index: usize,
     second_column: SecondColumn,
+    progress_mode: ProgressMode,
     active: bool,
 }
 
 impl Book {
-    pub fn new(rect: Rectangle, info: Info, index: usize, second_column: SecondColumn) -> Book {
+    pub fn new(rect: Rectangle, info: Info, index: usize, second_column: SecondColumn, progress_mode: ProgressMode) -> Book {
         Book {
             rect,
             children: vec![],
             info,
             index,
             second_column,
+            progress_mode,
             active: false,
         }
     }
@@ -144,7 +146,7 @@ impl View for Book {
                 let (small_radius, big_radius) = halves(progress_height);
                 let center = pt!(self.rect.min.x + first_width + second_width / 2,
                                  self.rect.min.y + self.rect.height() as i32 / 2);
-                match self.info.status() {
+                match self.info.status_by(self.progress_mode) {
                     Status::New | Status::Finished => {
                         let color = if self.info.reader.is_none() { WHITE } else { BLACK };
                         fb.draw_rounded_rectangle_with_border(&rect![center - pt!(small_radius, small_radius),
@@ -6,7 +6,7 @@ use crate::geom::{Rectangle, CornerSpec, BorderSpec, Dir};
 use crate::framebuffer::Framebuffer;
 use crate::view::{View, Event, Hub, Bus, Align};
 use crate::view::{THICKNESS_SMALL, BORDER_RADIUS_SMALL};
-use crate::symbolic_path::SymbolicPath;
+use crate::symbolic_path::{SymbolicPath, unescape};
 use crate::unit::scale_by_dpi;
 use crate::app::Context;
 
@@ -64,7 +64,7 @@ impl View for Category {
         let font = font_from_style(fonts, &NORMAL_STYLE, dpi);
         let x_height = font.x_heights.0 as i32;
         font.set_size(category_font_size(self.text.depth()), dpi);
-        let plan = font.plan(self.text.last_component(), self.max_width, None);
+        let plan = font.plan(&unescape(self.text.last_component()), self.max_width, None);
 
         let dx = self.align.offset(plan.width as i32, self.rect.width() as i32);
         let dy = (self.rect.height() as i32 - x_height) / 2;
@@ -18,7 +18,10 @@ use serde_json::Value as JsonValue;
 use fnv::{FnvHashSet, FnvHashMap};
 use failure::{Error, format_err};
 use crate::framebuffer::{Framebuffer, UpdateMode};
-use crate::metadata::{Info, ReaderInfo, Metadata, SortMethod, SimpleStatus, sort, make_query, auto_import, clean_up};
+use crate::metadata::{Info, ReaderInfo, Metadata, SortMethod, SimpleStatus, sort_with, make_query_with, auto_import, clean_up};
+use crate::metadata::{find_by_path, find_by_path_mut, rebuild_toc, export_annotations, ExportFormat, ANNOTATIONS_DIRNAME};
+use crate::metadata::{check_category_rename, rename_category, import_files, refresh_files, load_metadata, save_metadata};
+use crate::watcher::LibraryChange;
 use crate::view::{View, Event, Hub, Bus, ViewId, EntryId, EntryKind, THICKNESS_MEDIUM};
 use crate::settings::{Hook, SecondColumn};
 use crate::view::filler::Filler;
@@ -34,8 +37,7 @@ use crate::view::intermission::IntermKind;
 use crate::gesture::GestureEvent;
 use crate::input::{DeviceEvent, ButtonCode, ButtonStatus};
 use crate::device::{CURRENT_DEVICE, BAR_SIZES};
-use crate::symbolic_path::SymbolicPath;
-use crate::helpers::{load_json, save_json};
+use crate::symbolic_path::{self, SymbolicPath};
 use crate::unit::scale_by_dpi;
 use crate::trash::{self, trash, untrash};
 use crate::app::Context;
@@ -97,7 +99,8 @@ impl Home {
         let sort_method = SortMethod::Opened;
         let reverse_order = sort_method.reverse_order();
 
-        sort(&mut context.metadata, sort_method, reverse_order);
+        sort_with(&mut context.metadata, sort_method, reverse_order,
+                  context.settings.home.progress_mode, sort_method.null_order(reverse_order));
 
         let visible_books = context.metadata.clone();
         let visible_categories = context.metadata.iter()
@@ -197,15 +200,10 @@ impl Home {
 
     fn refresh_visibles(&mut self, update: bool, reset_page: bool, hub: &Hub, context: &mut Context) {
         self.visible_books = context.metadata.iter().filter(|info| {
-            info.is_match(&self.query) &&
-            (self.status_filter.is_none() || info.simple_status() == self.status_filter.unwrap()) &&
-            (self.selected_categories.is_subset(&info.categories) ||
-             self.selected_categories.iter()
-                                     .all(|s| info.categories
-                                                  .iter().any(|c| c == s || c.is_descendant_of(s)))) &&
-            (self.negated_categories.is_empty() ||
-             (self.negated_categories.is_disjoint(&info.categories) &&
-              info.categories.iter().all(|c| c.ancestors().all(|a| !self.negated_categories.contains(a)))))
+            info.is_match_with(&self.query, context.settings.home.fold_punctuation) &&
+            self.status_filter.map_or(true, |status| info.has_status(status)) &&
+            self.selected_categories.iter().all(|s| info.is_in_category(s)) &&
+            !self.negated_categories.iter().any(|n| info.is_in_category(n))
         }).cloned().collect();
 
         self.visible_categories = self.visible_books.iter()
@@ -732,9 +730,15 @@ impl Home {
                                EntryKind::RadioButton("Progress".to_string(),
                                                       EntryId::Sort(SortMethod::Progress),
                                                       self.sort_method == SortMethod::Progress),
+                               EntryKind::RadioButton("Date Finished".to_string(),
+                                                      EntryId::Sort(SortMethod::Finished),
+                                                      self.sort_method == SortMethod::Finished),
                                EntryKind::RadioButton("Author".to_string(),
                                                       EntryId::Sort(SortMethod::Author),
                                                       self.sort_method == SortMethod::Author),
+                               EntryKind::RadioButton("Category".to_string(),
+                                                      EntryId::Sort(SortMethod::Category),
+                                                      self.sort_method == SortMethod::Category),
                                EntryKind::RadioButton("File Size".to_string(),
                                                       EntryId::Sort(SortMethod::Size),
                                                       self.sort_method == SortMethod::Size),
@@ -825,6 +829,16 @@ impl Home {
                 entries.push(EntryKind::SubMenu("Set As".to_string(), submenu))
             }
 
+            if info.file.kind == "pdf" {
+                entries.push(EntryKind::Command("Rebuild Table of Contents".to_string(),
+                                                EntryId::RebuildToc(path.clone())));
+            }
+
+            if info.reader.as_ref().map_or(false, |r| !r.annotations.is_empty()) {
+                entries.push(EntryKind::Command("Export Annotations".to_string(),
+                                                EntryId::ExportAnnotations(path.clone())));
+            }
+
             entries.push(EntryKind::Separator);
             entries.push(EntryKind::Command("Remove".to_string(), EntryId::Remove(path.clone())));
 
@@ -1026,7 +1040,8 @@ impl Home {
             if entry.restore_books {
                 untrash(context).map_err(|e| eprintln!("Can't restore books from trash: {}", e)).ok();
             }
-            sort(&mut context.metadata, self.sort_method, self.reverse_order);
+            sort_with(&mut context.metadata, self.sort_method, self.reverse_order,
+                      context.settings.home.progress_mode, self.sort_method.null_order(self.reverse_order));
             self.refresh_visibles(true, false, hub, context);
         }
     }
@@ -1100,39 +1115,22 @@ impl Home {
             return;
         }
 
+        if let Err(e) = check_category_rename(&context.metadata, categ_old, categ_new, false) {
+            hub.send(Event::Notify(format!("Can't rename {}: {}.", categ_old, e))).ok();
+            return;
+        }
+
         self.history_push(false, context);
 
         self.selected_categories = self.selected_categories.iter().map(|c| {
-            if c == categ_old {
-                categ_new.to_string()
-            } else if c.is_descendant_of(categ_old) {
-                categ_new.join(&c[categ_old.len()+1..])
-            } else {
-                c.clone()
-            }
+            symbolic_path::rename(c, categ_old, categ_new).unwrap_or_else(|| c.clone())
         }).collect();
 
         self.negated_categories = self.negated_categories.iter().map(|c| {
-            if c == categ_old {
-                categ_new.to_string()
-            } else if c.is_descendant_of(categ_old) {
-                categ_new.join(&c[categ_old.len()+1..])
-            } else {
-                c.clone()
-            }
+            symbolic_path::rename(c, categ_old, categ_new).unwrap_or_else(|| c.clone())
         }).collect();
 
-        for info in &mut context.metadata {
-            info.categories = info.categories.iter().map(|c| {
-                if c == categ_old {
-                    categ_new.to_string()
-                } else if c.is_descendant_of(categ_old) {
-                    categ_new.join(&c[categ_old.len()+1..])
-                } else {
-                    c.clone()
-                }
-            }).collect();
-        }
+        rename_category(&mut context.metadata, categ_old, categ_new, false).ok();
 
         self.refresh_visibles(true, false, hub, context);
     }
@@ -1164,11 +1162,8 @@ impl Home {
 
         self.history_push(false, context);
 
-        for info in &mut context.metadata {
-            if info.file.path == *path {
-                info.categories.extend(categs.clone());
-                break;
-            }
+        if let Some(info) = find_by_path_mut(&mut context.metadata, path) {
+            info.categories.extend(categs.clone());
         }
 
         self.refresh_visibles(true, false, hub, context);
@@ -1178,11 +1173,8 @@ impl Home {
     fn remove_book_category(&mut self, path: &PathBuf, categ: &str, hub: &Hub, context: &mut Context) {
         self.history_push(false, context);
 
-        for info in &mut context.metadata {
-            if info.file.path == *path {
-                info.categories.remove(categ);
-                break;
-            }
+        if let Some(info) = find_by_path_mut(&mut context.metadata, path) {
+            info.categories.remove(categ);
         }
 
         self.refresh_visibles(true, false, hub, context);
@@ -1196,27 +1188,43 @@ impl Home {
                 if status == SimpleStatus::New {
                     info.reader = None;
                 } else {
-                    if let Some(r) = info.reader.as_mut() {
-                        r.finished = true;
-                    } else {
-                        info.reader = Some(ReaderInfo {
-                            finished: true,
-                            .. Default::default()
-                        });
-                    }
+                    info.reader.get_or_insert_with(ReaderInfo::default)
+                        .set_finished(true);
                 }
                 break;
             }
         }
 
         if self.sort_method == SortMethod::Progress ||
-           self.sort_method == SortMethod::Opened {
+           self.sort_method == SortMethod::Opened ||
+           self.sort_method == SortMethod::Finished {
             self.sort(false, hub, context);
         }
 
         self.refresh_visibles(true, false, hub, context);
     }
 
+    fn rebuild_toc(&mut self, path: &PathBuf, hub: &Hub, context: &mut Context) {
+        let library_path = &context.settings.library_path;
+        if let Some(info) = find_by_path_mut(&mut context.metadata, path) {
+            if let Err(e) = rebuild_toc(library_path, info) {
+                let msg = format!("Can't rebuild the table of contents: {}", e);
+                hub.send(Event::Notify(msg)).ok();
+            }
+        }
+    }
+
+    fn export_annotations(&mut self, path: &PathBuf, hub: &Hub, context: &mut Context) {
+        let out = context.settings.library_path.join(ANNOTATIONS_DIRNAME);
+        if let Some(info) = find_by_path(&context.metadata, path) {
+            let msg = match export_annotations(info, ExportFormat::Markdown, &out) {
+                Ok(()) => format!("Annotations exported to {}.", ANNOTATIONS_DIRNAME),
+                Err(e) => format!("Can't export the annotations: {}", e),
+            };
+            hub.send(Event::Notify(msg)).ok();
+        }
+    }
+
     fn set_reverse_order(&mut self, value: bool, hub: &Hub, context: &mut Context) {
         self.reverse_order = value;
         self.sort(true, hub, context);
@@ -1241,8 +1249,10 @@ impl Home {
             self.current_page = 0;
         }
 
-        sort(&mut context.metadata, self.sort_method, self.reverse_order);
-        sort(&mut self.visible_books, self.sort_method, self.reverse_order);
+        sort_with(&mut context.metadata, self.sort_method, self.reverse_order,
+                  context.settings.home.progress_mode, self.sort_method.null_order(self.reverse_order));
+        sort_with(&mut self.visible_books, self.sort_method, self.reverse_order,
+                  context.settings.home.progress_mode, self.sort_method.null_order(self.reverse_order));
         self.update_shelf(false, hub, context);
         let search_visible = locate::<SearchBar>(self).is_some();
         self.update_top_bar(search_visible, hub);
@@ -1267,18 +1277,36 @@ impl Home {
         } else {
             context.settings.library_path.join(&context.filename)
         };
-        save_json(&self.visible_books, path).map_err(|e| {
+        save_metadata(&self.visible_books, &path).map_err(|e| {
             eprintln!("Can't save: {}.", e);
         }).ok();
     }
 
+    // A corrupt file is restored from its backup, or kept aside, and the user is told so.
+    fn load_metadata_file(&self, path: &Path, hub: &Hub) -> Option<Metadata> {
+        match load_metadata(path) {
+            Ok((metadata, restoration)) => {
+                if let Some(restoration) = restoration {
+                    hub.send(Event::Notify(restoration.report())).ok();
+                }
+                Some(metadata)
+            },
+            Err(e) => {
+                match e.report() {
+                    Some(msg) => { hub.send(Event::Notify(msg)).ok(); },
+                    None => eprintln!("Can't load {}: {}", path.display(), e),
+                }
+                None
+            },
+        }
+    }
+
     fn load(&mut self, filename: &PathBuf, hub: &Hub, context: &mut Context) {
-        let md = load_json::<Metadata, _>(context.settings.library_path.join(filename))
-                           .map_err(|e| eprintln!("Can't load: {}", e));
-        if let Ok(metadata) = md {
-            let saved = save_json(&context.metadata,
-                                  context.settings.library_path.join(&context.filename))
-                                 .map_err(|e| eprintln!("Can't save: {}", e)).is_ok();
+        let md = self.load_metadata_file(&context.settings.library_path.join(filename), hub);
+        if let Some(metadata) = md {
+            let saved = save_metadata(&context.metadata,
+                                      &context.settings.library_path.join(&context.filename))
+                                     .map_err(|e| eprintln!("Can't save: {}", e)).is_ok();
             if saved {
                 context.filename = filename.clone();
                 context.metadata = metadata;
@@ -1291,9 +1319,8 @@ impl Home {
     }
 
     fn reload(&mut self, hub: &Hub, context: &mut Context) {
-        let md = load_json::<Metadata, _>(context.settings.library_path.join(&context.filename))
-                           .map_err(|e| eprintln!("Can't load: {}", e));
-        if let Ok(metadata) = md {
+        let md = self.load_metadata_file(&context.settings.library_path.join(&context.filename), hub);
+        if let Some(metadata) = md {
             context.metadata = metadata;
             self.history.clear();
             self.selected_categories.clear();
@@ -1309,6 +1336,48 @@ impl Home {
         self.refresh_visibles(true, false, hub, context);
     }
 
+    // The files were already added or removed, outside of Plato.
+    fn apply_library_change(&mut self, change: &LibraryChange, hub: &Hub, context: &mut Context) {
+        let library_path = &context.settings.library_path;
+        match change {
+            LibraryChange::Added(file_info) => {
+                if find_by_path(&context.metadata, &file_info.path).is_some() {
+                    return;
+                }
+                let mut imported_metadata = import_files(library_path, vec![file_info.clone()],
+                                                         &context.settings.import);
+                context.metadata.append(&mut imported_metadata);
+            },
+            LibraryChange::Removed(path) => {
+                context.metadata.retain(|info| !info.file.path.starts_with(path));
+            },
+            LibraryChange::Modified(path) => {
+                let size = library_path.join(path).metadata().map(|m| m.len());
+                let index = context.metadata.iter().position(|info| info.file.path == *path);
+                match (index, size) {
+                    (Some(index), Ok(size)) => {
+                        let mut modified_metadata = vec![context.metadata.remove(index)];
+                        modified_metadata[0].file.size = size;
+                        refresh_files(library_path, &mut modified_metadata, &context.settings.import);
+                        context.metadata.append(&mut modified_metadata);
+                    },
+                    _ => return,
+                }
+            },
+            LibraryChange::Rescan => {
+                clean_up(library_path, &mut context.metadata);
+                let imd = auto_import(library_path, &context.metadata, &context.settings.import)
+                                     .map_err(|e| eprintln!("Can't import: {}", e));
+                if let Ok(mut imported_metadata) = imd {
+                    context.metadata.append(&mut imported_metadata);
+                }
+            },
+        }
+        sort_with(&mut context.metadata, self.sort_method, self.reverse_order,
+                  context.settings.home.progress_mode, self.sort_method.null_order(self.reverse_order));
+        self.refresh_visibles(true, false, hub, context);
+    }
+
     fn import(&mut self, hub: &Hub, context: &mut Context) {
         let imd = auto_import(&context.settings.library_path,
                               &context.metadata,
@@ -1316,7 +1385,8 @@ impl Home {
                              .map_err(|e| eprintln!("Can't import: {}", e));
         if let Ok(mut imported_metadata) = imd {
             context.metadata.append(&mut imported_metadata);
-            sort(&mut context.metadata, self.sort_method, self.reverse_order);
+            sort_with(&mut context.metadata, self.sort_method, self.reverse_order,
+                      context.settings.home.progress_mode, self.sort_method.null_order(self.reverse_order));
             self.refresh_visibles(true, false, hub, context);
         }
     }
@@ -1452,6 +1522,10 @@ impl View for Home {
                 self.add_document(*info2, hub, context);
                 true
             },
+            Event::LibraryChanged(ref change) => {
+                self.apply_library_change(change, hub, context);
+                true
+            },
             Event::Select(EntryId::Remove(ref path)) |
             Event::RemoveDocument(ref path) => {
                 self.remove_document(path, hub, context);
@@ -1500,6 +1574,14 @@ impl View for Home {
                 self.set_status(path, status, hub, context);
                 true
             },
+            Event::Select(EntryId::RebuildToc(ref path)) => {
+                self.rebuild_toc(path, hub, context);
+                true
+            },
+            Event::Select(EntryId::ExportAnnotations(ref path)) => {
+                self.export_annotations(path, hub, context);
+                true
+            },
             Event::Select(EntryId::EmptyTrash) => {
                 trash::empty(context).map_err(|e| eprintln!("Can't empty the trash: {}", e)).ok();
                 true
@@ -1550,7 +1632,7 @@ impl View for Home {
                 true
             },
             Event::Submit(ViewId::HomeSearchInput, ref text) => {
-                self.query = make_query(text);
+                self.query = make_query_with(text, context.settings.home.fold_punctuation);
                 if self.query.is_some() {
                     // TODO: avoid updating things twice
                     self.toggle_keyboard(false, true, None, hub, context);
@@ -48,7 +48,8 @@ impl Shelf {
                                        self.rect.max.x, y_max],
                                  info.clone(),
                                  index,
-                                 self.second_column);
+                                 self.second_column,
+                                 context.settings.home.progress_mode);
             self.children.push(Box::new(book) as Box<dyn View>);
             if index < max_lines - 1 {
                 let separator = Filler::new(rect![self.rect.min.x, y_max,
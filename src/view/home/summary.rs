@@ -9,7 +9,7 @@ use super::category::{Category, Status};
 use crate::gesture::GestureEvent;
 use crate::color::TEXT_BUMP_SMALL;
 use crate::app::Context;
-use crate::symbolic_path::SymbolicPath;
+use crate::symbolic_path::{SymbolicPath, unescape};
 use crate::font::{Font, Fonts, font_from_style, category_font_size, NORMAL_STYLE};
 use crate::geom::{Rectangle, Dir, CycleDir, divide, small_half, big_half};
 
@@ -162,7 +162,7 @@ impl Summary {
 
         for categ in visible_categories.iter().skip(start_index) {
             font.set_size(category_font_size(categ.depth()), dpi);
-            let mut categ_width = font.plan(categ.last_component(),
+            let mut categ_width = font.plan(&unescape(categ.last_component()),
                                             None,
                                             None).width as i32;
             let mut max_categ_width = None;
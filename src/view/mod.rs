@@ -51,11 +51,12 @@ use downcast_rs::{Downcast, impl_downcast};
 use crate::font::Fonts;
 use crate::document::{Location, TextLocation, TocEntry};
 use crate::settings::{ButtonScheme, SecondColumn, RotationLock};
-use crate::metadata::{Info, ZoomMode, SortMethod, TextAlign, SimpleStatus, PageScheme, Margin};
+use crate::metadata::{Info, ZoomMode, SortMethod, TextAlign, SimpleStatus, PageScheme, Margin, HighlightStyle, ContrastPreset};
 use crate::geom::{LinearDir, CycleDir, Rectangle, Boundary};
 use crate::framebuffer::{Framebuffer, UpdateMode};
 use crate::input::{DeviceEvent, FingerStatus};
 use crate::gesture::GestureEvent;
+use crate::watcher::LibraryChange;
 use self::calculator::LineOrigin;
 use self::key::KeyKind;
 use self::intermission::IntermKind;
@@ -110,6 +111,11 @@ pub trait View: Downcast {
         true
     }
 
+    // Whether the gyroscope should be ignored.
+    fn rotation_locked(&self) -> bool {
+        false
+    }
+
     fn is_background(&self) -> bool {
         false
     }
@@ -234,6 +240,7 @@ pub enum Event {
     Key(KeyKind),
     AddDocument(Box<Info>),
     RemoveDocument(PathBuf),
+    LibraryChanged(LibraryChange),
     Open(Box<Info>),
     OpenToc(Vec<TocEntry>, usize),
     LoadPixmap(usize),
@@ -345,6 +352,8 @@ pub enum ViewId {
     GoToResultsPageInput,
     NamePage,
     NamePageInput,
+    LabelBookmark,
+    LabelBookmarkInput,
     EditNote,
     EditNoteInput,
     EditLanguages,
@@ -442,7 +451,7 @@ pub enum EntryKind {
     Separator,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum EntryId {
     Save,
     SaveAs,
@@ -462,23 +471,43 @@ pub enum EntryId {
     AddBookCategories(PathBuf),
     RemoveBookCategory(PathBuf, String),
     SetStatus(PathBuf, SimpleStatus),
+    RebuildToc(PathBuf),
+    ExportAnnotations(PathBuf),
     ToggleIntermissionImage(IntermKind, PathBuf),
     RemoveMatches,
     RemovePreset(usize),
     SecondColumn(SecondColumn),
     ApplyCroppings(usize, PageScheme),
     RemoveCroppings,
+    AutoCrop(usize, PageScheme),
+    ToggleCroppingException(usize),
+    ClearCroppingExceptions,
     SetZoomMode(ZoomMode),
+    SetContrastPreset(ContrastPreset),
+    SetHyphenation(Option<bool>),
+    SetParagraphSpacing(Option<usize>),
+    SetTextIndent(Option<usize>),
+    SetFontWeight(Option<usize>),
+    SetLetterSpacing(Option<usize>),
+    ToggleDithered,
+    ResetProgress,
+    SetRefreshEvery(Option<u8>),
+    SetInverted(Option<bool>),
+    GoForward,
     SetPageName,
     RemovePageName,
+    ToggleFirstPage,
+    LabelBookmark,
     HighlightSelection,
     AnnotateSelection,
     DefineSelection,
     SearchForSelection,
     AdjustSelection,
     RemoveAnnotation([TextLocation; 2]),
+    MergeAnnotations,
     EditAnnotationNote([TextLocation; 2]),
     RemoveAnnotationNote([TextLocation; 2]),
+    SetAnnotationStyle([TextLocation; 2], HighlightStyle),
     GoTo(usize),
     GoToSelectedPageName,
     SearchDirection(LinearDir),
@@ -496,6 +525,7 @@ pub enum EntryId {
     SetKeyboardLayout(String),
     ToggleFuzzy,
     ToggleInverted,
+    ToggleRotationLock,
     ToggleMonochrome,
     ToggleWifi,
     Rotate(i8),
@@ -4,6 +4,7 @@ use crate::color::{BLACK, WHITE};
 use crate::gesture::GestureEvent;
 use crate::geom::{Rectangle};
 use crate::document::BYTES_PER_PAGE;
+use crate::metadata::PageNumberStyle;
 use crate::framebuffer::{Framebuffer, UpdateMode};
 use super::{View, Event, Hub, Bus, ViewId};
 use crate::app::Context;
@@ -13,6 +14,8 @@ pub struct PageLabel {
     children: Vec<Box<dyn View>>,
     current_page: usize,
     pages_count: usize,
+    // The index of the page numbered one.
+    page_offset: i64,
     synthetic: bool,
 }
 
@@ -23,10 +26,15 @@ impl PageLabel {
             children: vec![],
             current_page,
             pages_count,
+            page_offset: 0,
             synthetic,
         }
     }
 
+    pub fn set_page_offset(&mut self, page_offset: i64) {
+        self.page_offset = page_offset;
+    }
+
     pub fn update(&mut self, current_page: usize, pages_count: usize, hub: &Hub) {
         self.current_page = current_page;
         self.pages_count = pages_count;
@@ -37,6 +45,9 @@ impl PageLabel {
         if self.pages_count == 0 {
             return "No pages".to_string();
         }
+        if !self.synthetic && self.page_offset != 0 {
+            return self.offset_text(size);
+        }
         let (current_page, pages_count, precision) = if self.synthetic {
             (self.current_page as f64 / BYTES_PER_PAGE,
              self.pages_count as f64 / BYTES_PER_PAGE, 1)
@@ -53,6 +64,27 @@ impl PageLabel {
             _ => format!("{:.1}%", percent),
         }
     }
+
+    // The front matter is numbered in lowercase roman numerals.
+    fn offset_text(&self, size: u8) -> String {
+        let number = self.current_page as i64 - self.page_offset + 1;
+        let current_page = if number > 0 {
+            number.to_string()
+        } else {
+            PageNumberStyle::LowerRoman.format(self.current_page as u32 + 1)
+        };
+        let pages_count = self.pages_count as i64 - self.page_offset;
+        let start = self.page_offset.max(0) as usize;
+        let percent = 100.0 * self.current_page.saturating_sub(start) as f32 /
+                      self.pages_count.saturating_sub(start) as f32;
+        match size {
+            0 => format!("Page {} of {} ({:.1}%)", current_page, pages_count, percent),
+            1 => format!("P. {} of {} ({:.1}%)", current_page, pages_count, percent),
+            2 => format!("{}/{} ({:.1}%)", current_page, pages_count, percent),
+            3 => format!("{} ({:.1}%)", current_page, percent),
+            _ => format!("{:.1}%", percent),
+        }
+    }
 }
 
 
@@ -21,7 +21,7 @@ pub struct BottomBar {
 }
 
 impl BottomBar {
-    pub fn new(rect: Rectangle, doc: &mut dyn Document, toc: Option<Vec<TocEntry>>, current_page: usize, pages_count: usize, neighbors: &Neighbors, synthetic: bool) -> BottomBar {
+    pub fn new(rect: Rectangle, doc: &mut dyn Document, toc: Option<Vec<TocEntry>>, current_page: usize, pages_count: usize, page_offset: i64, neighbors: &Neighbors, synthetic: bool) -> BottomBar {
         let mut children = Vec::new();
         let side = rect.height() as i32;
         let is_prev_disabled = neighbors.previous_page.is_none();
@@ -54,11 +54,12 @@ impl BottomBar {
                                        Align::Center);
         children.push(Box::new(chapter_label) as Box<dyn View>);
 
-        let page_label = PageLabel::new(rect![pt!(rect.max.x - side - big_half_width, rect.min.y),
-                                              pt!(rect.max.x - side, rect.max.y)],
-                                        current_page,
-                                        pages_count,
-                                        synthetic);
+        let mut page_label = PageLabel::new(rect![pt!(rect.max.x - side - big_half_width, rect.min.y),
+                                                  pt!(rect.max.x - side, rect.max.y)],
+                                            current_page,
+                                            pages_count,
+                                            synthetic);
+        page_label.set_page_offset(page_offset);
         children.push(Box::new(page_label) as Box<dyn View>);
 
         let next_rect = rect![rect.max - side, rect.max];
@@ -81,8 +82,9 @@ impl BottomBar {
         }
     }
 
-    pub fn update_page_label(&mut self, current_page: usize, pages_count: usize, hub: &Hub) {
+    pub fn update_page_label(&mut self, current_page: usize, pages_count: usize, page_offset: i64, hub: &Hub) {
         let page_label = self.child_mut(2).downcast_mut::<PageLabel>().unwrap();
+        page_label.set_page_offset(page_offset);
         page_label.update(current_page, pages_count, hub);
     }
 
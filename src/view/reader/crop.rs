@@ -0,0 +1,231 @@
+use crate::framebuffer::Pixmap;
+use crate::document::{Document, Location};
+use crate::metadata::{Margin, CroppingMargins, PageScheme, DEFAULT_CONTRAST_GRAY};
+
+// Pixels lighter than this are considered blank when detecting margins.
+const DEFAULT_WHITESPACE_THRESHOLD: u8 = DEFAULT_CONTRAST_GRAY as u8;
+
+// Returns the bounding box of the pixels darker than *threshold*:
+// `[x_min, y_min, x_max, y_max]`, or `None` for a blank page.
+// The rows and columns with at most *noise*, a fraction of their length, of such pixels are ignored.
+fn content_box(pixmap: &Pixmap, threshold: u8, noise: f32) -> Option<[u32; 4]> {
+    let (width, height) = (pixmap.width as usize, pixmap.height as usize);
+    let mut rows = vec![0usize; height];
+    let mut columns = vec![0usize; width];
+
+    for (y, row) in pixmap.data.chunks(width.max(1)).take(height).enumerate() {
+        for (x, _) in row.iter().enumerate().filter(|(_, &v)| v < threshold) {
+            rows[y] += 1;
+            columns[x] += 1;
+        }
+    }
+
+    let row_noise = (noise * width as f32) as usize;
+    let column_noise = (noise * height as f32) as usize;
+    let y_min = rows.iter().position(|&n| n > row_noise)?;
+    let y_max = rows.iter().rposition(|&n| n > row_noise)?;
+    let x_min = columns.iter().position(|&n| n > column_noise)?;
+    let x_max = columns.iter().rposition(|&n| n > column_noise)?;
+
+    Some([x_min as u32, y_min as u32, x_max as u32, y_max as u32])
+}
+
+// The margin that keeps the content of all the given pages.
+fn content_margin(pages: &[&Pixmap], threshold: u8, noise: f32) -> Option<Margin> {
+    pages.iter().filter_map(|pixmap| {
+        content_box(pixmap, threshold, noise).map(|[x_min, y_min, x_max, y_max]| {
+            let (width, height) = (pixmap.width as f32, pixmap.height as f32);
+            Margin::new(y_min as f32 / height,
+                        (width - 1.0 - x_max as f32) / width,
+                        (height - 1.0 - y_max as f32) / height,
+                        x_min as f32 / width)
+        })
+    }).fold(None, |acc: Option<Margin>, m| Some(match acc {
+        Some(a) => Margin::new(a.top.min(m.top), a.right.min(m.right),
+                               a.bottom.min(m.bottom), a.left.min(m.left)),
+        None => m,
+    }))
+}
+
+// The margins of the even and odd pages, *pages* alternating from an odd page if *odd_first*.
+fn even_odd_margins(pages: &[&Pixmap], odd_first: bool, threshold: u8, noise: f32) -> [Margin; 2] {
+    let even: Vec<&Pixmap> = pages.iter().skip(odd_first as usize).step_by(2).cloned().collect();
+    let odd: Vec<&Pixmap> = pages.iter().skip(!odd_first as usize).step_by(2).cloned().collect();
+    let even_margin = content_margin(&even, threshold, noise);
+    let odd_margin = content_margin(&odd, threshold, noise);
+    // A blank or missing page borrows the margin of the other parity.
+    let even_margin = even_margin.or_else(|| odd_margin.clone()).unwrap_or_default();
+    let odd_margin = odd_margin.unwrap_or_else(|| even_margin.clone());
+    [even_margin, odd_margin]
+}
+
+fn detect_margins(pages: &[&Pixmap], scheme: PageScheme, threshold: u8) -> CroppingMargins {
+    detect_noisy_margins(pages, scheme, threshold, 0.0)
+}
+
+// Guesses the cropping margins from rendered pages: the pages at even positions
+// within *pages* are even pages, the others are odd pages. With the `Cover` scheme,
+// the first page is the cover.
+fn detect_noisy_margins(pages: &[&Pixmap], scheme: PageScheme, threshold: u8, noise: f32) -> CroppingMargins {
+    match scheme {
+        PageScheme::Any => {
+            CroppingMargins::Any(content_margin(pages, threshold, noise).unwrap_or_default())
+        },
+        PageScheme::EvenOdd => {
+            CroppingMargins::EvenOdd(even_odd_margins(pages, false, threshold, noise))
+        },
+        PageScheme::Cover => {
+            let split = pages.len().min(1);
+            let cover = content_margin(&pages[..split], threshold, noise).unwrap_or_default();
+            let [even, odd] = even_odd_margins(&pages[split..], true, threshold, noise);
+            CroppingMargins::CoverEvenOdd([cover, even, odd])
+        },
+    }
+}
+
+// The width, in pixels, at which the pages are rendered to find their content.
+const AUTO_CROP_WIDTH: f32 = 300.0;
+// The number of pages rendered, besides the cover, to find the even and odd margins.
+const AUTO_CROP_SAMPLES: usize = 6;
+// Specks of dust and scanning artifacts cover at most that fraction of a row or a column.
+const AUTO_CROP_NOISE: f32 = 0.005;
+// The space kept around the content, as a fraction of the page dimensions.
+const AUTO_CROP_PADDING: f32 = 0.01;
+
+// The pages rendered to guess the margins of the page at *index*: the even pages are
+// at even positions, after the cover when there's one.
+fn auto_crop_samples(index: usize, count: usize, scheme: PageScheme) -> Vec<usize> {
+    let index = index.min(count.saturating_sub(1));
+    match scheme {
+        PageScheme::Any => vec![index],
+        PageScheme::EvenOdd => {
+            let start = (index - index % 2).min(count.saturating_sub(AUTO_CROP_SAMPLES) & !1);
+            (start..count.min(start + AUTO_CROP_SAMPLES)).collect()
+        },
+        PageScheme::Cover => {
+            let start = (index.max(1) - (index.max(1) + 1) % 2)
+                        .min(count.saturating_sub(AUTO_CROP_SAMPLES) | 1);
+            let mut samples = vec![0];
+            samples.extend(start..count.min(start + AUTO_CROP_SAMPLES));
+            samples
+        },
+    }
+}
+
+// Guesses the cropping margins of a scanned document by rendering a few pages at a low resolution.
+// The margins never crop more than *max_fraction* of a page dimension.
+pub fn auto_crop(doc: &mut dyn Document, index: usize, scheme: PageScheme, max_fraction: f32) -> CroppingMargins {
+    let pixmaps: Vec<Pixmap> = auto_crop_samples(index, doc.pages_count(), scheme).into_iter().map(|i| {
+        doc.dims(i).and_then(|(width, _)| doc.pixmap(Location::Exact(i), AUTO_CROP_WIDTH / width))
+                   .map(|(pixmap, _)| pixmap)
+                   // A page that can't be rendered counts as a blank page.
+                   .unwrap_or_else(|| Pixmap::new(1, 1))
+    }).collect();
+    let pages: Vec<&Pixmap> = pixmaps.iter().collect();
+    let mut margins = detect_noisy_margins(&pages, scheme, DEFAULT_WHITESPACE_THRESHOLD, AUTO_CROP_NOISE);
+    let adjust = |margin: &mut Margin| *margin = margin.padded(AUTO_CROP_PADDING).limited(max_fraction);
+    match margins {
+        CroppingMargins::EvenOdd(ref mut pair) => pair.iter_mut().for_each(adjust),
+        CroppingMargins::CoverEvenOdd(ref mut triple) => triple.iter_mut().for_each(adjust),
+        ref mut margins => adjust(margins.margin_mut(index)),
+    }
+    margins
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blank_page(width: u32, height: u32) -> Pixmap {
+        let mut pixmap = Pixmap::new(width, height);
+        for v in pixmap.data.iter_mut() {
+            *v = 250;
+        }
+        pixmap
+    }
+
+    fn page_with_box(width: u32, height: u32, bbox: [u32; 4]) -> Pixmap {
+        let mut pixmap = blank_page(width, height);
+        let [x_min, y_min, x_max, y_max] = bbox;
+        for y in y_min..=y_max {
+            for x in x_min..=x_max {
+                pixmap.data[(y * width + x) as usize] = 30;
+            }
+        }
+        pixmap
+    }
+
+    #[test]
+    fn margin_detection() {
+        let threshold = DEFAULT_WHITESPACE_THRESHOLD;
+        let even = page_with_box(100, 200, [10, 20, 79, 179]);
+        let odd = page_with_box(100, 200, [20, 40, 89, 159]);
+        let blank = blank_page(100, 200);
+
+        match detect_margins(&[&even], PageScheme::Any, threshold) {
+            CroppingMargins::Any(m) => {
+                assert_eq!((m.top, m.right, m.bottom, m.left), (0.1, 0.2, 0.1, 0.1));
+            },
+            _ => panic!("expected a single margin"),
+        }
+
+        match detect_margins(&[&even, &odd], PageScheme::Any, threshold) {
+            CroppingMargins::Any(m) => {
+                assert_eq!((m.top, m.right, m.bottom, m.left), (0.1, 0.1, 0.1, 0.1));
+            },
+            _ => panic!("expected a single margin"),
+        }
+
+        match detect_margins(&[&even, &odd], PageScheme::EvenOdd, threshold) {
+            CroppingMargins::EvenOdd([e, o]) => {
+                assert_eq!((e.top, e.right, e.bottom, e.left), (0.1, 0.2, 0.1, 0.1));
+                assert_eq!((o.top, o.right, o.bottom, o.left), (0.2, 0.1, 0.2, 0.2));
+            },
+            _ => panic!("expected a pair of margins"),
+        }
+
+        match detect_margins(&[&blank, &odd], PageScheme::EvenOdd, threshold) {
+            CroppingMargins::EvenOdd([e, o]) => assert_eq!((e.top, e.left), (o.top, o.left)),
+            _ => panic!("expected a pair of margins"),
+        }
+
+        // A light gray frame is content for a high threshold only.
+        let mut framed = page_with_box(100, 200, [30, 50, 69, 149]);
+        for x in 0..100 {
+            framed.data[x] = 235;
+        }
+        assert_eq!(detect_margins(&[&framed], PageScheme::Any, threshold).margin(0).top, 0.25);
+        assert_eq!(detect_margins(&[&framed], PageScheme::Any, 240).margin(0).top, 0.0);
+        assert_eq!(detect_margins(&[&blank], PageScheme::Any, threshold).margin(0).top, 0.0);
+
+        let cover = page_with_box(100, 200, [0, 0, 99, 199]);
+        match detect_margins(&[&cover, &odd, &even, &odd], PageScheme::Cover, DEFAULT_WHITESPACE_THRESHOLD) {
+            CroppingMargins::CoverEvenOdd([c, e, o]) => {
+                assert_eq!((c.top, c.left), (0.0, 0.0));
+                assert_eq!((e.top, e.right, e.bottom, e.left), (0.1, 0.2, 0.1, 0.1));
+                assert_eq!((o.top, o.right, o.bottom, o.left), (0.2, 0.1, 0.2, 0.2));
+            },
+            _ => panic!("expected three margins"),
+        }
+    }
+
+    #[test]
+    fn automatic_cropping() {
+        assert_eq!(auto_crop_samples(5, 10, PageScheme::Any), vec![5]);
+        assert_eq!(auto_crop_samples(3, 100, PageScheme::EvenOdd), vec![2, 3, 4, 5, 6, 7]);
+        assert_eq!(auto_crop_samples(9, 10, PageScheme::EvenOdd), vec![4, 5, 6, 7, 8, 9]);
+        assert_eq!(auto_crop_samples(4, 100, PageScheme::Cover), vec![0, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(auto_crop_samples(0, 3, PageScheme::Cover), vec![0, 1, 2]);
+        assert_eq!(auto_crop_samples(0, 0, PageScheme::EvenOdd), Vec::<usize>::new());
+
+        // A few specks don't extend the content.
+        let mut page = page_with_box(200, 200, [40, 40, 159, 159]);
+        page.data[5 * 200 + 5] = 0;
+        page.data[190 * 200 + 195] = 0;
+        match detect_noisy_margins(&[&page], PageScheme::Any, DEFAULT_WHITESPACE_THRESHOLD, 0.005) {
+            CroppingMargins::Any(m) => assert_eq!((m.top, m.right, m.bottom, m.left), (0.2, 0.2, 0.2, 0.2)),
+            _ => panic!("expected a single margin"),
+        }
+        assert_eq!(detect_margins(&[&page], PageScheme::Any, DEFAULT_WHITESPACE_THRESHOLD).margin(0).top, 0.025);
+    }
+}
@@ -3,6 +3,7 @@ mod bottom_bar;
 mod results_bar;
 mod margin_cropper;
 mod results_label;
+mod crop;
 
 use std::f32;
 use std::thread;
@@ -11,17 +12,15 @@ use std::sync::{Arc, Mutex, mpsc};
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering as AtomicOrdering;
 use std::path::PathBuf;
-use std::collections::{VecDeque, BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use chrono::Local;
 use regex::Regex;
-use septem::prelude::*;
-use septem::{Roman, Digit};
+use septem::Digit;
 use crate::input::{DeviceEvent, FingerStatus, ButtonCode, ButtonStatus};
 use crate::framebuffer::{Framebuffer, UpdateMode, Pixmap};
 use crate::view::{View, Event, AppCmd, Hub, Bus, ViewId, EntryKind, EntryId, SliderId, THICKNESS_MEDIUM};
 use crate::unit::{scale_by_dpi, mm_to_px};
 use crate::device::{CURRENT_DEVICE, BAR_SIZES};
-use crate::helpers::AsciiExtension;
 use crate::font::Fonts;
 use crate::font::family_names;
 use self::margin_cropper::{MarginCropper, BUTTON_DIAMETER};
@@ -29,6 +28,7 @@ use super::top_bar::TopBar;
 use self::tool_bar::ToolBar;
 use self::bottom_bar::BottomBar;
 use self::results_bar::ResultsBar;
+use self::crop::auto_crop;
 use crate::view::common::{locate, rlocate, locate_by_id};
 use crate::view::common::{toggle_main_menu, toggle_battery_menu, toggle_clock_menu};
 use crate::view::filler::Filler;
@@ -37,23 +37,32 @@ use crate::view::search_bar::SearchBar;
 use crate::view::keyboard::Keyboard;
 use crate::view::menu::{Menu, MenuKind};
 use crate::view::notification::Notification;
-use crate::settings::{guess_frontlight, FinishedAction};
+use crate::settings::{guess_frontlight, FinishedAction, ReaderDefaults, ResumePoint};
 use crate::settings::{DEFAULT_FONT_FAMILY, DEFAULT_TEXT_ALIGN, DEFAULT_LINE_HEIGHT, DEFAULT_MARGIN_WIDTH};
 use crate::frontlight::LightLevels;
 use crate::gesture::GestureEvent;
 use crate::document::{Document, open, Location, TextLocation, BoundedText, Neighbors, BYTES_PER_PAGE};
 use crate::document::{TocEntry, SimpleTocEntry, TocLocation, toc_as_html, chapter_from_index};
 use crate::document::pdf::PdfOpener;
-use crate::metadata::{Info, FileInfo, ReaderInfo, Annotation, TextAlign, ZoomMode, PageScheme};
-use crate::metadata::{Margin, CroppingMargins, make_query};
+use crate::metadata::{Info, FileInfo, ReaderInfo, Annotation, HighlightStyle, TextAlign, Direction, ZoomMode, PageScheme};
+use crate::metadata::{ContrastPreset, DitherMode, HyphenationSettings};
+use crate::metadata::{Margin, CroppingMargins, AnnotationContext, make_query};
+use crate::metadata::{annotation_context, relocate_annotations, merge_overlapping_annotations};
 use crate::metadata::{DEFAULT_CONTRAST_EXPONENT, DEFAULT_CONTRAST_GRAY};
 use crate::geom::{Point, Rectangle, Boundary, CornerSpec, BorderSpec, Dir, DiagDir, CycleDir, LinearDir, Axis, halves};
 use crate::color::{BLACK, WHITE};
 use crate::app::Context;
 
-const HISTORY_SIZE: usize = 32;
 const RECT_DIST_JITTER: f32 = 24.0;
 const ANNOTATION_DRIFT: u8 =  32;
+const LIGHT_ANNOTATION_DRIFT: u8 = 16;
+const DARK_ANNOTATION_DRIFT: u8 = 64;
+// In ems.
+const PARAGRAPH_SPACINGS: [f32; 4] = [0.0, 0.5, 1.0, 1.5];
+const TEXT_INDENTS: [f32; 4] = [0.0, 1.0, 1.5, 2.0];
+const FONT_WEIGHTS: [(&str, f32); 3] = [("Medium", 500.0), ("Semibold", 600.0), ("Bold", 700.0)];
+const LETTER_SPACINGS: [f32; 4] = [0.0, 0.02, 0.05, 0.1];
+const ZOOM_FACTORS: [f32; 4] = [1.5, 2.0, 2.5, 3.0];
 
 pub struct Reader {
     rect: Rectangle,
@@ -69,10 +78,12 @@ pub struct Reader {
     held_buttons: HashSet<ButtonCode>,
     selection: Option<Selection>,
     target_annotation: Option<[TextLocation; 2]>,
-    history: VecDeque<usize>,
     state: State,
     info: Info,
+    page_list: Vec<(String, TextLocation)>,
     current_page: usize,
+    // The current page and the location within it that the changes of layout keep on screen.
+    anchor: Option<(usize, usize)>,
     pages_count: usize,
     view_port: ViewPort,
     contrast: Contrast,
@@ -81,12 +92,15 @@ pub struct Reader {
     reflowable: bool,
     ephemeral: bool,
     finished: bool,
+    // The inversion of the display outside of this book.
+    global_inverted: bool,
 }
 
 #[derive(Debug)]
 struct ViewPort {
     zoom_mode: ZoomMode,
     top_offset: i32,
+    left_offset: i32,
     margin_width: i32,
 }
 
@@ -95,6 +109,7 @@ impl Default for ViewPort {
         ViewPort {
             zoom_mode: ZoomMode::FitToPage,
             top_offset: 0,
+            left_offset: 0,
             margin_width: 0,
         }
     }
@@ -170,14 +185,33 @@ fn scaling_factor(rect: &Rectangle, cropping_margin: &Margin, screen_margin_widt
     let surface_width = (rect.width() as i32 - 2 * screen_margin_width) as f32;
     let frame_width = (1.0 - (cropping_margin.left + cropping_margin.right)) * page_width;
     let width_ratio = surface_width / frame_width;
+    let surface_height = (rect.height() as i32 - 2 * screen_margin_width) as f32;
+    let frame_height = (1.0 - (cropping_margin.top + cropping_margin.bottom)) * page_height;
+    let height_ratio = surface_height / frame_height;
     match zoom_mode {
-        ZoomMode::FitToPage => {
-            let surface_height = (rect.height() as i32 - 2 * screen_margin_width) as f32;
-            let frame_height = (1.0 - (cropping_margin.top + cropping_margin.bottom)) * page_height;
-            let height_ratio = surface_height / frame_height;
-            width_ratio.min(height_ratio)
-        },
+        ZoomMode::FitToPage => width_ratio.min(height_ratio),
         ZoomMode::FitToWidth => width_ratio,
+        ZoomMode::FitToHeight => height_ratio,
+        ZoomMode::Custom(factor) => factor * width_ratio,
+    }
+}
+
+// Maps the print page labels to the pages of the current layout. The layout they were
+// resolved against is left to the caller.
+fn update_page_names(doc: &mut dyn Document, page_list: &[(String, TextLocation)], r: &mut ReaderInfo) {
+    r.page_names_layout = None;
+
+    if page_list.is_empty() {
+        return;
+    }
+
+    let labels: HashSet<&str> = page_list.iter().map(|(label, _)| label.as_str()).collect();
+    r.page_names.retain(|_, name| !labels.contains(name.as_str()));
+
+    for (label, location) in page_list {
+        if let Some(index) = doc.resolve_location(Location::Exact(location.location())) {
+            r.page_names.entry(index).or_insert_with(|| label.clone());
+        }
     }
 }
 
@@ -186,6 +220,19 @@ fn build_pixmap(rect: &Rectangle, doc: &mut dyn Document, location: usize) -> (P
     doc.pixmap(Location::Exact(location), scale).unwrap()
 }
 
+fn draw_highlight(fb: &mut dyn Framebuffer, rect: &Rectangle, clip: &Rectangle, style: HighlightStyle, thickness: i32) {
+    let (rect, drift) = match style {
+        HighlightStyle::Gray => (*rect, ANNOTATION_DRIFT),
+        HighlightStyle::Light => (*rect, LIGHT_ANNOTATION_DRIFT),
+        HighlightStyle::Dark => (*rect, DARK_ANNOTATION_DRIFT),
+        HighlightStyle::Underline => (rect![rect.min.x, rect.max.y - thickness,
+                                            rect.max.x, rect.max.y], u8::max_value()),
+    };
+    if let Some(ref rect) = rect.intersection(clip) {
+        fb.shift_region(rect, drift);
+    }
+}
+
 fn find_cut(frame: &Rectangle, y_pos: i32, scale: f32, dir: LinearDir, lines: &[BoundedText]) -> Option<i32> {
     let y_pos_u = y_pos as f32 / scale;
     let frame_u = frame.to_boundary() / scale;
@@ -237,16 +284,25 @@ impl Reader {
     pub fn new(rect: Rectangle, mut info: Info, hub: &Hub, context: &mut Context) -> Option<Reader> {
         let settings = &context.settings;
         let path = settings.library_path.join(&info.file.path);
+        let global_inverted = context.fb.inverted();
 
-        open(&path).and_then(|mut doc| {
+        let reader = open(&path).and_then(|mut doc| {
             let (width, height) = context.display.dims;
+            // A book that was never opened starts from the template it was imported with.
+            let defaults = if info.reader.is_none() {
+                info.reader_defaults.take().unwrap_or_default()
+            } else {
+                ReaderDefaults::default()
+            };
             let font_size = info.reader.as_ref().and_then(|r| r.font_size)
+                                .or(defaults.font_size)
                                 .unwrap_or(settings.reader.font_size);
             let first_location = doc.resolve_location(Location::Exact(0))?;
 
             doc.layout(width, height, font_size, CURRENT_DEVICE.dpi);
 
             let margin_width = info.reader.as_ref().and_then(|r| r.margin_width)
+                                   .or(defaults.margin_width)
                                    .unwrap_or(settings.reader.margin_width);
 
             if margin_width != DEFAULT_MARGIN_WIDTH {
@@ -254,6 +310,7 @@ impl Reader {
             }
 
             let font_family = info.reader.as_ref().and_then(|r| r.font_family.as_ref())
+                                  .or_else(|| defaults.font_family.as_ref())
                                   .unwrap_or(&settings.reader.font_family);
 
             if font_family != DEFAULT_FONT_FAMILY {
@@ -261,35 +318,116 @@ impl Reader {
             }
 
             let line_height = info.reader.as_ref().and_then(|r| r.line_height)
+                                  .or(defaults.line_height)
                                   .unwrap_or(settings.reader.line_height);
 
             if (line_height - DEFAULT_LINE_HEIGHT).abs() > f32::EPSILON {
                 doc.set_line_height(line_height);
             }
 
+            let direction = info.text_direction();
+
+            if direction != Direction::Ltr {
+                doc.set_direction(direction);
+            }
+
+            // An alignment chosen for the book always wins over the one of its language.
             let text_align = info.reader.as_ref().and_then(|r| r.text_align)
-                                 .unwrap_or(settings.reader.text_align);
+                                 .or(defaults.text_align)
+                                 .unwrap_or_else(|| direction.default_text_align(settings.reader.text_align));
 
             if text_align != DEFAULT_TEXT_ALIGN {
                 doc.set_text_align(text_align);
             }
 
-            let mut view_port = ViewPort::default();
+            let mut layout_key = format!("{}x{} {} {} {} {} {:?}", width, height, font_size,
+                                         font_family, margin_width, line_height, text_align);
+
+            if direction != Direction::Ltr {
+                layout_key += &format!(" {:?}", direction);
+            }
+
+            if let Some(hyphenation) = info.reader.as_ref().and_then(|r| r.hyphenation.as_ref()) {
+                let language = info.hyphenation_language();
+                doc.set_hyphenation(hyphenation.enabled, language);
+                layout_key += &format!(" {} {}", hyphenation.enabled, language.unwrap_or_default());
+            }
+
+            let paragraph_spacing = info.reader.as_ref().and_then(|r| r.paragraph_spacing);
+            let text_indent = info.reader.as_ref().and_then(|r| r.text_indent);
+
+            if paragraph_spacing.is_some() || text_indent.is_some() {
+                doc.set_paragraph_spacing(paragraph_spacing);
+                doc.set_text_indent(text_indent);
+                layout_key += &format!(" {:?} {:?}", paragraph_spacing, text_indent);
+            }
+
+            let font_weight = info.reader.as_ref().and_then(|r| r.font_weight);
+            let letter_spacing = info.reader.as_ref().and_then(|r| r.letter_spacing);
+
+            if font_weight.is_some() || letter_spacing.is_some() {
+                doc.set_font_weight(font_weight);
+                doc.set_letter_spacing(letter_spacing);
+                layout_key += &format!(" {:?} {:?}", font_weight, letter_spacing);
+            }
+
+            let mut view_port = ViewPort {
+                zoom_mode: settings.reader.zoom_mode(&info.file.kind),
+                .. Default::default()
+            };
             let mut contrast = Contrast::default();
             let pages_count = doc.pages_count();
             let current_page;
+            let mut anchor = None;
 
             // TODO: use get_or_insert_with?
             if let Some(ref mut r) = info.reader {
                 r.opened = Local::now();
 
-                if r.finished {
-                    r.finished = false;
-                    r.current_page = first_location;
+                // The file might have been replaced by another edition.
+                if r.pages_count != pages_count {
+                    let adjustments = r.sanitize(pages_count);
+                    if !adjustments.is_empty() {
+                        eprintln!("{}: {}.", info.file.path.display(), adjustments);
+                        hub.send(Event::Notify(format!("Edition changed: {}.", adjustments))).ok();
+                    }
                 }
 
-                current_page = doc.resolve_location(Location::Exact(r.current_page))
-                                  .unwrap_or(first_location);
+                let resume_at = if r.finished {
+                    r.set_finished(false);
+                    r.current_page = first_location;
+                    r.current_location = None;
+                    ResumePoint::LastPage
+                } else {
+                    settings.reader.resume_at
+                };
+
+                // The stored location is at or after the start of the stored page.
+                let location = r.current_location.filter(|_| doc.has_synthetic_page_numbers())
+                                .map(TextLocation::location)
+                                .filter(|&loc| loc >= r.current_page)
+                                .unwrap_or(r.current_page);
+                let resume_location = match resume_at {
+                    ResumePoint::LastPage => None,
+                    ResumePoint::LastAnnotation => r.latest_annotation_location().map(TextLocation::location),
+                    ResumePoint::FurthestPage => Some(r.furthest_page.max(r.current_page)),
+                };
+                // The resume point is ignored if the current layout can't resolve it.
+                let resumed = resume_location.filter(|&loc| loc < pages_count)
+                                             .and_then(|loc| doc.resolve_location(Location::Exact(loc))
+                                                                .map(|page| (page, loc)));
+                if let Some((page, loc)) = resumed {
+                    current_page = page;
+                    if doc.has_synthetic_page_numbers() {
+                        anchor = Some((page, loc));
+                    }
+                } else {
+                    current_page = doc.resolve_location(Location::Exact(location))
+                                      .unwrap_or(first_location);
+                    if location != r.current_page {
+                        anchor = Some((current_page, location));
+                    }
+                }
 
                 if let Some(zoom_mode) = r.zoom_mode {
                     view_port.zoom_mode = zoom_mode;
@@ -299,6 +437,33 @@ impl Reader {
                     view_port.top_offset = top_offset;
                 }
 
+                if let Some(left_offset) = r.left_offset {
+                    view_port.left_offset = left_offset;
+                }
+
+                // The stored margins might have been edited by hand.
+                if let Some(ref mut c) = r.cropping_margins {
+                    c.clamp();
+                }
+            } else {
+                current_page = info.start_location
+                                   .and_then(|loc| doc.resolve_location(Location::Exact(loc.location())))
+                                   .unwrap_or(first_location);
+
+                let mut r = ReaderInfo {
+                    current_page,
+                    pages_count,
+                    .. Default::default()
+                };
+                r.apply_defaults(&defaults);
+                info.reader = Some(r);
+            }
+
+            if let Some(ref mut r) = info.reader {
+                r.apply_contrast_preset(&settings.reader.contrast_presets);
+            }
+
+            if let Some(ref r) = info.reader {
                 if !doc.is_reflowable() {
                     view_port.margin_width = mm_to_px(r.screen_margin_width.unwrap_or(0) as f32,
                                                       CURRENT_DEVICE.dpi) as i32;
@@ -311,14 +476,29 @@ impl Reader {
                 if let Some(gray) = r.contrast_gray {
                     contrast.gray = gray;
                 }
-            } else {
-                current_page = first_location;
+            }
 
-                info.reader = Some(ReaderInfo {
-                    current_page,
-                    pages_count,
-                    .. Default::default()
-                });
+            let page_list = doc.page_list();
+
+            if let Some(ref mut r) = info.reader {
+                r.begin_session(Local::now(), current_page);
+
+                // The page names are resolved once per layout.
+                let page_names_layout = if doc.is_reflowable() {
+                    format!("{} {}", pages_count, layout_key)
+                } else {
+                    pages_count.to_string()
+                };
+                if r.page_names_layout.as_ref() != Some(&page_names_layout) {
+                    update_page_names(doc.as_mut(), &page_list, r);
+                    r.page_names_layout = Some(page_names_layout);
+                }
+
+                // The dynamic locations of the annotations are checked once per layout.
+                if doc.is_reflowable() && r.anchored_layout.as_ref() != Some(&layout_key) {
+                    relocate_annotations(doc.as_mut(), r);
+                    r.anchored_layout = Some(layout_key);
+                }
             }
 
             let synthetic = doc.has_synthetic_page_numbers();
@@ -342,10 +522,11 @@ impl Reader {
                 held_buttons: HashSet::new(),
                 selection: None,
                 target_annotation: None,
-                history: VecDeque::new(),
                 state: State::Idle,
                 info,
+                page_list,
                 current_page,
+                anchor,
                 pages_count,
                 view_port,
                 synthetic,
@@ -354,8 +535,20 @@ impl Reader {
                 ephemeral: false,
                 reflowable,
                 finished: false,
+                global_inverted,
             })
-        })
+        });
+
+        if let Some(ref reader) = reader {
+            if reader.info.effective_inverted(global_inverted) != global_inverted {
+                context.fb.set_inverted(!global_inverted);
+            }
+            if reader.info.reader.as_ref().map_or(false, |r| r.dithered()) {
+                context.fb.set_dithered(true);
+            }
+        }
+
+        reader
     }
 
     pub fn from_toc(rect: Rectangle, toc: &[TocEntry], chap_index: usize, hub: &Hub, context: &mut Context) -> Reader {
@@ -367,6 +560,7 @@ impl Reader {
                 path: PathBuf::from("toc:"),
                 kind: "html".to_string(),
                 size: html.len() as u64,
+                checksum: None,
             },
             .. Default::default()
         };
@@ -413,10 +607,11 @@ impl Reader {
             held_buttons: HashSet::new(),
             selection: None,
             target_annotation: None,
-            history: VecDeque::new(),
             state: State::Idle,
             info,
+            page_list: Vec::new(),
             current_page,
+            anchor: None,
             pages_count,
             view_port: ViewPort::default(),
             synthetic: false,
@@ -425,9 +620,16 @@ impl Reader {
             ephemeral: true,
             reflowable: true,
             finished: false,
+            global_inverted: context.fb.inverted(),
         }
     }
 
+    // The location that a change of layout keeps on screen.
+    fn reading_location(&self) -> usize {
+        self.anchor.filter(|&(page, _)| page == self.current_page)
+                   .map_or(self.current_page, |(_, location)| location)
+    }
+
     fn load_pixmap(&mut self, location: usize) {
         if self.cache.contains_key(&location) {
             return;
@@ -471,9 +673,8 @@ impl Reader {
 
         if let Some(location) = loc {
             if record {
-                self.history.push_back(self.current_page);
-                if self.history.len() > HISTORY_SIZE {
-                    self.history.pop_front();
+                if let Some(ref mut r) = self.info.reader {
+                    r.push_jump(self.current_page, context.settings.reader.history_size);
                 }
             }
 
@@ -482,6 +683,7 @@ impl Reader {
             }
 
             self.view_port.top_offset = 0;
+            self.view_port.left_offset = 0;
             self.current_page = location;
             self.update(None, hub, context);
             self.update_bottom_bar(hub);
@@ -593,7 +795,17 @@ impl Reader {
     }
 
     fn go_to_last_page(&mut self, hub: &Hub, context: &Context) {
-        if let Some(location) = self.history.pop_back() {
+        let current_page = self.current_page;
+        let history_size = context.settings.reader.history_size;
+        if let Some(location) = self.info.reader.as_mut().and_then(|r| r.go_back(current_page, history_size)) {
+            self.go_to_page(location, false, hub, context);
+        }
+    }
+
+    fn go_to_next_page(&mut self, hub: &Hub, context: &Context) {
+        let current_page = self.current_page;
+        let history_size = context.settings.reader.history_size;
+        if let Some(location) = self.info.reader.as_mut().and_then(|r| r.go_forward(current_page, history_size)) {
             self.go_to_page(location, false, hub, context);
         }
     }
@@ -603,6 +815,16 @@ impl Reader {
             return;
         }
 
+        if self.view_port.zoom_mode.is_windowed() {
+            let (_, max_top_offset) = self.max_offsets(self.current_page);
+            let next_top_offset = (self.view_port.top_offset - delta_y).max(0).min(max_top_offset);
+            if next_top_offset != self.view_port.top_offset {
+                self.view_port.top_offset = next_top_offset;
+                self.update(None, hub, context);
+            }
+            return;
+        }
+
         let mut next_top_offset = self.view_port.top_offset - delta_y;
         let mut location = self.current_page;
         let max_top_offset = self.cache[&location].frame.height().saturating_sub(1) as i32;
@@ -659,9 +881,22 @@ impl Reader {
         }
     }
 
+    // The largest offsets of the window of the windowed zoom modes, for the given page.
+    fn max_offsets(&mut self, location: usize) -> (i32, i32) {
+        self.load_pixmap(location);
+        let frame = self.cache[&location].frame;
+        let smw = self.view_port.margin_width;
+        ((frame.width() as i32 - self.rect.width() as i32 + 2 * smw).max(0),
+         (frame.height() as i32 - self.rect.height() as i32 + 2 * smw).max(0))
+    }
+
     fn go_to_neighbor(&mut self, dir: CycleDir, hub: &Hub, context: &mut Context) {
         let current_page = self.current_page;
         let top_offset = self.view_port.top_offset;
+        let left_offset = self.view_port.left_offset;
+        let smw = self.view_port.margin_width;
+        let available_width = self.rect.width() as i32 - 2 * smw;
+        let available_height = self.rect.height() as i32 - 2 * smw;
 
         let loc = {
             let neighloc = if dir == CycleDir::Previous {
@@ -708,6 +943,29 @@ impl Reader {
                         self.view_port.top_offset = next_top_offset;
                         Location::Exact(location)
                     },
+                    // Move up, then left, then to the bottom right corner of the previous page.
+                    ZoomMode::FitToHeight | ZoomMode::Custom(..) => {
+                        if top_offset > 0 {
+                            self.view_port.top_offset = (top_offset - available_height).max(0);
+                            Location::Exact(current_page)
+                        } else if left_offset > 0 {
+                            let (_, max_top_offset) = self.max_offsets(current_page);
+                            self.view_port.left_offset = (left_offset - available_width).max(0);
+                            self.view_port.top_offset = max_top_offset;
+                            Location::Exact(current_page)
+                        } else {
+                            let previous_location = self.doc.lock().unwrap()
+                                                        .resolve_location(Location::Previous(current_page));
+                            if let Some(location) = previous_location {
+                                let (max_left_offset, max_top_offset) = self.max_offsets(location);
+                                self.view_port.left_offset = max_left_offset;
+                                self.view_port.top_offset = max_top_offset;
+                                Location::Exact(location)
+                            } else {
+                                Location::Previous(current_page)
+                            }
+                        }
+                    },
                 }
             } else {
                 match self.view_port.zoom_mode {
@@ -724,13 +982,30 @@ impl Reader {
                             Location::Exact(last_chunk.location)
                         }
                     },
+                    // Move down, then right, then to the top left corner of the next page.
+                    ZoomMode::FitToHeight | ZoomMode::Custom(..) => {
+                        let (max_left_offset, max_top_offset) = self.max_offsets(current_page);
+                        if top_offset < max_top_offset {
+                            self.view_port.top_offset = (top_offset + available_height).min(max_top_offset);
+                            Location::Exact(current_page)
+                        } else if left_offset < max_left_offset {
+                            self.view_port.left_offset = (left_offset + available_width).min(max_left_offset);
+                            self.view_port.top_offset = 0;
+                            Location::Exact(current_page)
+                        } else {
+                            self.view_port.left_offset = 0;
+                            self.view_port.top_offset = 0;
+                            Location::Next(current_page)
+                        }
+                    },
                 }
             };
             let mut doc = self.doc.lock().unwrap();
             doc.resolve_location(neighloc)
         };
         match loc {
-            Some(location) if location != current_page || self.view_port.top_offset != top_offset => {
+            Some(location) if location != current_page || self.view_port.top_offset != top_offset ||
+                               self.view_port.left_offset != left_offset => {
                 if let Some(ref mut s) = self.search {
                     s.current_page = s.highlights.range(..=location).count().saturating_sub(1);
                 }
@@ -788,6 +1063,7 @@ impl Reader {
         }
         if let Some(location) = loc {
             self.view_port.top_offset = 0;
+            self.view_port.left_offset = 0;
             self.current_page = location;
             self.update_results_bar(hub);
             self.update_bottom_bar(hub);
@@ -809,6 +1085,7 @@ impl Reader {
                 s.current_page = s.highlights.range(..=location).count().saturating_sub(1);
             }
             self.view_port.top_offset = 0;
+            self.view_port.left_offset = 0;
             self.current_page = location;
             self.update_results_bar(hub);
             self.update_bottom_bar(hub);
@@ -819,6 +1096,7 @@ impl Reader {
     fn update_bottom_bar(&mut self, hub: &Hub) {
         if let Some(index) = locate::<BottomBar>(self) {
             let current_page = self.current_page;
+            let page_offset = self.info.reader.as_ref().map_or(0, |r| r.page_offset());
             let mut doc = self.doc.lock().unwrap();
             let chapter = self.toc().or_else(|| doc.toc())
                               .as_ref().and_then(|toc| doc.chapter(current_page, toc))
@@ -829,7 +1107,7 @@ impl Reader {
                 previous_page: doc.resolve_location(Location::Previous(current_page)),
                 next_page: doc.resolve_location(Location::Next(current_page)),
             };
-            bottom_bar.update_page_label(self.current_page, self.pages_count, hub);
+            bottom_bar.update_page_label(self.current_page, self.pages_count, page_offset, hub);
             bottom_bar.update_icons(&neighbors, hub);
             bottom_bar.update_chapter(&chapter, hub);
         }
@@ -837,6 +1115,7 @@ impl Reader {
 
     fn update_tool_bar(&mut self, hub: &Hub, context: &mut Context) {
         if let Some(index) = locate::<ToolBar>(self) {
+            let direction = self.info.text_direction();
             let tool_bar = self.children[index].as_mut().downcast_mut::<ToolBar>().unwrap();
             let settings = &context.settings;
             if self.reflowable {
@@ -850,7 +1129,7 @@ impl Reader {
                 tool_bar.update_font_size_slider(font_size, hub);
                 let text_align = self.info.reader.as_ref()
                                     .and_then(|r| r.text_align)
-                                    .unwrap_or(settings.reader.text_align);
+                                    .unwrap_or_else(|| direction.default_text_align(settings.reader.text_align));
                 tool_bar.update_text_align_icon(text_align, hub);
                 let line_height = self.info.reader.as_ref()
                                       .and_then(|r| r.line_height)
@@ -908,12 +1187,18 @@ impl Reader {
 
     fn update(&mut self, update_mode: Option<UpdateMode>, hub: &Hub, context: &Context) {
         self.page_turns += 1;
+        if let Some(ref mut r) = self.info.reader {
+            r.record_activity(Local::now(), self.current_page, context.settings.reader.idle_threshold);
+            r.reach_page(self.current_page);
+        }
         let update_mode = update_mode.unwrap_or_else(|| {
-            let refresh_rate = if context.fb.inverted() {
-                context.settings.reader.refresh_rate.inverted
-            } else {
-                context.settings.reader.refresh_rate.regular
-            };
+            let refresh_rate = self.info.reader.as_ref().and_then(|r| r.refresh_every).unwrap_or_else(|| {
+                if context.fb.inverted() {
+                    context.settings.reader.refresh_rate.inverted
+                } else {
+                    context.settings.reader.refresh_rate.regular
+                }
+            });
             if refresh_rate == 0 || self.page_turns % (refresh_rate as usize) != 0 {
                 UpdateMode::Partial
             } else {
@@ -974,6 +1259,22 @@ impl Reader {
                     }
                 }
             },
+            ZoomMode::FitToHeight | ZoomMode::Custom(..) => {
+                let (max_left_offset, max_top_offset) = self.max_offsets(location);
+                self.view_port.left_offset = self.view_port.left_offset.max(0).min(max_left_offset);
+                self.view_port.top_offset = self.view_port.top_offset.max(0).min(max_top_offset);
+                self.load_text(location);
+                let Resource { mut frame, scale, .. } = self.cache[&location];
+                let available_width = self.rect.width() as i32 - 2 * smw;
+                let available_height = self.rect.height() as i32 - 2 * smw;
+                frame.min.x += self.view_port.left_offset;
+                frame.min.y += self.view_port.top_offset;
+                frame.max.x = frame.max.x.min(frame.min.x + available_width);
+                frame.max.y = frame.max.y.min(frame.min.y + available_height);
+                let dx = smw + (available_width - frame.width() as i32) / 2;
+                let dy = smw + (available_height - frame.height() as i32) / 2;
+                self.chunks.push(RenderChunk { frame, location, position: pt!(dx, dy), scale });
+            },
         }
 
         hub.send(Event::Render(self.rect, update_mode)).ok();
@@ -1208,6 +1509,7 @@ impl Reader {
                                               sp_rect.max.y + tb_height as i32],
                                         self.reflowable,
                                         self.info.reader.as_ref(),
+                                        self.info.text_direction(),
                                         &context.settings.reader);
             self.children.insert(2, Box::new(tool_bar) as Box<dyn View>);
 
@@ -1424,6 +1726,7 @@ impl Reader {
                                                   self.rect.max.y - small_height as i32 - small_thickness],
                                             self.reflowable,
                                             self.info.reader.as_ref(),
+                                            self.info.text_direction(),
                                             &context.settings.reader);
                 self.children.insert(index, Box::new(tool_bar) as Box<dyn View>);
                 index += 1;
@@ -1450,6 +1753,7 @@ impl Reader {
                                             self.toc(),
                                             self.current_page,
                                             self.pages_count,
+                                            self.info.reader.as_ref().map_or(0, |r| r.page_offset()),
                                             &neighbors,
                                             self.synthetic);
             self.children.insert(index, Box::new(bottom_bar) as Box<dyn View>);
@@ -1549,6 +1853,36 @@ impl Reader {
         }
     }
 
+    fn toggle_label_bookmark(&mut self, enable: Option<bool>, hub: &Hub, context: &mut Context) {
+        if let Some(index) = locate_by_id(self, ViewId::LabelBookmark) {
+            if let Some(true) = enable {
+                return;
+            }
+
+            hub.send(Event::Expose(*self.child(index).rect(), UpdateMode::Gui)).ok();
+            self.children.remove(index);
+
+            if self.focus.map(|focus_id| focus_id == ViewId::LabelBookmarkInput).unwrap_or(false) {
+                self.toggle_keyboard(false, None, hub, context);
+            }
+        } else {
+            if let Some(false) = enable {
+                return;
+            }
+
+            let mut label_bookmark = NamedInput::new("Label bookmark".to_string(), ViewId::LabelBookmark, ViewId::LabelBookmarkInput, 24, context);
+            if let Some(label) = self.info.reader.as_ref().and_then(|r| r.bookmark_label(self.current_page)) {
+                let (tx, _rx) = mpsc::channel();
+                label_bookmark.set_text(label, &tx, context);
+            }
+
+            hub.send(Event::Render(*label_bookmark.rect(), UpdateMode::Gui)).ok();
+            hub.send(Event::Focus(Some(ViewId::LabelBookmarkInput))).ok();
+
+            self.children.push(Box::new(label_bookmark) as Box<dyn View>);
+        }
+    }
+
     fn toggle_go_to_page(&mut self, enable: Option<bool>, id: ViewId, hub: &Hub, context: &mut Context) {
         let (text, input_id) = if id == ViewId::GoToPage {
             ("Go to page", ViewId::GoToPageInput)
@@ -1607,6 +1941,16 @@ impl Reader {
                 entries.push(EntryKind::Command("Remove Note".to_string(), EntryId::RemoveAnnotationNote(sel)));
             }
 
+            entries.push(EntryKind::Separator);
+            let styles = HighlightStyle::ALL.iter().map(|&style| {
+                EntryKind::RadioButton(style.label().to_string(),
+                                       EntryId::SetAnnotationStyle(sel, style),
+                                       annot.style == style)
+            }).collect();
+            entries.push(EntryKind::SubMenu("Style".to_string(), styles));
+            entries.push(EntryKind::Separator);
+            entries.push(EntryKind::Command("Merge Overlapping".to_string(), EntryId::MergeAnnotations));
+
             let selection_menu = Menu::new(rect, ViewId::AnnotationMenu, MenuKind::Contextual, entries, context);
             hub.send(Event::Render(*selection_menu.rect(), UpdateMode::Gui)).ok();
             self.children.push(Box::new(selection_menu) as Box<dyn View>);
@@ -1668,10 +2012,104 @@ impl Reader {
                                                              zoom_mode == ZoomMode::FitToPage),
                                       EntryKind::RadioButton("Fit to Width".to_string(),
                                                              EntryId::SetZoomMode(ZoomMode::FitToWidth),
-                                                             zoom_mode == ZoomMode::FitToWidth)]));
+                                                             zoom_mode == ZoomMode::FitToWidth),
+                                      EntryKind::RadioButton("Fit to Height".to_string(),
+                                                             EntryId::SetZoomMode(ZoomMode::FitToHeight),
+                                                             zoom_mode == ZoomMode::FitToHeight),
+                                      EntryKind::Separator,
+                                      EntryKind::SubMenu("Custom".to_string(),
+                                          ZOOM_FACTORS.iter().map(|&factor| {
+                                              EntryKind::RadioButton(format!("{}%", (100.0 * factor).round()),
+                                                                     EntryId::SetZoomMode(ZoomMode::Custom(factor)),
+                                                                     matches!(zoom_mode, ZoomMode::Custom(f) if (f - factor).abs() < 1e-3))
+                                          }).collect())]));
+                if let Some(r) = self.info.reader.as_ref() {
+                    let preset = r.contrast_preset;
+                    entries.push(EntryKind::SubMenu("Contrast Preset".to_string(),
+                        ContrastPreset::ALL.iter().map(|&p| {
+                            EntryKind::RadioButton(p.label().to_string(),
+                                                   EntryId::SetContrastPreset(p),
+                                                   preset == Some(p))
+                        }).collect()));
+                    entries.push(EntryKind::CheckBox("Dithered".to_string(),
+                                                     EntryId::ToggleDithered,
+                                                     r.dithered()));
+                }
+            } else if let Some(r) = self.info.reader.as_ref() {
+                let enabled = r.hyphenation.as_ref().map(|h| h.enabled);
+                entries.push(EntryKind::SubMenu("Hyphenation".to_string(), vec![
+                                      EntryKind::RadioButton("Default".to_string(),
+                                                             EntryId::SetHyphenation(None),
+                                                             enabled.is_none()),
+                                      EntryKind::Separator,
+                                      EntryKind::RadioButton("Enabled".to_string(),
+                                                             EntryId::SetHyphenation(Some(true)),
+                                                             enabled == Some(true)),
+                                      EntryKind::RadioButton("Disabled".to_string(),
+                                                             EntryId::SetHyphenation(Some(false)),
+                                                             enabled == Some(false))]));
+                let em_entries = |values: &[f32], precision: usize, current: Option<f32>, id: fn(Option<usize>) -> EntryId| {
+                    let mut entries = vec![EntryKind::RadioButton("Default".to_string(), id(None), current.is_none()),
+                                           EntryKind::Separator];
+                    for (index, value) in values.iter().enumerate() {
+                        entries.push(EntryKind::RadioButton(format!("{:.*} em", precision, value), id(Some(index)),
+                                                            current.map_or(false, |v| (v - value).abs() < f32::EPSILON)));
+                    }
+                    entries
+                };
+                entries.push(EntryKind::SubMenu("Paragraph Spacing".to_string(),
+                                                em_entries(&PARAGRAPH_SPACINGS, 1, r.paragraph_spacing, EntryId::SetParagraphSpacing)));
+                entries.push(EntryKind::SubMenu("Text Indent".to_string(),
+                                                em_entries(&TEXT_INDENTS, 1, r.text_indent, EntryId::SetTextIndent)));
+                let mut weight_entries = vec![EntryKind::RadioButton("Default".to_string(),
+                                                                     EntryId::SetFontWeight(None),
+                                                                     r.font_weight.is_none()),
+                                              EntryKind::Separator];
+                for (index, (name, weight)) in FONT_WEIGHTS.iter().enumerate() {
+                    weight_entries.push(EntryKind::RadioButton(name.to_string(), EntryId::SetFontWeight(Some(index)),
+                                                               r.font_weight.map_or(false, |w| (w - weight).abs() < f32::EPSILON)));
+                }
+                entries.push(EntryKind::SubMenu("Font Weight".to_string(), weight_entries));
+                entries.push(EntryKind::SubMenu("Letter Spacing".to_string(),
+                                                em_entries(&LETTER_SPACINGS, 2, r.letter_spacing, EntryId::SetLetterSpacing)));
+            }
+            if let Some(r) = self.info.reader.as_ref() {
+                let refresh_every = r.refresh_every;
+                let mut refresh_entries = vec![EntryKind::RadioButton("Default".to_string(),
+                                                                      EntryId::SetRefreshEvery(None),
+                                                                      refresh_every.is_none()),
+                                               EntryKind::Separator];
+                for &(name, value) in &[("Never", 0), ("Every Page", 1), ("Every 4 Pages", 4),
+                                        ("Every 8 Pages", 8), ("Every 20 Pages", 20)] {
+                    refresh_entries.push(EntryKind::RadioButton(name.to_string(),
+                                                                EntryId::SetRefreshEvery(Some(value)),
+                                                                refresh_every == Some(value)));
+                }
+                entries.push(EntryKind::SubMenu("Full Refresh".to_string(), refresh_entries));
+                let inverted = r.inverted;
+                entries.push(EntryKind::SubMenu("Inverted".to_string(), vec![
+                                      EntryKind::RadioButton("Default".to_string(),
+                                                             EntryId::SetInverted(None),
+                                                             inverted.is_none()),
+                                      EntryKind::Separator,
+                                      EntryKind::RadioButton("Yes".to_string(),
+                                                             EntryId::SetInverted(Some(true)),
+                                                             inverted == Some(true)),
+                                      EntryKind::RadioButton("No".to_string(),
+                                                             EntryId::SetInverted(Some(false)),
+                                                             inverted == Some(false))]));
+                entries.push(EntryKind::CheckBox("Lock Rotation".to_string(),
+                                                 EntryId::ToggleRotationLock,
+                                                 r.rotation_lock == Some(true)));
             }
             entries.push(EntryKind::Command("Metadata".to_string(),
                                             EntryId::OpenMetadata));
+            if self.info.reader.as_ref().map_or(false, |r| !r.forward_history.is_empty()) {
+                entries.push(EntryKind::Command("Go Forward".to_string(),
+                                                EntryId::GoForward));
+            }
+            entries.push(EntryKind::Command("Reset Progress".to_string(),
+                                            EntryId::ResetProgress));
             let title_menu = Menu::new(rect, ViewId::TitleMenu, MenuKind::DropDown, entries, context);
             hub.send(Event::Render(*title_menu.rect(), UpdateMode::Gui)).ok();
             self.children.push(Box::new(title_menu) as Box<dyn View>);
@@ -1755,7 +2193,7 @@ impl Reader {
             }
 
             let text_align = self.info.reader.as_ref().and_then(|r| r.text_align)
-                                .unwrap_or(context.settings.reader.text_align);
+                                .unwrap_or_else(|| self.info.text_direction().default_text_align(context.settings.reader.text_align));
             let choices = [TextAlign::Justify, TextAlign::Left, TextAlign::Right, TextAlign::Center];
             let entries = choices.iter().map(|v| {
                 EntryKind::RadioButton(v.to_string(),
@@ -1891,6 +2329,11 @@ impl Reader {
             if has_name {
                 entries.push(EntryKind::Command("Remove Name".to_string(), EntryId::RemovePageName));
             }
+            if !self.synthetic {
+                let is_first = self.info.reader.as_ref()
+                                   .map_or(false, |r| r.page_offset == Some(self.current_page as i32));
+                entries.push(EntryKind::CheckBox("First Page".to_string(), EntryId::ToggleFirstPage, is_first));
+            }
             let names = self.info.reader.as_ref()
                             .map(|r| r.page_names.iter()
                                       .map(|(i, s)| EntryKind::Command(s.to_string(), EntryId::GoTo(*i)))
@@ -1901,6 +2344,26 @@ impl Reader {
                 entries.push(EntryKind::SubMenu("Go To".to_string(), names));
             }
 
+            let is_bookmarked = self.info.reader.as_ref()
+                                    .map_or(false, |r| r.bookmarks.contains(&self.current_page));
+            if is_bookmarked {
+                entries.push(EntryKind::Separator);
+                entries.push(EntryKind::Command("Label Bookmark".to_string(), EntryId::LabelBookmark));
+            }
+            let bookmarks = self.info.reader.as_ref()
+                                .map(|r| r.bookmarks.iter()
+                                          .map(|&i| EntryKind::Command(r.bookmark_label(i).map_or_else(|| format!("Page {}", i + 1),
+                                                                                                      String::from),
+                                                                       EntryId::GoTo(i)))
+                                          .collect::<Vec<EntryKind>>())
+                                .unwrap_or_default();
+            if !bookmarks.is_empty() {
+                if !is_bookmarked {
+                    entries.push(EntryKind::Separator);
+                }
+                entries.push(EntryKind::SubMenu("Bookmarks".to_string(), bookmarks));
+            }
+
             let page_menu = Menu::new(rect, ViewId::PageMenu, MenuKind::DropDown, entries, context);
             hub.send(Event::Render(*page_menu.rect(), UpdateMode::Gui)).ok();
             self.children.push(Box::new(page_menu) as Box<dyn View>);
@@ -1921,21 +2384,33 @@ impl Reader {
             }
 
             let current_page = self.current_page;
-            let is_split = self.info.reader.as_ref()
-                               .and_then(|r| r.cropping_margins
-                                              .as_ref().map(CroppingMargins::is_split));
+            let scheme = self.info.reader.as_ref()
+                             .and_then(|r| r.cropping_margins
+                                            .as_ref().map(CroppingMargins::scheme));
 
             let mut entries = vec![EntryKind::RadioButton("Any".to_string(),
                                                           EntryId::ApplyCroppings(current_page, PageScheme::Any),
-                                                          is_split.is_some() && !is_split.unwrap()),
+                                                          scheme == Some(PageScheme::Any)),
                                    EntryKind::RadioButton("Even/Odd".to_string(),
                                                           EntryId::ApplyCroppings(current_page, PageScheme::EvenOdd),
-                                                          is_split.is_some() && is_split.unwrap())];
-
-            let is_applied = self.info.reader.as_ref()
-                                 .map(|r| r.cropping_margins.is_some())
-                                 .unwrap_or(false);
-            if is_applied {
+                                                          scheme == Some(PageScheme::EvenOdd)),
+                                   EntryKind::RadioButton("Cover, Even/Odd".to_string(),
+                                                          EntryId::ApplyCroppings(current_page, PageScheme::Cover),
+                                                          scheme == Some(PageScheme::Cover)),
+                               EntryKind::Separator,
+                               EntryKind::Command("Detect".to_string(),
+                                                  EntryId::AutoCrop(current_page, scheme.unwrap_or(PageScheme::Any)))];
+
+            let margins = self.info.reader.as_ref()
+                              .and_then(|r| r.cropping_margins.as_ref());
+            if let Some(margins) = margins {
+                entries.extend_from_slice(&[EntryKind::Separator,
+                                            EntryKind::CheckBox("This Page Only".to_string(),
+                                                                EntryId::ToggleCroppingException(current_page),
+                                                                margins.has_exception(current_page))]);
+                if let CroppingMargins::Exceptions { .. } = margins {
+                    entries.push(EntryKind::Command("Clear Exceptions".to_string(), EntryId::ClearCroppingExceptions));
+                }
                 entries.extend_from_slice(&[EntryKind::Separator,
                                             EntryKind::Command("Remove".to_string(), EntryId::RemoveCroppings)]);
             }
@@ -1988,10 +2463,14 @@ impl Reader {
             doc.layout(width, height, font_size, CURRENT_DEVICE.dpi);
 
             if self.synthetic {
-                let current_page = self.current_page.min(doc.pages_count() - 1);
-                if let Some(location) =  doc.resolve_location(Location::Exact(current_page)) {
+                let current_location = self.reading_location().min(doc.pages_count() - 1);
+                if let Some(location) = doc.resolve_location(Location::Exact(current_location)) {
                     self.current_page = location;
                 }
+                self.anchor = Some((self.current_page, current_location));
+                if let Some(ref mut r) = self.info.reader {
+                    update_page_names(&mut **doc, &self.page_list, r);
+                }
             } else {
                 let ratio = doc.pages_count() / self.pages_count;
                 self.pages_count = doc.pages_count();
@@ -2020,10 +2499,112 @@ impl Reader {
             doc.set_text_align(text_align);
 
             if self.synthetic {
-                let current_page = self.current_page.min(doc.pages_count() - 1);
-                if let Some(location) =  doc.resolve_location(Location::Exact(current_page)) {
+                let current_location = self.reading_location().min(doc.pages_count() - 1);
+                if let Some(location) = doc.resolve_location(Location::Exact(current_location)) {
+                    self.current_page = location;
+                }
+                self.anchor = Some((self.current_page, current_location));
+                if let Some(ref mut r) = self.info.reader {
+                    update_page_names(&mut **doc, &self.page_list, r);
+                }
+            } else {
+                self.pages_count = doc.pages_count();
+                self.current_page = self.current_page.min(self.pages_count - 1);
+            }
+        }
+
+        self.cache.clear();
+        self.text.clear();
+        self.update(None, hub, context);
+        self.update_tool_bar(hub, context);
+        self.update_bottom_bar(hub);
+    }
+
+    fn set_hyphenation(&mut self, hyphenation: Option<HyphenationSettings>, hub: &Hub, context: &mut Context) {
+        if Arc::strong_count(&self.doc) > 1 {
+            return;
+        }
+
+        if let Some(ref mut r) = self.info.reader {
+            r.hyphenation = hyphenation;
+        }
+
+        let enabled = self.info.reader.as_ref().and_then(|r| r.hyphenation.as_ref()).map_or(true, |h| h.enabled);
+        let language = self.info.hyphenation_language().map(String::from);
+        self.relayout(|doc| doc.set_hyphenation(enabled, language.as_deref()), hub, context);
+    }
+
+    fn set_paragraph_spacing(&mut self, spacing: Option<f32>, hub: &Hub, context: &mut Context) {
+        if Arc::strong_count(&self.doc) > 1 {
+            return;
+        }
+
+        if let Some(ref mut r) = self.info.reader {
+            r.paragraph_spacing = spacing;
+        }
+
+        self.relayout(|doc| doc.set_paragraph_spacing(spacing), hub, context);
+    }
+
+    fn set_text_indent(&mut self, indent: Option<f32>, hub: &Hub, context: &mut Context) {
+        if Arc::strong_count(&self.doc) > 1 {
+            return;
+        }
+
+        if let Some(ref mut r) = self.info.reader {
+            r.text_indent = indent;
+        }
+
+        self.relayout(|doc| doc.set_text_indent(indent), hub, context);
+    }
+
+    fn set_font_weight(&mut self, weight: Option<f32>, hub: &Hub, context: &mut Context) {
+        if Arc::strong_count(&self.doc) > 1 {
+            return;
+        }
+
+        if let Some(ref mut r) = self.info.reader {
+            match weight {
+                Some(weight) => r.set_font_weight(weight),
+                None => r.font_weight = None,
+            }
+        }
+
+        let weight = self.info.reader.as_ref().and_then(|r| r.font_weight);
+        self.relayout(|doc| doc.set_font_weight(weight), hub, context);
+    }
+
+    fn set_letter_spacing(&mut self, spacing: Option<f32>, hub: &Hub, context: &mut Context) {
+        if Arc::strong_count(&self.doc) > 1 {
+            return;
+        }
+
+        if let Some(ref mut r) = self.info.reader {
+            match spacing {
+                Some(spacing) => r.set_letter_spacing(spacing),
+                None => r.letter_spacing = None,
+            }
+        }
+
+        let spacing = self.info.reader.as_ref().and_then(|r| r.letter_spacing);
+        self.relayout(|doc| doc.set_letter_spacing(spacing), hub, context);
+    }
+
+    // Applies a change of the layout and keeps the current page within the new bounds.
+    fn relayout<F: FnOnce(&mut dyn Document)>(&mut self, change: F, hub: &Hub, context: &mut Context) {
+        {
+            let mut doc = self.doc.lock().unwrap();
+            change(&mut **doc);
+
+            if self.synthetic {
+                let current_location = self.reading_location().min(doc.pages_count() - 1);
+                if let Some(location) = doc.resolve_location(Location::Exact(current_location)) {
                     self.current_page = location;
                 }
+                self.anchor = Some((self.current_page, current_location));
+                if let Some(ref mut r) = self.info.reader {
+                    update_page_names(&mut **doc, &self.page_list, r);
+                }
             } else {
                 self.pages_count = doc.pages_count();
                 self.current_page = self.current_page.min(self.pages_count - 1);
@@ -2057,10 +2638,14 @@ impl Reader {
             doc.set_font_family(font_family, font_path);
 
             if self.synthetic {
-                let current_page = self.current_page.min(doc.pages_count() - 1);
-                if let Some(location) =  doc.resolve_location(Location::Exact(current_page)) {
+                let current_location = self.reading_location().min(doc.pages_count() - 1);
+                if let Some(location) = doc.resolve_location(Location::Exact(current_location)) {
                     self.current_page = location;
                 }
+                self.anchor = Some((self.current_page, current_location));
+                if let Some(ref mut r) = self.info.reader {
+                    update_page_names(&mut **doc, &self.page_list, r);
+                }
             } else {
                 self.pages_count = doc.pages_count();
                 self.current_page = self.current_page.min(self.pages_count - 1);
@@ -2088,10 +2673,14 @@ impl Reader {
             doc.set_line_height(line_height);
 
             if self.synthetic {
-                let current_page = self.current_page.min(doc.pages_count() - 1);
-                if let Some(location) =  doc.resolve_location(Location::Exact(current_page)) {
+                let current_location = self.reading_location().min(doc.pages_count() - 1);
+                if let Some(location) = doc.resolve_location(Location::Exact(current_location)) {
                     self.current_page = location;
                 }
+                self.anchor = Some((self.current_page, current_location));
+                if let Some(ref mut r) = self.info.reader {
+                    update_page_names(&mut **doc, &self.page_list, r);
+                }
             } else {
                 self.pages_count = doc.pages_count();
                 self.current_page = self.current_page.min(self.pages_count - 1);
@@ -2127,10 +2716,14 @@ impl Reader {
             doc.set_margin_width(width);
 
             if self.synthetic {
-                let current_page = self.current_page.min(doc.pages_count() - 1);
-                if let Some(location) =  doc.resolve_location(Location::Exact(current_page)) {
+                let current_location = self.reading_location().min(doc.pages_count() - 1);
+                if let Some(location) = doc.resolve_location(Location::Exact(current_location)) {
                     self.current_page = location;
                 }
+                self.anchor = Some((self.current_page, current_location));
+                if let Some(ref mut r) = self.info.reader {
+                    update_page_names(&mut **doc, &self.page_list, r);
+                }
             } else {
                 self.pages_count = doc.pages_count();
                 self.current_page = self.current_page.min(self.pages_count - 1);
@@ -2140,6 +2733,7 @@ impl Reader {
             let ratio = (self.rect.width() as i32 - 2 * next_margin_width) as f32 /
                         (self.rect.width() as i32 - 2 * self.view_port.margin_width) as f32;
             self.view_port.top_offset = (self.view_port.top_offset as f32 * ratio) as i32;
+            self.view_port.left_offset = (self.view_port.left_offset as f32 * ratio) as i32;
             self.view_port.margin_width = next_margin_width;
         }
 
@@ -2152,8 +2746,8 @@ impl Reader {
 
     fn toggle_bookmark(&mut self, hub: &Hub) {
         if let Some(ref mut r) = self.info.reader {
-            if !r.bookmarks.insert(self.current_page) {
-                r.bookmarks.remove(&self.current_page);
+            if !r.remove_bookmark(self.current_page) {
+                r.add_bookmark(self.current_page, None);
             }
         }
         let dpi = CURRENT_DEVICE.dpi;
@@ -2167,7 +2761,10 @@ impl Reader {
 
     fn set_contrast_exponent(&mut self, exponent: f32, hub: &Hub, context: &mut Context) {
         if let Some(ref mut r) = self.info.reader {
-            r.contrast_exponent = Some(exponent);
+            r.set_contrast_exponent(exponent);
+            if r.contrast_preset.is_some() {
+                r.contrast_preset = Some(ContrastPreset::Custom);
+            }
         }
         self.contrast.exponent = exponent;
         self.update(None, hub, context);
@@ -2176,19 +2773,34 @@ impl Reader {
 
     fn set_contrast_gray(&mut self, gray: f32, hub: &Hub, context: &mut Context) {
         if let Some(ref mut r) = self.info.reader {
-            r.contrast_gray = Some(gray);
+            r.set_contrast_gray(gray);
+            if r.contrast_preset.is_some() {
+                r.contrast_preset = Some(ContrastPreset::Custom);
+            }
         }
         self.contrast.gray = gray;
         self.update(None, hub, context);
         self.update_tool_bar(hub, context);
     }
 
+    fn set_contrast_preset(&mut self, preset: ContrastPreset, hub: &Hub, context: &mut Context) {
+        if let Some(ref mut r) = self.info.reader {
+            r.contrast_preset = Some(preset);
+            r.apply_contrast_preset(&context.settings.reader.contrast_presets);
+            self.contrast.exponent = r.contrast_exponent.unwrap_or(DEFAULT_CONTRAST_EXPONENT);
+            self.contrast.gray = r.contrast_gray.unwrap_or(DEFAULT_CONTRAST_GRAY);
+        }
+        self.update(None, hub, context);
+        self.update_tool_bar(hub, context);
+    }
+
     fn set_zoom_mode(&mut self, zoom_mode: ZoomMode, hub: &Hub, context: &Context) {
         if self.view_port.zoom_mode == zoom_mode {
             return;
         }
-        self.view_port.zoom_mode = zoom_mode;
+        self.view_port.zoom_mode = zoom_mode.clamped();
         self.view_port.top_offset = 0;
+        self.view_port.left_offset = 0;
         self.cache.clear();
         self.update(None, hub, context);
     }
@@ -2207,13 +2819,16 @@ impl Reader {
             } else {
                 self.view_port.top_offset = 0;
             }
+        } else if self.view_port.zoom_mode.is_windowed() {
+            self.view_port.top_offset = 0;
+            self.view_port.left_offset = 0;
         }
         if let Some(r) = self.info.reader.as_mut() {
             if r.cropping_margins.is_none() {
                 r.cropping_margins = Some(CroppingMargins::Any(Margin::default()));
             }
             for c in r.cropping_margins.iter_mut() {
-                *c.margin_mut(index) = margin.clone();
+                *c.margin_mut(index) = margin.clamped();
             }
         }
         self.cache.clear();
@@ -2260,31 +2875,7 @@ impl Reader {
     }
 
     fn find_page_by_name(&self, name: &str) -> Option<usize> {
-        self.info.reader.as_ref().and_then(|r| {
-            if let Ok(a) = u32::from_str_radix(name, 10) {
-                r.page_names
-                 .iter().filter_map(|(i, s)| u32::from_str_radix(s, 10).ok().map(|b| (b, i)))
-                 .filter(|(b, _)| *b <= a)
-                 .max_by(|x, y| x.0.cmp(&y.0))
-                 .map(|(b, i)| *i + (a - b) as usize)
-            } else if let Some(a) = name.chars().next().and_then(|c| c.to_alphabetic_digit()) {
-                r.page_names
-                 .iter().filter_map(|(i, s)| s.chars().next()
-                                              .and_then(|c| c.to_alphabetic_digit())
-                                              .map(|c| (c, i)))
-                 .filter(|(b, _)| *b <= a)
-                 .max_by(|x, y| x.0.cmp(&y.0))
-                 .map(|(b, i)| *i + (a - b) as usize)
-            } else if let Ok(a) = Roman::from_str(name) {
-                r.page_names
-                 .iter().filter_map(|(i, s)| Roman::from_str(s).ok().map(|b| (*b, i)))
-                 .filter(|(b, _)| *b <= *a)
-                 .max_by(|x, y| x.0.cmp(&y.0))
-                 .map(|(b, i)| *i + (*a - b) as usize)
-            } else {
-                None
-            }
-        })
+        self.info.reader.as_ref().and_then(|r| r.page_for_name(name))
     }
 
     fn text_excerpt(&self, sel: [TextLocation; 2]) -> Option<String> {
@@ -2311,6 +2902,20 @@ impl Reader {
         Some(text)
     }
 
+    fn text_context(&self, sel: [TextLocation; 2]) -> AnnotationContext {
+        let mut words = self.text.values().flatten().collect::<Vec<&BoundedText>>();
+        words.sort_by_key(|word| word.location);
+        annotation_context(words, sel)
+    }
+
+    fn text_chapter(&self, sel: [TextLocation; 2]) -> String {
+        let mut doc = self.doc.lock().unwrap();
+        self.toc().or_else(|| doc.toc())
+            .as_ref().and_then(|toc| doc.chapter(sel[0].location(), toc))
+            .map(|c| c.title.clone())
+            .unwrap_or_default()
+    }
+
     fn selected_text(&self) -> Option<String> {
         self.selection.as_ref().and_then(|sel| self.text_excerpt([sel.start, sel.end]))
     }
@@ -2394,18 +2999,39 @@ impl Reader {
             return;
         }
 
+        if self.info.effective_inverted(self.global_inverted) != self.global_inverted {
+            context.fb.set_inverted(self.global_inverted);
+        }
+
+        if context.fb.dithered() {
+            context.fb.set_dithered(false);
+        }
+
+        let current_location = Some(TextLocation::Dynamic(self.reading_location())).filter(|_| self.synthetic);
+
         if let Some(ref mut r) = self.info.reader {
+            r.end_session(Local::now(), self.current_page, context.settings.reader.idle_threshold);
             r.current_page = self.current_page;
+            r.current_location = current_location;
+            r.reach_page(self.current_page);
             r.pages_count = self.pages_count;
-            r.finished = self.finished;
+            r.set_finished(self.finished);
+            // Once stored, the zoom mode doesn't follow the default of the kind anymore.
+            let default_zoom_mode = context.settings.reader.zoom_mode(&self.info.file.kind);
+            if r.zoom_mode.is_some() || self.view_port.zoom_mode != default_zoom_mode {
+                r.zoom_mode = Some(self.view_port.zoom_mode);
+            }
             if self.view_port.zoom_mode == ZoomMode::FitToPage {
-                r.zoom_mode = None;
                 r.top_offset = None;
             } else {
-                r.zoom_mode = Some(self.view_port.zoom_mode);
                 r.top_offset = Some(self.view_port.top_offset);
             }
-            r.rotation = Some(context.display.rotation);
+            if self.view_port.zoom_mode.is_windowed() {
+                r.left_offset = Some(self.view_port.left_offset);
+            } else {
+                r.left_offset = None;
+            }
+            r.set_rotation(context.display.rotation);
             if (self.contrast.exponent - DEFAULT_CONTRAST_EXPONENT).abs() > f32::EPSILON {
                 r.contrast_exponent = Some(self.contrast.exponent);
                 if (self.contrast.gray - DEFAULT_CONTRAST_GRAY).abs() > f32::EPSILON {
@@ -2946,25 +3572,18 @@ impl View for Reader {
                 let re = Regex::new(r#"^([-+"'])?(.+)$"#).unwrap();
                 if let Some(caps) = re.captures(text) {
                     let prefix = caps.get(1).map(|m| m.as_str());
-                    if prefix == Some("\"") || prefix == Some("'") {
-                        if let Some(location) = self.find_page_by_name(&caps[2]) {
-                            self.go_to_page(location, true, hub, context);
-                        }
-                    } else {
-                        if let Ok(number) = caps[2].parse::<f64>() {
-                            let location = if !self.synthetic {
-                                let mut index = number.max(0.0) as usize;
-                                match prefix {
-                                    Some("-") => index = self.current_page.saturating_sub(index),
-                                    Some("+") => index += self.current_page,
-                                    _ => index = index.saturating_sub(1),
-                                }
-                                index
-                            } else {
-                                (number * BYTES_PER_PAGE).max(0.0).round() as usize
-                            };
-                            self.go_to_page(location, true, hub, context);
-                        }
+                    let location = match (prefix, caps[2].parse::<f64>()) {
+                        (Some("-"), Ok(number)) | (Some("+"), Ok(number)) | (None, Ok(number)) if self.synthetic => {
+                            Some((number * BYTES_PER_PAGE).max(0.0).round() as usize)
+                        },
+                        (Some("-"), Ok(number)) => Some(self.current_page.saturating_sub(number.max(0.0) as usize)),
+                        (Some("+"), Ok(number)) => Some(self.current_page + number.max(0.0) as usize),
+                        // The page names, such as *xiv*, win over the page numbers.
+                        (Some("\""), _) | (Some("'"), _) | (None, _) => self.find_page_by_name(&caps[2]),
+                        _ => None,
+                    };
+                    if let Some(location) = location {
+                        self.go_to_page(location, true, hub, context);
                     }
                 }
                 true
@@ -2984,17 +3603,28 @@ impl View for Reader {
                 self.toggle_keyboard(false, None, hub, context);
                 true
             },
+            Event::Submit(ViewId::LabelBookmarkInput, ref text) => {
+                if let Some(ref mut r) = self.info.reader {
+                    r.rename_bookmark(self.current_page, text);
+                }
+                self.toggle_keyboard(false, None, hub, context);
+                true
+            },
             Event::Submit(ViewId::EditNoteInput, ref note) => {
                 let selection = self.selection.take().map(|sel| [sel.start, sel.end]);
 
                 if let Some(sel) = selection {
                     let text = self.text_excerpt(sel).unwrap();
+                    let context = self.text_context(sel);
+                    let chapter = self.text_chapter(sel);
                     self.info.reader.as_mut().map(|r| {
                         r.annotations.push(Annotation {
                             selection: sel,
                             note: note.to_string(),
                             text,
-                            modified: Local::now(),
+                            chapter,
+                            context: Some(context),
+                            .. Default::default()
                         });
                     });
                     if let Some(rect) = self.text_rect(sel) {
@@ -3181,6 +3811,10 @@ impl View for Reader {
                 self.target_annotation = None;
                 false
             },
+            Event::Close(ViewId::LabelBookmark) => {
+                self.toggle_keyboard(false, None, hub, context);
+                false
+            },
             Event::Close(ViewId::NamePage) => {
                 self.toggle_keyboard(false, None, hub, context);
                 false
@@ -3262,12 +3896,16 @@ impl View for Reader {
             Event::Select(EntryId::HighlightSelection) => {
                 if let Some(sel) = self.selection.take() {
                     let text = self.text_excerpt([sel.start, sel.end]).unwrap();
+                    let context = self.text_context([sel.start, sel.end]);
+                    let chapter = self.text_chapter([sel.start, sel.end]);
                     self.info.reader.as_mut().map(|r| {
                         r.annotations.push(Annotation {
                             selection: [sel.start, sel.end],
                             note: String::new(),
                             text,
-                            modified: Local::now(),
+                            chapter,
+                            context: Some(context),
+                            .. Default::default()
                         });
                     });
                     if let Some(rect) = self.text_rect([sel.start, sel.end]) {
@@ -3343,6 +3981,17 @@ impl View for Reader {
                 self.update_annotations();
                 true
             },
+            Event::Select(EntryId::SetAnnotationStyle(sel, style)) => {
+                if let Some(annot) = self.find_annotation_mut(sel) {
+                    annot.style = style;
+                    annot.modified = Local::now();
+                }
+                self.update_annotations();
+                if let Some(rect) = self.text_rect(sel) {
+                    hub.send(Event::RenderRegion(rect, UpdateMode::Gui)).ok();
+                }
+                true
+            },
             Event::Select(EntryId::RemoveAnnotation(sel)) => {
                 if let Some(annotations) = self.info.reader.as_mut().map(|r| &mut r.annotations) {
                     annotations.retain(|annot| annot.selection[0] != sel[0] || annot.selection[1] != sel[1]); 
@@ -3353,10 +4002,96 @@ impl View for Reader {
                 }
                 true
             },
+            Event::Select(EntryId::MergeAnnotations) => {
+                if let Some(r) = self.info.reader.as_mut() {
+                    if merge_overlapping_annotations(r) > 0 {
+                        self.update_annotations();
+                        hub.send(Event::Render(self.rect, UpdateMode::Gui)).ok();
+                    }
+                }
+                true
+            },
             Event::Select(EntryId::SetZoomMode(zoom_mode)) => {
                 self.set_zoom_mode(zoom_mode, hub, context);
                 true
             },
+            Event::Select(EntryId::SetHyphenation(enabled)) => {
+                // The language of the book is kept.
+                let language = self.info.reader.as_ref()
+                                   .and_then(|r| r.hyphenation.as_ref())
+                                   .and_then(|h| h.language.clone());
+                let hyphenation = enabled.map(|enabled| HyphenationSettings { enabled, language });
+                self.set_hyphenation(hyphenation, hub, context);
+                true
+            },
+            Event::Select(EntryId::SetParagraphSpacing(index)) => {
+                self.set_paragraph_spacing(index.map(|i| PARAGRAPH_SPACINGS[i]), hub, context);
+                true
+            },
+            Event::Select(EntryId::SetTextIndent(index)) => {
+                self.set_text_indent(index.map(|i| TEXT_INDENTS[i]), hub, context);
+                true
+            },
+            Event::Select(EntryId::SetFontWeight(index)) => {
+                self.set_font_weight(index.map(|i| FONT_WEIGHTS[i].1), hub, context);
+                true
+            },
+            Event::Select(EntryId::SetLetterSpacing(index)) => {
+                self.set_letter_spacing(index.map(|i| LETTER_SPACINGS[i]), hub, context);
+                true
+            },
+            Event::Select(EntryId::SetContrastPreset(preset)) => {
+                self.set_contrast_preset(preset, hub, context);
+                true
+            },
+            Event::Select(EntryId::ToggleDithered) => {
+                if let Some(r) = self.info.reader.as_mut() {
+                    let dithered = !r.dithered();
+                    r.dither = if dithered { Some(DitherMode::Ordered) } else { None };
+                    context.fb.set_dithered(dithered);
+                    hub.send(Event::Render(self.rect, UpdateMode::Full)).ok();
+                }
+                true
+            },
+            Event::Select(EntryId::SetRefreshEvery(refresh_every)) => {
+                if let Some(r) = self.info.reader.as_mut() {
+                    r.refresh_every = refresh_every;
+                }
+                true
+            },
+            Event::Select(EntryId::ToggleRotationLock) => {
+                if let Some(r) = self.info.reader.as_mut() {
+                    if r.rotation_lock == Some(true) {
+                        r.rotation_lock = None;
+                    } else {
+                        r.rotation_lock = Some(true);
+                        r.set_rotation(context.display.rotation);
+                    }
+                }
+                true
+            },
+            Event::Select(EntryId::SetInverted(inverted)) => {
+                if let Some(r) = self.info.reader.as_mut() {
+                    r.inverted = inverted;
+                }
+                let inverted = self.info.effective_inverted(self.global_inverted);
+                if inverted != context.fb.inverted() {
+                    context.fb.set_inverted(inverted);
+                    hub.send(Event::Render(self.rect, UpdateMode::Gui)).ok();
+                }
+                true
+            },
+            Event::Select(EntryId::GoForward) => {
+                self.go_to_next_page(hub, context);
+                true
+            },
+            Event::Select(EntryId::ResetProgress) => {
+                if let Some(r) = self.info.reader.as_mut() {
+                    r.current_page = self.current_page;
+                    r.reset_progress();
+                }
+                true
+            },
             Event::Select(EntryId::ApplyCroppings(index, scheme)) => {
                 self.info.reader.as_mut().map(|r| {
                     if r.cropping_margins.is_none() {
@@ -3366,6 +4101,39 @@ impl View for Reader {
                 });
                 true
             },
+            Event::Select(EntryId::AutoCrop(index, scheme)) => {
+                let margins = {
+                    let mut doc = self.doc.lock().unwrap();
+                    auto_crop(doc.as_mut(), index, scheme, context.settings.reader.auto_crop_limit)
+                };
+                if let Some(r) = self.info.reader.as_mut() {
+                    r.cropping_margins = Some(margins);
+                }
+                // Show the detected margin in the cropper.
+                self.toggle_margin_cropper(false, hub, context);
+                self.toggle_margin_cropper(true, hub, context);
+                true
+            },
+            Event::Select(EntryId::ToggleCroppingException(index)) => {
+                if let Some(c) = self.info.reader.as_mut().and_then(|r| r.cropping_margins.as_mut()) {
+                    if c.has_exception(index) {
+                        c.clear_exception(index);
+                    } else {
+                        c.add_exception(index);
+                    }
+                }
+                self.cache.clear();
+                self.update(None, hub, context);
+                true
+            },
+            Event::Select(EntryId::ClearCroppingExceptions) => {
+                if let Some(c) = self.info.reader.as_mut().and_then(|r| r.cropping_margins.as_mut()) {
+                    c.clear_exceptions();
+                }
+                self.cache.clear();
+                self.update(None, hub, context);
+                true
+            },
             Event::Select(EntryId::RemoveCroppings) => {
                 if let Some(r) = self.info.reader.as_mut() {
                     r.cropping_margins = None;
@@ -3417,12 +4185,24 @@ impl View for Reader {
                 self.toggle_name_page(None, hub, context);
                 true
             },
+            Event::Select(EntryId::LabelBookmark) => {
+                self.toggle_label_bookmark(None, hub, context);
+                true
+            },
             Event::Select(EntryId::RemovePageName) => {
                 if let Some(ref mut r) = self.info.reader {
                     r.page_names.remove(&self.current_page);
                 }
                 true
             },
+            Event::Select(EntryId::ToggleFirstPage) => {
+                if let Some(ref mut r) = self.info.reader {
+                    let offset = self.current_page as i32;
+                    r.page_offset = if r.page_offset == Some(offset) { None } else { Some(offset) };
+                }
+                self.update_bottom_bar(hub);
+                true
+            },
             Event::Reseed => {
                 self.reseed(hub, context);
                 true
@@ -3505,15 +4285,14 @@ impl View for Reader {
                 }
 
                 if let Some(annotations) = self.annotations.get(&chunk.location) {
+                    let thickness = scale_by_dpi(THICKNESS_MEDIUM, CURRENT_DEVICE.dpi) as i32;
                     for annot in annotations {
                         let [start, end] = annot.selection;
                         if let Some(text) = self.text.get(&chunk.location) {
                             let mut last_rect: Option<Rectangle> = None;
                             for word in text.iter().filter(|w| w.location >= start && w.location <= end) {
                                 let rect = (word.rect * scale).to_rect() - chunk.frame.min + chunk.position;
-                                if let Some(ref sel_rect) = rect.intersection(&region_rect) {
-                                    fb.shift_region(sel_rect, ANNOTATION_DRIFT);
-                                }
+                                draw_highlight(fb, &rect, &region_rect, annot.style, thickness);
                                 if let Some(last) = last_rect {
                                     if rect.min.y < last.max.y && last.min.y < rect.max.y && (last.max.x < rect.min.x || rect.max.x < last.min.x) {
                                         let space = if last.max.x < rect.min.x {
@@ -3523,9 +4302,7 @@ impl View for Reader {
                                             rect![rect.max.x, (last.min.y + rect.min.y) / 2,
                                                   last.min.x, (last.max.y + rect.max.y) / 2]
                                         };
-                                        if let Some(ref sel_rect) = space.intersection(&region_rect) {
-                                            fb.shift_region(sel_rect, ANNOTATION_DRIFT);
-                                        }
+                                        draw_highlight(fb, &space, &region_rect, annot.style, thickness);
                                     }
                                 }
                                 last_rect = Some(rect);
@@ -3678,10 +4455,19 @@ impl View for Reader {
             }
         }
 
-        if self.view_port.zoom_mode == ZoomMode::FitToWidth {
-            let ratio = (rect.width() as i32 - 2 * self.view_port.margin_width) as f32 /
-                        (self.rect.width() as i32 - 2 * self.view_port.margin_width) as f32;
-            self.view_port.top_offset = (self.view_port.top_offset as f32 * ratio) as i32;
+        match self.view_port.zoom_mode {
+            ZoomMode::FitToWidth | ZoomMode::Custom(..) => {
+                let ratio = (rect.width() as i32 - 2 * self.view_port.margin_width) as f32 /
+                            (self.rect.width() as i32 - 2 * self.view_port.margin_width) as f32;
+                self.view_port.top_offset = (self.view_port.top_offset as f32 * ratio) as i32;
+                self.view_port.left_offset = (self.view_port.left_offset as f32 * ratio) as i32;
+            },
+            ZoomMode::FitToHeight => {
+                let ratio = (rect.height() as i32 - 2 * self.view_port.margin_width) as f32 /
+                            (self.rect.height() as i32 - 2 * self.view_port.margin_width) as f32;
+                self.view_port.left_offset = (self.view_port.left_offset as f32 * ratio) as i32;
+            },
+            ZoomMode::FitToPage => (),
         }
 
         self.rect = rect;
@@ -3692,10 +4478,16 @@ impl View for Reader {
                                 .unwrap_or(context.settings.reader.font_size);
             let mut doc = self.doc.lock().unwrap();
             doc.layout(rect.width(), rect.height(), font_size, CURRENT_DEVICE.dpi);
-            let current_page = self.current_page.min(doc.pages_count() - 1);
-            if let Some(location) = doc.resolve_location(Location::Exact(current_page)) {
+            let current_location = self.reading_location().min(doc.pages_count() - 1);
+            if let Some(location) = doc.resolve_location(Location::Exact(current_location)) {
                 self.current_page = location;
             }
+            if self.synthetic {
+                self.anchor = Some((self.current_page, current_location));
+            }
+            if let Some(ref mut r) = self.info.reader {
+                update_page_names(&mut **doc, &self.page_list, r);
+            }
             self.text.clear();
         }
 
@@ -3707,6 +4499,10 @@ impl View for Reader {
         self.search.is_none()
     }
 
+    fn rotation_locked(&self) -> bool {
+        self.info.reader.as_ref().map_or(false, |r| r.rotation_lock == Some(true))
+    }
+
     fn is_background(&self) -> bool {
         true
     }
@@ -1,8 +1,9 @@
 use crate::device::CURRENT_DEVICE;
 use crate::framebuffer::{Framebuffer, UpdateMode};
 use crate::settings::ReaderSettings;
-use crate::metadata::{ReaderInfo, TextAlign};
+use crate::metadata::{ReaderInfo, TextAlign, Direction};
 use crate::metadata::{DEFAULT_CONTRAST_EXPONENT, DEFAULT_CONTRAST_GRAY};
+use crate::metadata::{MIN_CONTRAST_EXPONENT, MAX_CONTRAST_EXPONENT, MAX_CONTRAST_GRAY};
 use crate::view::{View, Event, Hub, Bus, SliderId, ViewId, THICKNESS_MEDIUM};
 use crate::view::filler::Filler;
 use crate::view::slider::Slider;
@@ -24,7 +25,7 @@ pub struct ToolBar {
 }
 
 impl ToolBar {
-    pub fn new(rect: Rectangle, reflowable: bool, reader_info: Option<&ReaderInfo>, reader_settings: &ReaderSettings) -> ToolBar {
+    pub fn new(rect: Rectangle, reflowable: bool, reader_info: Option<&ReaderInfo>, direction: Direction, reader_settings: &ReaderSettings) -> ToolBar {
         let mut children = Vec::new();
         let dpi = CURRENT_DEVICE.dpi;
         let thickness = scale_by_dpi(THICKNESS_MEDIUM, dpi) as i32;
@@ -77,7 +78,7 @@ impl ToolBar {
 
             // Start of second row.
             let text_align = reader_info.and_then(|r| r.text_align)
-                                        .unwrap_or(reader_settings.text_align);
+                                        .unwrap_or_else(|| direction.default_text_align(reader_settings.text_align));
             let text_align_rect = rect![rect.min.x, rect.max.y - side,
                                        rect.min.x + side, rect.max.y];
             let text_align_icon = Icon::new(text_align.icon_name(),
@@ -118,8 +119,8 @@ impl ToolBar {
                                            rect.min.x + side + slider_width, rect.min.y + side],
                                      SliderId::ContrastExponent,
                                      contrast_exponent,
-                                     1.0,
-                                     5.0);
+                                     MIN_CONTRAST_EXPONENT,
+                                     MAX_CONTRAST_EXPONENT);
             children.push(Box::new(slider) as Box<dyn View>);
 
             let gray_icon_rect = rect![rect.min.x + side + slider_width, rect.min.y,
@@ -136,7 +137,7 @@ impl ToolBar {
                                      SliderId::ContrastGray,
                                      contrast_gray,
                                      0.0,
-                                     255.0);
+                                     MAX_CONTRAST_GRAY);
             children.push(Box::new(slider) as Box<dyn View>);
 
             let filler = Filler::new(rect![rect.max.x - side / 3,
@@ -0,0 +1,323 @@
+use std::fs;
+use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
+use std::os::unix::io::AsRawFd;
+use fnv::FnvHashMap;
+use nix::errno::Errno;
+use nix::unistd::close;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::sys::inotify::{Inotify, InitFlags, AddWatchFlags, WatchDescriptor};
+use failure::{Error, ResultExt, format_err};
+use crate::metadata::{FileInfo, RESERVED_DIRECTORIES};
+use crate::settings::ImportSettings;
+use crate::document::file_kind;
+
+// The library is watched through inotify, which *nix* already wraps, rather than
+// through the *notify* crate: the latter would bring its own event loop and
+// dependencies to the device for a single Linux backend.
+
+// The events of a file are merged until the file stays quiet for that long.
+const DEBOUNCE_DELAY: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone)]
+pub enum LibraryChange {
+    Added(FileInfo),
+    // The path of a removed file, or of a directory moved out of the library.
+    Removed(PathBuf),
+    Modified(PathBuf),
+    // Some events were lost, the whole library needs to be scanned again.
+    Rescan,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Pending {
+    Added,
+    Removed,
+    Modified,
+}
+
+pub struct LibraryWatcher {
+    inotify: Inotify,
+    root: PathBuf,
+    settings: ImportSettings,
+    // The watched directories, relative to the root.
+    dirs: FnvHashMap<WatchDescriptor, PathBuf>,
+    // The changes that haven't settled yet, with their order of appearance.
+    pending: FnvHashMap<PathBuf, (usize, Pending)>,
+    sequence: usize,
+    // The event queue overflowed since the last changes were returned.
+    overflowed: bool,
+}
+
+impl LibraryWatcher {
+    pub fn new(dir: &Path, settings: ImportSettings) -> Result<LibraryWatcher, Error> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)
+                              .context("Can't initialize inotify.")?;
+        let mut watcher = LibraryWatcher {
+            inotify,
+            root: dir.to_path_buf(),
+            settings,
+            dirs: FnvHashMap::default(),
+            pending: FnvHashMap::default(),
+            sequence: 0,
+            overflowed: false,
+        };
+        watcher.watch_dir(Path::new(""))?;
+        Ok(watcher)
+    }
+
+    // Watches the given directory, relative to the root, and its subdirectories.
+    // Returns the files that are already there.
+    fn watch_dir(&mut self, relat: &Path) -> Result<Vec<PathBuf>, Error> {
+        let flags = AddWatchFlags::IN_CREATE | AddWatchFlags::IN_CLOSE_WRITE |
+                    AddWatchFlags::IN_DELETE | AddWatchFlags::IN_MOVE |
+                    AddWatchFlags::IN_ONLYDIR;
+        let wd = self.inotify.add_watch(&self.root.join(relat), flags)
+                     .context("Can't watch directory.")?;
+        self.dirs.insert(wd, relat.to_path_buf());
+
+        let mut files = Vec::new();
+        for entry in fs::read_dir(self.root.join(relat)).context("Can't read directory.")? {
+            let entry = entry.context("Can't read directory entry.")?;
+            let path = relat.join(entry.file_name());
+            if entry.path().is_dir() {
+                if self.is_watchable_dir(&path) {
+                    files.extend(self.watch_dir(&path)?);
+                }
+            } else if self.is_relevant_file(&path) {
+                files.push(path);
+            }
+        }
+        Ok(files)
+    }
+
+    // Stops watching a directory that left the library, and its subdirectories.
+    fn unwatch_dir(&mut self, relat: &Path) {
+        let wds: Vec<WatchDescriptor> = self.dirs.iter()
+                                            .filter(|(_, dir)| dir.starts_with(relat))
+                                            .map(|(wd, _)| *wd).collect();
+        for wd in wds {
+            self.inotify.rm_watch(wd).ok();
+            self.dirs.remove(&wd);
+        }
+    }
+
+    // Applies the hidden and reserved directory rules of the importer.
+    fn is_watchable_dir(&self, path: &Path) -> bool {
+        path.file_name().and_then(|name| name.to_str()).map_or(true, |name| {
+            (self.settings.traverse_hidden || !name.starts_with('.')) &&
+            !RESERVED_DIRECTORIES.contains(name)
+        })
+    }
+
+    fn is_relevant_file(&self, path: &Path) -> bool {
+        let hidden = path.file_name().map_or(true, |name| name.to_string_lossy().starts_with('.'));
+        !hidden && file_kind(path).map_or(false, |kind| self.settings.allowed_kinds.contains(&kind))
+    }
+
+    fn push(&mut self, path: PathBuf, change: Pending) {
+        let previous = self.pending.remove(&path).map(|(_, change)| change);
+        let change = match (previous, change) {
+            // A file created and deleted in a row never existed for the library.
+            (Some(Pending::Added), Pending::Removed) => return,
+            (Some(Pending::Added), _) => Pending::Added,
+            (Some(Pending::Removed), Pending::Added) |
+            (Some(Pending::Removed), Pending::Modified) => Pending::Modified,
+            (_, change) => change,
+        };
+        self.sequence += 1;
+        self.pending.insert(path, (self.sequence, change));
+    }
+
+    fn read_events(&mut self) -> Result<(), Error> {
+        loop {
+            let events = match self.inotify.read_events() {
+                Ok(events) => events,
+                Err(nix::Error::Sys(Errno::EAGAIN)) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            };
+
+            for event in events {
+                let path = match (self.dirs.get(&event.wd), event.name.as_ref()) {
+                    (Some(dir), Some(name)) => dir.join(name),
+                    _ => {
+                        if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+                            self.overflowed = true;
+                        } else if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+                            // The root was deleted or unmounted.
+                            if self.dirs.remove(&event.wd).map_or(false, |dir| dir == Path::new("")) {
+                                return Err(format_err!("The library isn't there anymore."));
+                            }
+                        }
+                        continue;
+                    },
+                };
+
+                if event.mask.contains(AddWatchFlags::IN_ISDIR) {
+                    if !self.is_watchable_dir(&path) {
+                        continue;
+                    }
+                    if event.mask.intersects(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO) {
+                        // The files might have been written before the directory was watched.
+                        for file in self.watch_dir(&path)? {
+                            self.push(file, Pending::Added);
+                        }
+                    } else if event.mask.contains(AddWatchFlags::IN_MOVED_FROM) {
+                        self.unwatch_dir(&path);
+                        self.push(path, Pending::Removed);
+                    }
+                    continue;
+                }
+
+                if !self.is_relevant_file(&path) {
+                    continue;
+                }
+
+                if event.mask.intersects(AddWatchFlags::IN_CREATE | AddWatchFlags::IN_MOVED_TO) {
+                    self.push(path, Pending::Added);
+                } else if event.mask.contains(AddWatchFlags::IN_CLOSE_WRITE) {
+                    self.push(path, Pending::Modified);
+                } else if event.mask.intersects(AddWatchFlags::IN_DELETE | AddWatchFlags::IN_MOVED_FROM) {
+                    self.push(path, Pending::Removed);
+                }
+            }
+        }
+    }
+
+    // Waits at most *timeout*, or forever, for changes, and returns them once they've settled.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> Result<Vec<LibraryChange>, Error> {
+        let start = Instant::now();
+
+        loop {
+            let settled = !self.pending.is_empty() || self.overflowed;
+            let delay = if !settled {
+                match timeout {
+                    Some(timeout) => {
+                        let elapsed = start.elapsed();
+                        if elapsed >= timeout {
+                            return Ok(Vec::new());
+                        }
+                        (timeout - elapsed).as_millis() as i32
+                    },
+                    None => -1,
+                }
+            } else {
+                DEBOUNCE_DELAY.as_millis() as i32
+            };
+
+            let mut fds = [PollFd::new(self.inotify.as_raw_fd(), PollFlags::POLLIN)];
+            match poll(&mut fds, delay) {
+                Ok(0) if settled => break,
+                Ok(0) => (),
+                Ok(_) => self.read_events()?,
+                Err(nix::Error::Sys(Errno::EINTR)) => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        if self.overflowed {
+            self.overflowed = false;
+            self.pending.clear();
+            // The directories created meanwhile aren't watched yet.
+            self.watch_dir(Path::new(""))?;
+            return Ok(vec![LibraryChange::Rescan]);
+        }
+
+        let mut pending: Vec<(PathBuf, (usize, Pending))> = self.pending.drain().collect();
+        pending.sort_by_key(|(_, (sequence, _))| *sequence);
+        let root = &self.root;
+        let changes = pending.into_iter().map(|(path, (_, change))| match change {
+            Pending::Added => {
+                let size = fs::metadata(root.join(&path)).map(|m| m.len()).unwrap_or_default();
+                let kind = file_kind(&path).unwrap_or_default();
+                LibraryChange::Added(FileInfo { path, kind, size, checksum: None })
+            },
+            Pending::Removed => LibraryChange::Removed(path),
+            Pending::Modified => LibraryChange::Modified(path),
+        }).collect();
+
+        Ok(changes)
+    }
+}
+
+impl Drop for LibraryWatcher {
+    fn drop(&mut self) {
+        close(self.inotify.as_raw_fd()).ok();
+    }
+}
+
+// Reports the changes of the library's files, filtered like the importer filters them.
+// Only returns if the library can't be watched anymore, e.g. when it's unmounted.
+pub fn watch_library(dir: &Path, settings: ImportSettings, mut on_change: impl FnMut(LibraryChange)) -> Result<(), Error> {
+    let mut watcher = LibraryWatcher::new(dir, settings)?;
+    loop {
+        for change in watcher.wait(None)? {
+            on_change(change);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::TempDir;
+
+    #[test]
+    fn file_changes() {
+        let dir = TempDir::new("watcher");
+        fs::create_dir_all(dir.join(".hidden")).unwrap();
+        let mut watcher = LibraryWatcher::new(&dir, ImportSettings::default()).unwrap();
+        let timeout = Some(Duration::from_secs(2));
+
+        fs::write(dir.join("Flatland.epub"), b"Flatland").unwrap();
+        fs::write(dir.join("notes.xyz"), b"Notes").unwrap();
+        fs::write(dir.join(".hidden/Secret.pdf"), b"Secret").unwrap();
+        let changes = watcher.wait(timeout).unwrap();
+        assert_eq!(changes.len(), 1);
+        match changes[0] {
+            LibraryChange::Added(ref info) => {
+                assert_eq!(info.path, PathBuf::from("Flatland.epub"));
+                assert_eq!(info.kind, "epub");
+                assert_eq!(info.size, 8);
+            },
+            ref change => panic!("unexpected change: {:?}", change),
+        }
+
+        fs::create_dir(dir.join("Abbott")).unwrap();
+        fs::rename(dir.join("Flatland.epub"), dir.join("Abbott/Flatland.epub")).unwrap();
+        let changes = watcher.wait(timeout).unwrap();
+        assert_eq!(changes.len(), 2);
+        assert!(changes.iter().any(|c| matches!(c, LibraryChange::Removed(path) if path == Path::new("Flatland.epub"))));
+        assert!(changes.iter().any(|c| matches!(c, LibraryChange::Added(info) if info.path == Path::new("Abbott/Flatland.epub"))));
+
+        fs::write(dir.join("Abbott/Flatland.epub"), b"Flatland, 2nd edition").unwrap();
+        fs::write(dir.join("Draft.pdf"), b"Draft").unwrap();
+        fs::remove_file(dir.join("Draft.pdf")).unwrap();
+        let changes = watcher.wait(timeout).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert!(matches!(changes[0], LibraryChange::Modified(ref path) if path == Path::new("Abbott/Flatland.epub")));
+
+        fs::remove_file(dir.join("Abbott/Flatland.epub")).unwrap();
+        let changes = watcher.wait(timeout).unwrap();
+        assert!(matches!(changes[..], [LibraryChange::Removed(ref path)] if path == Path::new("Abbott/Flatland.epub")));
+
+        assert!(watcher.wait(Some(Duration::from_millis(50))).unwrap().is_empty());
+    }
+
+    #[test]
+    fn queue_overflow() {
+        let dir = TempDir::new("watcher-overflow");
+        let mut watcher = LibraryWatcher::new(&dir, ImportSettings::default()).unwrap();
+        let max_events: usize = fs::read_to_string("/proc/sys/fs/inotify/max_queued_events").ok()
+                                   .and_then(|s| s.trim().parse().ok()).unwrap_or(16_384);
+        // Each file yields a creation and a write event.
+        for i in 0..max_events / 2 + 1 {
+            fs::write(dir.join(format!("{}.epub", i)), b"").unwrap();
+        }
+        let changes = watcher.wait(Some(Duration::from_secs(2))).unwrap();
+        assert!(matches!(changes[..], [LibraryChange::Rescan]));
+        fs::write(dir.join("Flatland.epub"), b"Flatland").unwrap();
+        let changes = watcher.wait(Some(Duration::from_secs(2))).unwrap();
+        assert!(matches!(changes[..], [LibraryChange::Added(ref info)] if info.path == Path::new("Flatland.epub")));
+    }
+}